@@ -0,0 +1,168 @@
+//! Measures how fast `Wolfwig::step` runs a looping ROM, without a window or sound. Run with
+//! `cargo bench`. Throughput is in emulated machine cycles per second, which the real hardware
+//! runs 1,048,576 of.
+//!
+//! The `core` cases run just the CPU on plain RAM, once calling `SM83` directly and once through
+//! `dyn Cpu` and `dyn Bus` like `Wolfwig` does, to show what the dynamic dispatch costs.
+//!
+//! `cpu_traced` is the `cpu` case with a tracing subscriber installed, to show what the spans cost
+//! when logging's on.
+#[macro_use]
+extern crate criterion;
+extern crate tracing_subscriber;
+extern crate wolfwig;
+
+use criterion::{BatchSize, Criterion, Throughput};
+use std::io;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+use wolfwig::{Bus, Cpu, Model, Wolfwig, SM83};
+
+// Machine cycles run per iteration, about six frames.
+const CYCLES: u32 = 100_000;
+
+// A loop that touches a bit of everything: immediate loads, ALU ops on registers and memory,
+// 16-bit increments, stack ops, a call, and a conditional jump back.
+const LOOP: &[u8] = &[
+    0x21, 0x00, 0xC0, // LD HL,0xC000
+    0x06, 0x40, //       LD B,0x40
+    0x3E, 0x12, //       LD A,0x12
+    0x86, //             ADD A,(HL)
+    0x77, //             LD (HL),A
+    0x23, //             INC HL
+    0xCB, 0x37, //       SWAP A
+    0xC5, //             PUSH BC
+    0xC1, //             POP BC
+    0xCD, 0x00, 0x02, // CALL 0x0200
+    0x05, //             DEC B
+    0x20, 0xF3, //       JR NZ,-13 (to ADD A,(HL))
+    0xC3, 0x60, 0x01, // JP 0x0160
+];
+
+// Builds a ROM that runs `setup` once and then `LOOP` forever.
+fn rom(setup: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]); // JP 0x0150
+    let mut code = setup.to_vec();
+    code.extend_from_slice(&[0xC3, 0x60, 0x01]); // JP 0x0160
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+    rom[0x160..0x160 + LOOP.len()].copy_from_slice(LOOP);
+    rom[0x200] = 0xC9; // RET
+    rom
+}
+
+// 64KiB of RAM with `rom` at the bottom, and no interrupts.
+struct Ram(Vec<u8>);
+
+impl Bus for Ram {
+    fn read(&self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        self.0[address as usize] = val;
+    }
+
+    fn get_interrupt(&self) -> Option<u16> {
+        None
+    }
+
+    fn disable_interrupt(&mut self) {}
+}
+
+fn ram(rom: &[u8]) -> Ram {
+    let mut ram = rom.to_vec();
+    ram.resize(0x10000, 0);
+    Ram(ram)
+}
+
+// LD A,0; LDH (0x40),A turns the LCD off, so only the CPU and timers run.
+fn lcd_off() -> Vec<u8> {
+    rom(&[0x3E, 0x00, 0xE0, 0x40])
+}
+
+fn bench_wolfwig(c: &mut Criterion, name: &str, rom: &[u8]) {
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(u64::from(CYCLES)));
+    group.bench_function(name, |b| {
+        b.iter_batched(
+            || Wolfwig::new_headless(rom.to_vec(), Model::Dmg),
+            |mut wolfwig| {
+                for _ in 0..CYCLES {
+                    wolfwig.step();
+                }
+                wolfwig
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn cpu(c: &mut Criterion) {
+    bench_wolfwig(c, "cpu", &lcd_off());
+}
+
+fn core(c: &mut Criterion) {
+    let rom = lcd_off();
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(u64::from(CYCLES)));
+    group.bench_function("core_static", |b| {
+        b.iter_batched(
+            || {
+                let mut cpu = SM83::new();
+                cpu.set_pc(0x0100);
+                (cpu, ram(&rom))
+            },
+            |(mut cpu, mut mem)| {
+                for _ in 0..CYCLES {
+                    cpu.step(&mut mem);
+                }
+                (cpu, mem)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("core_dyn", |b| {
+        b.iter_batched(
+            || {
+                let mut cpu: Box<dyn Cpu> = Box::new(SM83::new());
+                cpu.set_pc(0x0100);
+                let mem: Box<dyn Bus> = Box::new(ram(&rom));
+                (cpu, mem)
+            },
+            |(mut cpu, mut mem)| {
+                for _ in 0..CYCLES {
+                    cpu.step(&mut *mem);
+                }
+                (cpu, mem)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// The LCD is left on from the boot state, so the PPU renders every frame.
+fn with_lcd(c: &mut Criterion) {
+    bench_wolfwig(c, "with_lcd", &rom(&[]));
+}
+
+// Logs at info level with --time_spans, but to nowhere. The CPU logs every cycle at info, which
+// would swamp the spans, so it's left at warn. The subscriber stays installed for the rest of the
+// process, so this has to run last.
+fn cpu_traced(c: &mut Criterion) {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new("info,wolfwig::cpu=warn"))
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::sink)
+        .init();
+    bench_wolfwig(c, "cpu_traced", &lcd_off());
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = cpu, core, with_lcd, cpu_traced
+}
+criterion_main!(benches);
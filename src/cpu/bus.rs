@@ -0,0 +1,80 @@
+//! What the CPU sees of the rest of the machine. `Peripherals` is the real thing, and `FlatRam` is
+//! 64KiB of plain RAM, so the CPU can be tested on its own.
+
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+    fn write(&mut self, address: u16, val: u8);
+
+    /// Reads a byte of an instruction. Unlike `read`, this isn't seen by watchpoints or read
+    /// hooks, since most fetches are answered from the decode cache and never get here.
+    fn fetch(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+
+    /// The vector of the highest priority interrupt that's both requested and enabled.
+    fn get_interrupt(&self) -> Option<u16>;
+
+    /// Clears the request for the interrupt `get_interrupt` returns, as it's dispatched.
+    fn disable_interrupt(&mut self);
+
+    /// Whether any selected joypad line is low, which is what wakes the CPU from STOP.
+    fn joypad_pressed(&self) -> bool {
+        false
+    }
+
+    /// Counts writes to the page containing `address`, so decodes from there can be cached.
+    /// None means what's there can change without being written, and the default is that nothing
+    /// is cached.
+    fn code_version(&self, _address: u16) -> Option<u32> {
+        None
+    }
+
+    /// The ROM bank mapped at `address`, or 0 outside the switchable bank.
+    fn rom_bank_at(&self, _address: u16) -> u16 {
+        0
+    }
+}
+
+/// RAM across the whole address space. IF and IE work as interrupt flags, and nothing else is
+/// special.
+#[cfg(test)]
+pub struct FlatRam {
+    mem: Vec<u8>,
+}
+
+#[cfg(test)]
+impl FlatRam {
+    /// With `program` at 0x0000.
+    pub fn new(program: &[u8]) -> Self {
+        let mut mem = vec![0; 0x10000];
+        mem[..program.len()].copy_from_slice(program);
+        Self { mem }
+    }
+
+    // The lowest requested and enabled interrupt bit, which is the highest priority.
+    fn pending_bit(&self) -> Option<u16> {
+        let pending = self.mem[0xFF0F] & self.mem[0xFFFF] & 0x1F;
+        (0..5).find(|bit| pending & 1 << bit != 0)
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatRam {
+    fn read(&self, address: u16) -> u8 {
+        self.mem[address as usize]
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        self.mem[address as usize] = val;
+    }
+
+    fn get_interrupt(&self) -> Option<u16> {
+        self.pending_bit().map(|bit| 0x40 + 8 * bit)
+    }
+
+    fn disable_interrupt(&mut self) {
+        if let Some(bit) = self.pending_bit() {
+            self.mem[0xFF0F] &= !(1 << bit);
+        }
+    }
+}
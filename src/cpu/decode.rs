@@ -1,12 +1,13 @@
-use peripherals::Peripherals;
+use cpu::bus::Bus;
 use std::fmt;
+use std::sync::OnceLock;
 
 use cpu::registers::Flag::{self, Carry, NotCarry, NotZero, Zero};
 use cpu::registers::Reg16::{self, AF, BC, DE, HL, SP};
 use cpu::registers::Reg8::{self, A, B, C, D, E, H, L};
 use util;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Address {
     Register16(Reg16),
     Immediate16(u16),
@@ -24,7 +25,7 @@ impl fmt::Display for Address {
 ///! Op
 /// TODO(slongfield): Encode the microops that make up these instructions, and the flags that
 /// they affect. Right now, mostly just doing this to display the instructions.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Op {
     Alu8(Alu8Op),
     Alu16(Alu16Op),
@@ -35,8 +36,7 @@ pub enum Op {
     ConditionalReturn(Flag),
     DisableInterrupts,
     EnableInterrupts,
-    SetupInterrupt,
-    ExecuteInterrupt(u16),
+    DispatchInterrupt,
     Halt,
     Jump(Address),
     JumpRelative(u16),
@@ -104,14 +104,14 @@ impl fmt::Display for Op {
             Op::StoreAndDecrement(addr, src) => write!(f, "LD ({}-) {}", addr, src),
             Op::StoreAndIncrement(addr, src) => write!(f, "LD ({}+) {}", addr, src),
             Op::WideStore(addr, src) => write!(f, "LD ({}) {}", addr, src),
-            Op::Unknown(_) | Op::SetupInterrupt | Op::ExecuteInterrupt(_) => {
+            Op::Unknown(_) | Op::DispatchInterrupt => {
                 write!(f, "Don't know how to display op")
             }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Alu8Data {
     Reg(Reg8),
     Imm(u8),
@@ -130,7 +130,7 @@ impl fmt::Display for Alu8Data {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Alu8 {
     Add,
     AddWithCarry,
@@ -156,11 +156,10 @@ pub enum Alu8 {
     SubWithCarry,
     Swap,
     TestBit,
-    Unknown,
     Xor,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Alu8Op {
     pub op: Alu8,
     pub dest: Alu8Data,
@@ -402,12 +401,9 @@ impl Alu8Op {
         }
     }
 
-    fn unknown() -> Self {
-        Self {
-            op: Alu8::Unknown,
-            dest: Alu8Data::Reg(A),
-            y: Alu8Data::Ignore,
-        }
+    /// Whether the op reads memory and writes the result back, like INC (HL).
+    pub fn writes_memory(&self) -> bool {
+        matches!(self.dest, Alu8Data::Addr(_)) && !matches!(self.op, Alu8::TestBit)
     }
 }
 
@@ -439,29 +435,27 @@ impl fmt::Display for Alu8Op {
             Alu8::Swap => write!(f, "SWAP"),
             Alu8::TestBit => write!(f, "TEST {},{}", self.y, self.dest),
             Alu8::Xor => write!(f, "XOR {},{}", self.dest, self.y),
-            Alu8::Unknown => write!(f, "UNKNOWN ALU8!"),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Alu16Data {
     Reg(Reg16),
     Imm(i8),
     Ignore,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Alu16 {
     Add,
     Decrement,
     Increment,
     Move,
     MoveAndAdd,
-    Unknown,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Alu16Op {
     pub op: Alu16,
     pub dest: Reg16,
@@ -523,15 +517,6 @@ impl Alu16Op {
             imm,
         }
     }
-
-    fn unknown() -> Self {
-        Self {
-            op: Alu16::Unknown,
-            dest: HL,
-            y: Alu16Data::Ignore,
-            imm: 0,
-        }
-    }
 }
 
 impl fmt::Display for Alu16Op {
@@ -546,670 +531,505 @@ impl fmt::Display for Alu16Op {
             Alu16::Decrement => write!(f, "DEC {}", self.dest),
             Alu16::Move => write!(f, "LD {},{:?}", self.dest, self.y),
             Alu16::MoveAndAdd => write!(f, "LD {},{:?}+0x{:02X}", self.dest, self.y, self.imm),
-            Alu16::Unknown => write!(f, "ALU16 ???"),
         }
     }
 }
 
-///! Decode takes the ROM and current PC, and returns the Op a that PC, as well as the number of
-///! bytes in that op, and the number of cycles it runs for.
-pub fn decode(rom: &Peripherals, pc: u16) -> (Op, usize, usize) {
-    if let Some((op, size, time)) = decode_alu8(&rom, pc) {
-        return (op, size, time);
-    }
-    if let Some((op, size, time)) = decode_alu16(&rom, pc) {
-        return (op, size, time);
-    }
-    if let Some((op, size, time)) = decode_load(&rom, pc) {
-        return (op, size, time);
-    }
-    if let Some((op, size, time)) = decode_jump(&rom, pc) {
-        return (op, size, time);
-    }
-    match rom.read(pc) {
-        0x00 => (Op::Nop, 1, 1),
-        0x10 => (Op::Stop, 2, 1),
-        0x76 => (Op::Halt, 1, 1),
-        0xF3 => (Op::DisableInterrupts, 1, 1),
-        0xFB => (Op::EnableInterrupts, 1, 1),
-        0xCB => decode_extended(rom.read(pc + 1)),
-        code => (Op::Unknown(code), 1, 0),
+// Immediate values that follow an opcode, read once when it's decoded.
+#[derive(Clone, Copy)]
+struct Operands {
+    pc: u16,
+    imm8: u8,
+    imm16: u16,
+}
+
+impl Operands {
+    // Target of a relative jump, which is relative to the end of the two-byte instruction.
+    fn relative(self) -> u16 {
+        self.pc
+            .wrapping_add(2)
+            .wrapping_add(i16::from(self.imm8 as i8) as u16)
     }
 }
 
-///! Decode ALU operations.
-fn decode_alu8(rom: &Peripherals, pc: u16) -> Option<(Op, usize, usize)> {
-    let imm8 = rom.read(pc + 1);
-    let inst = match rom.read(pc) {
-        0x04 => (Alu8Op::increment(Alu8Data::Reg(B)), 1, 1),
-        0x14 => (Alu8Op::increment(Alu8Data::Reg(D)), 1, 1),
-        0x24 => (Alu8Op::increment(Alu8Data::Reg(H)), 1, 1),
-        0x34 => (Alu8Op::increment(Alu8Data::Addr(HL)), 1, 3),
-        0x0C => (Alu8Op::increment(Alu8Data::Reg(C)), 1, 1),
-        0x1C => (Alu8Op::increment(Alu8Data::Reg(E)), 1, 1),
-        0x2C => (Alu8Op::increment(Alu8Data::Reg(L)), 1, 1),
-        0x3C => (Alu8Op::increment(Alu8Data::Reg(A)), 1, 1),
-
-        0x05 => (Alu8Op::decrement(Alu8Data::Reg(B)), 1, 1),
-        0x15 => (Alu8Op::decrement(Alu8Data::Reg(D)), 1, 1),
-        0x25 => (Alu8Op::decrement(Alu8Data::Reg(H)), 1, 1),
-        0x35 => (Alu8Op::decrement(Alu8Data::Addr(HL)), 1, 3),
-        0x0D => (Alu8Op::decrement(Alu8Data::Reg(C)), 1, 1),
-        0x1D => (Alu8Op::decrement(Alu8Data::Reg(E)), 1, 1),
-        0x2D => (Alu8Op::decrement(Alu8Data::Reg(L)), 1, 1),
-        0x3D => (Alu8Op::decrement(Alu8Data::Reg(A)), 1, 1),
-
-        0x07 => (Alu8Op::rotate_left_carry_a(), 1, 1),
-        0x0F => (Alu8Op::rotate_right_carry_a(), 1, 1),
-        0x17 => (Alu8Op::rotate_left_a(), 1, 1),
-        0x1F => (Alu8Op::rotate_right_a(), 1, 1),
-
-        0x27 => (Alu8Op::decimal_adjust(), 1, 1),
-        0x2F => (Alu8Op::complement(), 1, 1),
-
-        0x37 => (Alu8Op::set_carry_flag(), 1, 1),
-        0x3F => (Alu8Op::clear_carry_flag(), 1, 1),
-
-        0x80 => (Alu8Op::add(Alu8Data::Reg(B)), 1, 1),
-        0x81 => (Alu8Op::add(Alu8Data::Reg(C)), 1, 1),
-        0x82 => (Alu8Op::add(Alu8Data::Reg(D)), 1, 1),
-        0x83 => (Alu8Op::add(Alu8Data::Reg(E)), 1, 1),
-        0x84 => (Alu8Op::add(Alu8Data::Reg(H)), 1, 1),
-        0x85 => (Alu8Op::add(Alu8Data::Reg(L)), 1, 1),
-        0x86 => (Alu8Op::add(Alu8Data::Addr(HL)), 1, 1),
-        0x87 => (Alu8Op::add(Alu8Data::Reg(A)), 1, 2),
-        0x88 => (Alu8Op::add_with_carry(Alu8Data::Reg(B)), 1, 1),
-        0x89 => (Alu8Op::add_with_carry(Alu8Data::Reg(C)), 1, 1),
-        0x8A => (Alu8Op::add_with_carry(Alu8Data::Reg(D)), 1, 1),
-        0x8B => (Alu8Op::add_with_carry(Alu8Data::Reg(E)), 1, 1),
-        0x8C => (Alu8Op::add_with_carry(Alu8Data::Reg(H)), 1, 1),
-        0x8D => (Alu8Op::add_with_carry(Alu8Data::Reg(L)), 1, 1),
-        0x8E => (Alu8Op::add_with_carry(Alu8Data::Addr(HL)), 1, 2),
-        0x8F => (Alu8Op::add_with_carry(Alu8Data::Reg(A)), 1, 1),
-
-        0x90 => (Alu8Op::sub(Alu8Data::Reg(B)), 1, 1),
-        0x91 => (Alu8Op::sub(Alu8Data::Reg(C)), 1, 1),
-        0x92 => (Alu8Op::sub(Alu8Data::Reg(D)), 1, 1),
-        0x93 => (Alu8Op::sub(Alu8Data::Reg(E)), 1, 1),
-        0x94 => (Alu8Op::sub(Alu8Data::Reg(H)), 1, 1),
-        0x95 => (Alu8Op::sub(Alu8Data::Reg(L)), 1, 1),
-        0x96 => (Alu8Op::sub(Alu8Data::Addr(HL)), 1, 2),
-        0x97 => (Alu8Op::sub(Alu8Data::Reg(A)), 1, 1),
-        0x98 => (Alu8Op::sub_with_carry(Alu8Data::Reg(B)), 1, 1),
-        0x99 => (Alu8Op::sub_with_carry(Alu8Data::Reg(C)), 1, 1),
-        0x9A => (Alu8Op::sub_with_carry(Alu8Data::Reg(D)), 1, 1),
-        0x9B => (Alu8Op::sub_with_carry(Alu8Data::Reg(E)), 1, 1),
-        0x9C => (Alu8Op::sub_with_carry(Alu8Data::Reg(H)), 1, 1),
-        0x9D => (Alu8Op::sub_with_carry(Alu8Data::Reg(L)), 1, 1),
-        0x9E => (Alu8Op::sub_with_carry(Alu8Data::Addr(HL)), 1, 2),
-        0x9F => (Alu8Op::sub_with_carry(Alu8Data::Reg(A)), 1, 1),
-
-        0xA0 => (Alu8Op::and(Alu8Data::Reg(B)), 1, 1),
-        0xA1 => (Alu8Op::and(Alu8Data::Reg(C)), 1, 1),
-        0xA2 => (Alu8Op::and(Alu8Data::Reg(D)), 1, 1),
-        0xA3 => (Alu8Op::and(Alu8Data::Reg(E)), 1, 1),
-        0xA4 => (Alu8Op::and(Alu8Data::Reg(H)), 1, 1),
-        0xA5 => (Alu8Op::and(Alu8Data::Reg(L)), 1, 1),
-        0xA6 => (Alu8Op::and(Alu8Data::Addr(HL)), 1, 2),
-        0xA7 => (Alu8Op::and(Alu8Data::Reg(A)), 1, 1),
-
-        0xA8 => (Alu8Op::xor(Alu8Data::Reg(B)), 1, 1),
-        0xA9 => (Alu8Op::xor(Alu8Data::Reg(C)), 1, 1),
-        0xAA => (Alu8Op::xor(Alu8Data::Reg(D)), 1, 1),
-        0xAB => (Alu8Op::xor(Alu8Data::Reg(E)), 1, 1),
-        0xAC => (Alu8Op::xor(Alu8Data::Reg(H)), 1, 1),
-        0xAD => (Alu8Op::xor(Alu8Data::Reg(L)), 1, 1),
-        0xAE => (Alu8Op::xor(Alu8Data::Addr(HL)), 1, 2),
-        0xAF => (Alu8Op::xor(Alu8Data::Reg(A)), 1, 1),
-
-        0xB0 => (Alu8Op::or(Alu8Data::Reg(B)), 1, 1),
-        0xB1 => (Alu8Op::or(Alu8Data::Reg(C)), 1, 1),
-        0xB2 => (Alu8Op::or(Alu8Data::Reg(D)), 1, 1),
-        0xB3 => (Alu8Op::or(Alu8Data::Reg(E)), 1, 1),
-        0xB4 => (Alu8Op::or(Alu8Data::Reg(H)), 1, 1),
-        0xB5 => (Alu8Op::or(Alu8Data::Reg(L)), 1, 1),
-        0xB6 => (Alu8Op::or(Alu8Data::Addr(HL)), 1, 2),
-        0xB7 => (Alu8Op::or(Alu8Data::Reg(A)), 1, 1),
-
-        0xB8 => (Alu8Op::compare(Alu8Data::Reg(B)), 1, 1),
-        0xB9 => (Alu8Op::compare(Alu8Data::Reg(C)), 1, 1),
-        0xBA => (Alu8Op::compare(Alu8Data::Reg(D)), 1, 1),
-        0xBB => (Alu8Op::compare(Alu8Data::Reg(E)), 1, 1),
-        0xBC => (Alu8Op::compare(Alu8Data::Reg(H)), 1, 1),
-        0xBD => (Alu8Op::compare(Alu8Data::Reg(L)), 1, 1),
-        0xBE => (Alu8Op::compare(Alu8Data::Addr(HL)), 1, 2),
-        0xBF => (Alu8Op::compare(Alu8Data::Reg(A)), 1, 1),
-
-        0xC6 => (Alu8Op::add(Alu8Data::Imm(imm8)), 2, 1),
-        0xD6 => (Alu8Op::sub(Alu8Data::Imm(imm8)), 2, 1),
-        0xE6 => (Alu8Op::and(Alu8Data::Imm(imm8)), 2, 1),
-        0xF6 => (Alu8Op::or(Alu8Data::Imm(imm8)), 2, 1),
-        0xCE => (Alu8Op::add_with_carry(Alu8Data::Imm(imm8)), 2, 1),
-        0xDE => (Alu8Op::sub_with_carry(Alu8Data::Imm(imm8)), 2, 1),
-        0xEE => (Alu8Op::xor(Alu8Data::Imm(imm8)), 2, 1),
-        0xFE => (Alu8Op::compare(Alu8Data::Imm(imm8)), 2, 1),
-
-        _ => (Alu8Op::unknown(), 0, 0),
-    };
-    match inst {
-        (
-            Alu8Op {
-                op: Alu8::Unknown, ..
-            },
-            _,
-            _,
-        ) => None,
-        (op, size, time) => Some((Op::Alu8(op), size, time)),
+struct Entry {
+    build: Box<dyn Fn(Operands) -> Op + Send + Sync>,
+    size: usize,
+    cycles: usize,
+}
+
+// What each opcode decodes to, plus a second table for the ones after the 0xCB prefix.
+struct Table {
+    main: Vec<Entry>,
+    extended: Vec<Entry>,
+}
+
+// Registers in the order opcodes encode them in three bits, with None for (HL).
+const REG8: [Option<Reg8>; 8] = [
+    Some(B),
+    Some(C),
+    Some(D),
+    Some(E),
+    Some(H),
+    Some(L),
+    None,
+    Some(A),
+];
+
+// Conditions in the order opcodes encode them in two bits.
+const CONDITIONS: [Flag; 4] = [NotZero, Zero, NotCarry, Carry];
+
+// ALU ops on A for 0x80-0xBF and 0xC6-0xFE, in opcode order.
+const ALU: [fn(Alu8Data) -> Alu8Op; 8] = [
+    Alu8Op::add,
+    Alu8Op::add_with_carry,
+    Alu8Op::sub,
+    Alu8Op::sub_with_carry,
+    Alu8Op::and,
+    Alu8Op::xor,
+    Alu8Op::or,
+    Alu8Op::compare,
+];
+
+// Rotates and shifts for 0xCB 0x00-0x3F, in opcode order.
+const SHIFTS: [fn(Alu8Data) -> Alu8Op; 8] = [
+    Alu8Op::rotate_left_carry,
+    Alu8Op::rotate_right_carry,
+    Alu8Op::rotate_left,
+    Alu8Op::rotate_right,
+    Alu8Op::shift_left_arithmetic,
+    Alu8Op::shift_right_arithmetic,
+    Alu8Op::swap,
+    Alu8Op::shift_right_logical,
+];
+
+fn operand(index: u8) -> Alu8Data {
+    match REG8[index as usize & 7] {
+        Some(reg) => Alu8Data::Reg(reg),
+        None => Alu8Data::Addr(HL),
     }
 }
 
-///! Decode ALU operations.
-fn decode_alu16(rom: &Peripherals, pc: u16) -> Option<(Op, usize, usize)> {
-    let inst = match rom.read(pc) {
-        0x03 => (Alu16Op::inc(BC), 1, 2),
-        0x13 => (Alu16Op::inc(DE), 1, 2),
-        0x23 => (Alu16Op::inc(HL), 1, 2),
-        0x33 => (Alu16Op::inc(SP), 1, 2),
+fn set<F>(table: &mut [Entry], code: u8, size: usize, cycles: usize, build: F)
+where
+    F: Fn(Operands) -> Op + Send + Sync + 'static,
+{
+    table[code as usize] = Entry {
+        build: Box::new(build),
+        size,
+        cycles,
+    };
+}
 
-        0x09 => (Alu16Op::add(HL, BC), 1, 2),
-        0x19 => (Alu16Op::add(HL, DE), 1, 2),
-        0x29 => (Alu16Op::add(HL, HL), 1, 2),
-        0x39 => (Alu16Op::add(HL, SP), 1, 2),
+impl Table {
+    fn new() -> Self {
+        Self {
+            main: Self::main(),
+            extended: Self::extended(),
+        }
+    }
 
-        0x0B => (Alu16Op::dec(BC), 1, 2),
-        0x1B => (Alu16Op::dec(DE), 1, 2),
-        0x2B => (Alu16Op::dec(HL), 1, 2),
-        0x3B => (Alu16Op::dec(SP), 1, 2),
+    fn unknown() -> Vec<Entry> {
+        (0..=0xFF)
+            .map(|code| Entry {
+                build: Box::new(move |_| Op::Unknown(code)),
+                size: 1,
+                cycles: 0,
+            })
+            .collect()
+    }
 
-        0xE8 => (Alu16Op::add_imm(SP, rom.read(pc + 1) as i8), 2, 4),
+    fn main() -> Vec<Entry> {
+        let mut t = Self::unknown();
+        set(&mut t, 0x00, 1, 1, |_| Op::Nop);
+        set(&mut t, 0x10, 2, 1, |_| Op::Stop);
+        set(&mut t, 0x76, 1, 1, |_| Op::Halt);
+        set(&mut t, 0xF3, 1, 1, |_| Op::DisableInterrupts);
+        set(&mut t, 0xFB, 1, 1, |_| Op::EnableInterrupts);
+
+        // 16-bit loads and arithmetic, one row per register pair.
+        for (row, &reg) in [BC, DE, HL, SP].iter().enumerate() {
+            let base = (row as u8) << 4;
+            set(&mut t, base | 0x01, 3, 3, move |o| {
+                Op::SetWide(reg, o.imm16)
+            });
+            set(&mut t, base | 0x03, 1, 2, move |_| {
+                Op::Alu16(Alu16Op::inc(reg))
+            });
+            set(&mut t, base | 0x09, 1, 2, move |_| {
+                Op::Alu16(Alu16Op::add(HL, reg))
+            });
+            set(&mut t, base | 0x0B, 1, 2, move |_| {
+                Op::Alu16(Alu16Op::dec(reg))
+            });
+        }
+        for (row, &reg) in [BC, DE, HL, AF].iter().enumerate() {
+            let base = 0xC0 | (row as u8) << 4;
+            set(&mut t, base | 0x01, 1, 3, move |_| Op::Pop(reg));
+            set(&mut t, base | 0x05, 1, 4, move |_| Op::Push(reg));
+        }
+        set(&mut t, 0x08, 3, 5, |o| {
+            Op::WideStore(Address::Immediate16(o.imm16), SP)
+        });
+        set(&mut t, 0xE8, 2, 4, |o| {
+            Op::Alu16(Alu16Op::add_imm(SP, o.imm8 as i8))
+        });
+        set(&mut t, 0xF8, 2, 3, |o| {
+            Op::Alu16(Alu16Op::move_and_add(HL, SP, o.imm8 as i8))
+        });
+        set(&mut t, 0xF9, 1, 2, |_| Op::Alu16(Alu16Op::move_reg(SP, HL)));
+
+        // Loads and stores of A through register pairs.
+        set(&mut t, 0x02, 1, 2, |_| {
+            Op::Store(Address::Register16(BC), A)
+        });
+        set(&mut t, 0x12, 1, 2, |_| {
+            Op::Store(Address::Register16(DE), A)
+        });
+        set(&mut t, 0x22, 1, 2, |_| {
+            Op::StoreAndIncrement(Address::Register16(HL), A)
+        });
+        set(&mut t, 0x32, 1, 2, |_| {
+            Op::StoreAndDecrement(Address::Register16(HL), A)
+        });
+        set(&mut t, 0x0A, 1, 2, |_| Op::Load(A, Address::Register16(BC)));
+        set(&mut t, 0x1A, 1, 2, |_| Op::Load(A, Address::Register16(DE)));
+        set(&mut t, 0x2A, 1, 2, |_| {
+            Op::LoadAndIncrement(A, Address::Register16(HL))
+        });
+        set(&mut t, 0x3A, 1, 2, |_| {
+            Op::LoadAndDecrement(A, Address::Register16(HL))
+        });
+        set(&mut t, 0xEA, 3, 4, |o| {
+            Op::Store(Address::Immediate16(o.imm16), A)
+        });
+        set(&mut t, 0xFA, 3, 4, |o| {
+            Op::Load(A, Address::Immediate16(o.imm16))
+        });
+        set(&mut t, 0xE0, 2, 3, |o| Op::SetIO(o.imm8));
+        set(&mut t, 0xF0, 2, 3, |o| Op::ReadIO(o.imm8));
+        set(&mut t, 0xE2, 1, 2, |_| Op::SetIOC);
+        set(&mut t, 0xF2, 1, 2, |_| Op::ReadIOC);
+
+        // 8-bit increments, decrements, and immediate loads, one column per register.
+        for index in 0..8 {
+            let base = index << 3;
+            let data = operand(index);
+            let (cycles, imm_cycles) = if REG8[index as usize].is_some() {
+                (1, 2)
+            } else {
+                (3, 3)
+            };
+            set(&mut t, base | 0x04, 1, cycles, move |_| {
+                Op::Alu8(Alu8Op::increment(data))
+            });
+            set(&mut t, base | 0x05, 1, cycles, move |_| {
+                Op::Alu8(Alu8Op::decrement(data))
+            });
+            set(&mut t, base | 0x06, 2, imm_cycles, move |o| {
+                match REG8[index as usize] {
+                    Some(reg) => Op::Set(reg, o.imm8),
+                    None => Op::SetAddr(HL, o.imm8),
+                }
+            });
+        }
+        set(&mut t, 0x07, 1, 1, |_| {
+            Op::Alu8(Alu8Op::rotate_left_carry_a())
+        });
+        set(&mut t, 0x0F, 1, 1, |_| {
+            Op::Alu8(Alu8Op::rotate_right_carry_a())
+        });
+        set(&mut t, 0x17, 1, 1, |_| Op::Alu8(Alu8Op::rotate_left_a()));
+        set(&mut t, 0x1F, 1, 1, |_| Op::Alu8(Alu8Op::rotate_right_a()));
+        set(&mut t, 0x27, 1, 1, |_| Op::Alu8(Alu8Op::decimal_adjust()));
+        set(&mut t, 0x2F, 1, 1, |_| Op::Alu8(Alu8Op::complement()));
+        set(&mut t, 0x37, 1, 1, |_| Op::Alu8(Alu8Op::set_carry_flag()));
+        set(&mut t, 0x3F, 1, 1, |_| Op::Alu8(Alu8Op::clear_carry_flag()));
+
+        // Register to register moves, which has HALT in the middle where LD (HL),(HL) would be.
+        for code in 0x40..0x80 {
+            if code == 0x76 {
+                continue;
+            }
+            match (REG8[(code >> 3) as usize & 7], REG8[code as usize & 7]) {
+                (Some(dest), Some(src)) => set(&mut t, code, 1, 1, move |_| Op::Move(dest, src)),
+                (Some(dest), None) => set(&mut t, code, 1, 2, move |_| {
+                    Op::Load(dest, Address::Register16(HL))
+                }),
+                (None, Some(src)) => set(&mut t, code, 1, 2, move |_| {
+                    Op::Store(Address::Register16(HL), src)
+                }),
+                (None, None) => unreachable!(),
+            }
+        }
 
-        0xF8 => (Alu16Op::move_and_add(HL, SP, rom.read(pc + 1) as i8), 2, 3),
+        // ALU ops on A, with a register in 0x80-0xBF, or an immediate in 0xC6-0xFE.
+        for (row, &alu) in ALU.iter().enumerate() {
+            let row = row as u8;
+            for index in 0..8 {
+                let data = operand(index);
+                let cycles = if REG8[index as usize].is_some() { 1 } else { 2 };
+                set(&mut t, 0x80 | row << 3 | index, 1, cycles, move |_| {
+                    Op::Alu8(alu(data))
+                });
+            }
+            set(&mut t, 0xC6 | row << 3, 2, 2, move |o| {
+                Op::Alu8(alu(Alu8Data::Imm(o.imm8)))
+            });
+        }
 
-        0xF9 => (Alu16Op::move_reg(SP, HL), 1, 2),
+        // Jumps, calls, and returns. The conditional ones take longer when they're taken.
+        set(&mut t, 0x18, 2, 3, |o| Op::JumpRelative(o.relative()));
+        set(&mut t, 0xC3, 3, 4, |o| {
+            Op::Jump(Address::Immediate16(o.imm16))
+        });
+        set(&mut t, 0xE9, 1, 1, |_| Op::Jump(Address::Register16(HL)));
+        set(&mut t, 0xCD, 3, 6, |o| Op::Call(o.imm16));
+        set(&mut t, 0xC9, 1, 4, |_| Op::Return);
+        set(&mut t, 0xD9, 1, 4, |_| Op::ReturnAndEnableInterrupts);
+        for (index, &flag) in CONDITIONS.iter().enumerate() {
+            let row = (index as u8) << 3;
+            set(&mut t, 0x20 | row, 2, 2, move |o| {
+                Op::ConditionalJumpRelative(flag, o.relative())
+            });
+            set(&mut t, 0xC0 | row, 1, 2, move |_| {
+                Op::ConditionalReturn(flag)
+            });
+            set(&mut t, 0xC2 | row, 3, 3, move |o| {
+                Op::ConditionalJump(flag, o.imm16)
+            });
+            set(&mut t, 0xC4 | row, 3, 3, move |o| {
+                Op::ConditionalCall(flag, o.imm16)
+            });
+        }
+        for index in 0..8 {
+            let target = u16::from(index) << 3;
+            set(&mut t, 0xC7 | index << 3, 1, 4, move |_| Op::Reset(target));
+        }
+        t
+    }
 
-        _ => (Alu16Op::unknown(), 0, 0),
-    };
-    match inst {
-        (
-            Alu16Op {
-                op: Alu16::Unknown, ..
-            },
-            _,
-            _,
-        ) => None,
-        (op, size, time) => Some((Op::Alu16(op), size, time)),
+    fn extended() -> Vec<Entry> {
+        let mut t = Self::unknown();
+        for code in 0..=0xFF {
+            let data = operand(code);
+            let bit = (code >> 3) & 7;
+            let reg = REG8[code as usize & 7].is_some();
+            let cycles = match (code >> 6, reg) {
+                (_, true) => 2,
+                // BIT only reads (HL), so it's a cycle shorter than the others.
+                (1, false) => 3,
+                (_, false) => 4,
+            };
+            match code >> 6 {
+                0 => {
+                    let shift = SHIFTS[bit as usize];
+                    set(&mut t, code, 2, cycles, move |_| Op::Alu8(shift(data)))
+                }
+                1 => set(&mut t, code, 2, cycles, move |_| {
+                    Op::Alu8(Alu8Op::test_bit(data, bit))
+                }),
+                2 => set(&mut t, code, 2, cycles, move |_| {
+                    Op::Alu8(Alu8Op::reset_bit(data, bit))
+                }),
+                _ => set(&mut t, code, 2, cycles, move |_| {
+                    Op::Alu8(Alu8Op::set_bit(data, bit))
+                }),
+            }
+        }
+        t
     }
 }
 
-///! Decode move, load, and store operations.
-fn decode_load(rom: &Peripherals, pc: u16) -> Option<(Op, usize, usize)> {
-    let imm16 = util::bytes_to_u16(&[rom.read(pc + 2), rom.read(pc + 1)]);
-    let imm8 = rom.read(pc + 1);
-    let inst = match rom.read(pc) {
-        0x01 => (Op::SetWide(BC, imm16), 3, 3),
-        0x11 => (Op::SetWide(DE, imm16), 3, 3),
-        0x21 => (Op::SetWide(HL, imm16), 3, 3),
-        0x31 => (Op::SetWide(SP, imm16), 3, 3),
-
-        0x02 => (Op::Store(Address::Register16(BC), A), 1, 2),
-        0x12 => (Op::Store(Address::Register16(DE), A), 1, 2),
-        0x22 => (Op::StoreAndIncrement(Address::Register16(HL), A), 1, 2),
-        0x32 => (Op::StoreAndDecrement(Address::Register16(HL), A), 1, 2),
-
-        0x06 => (Op::Set(B, imm8), 2, 2),
-        0x16 => (Op::Set(D, imm8), 2, 2),
-        0x26 => (Op::Set(H, imm8), 2, 2),
-        0x36 => (Op::SetAddr(HL, imm8), 2, 2),
-
-        0x08 => (Op::WideStore(Address::Immediate16(imm16), SP), 3, 5),
-
-        0x0E => (Op::Set(C, imm8), 2, 2),
-        0x1E => (Op::Set(E, imm8), 2, 2),
-        0x2E => (Op::Set(L, imm8), 2, 2),
-        0x3E => (Op::Set(A, imm8), 2, 2),
-
-        0x0A => (Op::Load(A, Address::Register16(BC)), 1, 2),
-        0x1A => (Op::Load(A, Address::Register16(DE)), 1, 2),
-        0xFA => (Op::Load(A, Address::Immediate16(imm16)), 3, 2),
-        0x2A => (Op::LoadAndIncrement(A, Address::Register16(HL)), 1, 2),
-        0x3A => (Op::LoadAndDecrement(A, Address::Register16(HL)), 1, 2),
-
-        0x40 => (Op::Move(B, B), 1, 1),
-        0x41 => (Op::Move(B, C), 1, 1),
-        0x42 => (Op::Move(B, D), 1, 1),
-        0x43 => (Op::Move(B, E), 1, 1),
-        0x44 => (Op::Move(B, H), 1, 1),
-        0x45 => (Op::Move(B, L), 1, 1),
-        0x47 => (Op::Move(B, A), 1, 1),
-
-        0x48 => (Op::Move(C, B), 1, 1),
-        0x49 => (Op::Move(C, C), 1, 1),
-        0x4A => (Op::Move(C, D), 1, 1),
-        0x4B => (Op::Move(C, E), 1, 1),
-        0x4C => (Op::Move(C, H), 1, 1),
-        0x4D => (Op::Move(C, L), 1, 1),
-        0x4F => (Op::Move(C, A), 1, 1),
-
-        0x50 => (Op::Move(D, B), 1, 1),
-        0x51 => (Op::Move(D, C), 1, 1),
-        0x52 => (Op::Move(D, D), 1, 1),
-        0x53 => (Op::Move(D, E), 1, 1),
-        0x54 => (Op::Move(D, H), 1, 1),
-        0x55 => (Op::Move(D, L), 1, 1),
-        0x57 => (Op::Move(D, A), 1, 1),
-
-        0x58 => (Op::Move(E, B), 1, 1),
-        0x59 => (Op::Move(E, C), 1, 1),
-        0x5A => (Op::Move(E, D), 1, 1),
-        0x5B => (Op::Move(E, E), 1, 1),
-        0x5C => (Op::Move(E, H), 1, 1),
-        0x5D => (Op::Move(E, L), 1, 1),
-        0x5F => (Op::Move(E, A), 1, 1),
-
-        0x60 => (Op::Move(H, B), 1, 1),
-        0x61 => (Op::Move(H, C), 1, 1),
-        0x62 => (Op::Move(H, D), 1, 1),
-        0x63 => (Op::Move(H, E), 1, 1),
-        0x64 => (Op::Move(H, H), 1, 1),
-        0x65 => (Op::Move(H, L), 1, 1),
-        0x67 => (Op::Move(H, A), 1, 1),
-
-        0x68 => (Op::Move(L, B), 1, 1),
-        0x69 => (Op::Move(L, C), 1, 1),
-        0x6A => (Op::Move(L, D), 1, 1),
-        0x6B => (Op::Move(L, E), 1, 1),
-        0x6C => (Op::Move(L, H), 1, 1),
-        0x6D => (Op::Move(L, L), 1, 1),
-        0x6F => (Op::Move(L, A), 1, 1),
-
-        0x78 => (Op::Move(A, B), 1, 1),
-        0x79 => (Op::Move(A, C), 1, 1),
-        0x7A => (Op::Move(A, D), 1, 1),
-        0x7B => (Op::Move(A, E), 1, 1),
-        0x7C => (Op::Move(A, H), 1, 1),
-        0x7D => (Op::Move(A, L), 1, 1),
-        0x7F => (Op::Move(A, A), 1, 1),
-
-        0x46 => (Op::Load(B, Address::Register16(HL)), 1, 2),
-        0x4E => (Op::Load(C, Address::Register16(HL)), 1, 2),
-        0x56 => (Op::Load(D, Address::Register16(HL)), 1, 2),
-        0x5E => (Op::Load(E, Address::Register16(HL)), 1, 2),
-        0x66 => (Op::Load(H, Address::Register16(HL)), 1, 2),
-        0x6E => (Op::Load(L, Address::Register16(HL)), 1, 2),
-        0x7E => (Op::Load(A, Address::Register16(HL)), 1, 2),
-
-        0x70 => (Op::Store(Address::Register16(HL), B), 1, 2),
-        0x71 => (Op::Store(Address::Register16(HL), C), 1, 2),
-        0x72 => (Op::Store(Address::Register16(HL), D), 1, 2),
-        0x73 => (Op::Store(Address::Register16(HL), E), 1, 2),
-        0x74 => (Op::Store(Address::Register16(HL), H), 1, 2),
-        0x75 => (Op::Store(Address::Register16(HL), L), 1, 2),
-        0x77 => (Op::Store(Address::Register16(HL), A), 1, 2),
-        0xEA => (Op::Store(Address::Immediate16(imm16), A), 3, 2),
-
-        0xE0 => (Op::SetIO(imm8), 2, 3),
-        0xE2 => (Op::SetIOC, 1, 3),
-        0xF0 => (Op::ReadIO(imm8), 2, 3),
-        0xF2 => (Op::ReadIOC, 1, 3),
-
-        0xC1 => (Op::Pop(BC), 1, 3),
-        0xD1 => (Op::Pop(DE), 1, 3),
-        0xE1 => (Op::Pop(HL), 1, 3),
-        0xF1 => (Op::Pop(AF), 1, 3),
-        0xC5 => (Op::Push(BC), 1, 4),
-        0xD5 => (Op::Push(DE), 1, 4),
-        0xE5 => (Op::Push(HL), 1, 4),
-        0xF5 => (Op::Push(AF), 1, 4),
-
-        code => (Op::Unknown(code), 0, 0),
-    };
-    match inst {
-        (Op::Unknown(_), _, _) => None,
-        (op, size, time) => Some((op, size, time)),
-    }
+fn table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(Table::new)
 }
 
-///! Decode ALU operations.
-fn decode_jump(rom: &Peripherals, pc: u16) -> Option<(Op, usize, usize)> {
-    let dest16 = util::bytes_to_u16(&[rom.read(pc + 2), rom.read(pc + 1)]);
-    let relative_dest = (((pc + 2) as isize) + ((rom.read(pc + 1) as i8) as isize)) as u16;
-    let inst = match rom.read(pc) {
-        // Conditional jumps take an extra cycle if they're taken.
-        // TODO(slongfield) Annotate this.
-        0x20 => (Op::ConditionalJumpRelative(NotZero, relative_dest), 2, 2),
-        0x30 => (Op::ConditionalJumpRelative(NotCarry, relative_dest), 2, 2),
-        0x28 => (Op::ConditionalJumpRelative(Zero, relative_dest), 2, 2),
-        0x38 => (Op::ConditionalJumpRelative(Carry, relative_dest), 2, 2),
-        0x18 => (Op::JumpRelative(relative_dest), 2, 3),
-        0xC2 => (Op::ConditionalJump(NotZero, dest16), 3, 3),
-        0xD2 => (Op::ConditionalJump(NotCarry, dest16), 3, 3),
-        0xCA => (Op::ConditionalJump(Zero, dest16), 3, 3),
-        0xDA => (Op::ConditionalJump(Carry, dest16), 3, 3),
-        0xC3 => (Op::Jump(Address::Immediate16(dest16)), 3, 4),
-        0xE9 => (Op::Jump(Address::Register16(HL)), 1, 4),
-        0xC7 => (Op::Reset(0x0), 1, 4),
-        0xD7 => (Op::Reset(0x10), 1, 4),
-        0xE7 => (Op::Reset(0x20), 1, 4),
-        0xF7 => (Op::Reset(0x30), 1, 4),
-        0xCF => (Op::Reset(0x8), 1, 4),
-        0xDF => (Op::Reset(0x18), 1, 4),
-        0xEF => (Op::Reset(0x28), 1, 4),
-        0xFF => (Op::Reset(0x38), 1, 4),
-        // Conditional returns take 3 extra cycles if they're taken.
-        0xC0 => (Op::ConditionalReturn(NotZero), 1, 2),
-        0xD0 => (Op::ConditionalReturn(NotCarry), 1, 2),
-        0xC8 => (Op::ConditionalReturn(Zero), 1, 2),
-        0xD8 => (Op::ConditionalReturn(Carry), 1, 2),
-        0xC9 => (Op::Return, 1, 4),
-        0xD9 => (Op::ReturnAndEnableInterrupts, 1, 4),
-        // Conditional calls take an extra 3 cycles.
-        0xC4 => (Op::ConditionalCall(NotZero, dest16), 3, 3),
-        0xD4 => (Op::ConditionalCall(NotCarry, dest16), 3, 3),
-        0xCC => (Op::ConditionalCall(Zero, dest16), 3, 3),
-        0xDC => (Op::ConditionalCall(Carry, dest16), 3, 3),
-        0xCD => (Op::Call(dest16), 3, 3),
-
-        code => (Op::Unknown(code), 0, 0),
+///! Decode takes the ROM and current PC, and returns the Op a that PC, as well as the number of
+///! bytes in that op, and the number of cycles it runs for.
+pub fn decode<B: Bus + ?Sized>(rom: &B, pc: u16) -> (Op, usize, usize) {
+    let code = rom.fetch(pc);
+    let entry = if code == 0xCB {
+        &table().extended[rom.fetch(pc.wrapping_add(1)) as usize]
+    } else {
+        &table().main[code as usize]
     };
-    match inst {
-        (Op::Unknown(_), _, _) => None,
-        (op, size, time) => Some((op, size, time)),
+    let mut operands = Operands {
+        pc,
+        imm8: 0,
+        imm16: 0,
+    };
+    if entry.size == 2 && code != 0xCB {
+        operands.imm8 = rom.fetch(pc.wrapping_add(1));
+    } else if entry.size == 3 {
+        operands.imm16 =
+            util::bytes_to_u16(&[rom.fetch(pc.wrapping_add(2)), rom.fetch(pc.wrapping_add(1))]);
     }
+    ((entry.build)(operands), entry.size, entry.cycles)
 }
 
-///! Decode prefix 0xCB extended ops
-fn decode_extended(opcode: u8) -> (Op, usize, usize) {
-    let (alu_op, time) = match opcode {
-        0x00 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(B)), 2),
-        0x01 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(C)), 2),
-        0x02 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(D)), 2),
-        0x03 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(E)), 2),
-        0x04 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(H)), 2),
-        0x05 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(L)), 2),
-        0x06 => (Alu8Op::rotate_left_carry(Alu8Data::Addr(HL)), 4),
-        0x07 => (Alu8Op::rotate_left_carry(Alu8Data::Reg(A)), 2),
-
-        0x08 => (Alu8Op::rotate_right_carry(Alu8Data::Reg(B)), 2),
-        0x09 => (Alu8Op::rotate_right_carry(Alu8Data::Reg(C)), 2),
-        0x0A => (Alu8Op::rotate_right_carry(Alu8Data::Reg(D)), 2),
-        0x0B => (Alu8Op::rotate_right_carry(Alu8Data::Reg(E)), 2),
-        0x0C => (Alu8Op::rotate_right_carry(Alu8Data::Reg(H)), 2),
-        0x0D => (Alu8Op::rotate_right_carry(Alu8Data::Reg(L)), 2),
-        0x0E => (Alu8Op::rotate_right_carry(Alu8Data::Addr(HL)), 4),
-        0x0F => (Alu8Op::rotate_right_carry(Alu8Data::Reg(A)), 2),
-
-        0x10 => (Alu8Op::rotate_left(Alu8Data::Reg(B)), 2),
-        0x11 => (Alu8Op::rotate_left(Alu8Data::Reg(C)), 2),
-        0x12 => (Alu8Op::rotate_left(Alu8Data::Reg(D)), 2),
-        0x13 => (Alu8Op::rotate_left(Alu8Data::Reg(E)), 2),
-        0x14 => (Alu8Op::rotate_left(Alu8Data::Reg(H)), 2),
-        0x15 => (Alu8Op::rotate_left(Alu8Data::Reg(L)), 2),
-        0x16 => (Alu8Op::rotate_left(Alu8Data::Addr(HL)), 4),
-        0x17 => (Alu8Op::rotate_left(Alu8Data::Reg(A)), 2),
-
-        0x18 => (Alu8Op::rotate_right(Alu8Data::Reg(B)), 2),
-        0x19 => (Alu8Op::rotate_right(Alu8Data::Reg(C)), 2),
-        0x1A => (Alu8Op::rotate_right(Alu8Data::Reg(D)), 2),
-        0x1B => (Alu8Op::rotate_right(Alu8Data::Reg(E)), 2),
-        0x1C => (Alu8Op::rotate_right(Alu8Data::Reg(H)), 2),
-        0x1D => (Alu8Op::rotate_right(Alu8Data::Reg(L)), 2),
-        0x1E => (Alu8Op::rotate_right(Alu8Data::Addr(HL)), 4),
-        0x1F => (Alu8Op::rotate_right(Alu8Data::Reg(A)), 2),
-
-        0x20 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(B)), 2),
-        0x21 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(C)), 2),
-        0x22 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(D)), 2),
-        0x23 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(E)), 2),
-        0x24 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(H)), 2),
-        0x25 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(L)), 2),
-        0x26 => (Alu8Op::shift_left_arithmetic(Alu8Data::Addr(HL)), 4),
-        0x27 => (Alu8Op::shift_left_arithmetic(Alu8Data::Reg(A)), 2),
-
-        0x28 => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(B)), 2),
-        0x29 => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(C)), 2),
-        0x2A => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(D)), 2),
-        0x2B => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(E)), 2),
-        0x2C => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(H)), 2),
-        0x2D => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(L)), 2),
-        0x2E => (Alu8Op::shift_right_arithmetic(Alu8Data::Addr(HL)), 4),
-        0x2F => (Alu8Op::shift_right_arithmetic(Alu8Data::Reg(A)), 2),
-
-        0x30 => (Alu8Op::swap(Alu8Data::Reg(B)), 2),
-        0x31 => (Alu8Op::swap(Alu8Data::Reg(C)), 2),
-        0x32 => (Alu8Op::swap(Alu8Data::Reg(D)), 2),
-        0x33 => (Alu8Op::swap(Alu8Data::Reg(E)), 2),
-        0x34 => (Alu8Op::swap(Alu8Data::Reg(H)), 2),
-        0x35 => (Alu8Op::swap(Alu8Data::Reg(L)), 2),
-        0x36 => (Alu8Op::swap(Alu8Data::Addr(HL)), 4),
-        0x37 => (Alu8Op::swap(Alu8Data::Reg(A)), 2),
-
-        0x38 => (Alu8Op::shift_right_logical(Alu8Data::Reg(B)), 2),
-        0x39 => (Alu8Op::shift_right_logical(Alu8Data::Reg(C)), 2),
-        0x3A => (Alu8Op::shift_right_logical(Alu8Data::Reg(D)), 2),
-        0x3B => (Alu8Op::shift_right_logical(Alu8Data::Reg(E)), 2),
-        0x3C => (Alu8Op::shift_right_logical(Alu8Data::Reg(H)), 2),
-        0x3D => (Alu8Op::shift_right_logical(Alu8Data::Reg(L)), 2),
-        0x3E => (Alu8Op::shift_right_logical(Alu8Data::Addr(HL)), 4),
-        0x3F => (Alu8Op::shift_right_logical(Alu8Data::Reg(A)), 2),
-
-        0x40 => (Alu8Op::test_bit(Alu8Data::Reg(B), 0), 2),
-        0x41 => (Alu8Op::test_bit(Alu8Data::Reg(C), 0), 2),
-        0x42 => (Alu8Op::test_bit(Alu8Data::Reg(D), 0), 2),
-        0x43 => (Alu8Op::test_bit(Alu8Data::Reg(E), 0), 2),
-        0x44 => (Alu8Op::test_bit(Alu8Data::Reg(H), 0), 2),
-        0x45 => (Alu8Op::test_bit(Alu8Data::Reg(L), 0), 2),
-        0x46 => (Alu8Op::test_bit(Alu8Data::Addr(HL), 0), 4),
-        0x47 => (Alu8Op::test_bit(Alu8Data::Reg(A), 0), 2),
-
-        0x48 => (Alu8Op::test_bit(Alu8Data::Reg(B), 1), 2),
-        0x49 => (Alu8Op::test_bit(Alu8Data::Reg(C), 1), 2),
-        0x4A => (Alu8Op::test_bit(Alu8Data::Reg(D), 1), 2),
-        0x4B => (Alu8Op::test_bit(Alu8Data::Reg(E), 1), 2),
-        0x4C => (Alu8Op::test_bit(Alu8Data::Reg(H), 1), 2),
-        0x4D => (Alu8Op::test_bit(Alu8Data::Reg(L), 1), 2),
-        0x4E => (Alu8Op::test_bit(Alu8Data::Addr(HL), 1), 4),
-        0x4F => (Alu8Op::test_bit(Alu8Data::Reg(A), 1), 2),
-
-        0x50 => (Alu8Op::test_bit(Alu8Data::Reg(B), 2), 2),
-        0x51 => (Alu8Op::test_bit(Alu8Data::Reg(C), 2), 2),
-        0x52 => (Alu8Op::test_bit(Alu8Data::Reg(D), 2), 2),
-        0x53 => (Alu8Op::test_bit(Alu8Data::Reg(E), 2), 2),
-        0x54 => (Alu8Op::test_bit(Alu8Data::Reg(H), 2), 2),
-        0x55 => (Alu8Op::test_bit(Alu8Data::Reg(L), 2), 2),
-        0x56 => (Alu8Op::test_bit(Alu8Data::Addr(HL), 2), 4),
-        0x57 => (Alu8Op::test_bit(Alu8Data::Reg(A), 2), 2),
-
-        0x58 => (Alu8Op::test_bit(Alu8Data::Reg(B), 3), 2),
-        0x59 => (Alu8Op::test_bit(Alu8Data::Reg(C), 3), 2),
-        0x5A => (Alu8Op::test_bit(Alu8Data::Reg(D), 3), 2),
-        0x5B => (Alu8Op::test_bit(Alu8Data::Reg(E), 3), 2),
-        0x5C => (Alu8Op::test_bit(Alu8Data::Reg(H), 3), 2),
-        0x5D => (Alu8Op::test_bit(Alu8Data::Reg(L), 3), 2),
-        0x5E => (Alu8Op::test_bit(Alu8Data::Addr(HL), 3), 4),
-        0x5F => (Alu8Op::test_bit(Alu8Data::Reg(A), 3), 2),
-
-        0x60 => (Alu8Op::test_bit(Alu8Data::Reg(B), 4), 2),
-        0x61 => (Alu8Op::test_bit(Alu8Data::Reg(C), 4), 2),
-        0x62 => (Alu8Op::test_bit(Alu8Data::Reg(D), 4), 2),
-        0x63 => (Alu8Op::test_bit(Alu8Data::Reg(E), 4), 2),
-        0x64 => (Alu8Op::test_bit(Alu8Data::Reg(H), 4), 2),
-        0x65 => (Alu8Op::test_bit(Alu8Data::Reg(L), 4), 2),
-        0x66 => (Alu8Op::test_bit(Alu8Data::Addr(HL), 4), 4),
-        0x67 => (Alu8Op::test_bit(Alu8Data::Reg(A), 4), 2),
-
-        0x68 => (Alu8Op::test_bit(Alu8Data::Reg(B), 5), 2),
-        0x69 => (Alu8Op::test_bit(Alu8Data::Reg(C), 5), 2),
-        0x6A => (Alu8Op::test_bit(Alu8Data::Reg(D), 5), 2),
-        0x6B => (Alu8Op::test_bit(Alu8Data::Reg(E), 5), 2),
-        0x6C => (Alu8Op::test_bit(Alu8Data::Reg(H), 5), 2),
-        0x6D => (Alu8Op::test_bit(Alu8Data::Reg(L), 5), 2),
-        0x6E => (Alu8Op::test_bit(Alu8Data::Addr(HL), 5), 4),
-        0x6F => (Alu8Op::test_bit(Alu8Data::Reg(A), 5), 2),
-
-        0x70 => (Alu8Op::test_bit(Alu8Data::Reg(B), 6), 2),
-        0x71 => (Alu8Op::test_bit(Alu8Data::Reg(C), 6), 2),
-        0x72 => (Alu8Op::test_bit(Alu8Data::Reg(D), 6), 2),
-        0x73 => (Alu8Op::test_bit(Alu8Data::Reg(E), 6), 2),
-        0x74 => (Alu8Op::test_bit(Alu8Data::Reg(H), 6), 2),
-        0x75 => (Alu8Op::test_bit(Alu8Data::Reg(L), 6), 2),
-        0x76 => (Alu8Op::test_bit(Alu8Data::Addr(HL), 6), 4),
-        0x77 => (Alu8Op::test_bit(Alu8Data::Reg(A), 6), 2),
-
-        0x78 => (Alu8Op::test_bit(Alu8Data::Reg(B), 7), 2),
-        0x79 => (Alu8Op::test_bit(Alu8Data::Reg(C), 7), 2),
-        0x7A => (Alu8Op::test_bit(Alu8Data::Reg(D), 7), 2),
-        0x7B => (Alu8Op::test_bit(Alu8Data::Reg(E), 7), 2),
-        0x7C => (Alu8Op::test_bit(Alu8Data::Reg(H), 7), 2),
-        0x7D => (Alu8Op::test_bit(Alu8Data::Reg(L), 7), 2),
-        0x7E => (Alu8Op::test_bit(Alu8Data::Addr(HL), 7), 4),
-        0x7F => (Alu8Op::test_bit(Alu8Data::Reg(A), 7), 2),
-
-        // Reset Bits
-        0x80 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 0), 2),
-        0x81 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 0), 2),
-        0x82 => (Alu8Op::reset_bit(Alu8Data::Reg(D), 0), 2),
-        0x83 => (Alu8Op::reset_bit(Alu8Data::Reg(E), 0), 2),
-        0x84 => (Alu8Op::reset_bit(Alu8Data::Reg(H), 0), 2),
-        0x85 => (Alu8Op::reset_bit(Alu8Data::Reg(L), 0), 2),
-        0x86 => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 0), 4),
-        0x87 => (Alu8Op::reset_bit(Alu8Data::Reg(A), 0), 2),
-
-        0x88 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 1), 2),
-        0x89 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 1), 2),
-        0x8A => (Alu8Op::reset_bit(Alu8Data::Reg(D), 1), 2),
-        0x8B => (Alu8Op::reset_bit(Alu8Data::Reg(E), 1), 2),
-        0x8C => (Alu8Op::reset_bit(Alu8Data::Reg(H), 1), 2),
-        0x8D => (Alu8Op::reset_bit(Alu8Data::Reg(L), 1), 2),
-        0x8E => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 1), 4),
-        0x8F => (Alu8Op::reset_bit(Alu8Data::Reg(A), 1), 2),
-
-        0x90 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 2), 2),
-        0x91 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 2), 2),
-        0x92 => (Alu8Op::reset_bit(Alu8Data::Reg(D), 2), 2),
-        0x93 => (Alu8Op::reset_bit(Alu8Data::Reg(E), 2), 2),
-        0x94 => (Alu8Op::reset_bit(Alu8Data::Reg(H), 2), 2),
-        0x95 => (Alu8Op::reset_bit(Alu8Data::Reg(L), 2), 2),
-        0x96 => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 2), 4),
-        0x97 => (Alu8Op::reset_bit(Alu8Data::Reg(A), 2), 2),
-
-        0x98 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 3), 2),
-        0x99 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 3), 2),
-        0x9A => (Alu8Op::reset_bit(Alu8Data::Reg(D), 3), 2),
-        0x9B => (Alu8Op::reset_bit(Alu8Data::Reg(E), 3), 2),
-        0x9C => (Alu8Op::reset_bit(Alu8Data::Reg(H), 3), 2),
-        0x9D => (Alu8Op::reset_bit(Alu8Data::Reg(L), 3), 2),
-        0x9E => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 3), 4),
-        0x9F => (Alu8Op::reset_bit(Alu8Data::Reg(A), 3), 2),
-
-        0xA0 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 4), 2),
-        0xA1 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 4), 2),
-        0xA2 => (Alu8Op::reset_bit(Alu8Data::Reg(D), 4), 2),
-        0xA3 => (Alu8Op::reset_bit(Alu8Data::Reg(E), 4), 2),
-        0xA4 => (Alu8Op::reset_bit(Alu8Data::Reg(H), 4), 2),
-        0xA5 => (Alu8Op::reset_bit(Alu8Data::Reg(L), 4), 2),
-        0xA6 => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 4), 4),
-        0xA7 => (Alu8Op::reset_bit(Alu8Data::Reg(A), 4), 2),
-
-        0xA8 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 5), 2),
-        0xA9 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 5), 2),
-        0xAA => (Alu8Op::reset_bit(Alu8Data::Reg(D), 5), 2),
-        0xAB => (Alu8Op::reset_bit(Alu8Data::Reg(E), 5), 2),
-        0xAC => (Alu8Op::reset_bit(Alu8Data::Reg(H), 5), 2),
-        0xAD => (Alu8Op::reset_bit(Alu8Data::Reg(L), 5), 2),
-        0xAE => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 5), 4),
-        0xAF => (Alu8Op::reset_bit(Alu8Data::Reg(A), 5), 2),
-
-        0xB0 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 6), 2),
-        0xB1 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 6), 2),
-        0xB2 => (Alu8Op::reset_bit(Alu8Data::Reg(D), 6), 2),
-        0xB3 => (Alu8Op::reset_bit(Alu8Data::Reg(E), 6), 2),
-        0xB4 => (Alu8Op::reset_bit(Alu8Data::Reg(H), 6), 2),
-        0xB5 => (Alu8Op::reset_bit(Alu8Data::Reg(L), 6), 2),
-        0xB6 => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 6), 4),
-        0xB7 => (Alu8Op::reset_bit(Alu8Data::Reg(A), 6), 2),
-
-        0xB8 => (Alu8Op::reset_bit(Alu8Data::Reg(B), 7), 2),
-        0xB9 => (Alu8Op::reset_bit(Alu8Data::Reg(C), 7), 2),
-        0xBA => (Alu8Op::reset_bit(Alu8Data::Reg(D), 7), 2),
-        0xBB => (Alu8Op::reset_bit(Alu8Data::Reg(E), 7), 2),
-        0xBC => (Alu8Op::reset_bit(Alu8Data::Reg(H), 7), 2),
-        0xBD => (Alu8Op::reset_bit(Alu8Data::Reg(L), 7), 2),
-        0xBE => (Alu8Op::reset_bit(Alu8Data::Addr(HL), 7), 4),
-        0xBF => (Alu8Op::reset_bit(Alu8Data::Reg(A), 7), 2),
-
-        // Set bits.
-        0xC0 => (Alu8Op::set_bit(Alu8Data::Reg(B), 0), 2),
-        0xC1 => (Alu8Op::set_bit(Alu8Data::Reg(C), 0), 2),
-        0xC2 => (Alu8Op::set_bit(Alu8Data::Reg(D), 0), 2),
-        0xC3 => (Alu8Op::set_bit(Alu8Data::Reg(E), 0), 2),
-        0xC4 => (Alu8Op::set_bit(Alu8Data::Reg(H), 0), 2),
-        0xC5 => (Alu8Op::set_bit(Alu8Data::Reg(L), 0), 2),
-        0xC6 => (Alu8Op::set_bit(Alu8Data::Addr(HL), 0), 4),
-        0xC7 => (Alu8Op::set_bit(Alu8Data::Reg(A), 0), 2),
-
-        0xC8 => (Alu8Op::set_bit(Alu8Data::Reg(B), 1), 2),
-        0xC9 => (Alu8Op::set_bit(Alu8Data::Reg(C), 1), 2),
-        0xCA => (Alu8Op::set_bit(Alu8Data::Reg(D), 1), 2),
-        0xCB => (Alu8Op::set_bit(Alu8Data::Reg(E), 1), 2),
-        0xCC => (Alu8Op::set_bit(Alu8Data::Reg(H), 1), 2),
-        0xCD => (Alu8Op::set_bit(Alu8Data::Reg(L), 1), 2),
-        0xCE => (Alu8Op::set_bit(Alu8Data::Addr(HL), 1), 4),
-        0xCF => (Alu8Op::set_bit(Alu8Data::Reg(A), 1), 2),
-
-        0xD0 => (Alu8Op::set_bit(Alu8Data::Reg(B), 2), 2),
-        0xD1 => (Alu8Op::set_bit(Alu8Data::Reg(C), 2), 2),
-        0xD2 => (Alu8Op::set_bit(Alu8Data::Reg(D), 2), 2),
-        0xD3 => (Alu8Op::set_bit(Alu8Data::Reg(E), 2), 2),
-        0xD4 => (Alu8Op::set_bit(Alu8Data::Reg(H), 2), 2),
-        0xD5 => (Alu8Op::set_bit(Alu8Data::Reg(L), 2), 2),
-        0xD6 => (Alu8Op::set_bit(Alu8Data::Addr(HL), 2), 4),
-        0xD7 => (Alu8Op::set_bit(Alu8Data::Reg(A), 2), 2),
-
-        0xD8 => (Alu8Op::set_bit(Alu8Data::Reg(B), 3), 2),
-        0xD9 => (Alu8Op::set_bit(Alu8Data::Reg(C), 3), 2),
-        0xDA => (Alu8Op::set_bit(Alu8Data::Reg(D), 3), 2),
-        0xDB => (Alu8Op::set_bit(Alu8Data::Reg(E), 3), 2),
-        0xDC => (Alu8Op::set_bit(Alu8Data::Reg(H), 3), 2),
-        0xDD => (Alu8Op::set_bit(Alu8Data::Reg(L), 3), 2),
-        0xDE => (Alu8Op::set_bit(Alu8Data::Addr(HL), 3), 4),
-        0xDF => (Alu8Op::set_bit(Alu8Data::Reg(A), 3), 2),
-
-        0xE0 => (Alu8Op::set_bit(Alu8Data::Reg(B), 4), 2),
-        0xE1 => (Alu8Op::set_bit(Alu8Data::Reg(C), 4), 2),
-        0xE2 => (Alu8Op::set_bit(Alu8Data::Reg(D), 4), 2),
-        0xE3 => (Alu8Op::set_bit(Alu8Data::Reg(E), 4), 2),
-        0xE4 => (Alu8Op::set_bit(Alu8Data::Reg(H), 4), 2),
-        0xE5 => (Alu8Op::set_bit(Alu8Data::Reg(L), 4), 2),
-        0xE6 => (Alu8Op::set_bit(Alu8Data::Addr(HL), 4), 4),
-        0xE7 => (Alu8Op::set_bit(Alu8Data::Reg(A), 4), 2),
-
-        0xE8 => (Alu8Op::set_bit(Alu8Data::Reg(B), 5), 2),
-        0xE9 => (Alu8Op::set_bit(Alu8Data::Reg(C), 5), 2),
-        0xEA => (Alu8Op::set_bit(Alu8Data::Reg(D), 5), 2),
-        0xEB => (Alu8Op::set_bit(Alu8Data::Reg(E), 5), 2),
-        0xEC => (Alu8Op::set_bit(Alu8Data::Reg(H), 5), 2),
-        0xED => (Alu8Op::set_bit(Alu8Data::Reg(L), 5), 2),
-        0xEE => (Alu8Op::set_bit(Alu8Data::Addr(HL), 5), 4),
-        0xEF => (Alu8Op::set_bit(Alu8Data::Reg(A), 5), 2),
-
-        0xF0 => (Alu8Op::set_bit(Alu8Data::Reg(B), 6), 2),
-        0xF1 => (Alu8Op::set_bit(Alu8Data::Reg(C), 6), 2),
-        0xF2 => (Alu8Op::set_bit(Alu8Data::Reg(D), 6), 2),
-        0xF3 => (Alu8Op::set_bit(Alu8Data::Reg(E), 6), 2),
-        0xF4 => (Alu8Op::set_bit(Alu8Data::Reg(H), 6), 2),
-        0xF5 => (Alu8Op::set_bit(Alu8Data::Reg(L), 6), 2),
-        0xF6 => (Alu8Op::set_bit(Alu8Data::Addr(HL), 6), 4),
-        0xF7 => (Alu8Op::set_bit(Alu8Data::Reg(A), 6), 2),
-
-        0xF8 => (Alu8Op::set_bit(Alu8Data::Reg(B), 7), 2),
-        0xF9 => (Alu8Op::set_bit(Alu8Data::Reg(C), 7), 2),
-        0xFA => (Alu8Op::set_bit(Alu8Data::Reg(D), 7), 2),
-        0xFB => (Alu8Op::set_bit(Alu8Data::Reg(E), 7), 2),
-        0xFC => (Alu8Op::set_bit(Alu8Data::Reg(H), 7), 2),
-        0xFD => (Alu8Op::set_bit(Alu8Data::Reg(L), 7), 2),
-        0xFE => (Alu8Op::set_bit(Alu8Data::Addr(HL), 7), 4),
-        0xFF => (Alu8Op::set_bit(Alu8Data::Reg(A), 7), 2),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::Peripherals;
 
-    (Op::Alu8(alu_op), 2, time)
+    fn decode_bytes(bytes: &[u8]) -> (String, usize, usize) {
+        let mut mem = Peripherals::new_fake();
+        for (i, &byte) in bytes.iter().enumerate() {
+            mem.write(0xC000 + i as u16, byte);
+        }
+        let (op, size, cycles) = decode(&mem, 0xC000);
+        (op.to_string(), size, cycles)
+    }
+
+    #[test]
+    fn decodes_from_table() {
+        assert_eq!(decode_bytes(&[0x00]), ("NOP".to_string(), 1, 1));
+        assert_eq!(decode_bytes(&[0x41]), ("LD B C".to_string(), 1, 1));
+        assert_eq!(
+            decode_bytes(&[0x36, 0x12]),
+            ("LD (HL) 0x12".to_string(), 2, 3)
+        );
+        assert_eq!(
+            decode_bytes(&[0xFA, 0x34, 0x12]),
+            ("LD A (0x1234)".to_string(), 3, 4)
+        );
+        assert_eq!(decode_bytes(&[0xBE]), ("CP A,(HL)".to_string(), 1, 2));
+        assert_eq!(
+            decode_bytes(&[0x18, 0xFE]),
+            ("JR (49152)".to_string(), 2, 3)
+        );
+        assert_eq!(decode_bytes(&[0xFF]), ("RST 38H".to_string(), 1, 4));
+        assert_eq!(
+            decode_bytes(&[0xCB, 0x7E]),
+            ("TEST 0x07,(HL)".to_string(), 2, 3)
+        );
+        assert_eq!(decode_bytes(&[0xCB, 0x37]), ("SWAP".to_string(), 2, 2));
+        assert_eq!(decode_bytes(&[0xD3]).1, 1);
+    }
+
+    // Size in bytes of each opcode, from the opcode tables. 0 is undefined.
+    #[rustfmt::skip]
+    const SIZES: [usize; 0x100] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+        1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
+        2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
+        2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    ];
+
+    // Machine cycles each opcode takes, with conditional branches not taken.
+    #[rustfmt::skip]
+    const CYCLES: [usize; 0x100] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    // A small xorshift generator, for filling in operands.
+    struct Rng(u32);
+
+    impl Rng {
+        fn byte(&mut self) -> u8 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as u8
+        }
+    }
+
+    #[test]
+    fn every_opcode_matches_reference() {
+        let mut rng = Rng(0x1234_5678);
+        for code in 0..=0xFFu8 {
+            // Whatever follows the opcode shouldn't change how long it is.
+            for _ in 0..8 {
+                let bytes = [code, rng.byte(), rng.byte()];
+                let mut mem = Peripherals::new_fake();
+                for (i, &byte) in bytes.iter().enumerate() {
+                    mem.write(0xC000 + i as u16, byte);
+                }
+                let (op, size, cycles) = decode(&mem, 0xC000);
+                if code == 0xCB {
+                    let extended = bytes[1];
+                    let reference = match (extended >> 6, extended & 7) {
+                        (_, reg) if reg != 6 => 2,
+                        (1, _) => 3,
+                        _ => 4,
+                    };
+                    assert_eq!((size, cycles), (2, reference), "0xCB 0x{:02X}", extended);
+                } else if SIZES[code as usize] == 0 {
+                    assert!(matches!(op, Op::Unknown(_)), "0x{:02X}", code);
+                } else {
+                    assert!(!matches!(op, Op::Unknown(_)), "0x{:02X}", code);
+                    assert_eq!(
+                        (size, cycles),
+                        (SIZES[code as usize], CYCLES[code as usize]),
+                        "0x{:02X} {}",
+                        code,
+                        op
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn instruction_stream_stays_in_step() {
+        // Decoding a stream one instruction after another has to land on the start of each
+        // instruction.
+        let mut rng = Rng(0x8765_4321);
+        let mut mem = Peripherals::new_fake();
+        let mut starts = vec![];
+        let mut addr = 0xC000u16;
+        while addr < 0xCF00 {
+            let code = loop {
+                let code = rng.byte();
+                if SIZES[code as usize] != 0 {
+                    break code;
+                }
+            };
+            starts.push(addr);
+            let size = if code == 0xCB {
+                2
+            } else {
+                SIZES[code as usize]
+            };
+            mem.write(addr, code);
+            for offset in 1..size {
+                mem.write(addr + offset as u16, rng.byte());
+            }
+            addr += size as u16;
+        }
+        let mut pc = 0xC000u16;
+        for &start in &starts {
+            assert_eq!(pc, start);
+            pc = pc.wrapping_add(decode(&mem, pc).1 as u16);
+        }
+    }
 }
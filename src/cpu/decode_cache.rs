@@ -0,0 +1,73 @@
+//! Caches decoded instructions, so loops don't decode the same bytes every time around. Entries
+//! are keyed by PC and the ROM bank mapped there, and are dropped once anything writes to the
+//! page they were decoded from.
+use cpu::bus::Bus;
+use cpu::decode::{self, Op};
+
+struct Entry {
+    bank: u16,
+    version: u32,
+    op: Op,
+    size: usize,
+    cycles: usize,
+}
+
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: (0..0x10000).map(|_| None).collect(),
+        }
+    }
+
+    /// Same as `decode::decode`, but reuses the last decode at this PC if memory there hasn't
+    /// changed since.
+    pub fn decode<B: Bus + ?Sized>(&mut self, mem: &B, pc: u16) -> (Op, usize, usize) {
+        let version = match mem.code_version(pc) {
+            Some(version) => version,
+            None => return decode::decode(mem, pc),
+        };
+        let bank = mem.rom_bank_at(pc);
+        if let Some(ref entry) = self.entries[pc as usize] {
+            if entry.bank == bank && entry.version == version {
+                return (entry.op.clone(), entry.size, entry.cycles);
+            }
+        }
+        let (op, size, cycles) = decode::decode(mem, pc);
+        // Instructions that run onto the next page would also depend on writes there.
+        let last = pc.wrapping_add(size.max(1) as u16 - 1);
+        if last >> 8 == pc >> 8 {
+            self.entries[pc as usize] = Some(Entry {
+                bank,
+                version,
+                op: op.clone(),
+                size,
+                cycles,
+            });
+        }
+        (op, size, cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::Peripherals;
+
+    #[test]
+    fn invalidated_by_writes() {
+        let mut cache = DecodeCache::new();
+        let mut mem = Peripherals::new_fake();
+        mem.write(0xC000, 0x00);
+        assert_eq!(cache.decode(&mem, 0xC000).0.to_string(), "NOP");
+        assert_eq!(cache.decode(&mem, 0xC000).0.to_string(), "NOP");
+        mem.write(0xC000, 0x3C);
+        assert_eq!(cache.decode(&mem, 0xC000).0.to_string(), "INC A");
+        // Writes through echo RAM change the same bytes.
+        mem.write(0xE000, 0x00);
+        assert_eq!(cache.decode(&mem, 0xC000).0.to_string(), "NOP");
+    }
+}
@@ -1,3 +1,193 @@
+//! The CPU. `Wolfwig` only uses it through `Cpu`, and it only sees the rest of the machine through
+//! `Bus`, so another core, or one wrapping `SM83` to log or check what it does, can be swapped in
+//! with `Wolfwig::with_cpu` without changing anything else.
+use cpu::bus::Bus;
+use cpu::registers::{Reg16, Registers};
+use cpu::sm83::{CallFrame, Executed, SM83};
+use savestate::{Reader, Writer};
+use std::io;
+
+pub mod bus;
 pub mod decode;
+pub mod decode_cache;
+pub mod opcode_counts;
 pub mod registers;
 pub mod sm83;
+pub mod trace;
+
+pub trait Cpu: Send {
+    /// Runs one machine cycle. Returns whether the CPU is in STOP.
+    fn step(&mut self, mem: &mut dyn Bus) -> bool;
+
+    fn regs(&self) -> &Registers;
+    fn regs_mut(&mut self) -> &mut Registers;
+
+    fn pc(&self) -> u16 {
+        self.regs().read16(Reg16::PC)
+    }
+
+    /// Moves execution to a new PC, dropping anything already in progress.
+    fn set_pc(&mut self, pc: u16);
+
+    /// Whether the next cycle starts executing an instruction.
+    fn at_instruction(&self) -> bool;
+
+    fn stopped(&self) -> bool;
+
+    /// Whether the CPU has hung, like on an illegal opcode.
+    fn locked(&self) -> bool;
+
+    /// Returns the PC of an instruction the CPU didn't know how to execute, if it has run one
+    /// since the last call.
+    fn take_unknown_op(&mut self) -> Option<u16>;
+
+    /// Calls that haven't returned yet, outermost first, for backtraces. Cores that don't keep
+    /// track have none.
+    fn call_stack(&self) -> &[CallFrame] {
+        &[]
+    }
+
+    /// The last instructions run, oldest first, for crash reports. Cores that don't keep track
+    /// have none.
+    fn recent(&self) -> Vec<Executed> {
+        vec![]
+    }
+
+    fn save_state(&self, w: &mut Writer);
+
+    /// Memory has already been loaded.
+    fn load_state(&mut self, r: &mut Reader, mem: &dyn Bus) -> io::Result<()>;
+}
+
+impl Cpu for SM83 {
+    fn step(&mut self, mem: &mut dyn Bus) -> bool {
+        SM83::step(self, mem)
+    }
+
+    fn regs(&self) -> &Registers {
+        &self.regs
+    }
+
+    fn regs_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        SM83::set_pc(self, pc)
+    }
+
+    fn at_instruction(&self) -> bool {
+        SM83::at_instruction(self)
+    }
+
+    fn stopped(&self) -> bool {
+        SM83::stopped(self)
+    }
+
+    fn locked(&self) -> bool {
+        SM83::locked(self)
+    }
+
+    fn take_unknown_op(&mut self) -> Option<u16> {
+        SM83::take_unknown_op(self)
+    }
+
+    fn call_stack(&self) -> &[CallFrame] {
+        SM83::call_stack(self)
+    }
+
+    fn recent(&self) -> Vec<Executed> {
+        SM83::recent(self)
+    }
+
+    fn save_state(&self, w: &mut Writer) {
+        SM83::save_state(self, w)
+    }
+
+    fn load_state(&mut self, r: &mut Reader, mem: &dyn Bus) -> io::Result<()> {
+        SM83::load_state(self, r, mem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::Peripherals;
+
+    // Counts the instructions run by the core it wraps.
+    struct Counting {
+        core: SM83,
+        instructions: u32,
+    }
+
+    impl Cpu for Counting {
+        fn step(&mut self, mem: &mut dyn Bus) -> bool {
+            if self.core.at_instruction() {
+                self.instructions += 1;
+            }
+            self.core.step(mem)
+        }
+
+        fn regs(&self) -> &Registers {
+            &self.core.regs
+        }
+
+        fn regs_mut(&mut self) -> &mut Registers {
+            &mut self.core.regs
+        }
+
+        fn set_pc(&mut self, pc: u16) {
+            self.core.set_pc(pc)
+        }
+
+        fn at_instruction(&self) -> bool {
+            self.core.at_instruction()
+        }
+
+        fn stopped(&self) -> bool {
+            self.core.stopped()
+        }
+
+        fn locked(&self) -> bool {
+            self.core.locked()
+        }
+
+        fn take_unknown_op(&mut self) -> Option<u16> {
+            self.core.take_unknown_op()
+        }
+
+        fn save_state(&self, w: &mut Writer) {
+            self.core.save_state(w)
+        }
+
+        fn load_state(&mut self, r: &mut Reader, mem: &dyn Bus) -> io::Result<()> {
+            self.core.load_state(r, mem)
+        }
+    }
+
+    #[test]
+    fn wrapped_core() {
+        let mut mem = Peripherals::new_fake();
+        // INC A; INC A; JR -2, in work RAM.
+        for (offset, &byte) in [0x3C, 0x3C, 0x18, 0xFE].iter().enumerate() {
+            mem.write(0xC000 + offset as u16, byte);
+        }
+        let mut counting = Counting {
+            core: SM83::new(),
+            instructions: 0,
+        };
+        {
+            let cpu: &mut dyn Cpu = &mut counting;
+            cpu.set_pc(0xC000);
+            // Two cycles to get going, one for each INC, and three for the jump.
+            for _ in 0..2 + 1 + 1 + 3 {
+                cpu.step(&mut mem);
+            }
+            assert_eq!(cpu.regs().read8(registers::Reg8::A), 2);
+            assert_eq!(cpu.pc(), 0xC002);
+            assert!(cpu.call_stack().is_empty());
+            assert!(cpu.recent().is_empty());
+        }
+        assert_eq!(counting.instructions, 3);
+    }
+}
@@ -0,0 +1,92 @@
+//! Counts how many times each opcode runs, to see which are worth optimizing, and which ones a
+//! test ROM covers.
+use peripherals::Peripherals;
+
+// Opcodes that do something: all but the 11 undefined ones and the 0xCB prefix, and then all 256
+// of the 0xCB ones.
+const DEFINED_OPCODES: usize = 256 - 11 - 1 + 256;
+
+pub struct OpcodeCounts {
+    // Indexed by opcode, then 0x100 plus the second byte for the 0xCB ones.
+    counts: Vec<u64>,
+    total: u64,
+    pub running: bool,
+}
+
+impl OpcodeCounts {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; 0x200],
+            total: 0,
+            running: true,
+        }
+    }
+
+    /// Counts the instruction at `pc`, which is about to run.
+    pub fn record(&mut self, mem: &Peripherals, pc: u16) {
+        if !self.running {
+            return;
+        }
+        let index = match mem.peek(pc) {
+            0xCB => 0x100 | usize::from(mem.peek(pc.wrapping_add(1))),
+            code => usize::from(code),
+        };
+        self.counts[index] += 1;
+        self.total += 1;
+    }
+
+    /// Lists the `count` most run opcodes, most run first, or all of them that have run.
+    pub fn report(&self, count: Option<usize>) -> String {
+        if self.total == 0 {
+            return "No instructions counted".to_string();
+        }
+        let mut run: Vec<(usize, u64)> = (0..self.counts.len())
+            .map(|index| (index, self.counts[index]))
+            .filter(|&(_, n)| n != 0)
+            .collect();
+        run.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut lines = vec![format!(
+            "{} instructions counted, {} of {} opcodes run",
+            self.total,
+            run.len(),
+            DEFINED_OPCODES
+        )];
+        for (index, n) in run.into_iter().take(count.unwrap_or(usize::MAX)) {
+            let opcode = if index & 0x100 != 0 {
+                format!("0xCB 0x{:02X}", index & 0xFF)
+            } else {
+                format!("0x{:02X}", index)
+            };
+            let percent = n as f64 * 100.0 / self.total as f64;
+            lines.push(format!("{:<9} {:>12} {:>6.2}%", opcode, n, percent));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_most_run_first() {
+        let mut mem = Peripherals::new_fake();
+        // INC A; BIT 7,H; INC A
+        for (offset, &byte) in [0x3C, 0xCB, 0x7C, 0x3C].iter().enumerate() {
+            mem.write(0xC000 + offset as u16, byte);
+        }
+        let mut counts = OpcodeCounts::new();
+        for &pc in &[0xC000, 0xC001, 0xC003] {
+            counts.record(&mem, pc);
+        }
+        counts.running = false;
+        counts.record(&mem, 0xC001);
+        assert_eq!(
+            counts.report(None),
+            "3 instructions counted, 2 of 500 opcodes run\n\
+             0x3C                 2  66.67%\n\
+             0xCB 0x7C            1  33.33%"
+        );
+        assert_eq!(counts.report(Some(1)).lines().count(), 2);
+    }
+}
@@ -62,6 +62,7 @@ impl fmt::Display for Flag {
 }
 
 ///! Structure that holds the current register values from the CPU.
+#[derive(Debug, Copy, Clone)]
 pub struct Registers {
     a: u8,
     f: u8,
@@ -75,6 +76,12 @@ pub struct Registers {
     pc: u16,
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Self {
         Self {
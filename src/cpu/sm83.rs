@@ -1,7 +1,11 @@
 use self::decode::{Address, Alu16, Alu16Data, Alu16Op, Alu8, Alu8Data, Alu8Op, Op};
+use cpu::bus::Bus;
 use cpu::decode;
+use cpu::decode_cache::DecodeCache;
 use cpu::registers::{Flag, Reg16, Reg8, Registers};
-use peripherals::Peripherals;
+use savestate::{Reader, Writer};
+use std::collections::VecDeque;
+use std::io;
 use std::mem;
 
 struct NextOp {
@@ -20,15 +24,61 @@ impl NextOp {
     }
 }
 
+// Deepest call stack that's tracked. Code that never returns (or that unwinds the stack by hand)
+// would otherwise grow it forever.
+const MAX_CALL_DEPTH: usize = 256;
+
+// Instructions kept for crash reports, so there's some idea of how the CPU got where it did
+// without running with a full trace.
+const RECENT_INSTRUCTIONS: usize = 64;
+
+/// An instruction that was run, and the registers from just before it was.
+#[derive(Clone, Debug)]
+pub struct Executed {
+    pub pc: u16,
+    pub op: Op,
+    pub regs: Registers,
+}
+
+/// A call that hasn't returned yet, kept for backtraces.
+#[derive(Clone, Copy, Debug)]
+pub struct CallFrame {
+    /// Address of the CALL or RST, or the interrupted instruction.
+    pub call_pc: u16,
+    pub target: u16,
+    pub return_pc: u16,
+    /// Stack pointer after the return address was pushed.
+    pub sp: u16,
+    pub interrupt: bool,
+}
+
 ///! Emulation of the Sharp 8-bit SM83 processor.
 pub struct SM83 {
     pub regs: Registers,
     next_op: NextOp,
     cycle: usize,
     interrupt_enable: bool,
+    // Set by EI, which only enables interrupts once the instruction after it has run.
+    enable_pending: bool,
     halted: bool,
-    interrupted: bool,
     stopped: bool,
+    // Set by the undefined opcodes, which hang the CPU until it's reset.
+    locked: bool,
+    call_stack: Vec<CallFrame>,
+    // The last RECENT_INSTRUCTIONS instructions, oldest first.
+    recent: VecDeque<Executed>,
+    // PC of the last instruction that couldn't be executed, until someone takes it.
+    unknown_op: Option<u16>,
+    decode_cache: DecodeCache,
+    // Data from accesses made on earlier cycles of the current op, for `execute_op` to finish
+    // with. Set even for writes, so the op knows they're done.
+    early: Option<u16>,
+}
+
+impl Default for SM83 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SM83 {
@@ -38,54 +88,54 @@ impl SM83 {
             next_op: NextOp::new(),
             cycle: 0,
             interrupt_enable: false,
-            interrupted: false,
+            enable_pending: false,
             halted: false,
             stopped: false,
+            locked: false,
+            call_stack: vec![],
+            recent: VecDeque::with_capacity(RECENT_INSTRUCTIONS),
+            unknown_op: None,
+            decode_cache: DecodeCache::new(),
+            early: None,
         }
     }
 
-    pub fn step(&mut self, mem: &mut Peripherals) -> bool {
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Runs one cycle. Returns whether the CPU is in STOP.
+    pub fn step<B: Bus + ?Sized>(&mut self, mem: &mut B) -> bool {
+        if self.locked {
+            // Not even interrupts get a locked CPU going again.
+            self.cycle += 1;
+            return false;
+        }
+        if self.stopped {
+            // STOP is only left when a selected joypad line goes low.
+            if mem.joypad_pressed() {
+                self.stopped = false;
+            } else {
+                return true;
+            }
+        }
         // TODO(slongfield): Handle interrupts.
         info!(
             "Executing cycle: {}, pc: {}",
             self.cycle,
             self.regs.read16(Reg16::PC)
         );
-        if self.next_op.delay_cycles == 0 {
-            if !self.halted {
-                let op = mem::replace(&mut self.next_op, NextOp::new());
-                let pc = self.execute_op(mem, &op);
-                if self.interrupted {
-                    if let Some(interrupt_pc) = mem.get_interrupt() {
-                        self.next_op.op = Op::ExecuteInterrupt(interrupt_pc);
-                        self.next_op.delay_cycles = 0;
-                        self.interrupted = false;
-                        mem.disable_interrupt();
-                    } else {
-                        panic!("Interrupt dropped while attempting to execute!");
-                    }
-                } else if mem.get_interrupt() != None && self.interrupt_enable {
-                    self.next_op.op = Op::SetupInterrupt;
-                    self.next_op.delay_cycles = 3;
-                    self.interrupted = true;
-                    self.interrupt_enable = false;
-                } else {
-                    let (op, size, cycles) = decode::decode(mem, pc);
-                    self.next_op.op = op;
-                    self.next_op.pc_offset = size as u16;
-                    if cycles > 0 {
-                        self.next_op.delay_cycles = cycles - 1;
-                    } else {
-                        self.next_op.delay_cycles = 0;
-                    }
-                }
-            } else if mem.get_interrupt() != None {
-                mem::replace(&mut self.next_op, NextOp::new());
-                self.next_op.op = Op::SetupInterrupt;
-                self.next_op.delay_cycles = 3;
-                self.interrupted = true;
-                self.interrupt_enable = false;
+        if self.halted {
+            if mem.get_interrupt().is_some() {
+                // Without IME, an interrupt only wakes the CPU, which carries on after the HALT.
                 self.halted = false;
+                if self.interrupt_enable {
+                    self.dispatch_interrupt();
+                }
             } else {
                 info!(
                     "Executing halted: {} {:?}",
@@ -93,8 +143,37 @@ impl SM83 {
                     mem.get_interrupt()
                 );
             }
-        } else if self.next_op.delay_cycles > 0 {
+        } else if self.next_op.delay_cycles == 0 {
+            let op = mem::replace(&mut self.next_op, NextOp::new());
+            if op.pc_offset != 0 {
+                self.record(&op.op);
+            }
+            let enable_after = self.enable_pending;
+            let pc = self.execute_op(mem, &op);
+            self.early = None;
+            // DI straight after EI cancels it.
+            if enable_after && self.enable_pending {
+                self.interrupt_enable = true;
+                self.enable_pending = false;
+            }
+            if mem.get_interrupt().is_some() && self.interrupt_enable {
+                self.dispatch_interrupt();
+            } else {
+                let (op, size, cycles) = self.decode_cache.decode(mem, pc);
+                let cycles = cycles + self.taken_cycles(&op);
+                self.next_op.op = op;
+                self.next_op.pc_offset = size as u16;
+                if cycles > 0 {
+                    self.next_op.delay_cycles = cycles - 1;
+                } else {
+                    self.next_op.delay_cycles = 0;
+                }
+            }
+        } else {
             self.next_op.delay_cycles -= 1;
+            if self.next_op.delay_cycles <= 1 {
+                self.early_access(mem, self.next_op.delay_cycles);
+            }
         }
         self.cycle += 1;
         self.stopped
@@ -104,25 +183,296 @@ impl SM83 {
         self.regs.read16(Reg16::PC)
     }
 
-    fn execute_op(&mut self, mem: &mut Peripherals, op: &NextOp) -> u16 {
+    /// Saves the registers and where the CPU is up to. The already-decoded next op isn't saved
+    /// directly, since it can be decoded again from memory on load.
+    pub fn save_state(&self, w: &mut Writer) {
+        for reg in &[
+            Reg16::AF,
+            Reg16::BC,
+            Reg16::DE,
+            Reg16::HL,
+            Reg16::SP,
+            Reg16::PC,
+        ] {
+            w.u16(self.regs.read16(*reg));
+        }
+        w.u32(self.next_op.delay_cycles as u32);
+        w.u16(self.next_op.pc_offset);
+        match self.next_op.op {
+            Op::DispatchInterrupt => w.u8(1),
+            _ if self.next_op.pc_offset == 0 => w.u8(3),
+            _ => w.u8(0),
+        }
+        w.u64(self.cycle as u64);
+        w.bool(self.interrupt_enable);
+        w.bool(self.enable_pending);
+        w.bool(self.halted);
+        w.bool(self.stopped);
+        w.bool(self.locked);
+        w.bool(self.early.is_some());
+        w.u16(self.early.unwrap_or(0));
+    }
+
+    /// Loads state written by `save_state`. Memory has to be loaded first, so the next op can be
+    /// decoded.
+    pub fn load_state<B: Bus + ?Sized>(&mut self, r: &mut Reader, mem: &B) -> io::Result<()> {
+        for reg in &[
+            Reg16::AF,
+            Reg16::BC,
+            Reg16::DE,
+            Reg16::HL,
+            Reg16::SP,
+            Reg16::PC,
+        ] {
+            self.regs.set16(*reg, r.u16()?);
+        }
+        self.next_op.delay_cycles = r.u32()? as usize;
+        self.next_op.pc_offset = r.u16()?;
+        self.next_op.op = match r.u8()? {
+            1 => Op::DispatchInterrupt,
+            3 => Op::Nop,
+            _ => decode::decode(mem, self.pc()).0,
+        };
+        self.cycle = r.u64()? as usize;
+        self.interrupt_enable = r.bool()?;
+        self.enable_pending = r.bool()?;
+        self.halted = r.bool()?;
+        self.stopped = r.bool()?;
+        self.locked = r.bool()?;
+        let early = r.bool()?;
+        let data = r.u16()?;
+        self.early = if early { Some(data) } else { None };
+        // Calls made before the state was saved can't be known, and neither can what ran.
+        self.call_stack.clear();
+        self.recent.clear();
+        Ok(())
+    }
+
+    /// Whether the next cycle starts executing an instruction, rather than continuing one or
+    /// dispatching an interrupt.
+    pub fn at_instruction(&self) -> bool {
+        // Interrupt dispatch and the placeholder op after a reset don't move the PC on.
+        self.next_op.delay_cycles == 0
+            && self.next_op.pc_offset != 0
+            && !self.halted
+            && !self.stopped
+            && !self.locked
+    }
+
+    /// Moves execution to a new PC, dropping the already-decoded next op. This also gets a
+    /// locked CPU going again.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.regs.set16(Reg16::PC, pc);
+        self.next_op = NextOp::new();
+        self.locked = false;
+    }
+
+    /// Returns the PC of an instruction the CPU didn't know how to execute, if it has run one
+    /// since the last call.
+    pub fn take_unknown_op(&mut self) -> Option<u16> {
+        self.unknown_op.take()
+    }
+
+    /// Calls that haven't returned yet, outermost first.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
+    /// The last instructions run, oldest first.
+    pub fn recent(&self) -> Vec<Executed> {
+        self.recent.iter().cloned().collect()
+    }
+
+    // Cycles a conditional branch takes on top of what it was decoded with, if it's going to be
+    // taken. Nothing changes the flags between an op being decoded and run, so that's already
+    // known.
+    fn taken_cycles(&self, op: &Op) -> usize {
+        match *op {
+            Op::ConditionalJumpRelative(flag, _) | Op::ConditionalJump(flag, _)
+                if self.regs.read_flag(flag) =>
+            {
+                1
+            }
+            Op::ConditionalCall(flag, _) | Op::ConditionalReturn(flag)
+                if self.regs.read_flag(flag) =>
+            {
+                3
+            }
+            _ => 0,
+        }
+    }
+
+    fn record(&mut self, op: &Op) {
+        if self.recent.len() == RECENT_INSTRUCTIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(Executed {
+            pc: self.pc(),
+            op: op.clone(),
+            regs: self.regs,
+        });
+    }
+
+    fn enter(&mut self, call_pc: u16, target: u16, return_pc: u16, sp: u16, interrupt: bool) {
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(CallFrame {
+            call_pc,
+            target,
+            return_pc,
+            sp,
+            interrupt,
+        });
+    }
+
+    // Drops frames whose return address has been popped off of the stack.
+    fn unwind(&mut self, sp: u16) {
+        while self.call_stack.last().is_some_and(|frame| frame.sp < sp) {
+            self.call_stack.pop();
+        }
+    }
+
+    // Interrupt dispatch takes five cycles: two waiting, two pushing the PC, and one jumping.
+    fn dispatch_interrupt(&mut self) {
+        self.next_op = NextOp {
+            delay_cycles: 4,
+            pc_offset: 0,
+            op: Op::DispatchInterrupt,
+        };
+        self.interrupt_enable = false;
+        // An interrupt that's already pending when HALT runs is dispatched straight away.
+        self.halted = false;
+    }
+
+    // Picks the vector for an interrupt being dispatched, and acknowledges it. If nothing is
+    // pending any more, which happens when pushing the PC overwrote IE, dispatch goes to 0x0000.
+    fn interrupt_vector<B: Bus + ?Sized>(mem: &mut B) -> u16 {
+        match mem.get_interrupt() {
+            Some(vector) => {
+                mem.disable_interrupt();
+                vector
+            }
+            None => 0x0000,
+        }
+    }
+
+    // Makes the memory accesses that land before the last cycle of an op, with `remaining`
+    // cycles to go before `execute_op` runs it. Everything else happens on the last cycle. Only
+    // ops that make more than one access need anything here, since an op's last access is always
+    // on its last cycle, or the one before when it ends on an internal cycle.
+    fn early_access<B: Bus + ?Sized>(&mut self, mem: &mut B, remaining: usize) {
+        let sp = self.regs.read16(Reg16::SP);
+        let return_pc = self.pc().wrapping_add(self.next_op.pc_offset);
+        // Conditional calls and returns that aren't taken don't touch the stack.
+        let taken = match self.next_op.op {
+            Op::ConditionalCall(flag, _) | Op::ConditionalReturn(flag) => self.regs.read_flag(flag),
+            _ => true,
+        };
+        match (&self.next_op.op, remaining) {
+            // Read-modify-write ops read the cycle before they write.
+            (Op::Alu8(alu_op), 0) if alu_op.writes_memory() => {
+                let addr = self.regs.read16(Reg16::HL);
+                self.early = Some(u16::from(mem.read(addr)));
+            }
+            // Pushes write the high byte first.
+            (Op::Push(reg), 0) => {
+                let data = self.regs.read16(*reg);
+                mem.write(sp.wrapping_sub(1), (data >> 8) as u8);
+                self.early = Some(0);
+            }
+            (Op::Call(_), 0) | (Op::ConditionalCall(..), 0) | (Op::Reset(_), 0) if taken => {
+                mem.write(sp.wrapping_sub(1), (return_pc >> 8) as u8);
+                self.early = Some(0);
+            }
+            // The vector is only picked after the high byte of the PC is pushed.
+            (Op::DispatchInterrupt, 1) => {
+                mem.write(sp.wrapping_sub(1), (return_pc >> 8) as u8);
+            }
+            (Op::DispatchInterrupt, 0) => {
+                let vector = Self::interrupt_vector(mem);
+                mem.write(sp.wrapping_sub(2), return_pc as u8);
+                self.early = Some(vector);
+            }
+            (Op::WideStore(Address::Immediate16(addr), reg), 0) => {
+                let data = self.regs.read16(*reg);
+                mem.write(*addr, data as u8);
+                self.early = Some(0);
+            }
+            // Pops read the low byte first. Returns read both before jumping.
+            (Op::Pop(_), 0)
+            | (Op::Return, 1)
+            | (Op::ReturnAndEnableInterrupts, 1)
+            | (Op::ConditionalReturn(_), 1)
+                if taken =>
+            {
+                self.early = Some(u16::from(mem.read(sp)));
+            }
+            (Op::Return, 0)
+            | (Op::ReturnAndEnableInterrupts, 0)
+            | (Op::ConditionalReturn(_), 0)
+                if taken =>
+            {
+                let high = u16::from(mem.read(sp.wrapping_add(1)));
+                self.early = self.early.map(|low| (high << 8) | low);
+            }
+            _ => {}
+        }
+    }
+
+    // Pops a return address, unless it was already read on earlier cycles.
+    fn pop_return<B: Bus + ?Sized>(&mut self, mem: &mut B) -> u16 {
+        let sp = self.regs.read16(Reg16::SP);
+        let addr = match self.early.take() {
+            Some(addr) => addr,
+            None => {
+                let low = u16::from(mem.read(sp));
+                let high = u16::from(mem.read(sp.wrapping_add(1)));
+                (high << 8) | low
+            }
+        };
+        self.regs.set16(Reg16::SP, sp.wrapping_add(2));
+        self.unwind(sp.wrapping_add(2));
+        addr
+    }
+
+    // Pushes a 16-bit value, skipping the high byte if it was already written on an earlier
+    // cycle.
+    fn push<B: Bus + ?Sized>(&mut self, mem: &mut B, data: u16) {
+        let sp = self.regs.read16(Reg16::SP);
+        if self.early.take().is_none() {
+            mem.write(sp.wrapping_sub(1), (data >> 8) as u8);
+        }
+        mem.write(sp.wrapping_sub(2), data as u8);
+        self.regs.set16(Reg16::SP, sp.wrapping_sub(2));
+    }
+
+    fn execute_op<B: Bus + ?Sized>(&mut self, mem: &mut B, op: &NextOp) -> u16 {
         let pc = self.regs.read16(Reg16::PC);
         let mut next_pc = pc + op.pc_offset;
         match op.op {
             Op::Nop => {}
             Op::EnableInterrupts => {
-                self.interrupt_enable = true;
+                self.enable_pending = true;
             }
             Op::DisableInterrupts => {
                 self.interrupt_enable = false;
-            }
-            Op::SetupInterrupt => {
-                let sp = self.regs.read16(Reg16::SP);
-                mem.write(sp - 1, ((next_pc >> 8) & 0xFF) as u8);
-                mem.write(sp - 2, (next_pc & 0xFF) as u8);
-                self.regs.set16(Reg16::SP, sp - 2);
-            }
-            Op::ExecuteInterrupt(new_pc) => {
-                next_pc = new_pc;
+                self.enable_pending = false;
+            }
+            Op::DispatchInterrupt => {
+                let sp = self.regs.read16(Reg16::SP).wrapping_sub(2);
+                let vector = match self.early.take() {
+                    Some(vector) => vector,
+                    None => {
+                        mem.write(sp.wrapping_add(1), (next_pc >> 8) as u8);
+                        let vector = Self::interrupt_vector(mem);
+                        mem.write(sp, next_pc as u8);
+                        vector
+                    }
+                };
+                self.regs.set16(Reg16::SP, sp);
+                self.enter(pc, vector, next_pc, sp, true);
+                next_pc = vector;
             }
             Op::Halt => {
                 // TODO(slongfield): Add halted bug. If interrupts are not enabled. Halt skips the
@@ -130,8 +480,9 @@ impl SM83 {
                 self.halted = true;
             }
             Op::Stop => {
-                // TODO(slongfield): Should only stop until a button is pressed.
-                self.stopped = true
+                self.stopped = true;
+                // Entering STOP resets the divider.
+                mem.write(0xFF04, 0);
             }
 
             Op::Set(reg, val) => self.regs.set8(reg, val),
@@ -175,8 +526,10 @@ impl SM83 {
             }
             Op::WideStore(Address::Immediate16(addr), data_reg) => {
                 let data = self.regs.read16(data_reg);
-                mem.write(addr, data as u8);
-                mem.write(addr + 1, (data >> 8) as u8);
+                if self.early.take().is_none() {
+                    mem.write(addr, data as u8);
+                }
+                mem.write(addr.wrapping_add(1), (data >> 8) as u8);
             }
             Op::StoreAndDecrement(Address::Register16(addr_reg), data_reg) => {
                 let data = self.regs.read8(data_reg);
@@ -207,44 +560,28 @@ impl SM83 {
             }
 
             Op::Call(new_pc) => {
+                self.push(mem, next_pc);
                 let sp = self.regs.read16(Reg16::SP);
-                mem.write(sp - 1, ((next_pc >> 8) & 0xFF) as u8);
-                mem.write(sp - 2, (next_pc & 0xFF) as u8);
-                self.regs.set16(Reg16::SP, sp - 2);
+                self.enter(pc, new_pc, next_pc, sp, false);
                 next_pc = new_pc;
             }
             Op::ConditionalCall(flag, new_pc) => {
                 if self.regs.read_flag(flag) {
+                    self.push(mem, next_pc);
                     let sp = self.regs.read16(Reg16::SP);
-                    mem.write(sp - 1, ((next_pc >> 8) & 0xFF) as u8);
-                    mem.write(sp - 2, (next_pc & 0xFF) as u8);
-                    self.regs.set16(Reg16::SP, sp - 2);
+                    self.enter(pc, new_pc, next_pc, sp, false);
                     next_pc = new_pc;
                 }
             }
 
-            Op::Return => {
-                let sp = self.regs.read16(Reg16::SP);
-                let pc_low = u16::from(mem.read(sp));
-                let pc_high = u16::from(mem.read(sp + 1));
-                self.regs.set16(Reg16::SP, sp + 2);
-                next_pc = (pc_high << 8) | pc_low;
-            }
+            Op::Return => next_pc = self.pop_return(mem),
             Op::ReturnAndEnableInterrupts => {
-                let sp = self.regs.read16(Reg16::SP);
-                let pc_low = u16::from(mem.read(sp));
-                let pc_high = u16::from(mem.read(sp + 1));
-                self.regs.set16(Reg16::SP, sp + 2);
                 self.interrupt_enable = true;
-                next_pc = (pc_high << 8) | pc_low;
+                next_pc = self.pop_return(mem);
             }
             Op::ConditionalReturn(flag) => {
                 if self.regs.read_flag(flag) {
-                    let sp = self.regs.read16(Reg16::SP);
-                    let pc_low = u16::from(mem.read(sp));
-                    let pc_high = u16::from(mem.read(sp + 1));
-                    self.regs.set16(Reg16::SP, sp + 2);
-                    next_pc = (pc_high << 8) | pc_low;
+                    next_pc = self.pop_return(mem);
                 }
             }
 
@@ -254,21 +591,19 @@ impl SM83 {
             }
             Op::Push(reg) => {
                 let data = self.regs.read16(reg);
-                let sp = self.regs.read16(Reg16::SP);
-                mem.write(sp - 1, ((data >> 8) & 0xFF) as u8);
-                mem.write(sp - 2, (data & 0xFF) as u8);
-                self.regs.set16(Reg16::SP, sp - 2);
+                self.push(mem, data);
             }
             Op::Pop(reg) => {
                 let sp = self.regs.read16(Reg16::SP);
-                let data_low = u16::from(mem.read(sp));
-                let data_high = u16::from(mem.read(sp + 1));
-                self.regs.set16(Reg16::SP, sp + 2);
+                let data_low = match self.early.take() {
+                    Some(low) => low,
+                    None => u16::from(mem.read(sp)),
+                };
+                let data_high = u16::from(mem.read(sp.wrapping_add(1)));
+                self.regs.set16(Reg16::SP, sp.wrapping_add(2));
                 self.regs.set16(reg, (data_high << 8) | data_low);
             }
             Op::ConditionalJumpRelative(flag, new_pc) => {
-                // TODO(slongfield): When this branch is taken, it should consume an additional
-                // cycle.
                 if self.regs.read_flag(flag) {
                     next_pc = new_pc
                 }
@@ -286,39 +621,51 @@ impl SM83 {
 
             // This is basically the same as call.
             Op::Reset(new_pc) => {
+                self.push(mem, next_pc);
                 let sp = self.regs.read16(Reg16::SP);
-                mem.write(sp - 1, ((next_pc >> 8) & 0xFF) as u8);
-                mem.write(sp - 2, (next_pc & 0xFF) as u8);
-                self.regs.set16(Reg16::SP, sp - 2);
+                self.enter(pc, new_pc, next_pc, sp, false);
                 next_pc = new_pc;
             }
 
             Op::Alu8(ref alu_op) => self.execute_alu8(&alu_op, mem),
             Op::Alu16(ref alu_op) => self.execute_alu16(&alu_op),
-            _ => error!(
-                "Cycle: {} PC: 0x{:04X} Unknown op: {:?}",
-                self.cycle,
-                self.regs.read16(Reg16::PC),
-                op.op
-            ),
+            Op::Unknown(code) => {
+                error!(
+                    "Cycle: {} PC: 0x{:04X} Locked up on opcode 0x{:02X}",
+                    self.cycle, pc, code
+                );
+                self.locked = true;
+                self.unknown_op = Some(pc);
+                next_pc = pc;
+            }
+            _ => {
+                error!(
+                    "Cycle: {} PC: 0x{:04X} Unknown op: {:?}",
+                    self.cycle,
+                    self.regs.read16(Reg16::PC),
+                    op.op
+                );
+                self.unknown_op = Some(pc);
+            }
         }
         self.regs.set16(Reg16::PC, next_pc);
         next_pc
     }
 
-    fn get_alu8_data(&mut self, data: &Alu8Data, mem: &mut Peripherals) -> u8 {
+    fn get_alu8_data<B: Bus + ?Sized>(&mut self, data: &Alu8Data, mem: &mut B) -> u8 {
         match data {
             Alu8Data::Reg(reg) => self.regs.read8(*reg),
             Alu8Data::Imm(data) => *data,
-            Alu8Data::Addr(reg16) => {
-                let addr = self.regs.read16(*reg16);
-                mem.read(addr)
-            }
+            Alu8Data::Addr(reg16) => match self.early.take() {
+                // Read-modify-write ops read on an earlier cycle.
+                Some(data) => data as u8,
+                None => mem.read(self.regs.read16(*reg16)),
+            },
             Alu8Data::Ignore => 0xFF,
         }
     }
 
-    fn set_alu8_data(&mut self, dest: &Alu8Data, val: u8, mem: &mut Peripherals) {
+    fn set_alu8_data<B: Bus + ?Sized>(&mut self, dest: &Alu8Data, val: u8, mem: &mut B) {
         match dest {
             Alu8Data::Reg(reg) => self.regs.set8(*reg, val),
             Alu8Data::Addr(reg16) => {
@@ -329,7 +676,7 @@ impl SM83 {
         }
     }
 
-    fn execute_alu8(&mut self, op: &Alu8Op, mem: &mut Peripherals) {
+    fn execute_alu8<B: Bus + ?Sized>(&mut self, op: &Alu8Op, mem: &mut B) {
         let x = self.get_alu8_data(&op.dest, mem);
         let y = self.get_alu8_data(&op.y, mem);
         let (out, zero, subtract, half_carry, carry) = match op.op {
@@ -513,10 +860,6 @@ impl SM83 {
                 let zero = out == 0;
                 (Some(out), Some(zero), Some(false), Some(false), Some(false))
             }
-            Alu8::Unknown => {
-                error!("Attempted to execute Unknown ALU8Op!");
-                (None, None, None, None, None)
-            }
         };
         if let Some(data) = out {
             self.set_alu8_data(&op.dest, data, mem);
@@ -586,13 +929,10 @@ impl SM83 {
                     (Some(false), Some(false), Some(half), Some(carry))
                 } else {
                     error!("Invalid MoveAndAdd");
+                    self.unknown_op = Some(self.pc());
                     (None, None, None, None)
                 }
             }
-            Alu16::Unknown => {
-                error!("Executing unknown ALU 16 Op!");
-                (None, None, None, None)
-            }
         };
         if let Some(zero) = zero {
             self.regs.set_flag(Flag::Zero, zero);
@@ -612,6 +952,10 @@ impl SM83 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cpu::bus::FlatRam;
+    use model::Model;
+    use peripherals::{Button, FakeInput, Peripherals};
+    use std::cell::RefCell;
 
     #[test]
     fn rotate_left_carry() {
@@ -971,4 +1315,521 @@ mod tests {
         cpu.execute_alu8(&daa, &mut mem);
         assert_eq!(cpu.regs.read8(Reg8::A), 0xF9);
     }
+
+    #[test]
+    fn stop_wakes_on_joypad() {
+        let mut cpu = SM83::new();
+        let (mut mem, inputs) = Peripherals::new_fake_with_inputs();
+        cpu.stopped = true;
+        // Select the buttons, rather than the directions.
+        mem.write(0xFF00, 0x10);
+
+        inputs
+            .send(FakeInput {
+                cycle: 0,
+                button: Button::Down,
+                pressed: true,
+            })
+            .unwrap();
+        mem.step_idle();
+        assert!(cpu.step(&mut mem));
+
+        inputs
+            .send(FakeInput {
+                cycle: 0,
+                button: Button::Start,
+                pressed: true,
+            })
+            .unwrap();
+        mem.step_idle();
+        assert!(!cpu.step(&mut mem));
+        assert!(!cpu.stopped());
+    }
+
+    #[test]
+    fn runs_on_flat_ram() {
+        let mut cpu = SM83::new();
+        // LD A, 5; INC A; LD (0xC000), A; EI; JR -2
+        let mut mem = FlatRam::new(&[0x3E, 0x05, 0x3C, 0xEA, 0x00, 0xC0, 0xFB, 0x18, 0xFE]);
+        cpu.regs.set16(Reg16::SP, 0xFFFE);
+        for _ in 0..20 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(mem.read(0xC000), 6);
+        assert_eq!(cpu.pc(), 0x0007);
+
+        mem.write(0xFFFF, 0x04);
+        mem.write(0xFF0F, 0x04);
+        for _ in 0..8 {
+            cpu.step(&mut mem);
+        }
+        // The timer interrupt was taken from the loop, and acknowledged.
+        assert_eq!(mem.read(0xFF0F), 0x00);
+        assert_eq!(cpu.regs.read16(Reg16::SP), 0xFFFC);
+        assert_eq!([mem.read(0xFFFC), mem.read(0xFFFD)], [0x07, 0x00]);
+        assert!(cpu.pc() >= 0x50);
+    }
+
+    #[test]
+    fn serial_interrupt() {
+        let mut mem = Peripherals::new_fake();
+        mem.write(0xFFFF, 0x08);
+        mem.write(0xFF01, 0x42);
+        // Start a transfer on the internal clock.
+        mem.write(0xFF02, 0x81);
+        assert_eq!(mem.get_interrupt(), None);
+
+        // Eight bits at 8192Hz.
+        for _ in 0..8 * 128 {
+            mem.step();
+        }
+        assert_eq!(mem.read(0xFF02) & 0x80, 0);
+        assert_eq!(mem.read(0xFF0F) & 0x08, 0x08);
+        assert_eq!(mem.get_interrupt(), Some(0x58));
+    }
+
+    #[test]
+    fn call_stack() {
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        cpu.regs.set16(Reg16::SP, 0xFFFE);
+        cpu.regs.set16(Reg16::PC, 0x150);
+
+        let call = NextOp {
+            delay_cycles: 0,
+            pc_offset: 3,
+            op: Op::Call(0x200),
+        };
+        let pc = cpu.execute_op(&mut mem, &call);
+        cpu.regs.set16(Reg16::PC, pc);
+        let rst = NextOp {
+            delay_cycles: 0,
+            pc_offset: 1,
+            op: Op::Reset(0x38),
+        };
+        let pc = cpu.execute_op(&mut mem, &rst);
+        cpu.regs.set16(Reg16::PC, pc);
+
+        let returns: Vec<u16> = cpu.call_stack().iter().map(|f| f.return_pc).collect();
+        assert_eq!(returns, vec![0x153, 0x201]);
+        assert_eq!(cpu.call_stack()[1].target, 0x38);
+
+        let ret = NextOp {
+            delay_cycles: 0,
+            pc_offset: 1,
+            op: Op::Return,
+        };
+        assert_eq!(cpu.execute_op(&mut mem, &ret), 0x201);
+        assert_eq!(cpu.call_stack().len(), 1);
+        assert_eq!(cpu.execute_op(&mut mem, &ret), 0x153);
+        assert!(cpu.call_stack().is_empty());
+    }
+
+    // Runs the program at 0xC000, and returns the value at `addr` after each cycle.
+    fn watch_cycles(program: &[u8], setup: fn(&mut SM83), addr: u16, cycles: usize) -> Vec<u8> {
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        for (i, &byte) in program.iter().enumerate() {
+            mem.write(0xC000 + i as u16, byte);
+        }
+        cpu.regs.set16(Reg16::PC, 0xC000);
+        setup(&mut cpu);
+        // The first cycle only decodes the first instruction.
+        cpu.step(&mut mem);
+        (0..cycles)
+            .map(|_| {
+                cpu.step(&mut mem);
+                mem.read(addr)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accesses_land_on_their_cycles() {
+        // INC (HL) reads on its second cycle, and writes on its third.
+        let values = watch_cycles(&[0x34], |cpu| cpu.regs.set16(Reg16::HL, 0xC100), 0xC100, 3);
+        assert_eq!(values, vec![0, 0, 1]);
+
+        // PUSH BC writes B on its third cycle, and C on its fourth.
+        let setup = |cpu: &mut SM83| {
+            cpu.regs.set16(Reg16::SP, 0xD000);
+            cpu.regs.set16(Reg16::BC, 0x1234);
+        };
+        assert_eq!(
+            watch_cycles(&[0xC5], setup, 0xCFFF, 4),
+            vec![0, 0, 0x12, 0x12]
+        );
+        assert_eq!(watch_cycles(&[0xC5], setup, 0xCFFE, 4), vec![0, 0, 0, 0x34]);
+    }
+
+    // Records reads and writes, but not instruction fetches, along with the cycle they're made on.
+    struct Accesses {
+        ram: FlatRam,
+        cycle: usize,
+        made: RefCell<Vec<(usize, char, u16)>>,
+    }
+
+    impl Bus for Accesses {
+        fn read(&self, address: u16) -> u8 {
+            self.made.borrow_mut().push((self.cycle, 'R', address));
+            self.ram.read(address)
+        }
+
+        fn write(&mut self, address: u16, val: u8) {
+            self.made.get_mut().push((self.cycle, 'W', address));
+            self.ram.write(address, val);
+        }
+
+        fn fetch(&self, address: u16) -> u8 {
+            self.ram.read(address)
+        }
+
+        fn get_interrupt(&self) -> Option<u16> {
+            self.ram.get_interrupt()
+        }
+
+        fn disable_interrupt(&mut self) {
+            self.ram.disable_interrupt();
+        }
+    }
+
+    #[test]
+    fn access_timing() {
+        const SP: u16 = 0xD000;
+        const HL: u16 = 0xC100;
+        // Each op, with Z set or not, the cycles it takes, and the accesses it makes on each of
+        // them, numbered like in `accesses_land_on_their_cycles`.
+        let ops: &[(&[u8], bool, usize, &[(usize, char, u16)])] = &[
+            (&[0x7E], false, 2, &[(2, 'R', HL)]),
+            (&[0x77], false, 2, &[(2, 'W', HL)]),
+            (&[0x36, 0x42], false, 3, &[(3, 'W', HL)]),
+            (&[0x34], false, 3, &[(2, 'R', HL), (3, 'W', HL)]),
+            (&[0xCB, 0x06], false, 4, &[(3, 'R', HL), (4, 'W', HL)]),
+            (&[0xCB, 0x46], false, 3, &[(3, 'R', HL)]),
+            (&[0xF0, 0x05], false, 3, &[(3, 'R', 0xFF05)]),
+            (&[0xE0, 0x05], false, 3, &[(3, 'W', 0xFF05)]),
+            (&[0xFA, 0x00, 0xC2], false, 4, &[(4, 'R', 0xC200)]),
+            (&[0xEA, 0x00, 0xC2], false, 4, &[(4, 'W', 0xC200)]),
+            (
+                &[0x08, 0x00, 0xC2],
+                false,
+                5,
+                &[(4, 'W', 0xC200), (5, 'W', 0xC201)],
+            ),
+            (&[0xC5], false, 4, &[(3, 'W', SP - 1), (4, 'W', SP - 2)]),
+            (&[0xC1], false, 3, &[(2, 'R', SP), (3, 'R', SP + 1)]),
+            (
+                &[0xCD, 0x00, 0x10],
+                false,
+                6,
+                &[(5, 'W', SP - 1), (6, 'W', SP - 2)],
+            ),
+            (
+                &[0xC4, 0x00, 0x10],
+                false,
+                6,
+                &[(5, 'W', SP - 1), (6, 'W', SP - 2)],
+            ),
+            (&[0xC4, 0x00, 0x10], true, 3, &[]),
+            (&[0xFF], false, 4, &[(3, 'W', SP - 1), (4, 'W', SP - 2)]),
+            (&[0xC9], false, 4, &[(2, 'R', SP), (3, 'R', SP + 1)]),
+            (&[0xD9], false, 4, &[(2, 'R', SP), (3, 'R', SP + 1)]),
+            (&[0xC0], false, 5, &[(3, 'R', SP), (4, 'R', SP + 1)]),
+            (&[0xC0], true, 2, &[]),
+        ];
+        for &(program, zero, cycles, expected) in ops {
+            let mut mem = Accesses {
+                ram: FlatRam::new(program),
+                cycle: 0,
+                made: RefCell::new(vec![]),
+            };
+            let mut cpu = SM83::new();
+            cpu.regs.set16(Reg16::SP, SP);
+            cpu.regs.set16(Reg16::HL, HL);
+            cpu.regs
+                .set16(Reg16::AF, if zero { 0x0080 } else { 0x0000 });
+            cpu.step(&mut mem);
+            for cycle in 1..=cycles {
+                assert!(cpu.recent().is_empty(), "{:02X?} was too quick", program);
+                mem.cycle = cycle;
+                cpu.step(&mut mem);
+            }
+            assert_eq!(cpu.recent().len(), 1, "{:02X?} took too long", program);
+            assert_eq!(mem.made.into_inner(), expected, "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn delayed_enable_interrupts() {
+        for &(program, dispatched) in &[(&[0xFB, 0x00, 0x00], true), (&[0xFB, 0xF3, 0x00], false)] {
+            let mut cpu = SM83::new();
+            let mut mem = Peripherals::new_fake();
+            for (i, &byte) in program.iter().enumerate() {
+                mem.write(0xC000 + i as u16, byte);
+            }
+            mem.write(0xFFFF, 0x01);
+            mem.write(0xFF0F, 0x01);
+            cpu.regs.set16(Reg16::PC, 0xC000);
+
+            // Decode EI, then run it. The interrupt waits for the instruction after.
+            cpu.step(&mut mem);
+            cpu.step(&mut mem);
+            assert!(!matches!(cpu.next_op.op, Op::DispatchInterrupt));
+            cpu.step(&mut mem);
+            assert_eq!(matches!(cpu.next_op.op, Op::DispatchInterrupt), dispatched);
+            assert_eq!(cpu.pc(), 0xC002);
+        }
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        // With SP at 0x0000, pushing the high byte of the PC overwrites IE and cancels the
+        // interrupt, so dispatch goes to 0x0000 and leaves IF alone.
+        for &(sp, vector, acknowledged) in &[(0xD000, 0x0040, true), (0x0000, 0x0000, false)] {
+            let mut cpu = SM83::new();
+            let mut mem = Peripherals::new_fake();
+            mem.write(0xFFFF, 0x01);
+            mem.write(0xFF0F, 0x01);
+            cpu.regs.set16(Reg16::PC, 0xC000);
+            cpu.regs.set16(Reg16::SP, sp);
+            cpu.interrupt_enable = true;
+
+            // The placeholder op runs, then dispatch takes five cycles.
+            cpu.step(&mut mem);
+            for _ in 0..4 {
+                cpu.step(&mut mem);
+                assert_eq!(cpu.pc(), 0xC000);
+            }
+            cpu.step(&mut mem);
+            assert_eq!(cpu.pc(), vector);
+            assert_eq!(cpu.regs.read16(Reg16::SP), sp.wrapping_sub(2));
+            assert_eq!(mem.read(0xFF0F) & 0x01 == 0, acknowledged);
+            assert!(!cpu.interrupt_enable);
+        }
+    }
+
+    #[test]
+    fn halt_without_ime() {
+        // HALT, INC A. With IME off, the interrupt wakes the CPU without being dispatched.
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        mem.write(0xC000, 0x76);
+        mem.write(0xC001, 0x3C);
+        mem.write(0xFFFF, 0x01);
+        cpu.regs.set16(Reg16::PC, 0xC000);
+        for _ in 0..4 {
+            cpu.step(&mut mem);
+        }
+        assert!(cpu.halted);
+        mem.write(0xFF0F, 0x01);
+        // One cycle to wake, then INC A runs.
+        cpu.step(&mut mem);
+        assert!(!cpu.halted);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.pc(), 0xC002);
+        assert_eq!(cpu.regs.read8(Reg8::A), 1);
+        assert_eq!(mem.read(0xFF0F) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn taken_branch_cycles() {
+        // JR NZ,+0 then NOP, so both ways end up at the NOP. Taking the branch costs a cycle.
+        for &(zero, cycles) in &[(false, 5), (true, 4)] {
+            let mut cpu = SM83::new();
+            let mut mem = Peripherals::new_fake();
+            mem.write(0xC000, 0x20);
+            mem.write(0xC001, 0x00);
+            cpu.regs.set16(Reg16::PC, 0xC000);
+            cpu.regs.set_flag(Flag::Zero, zero);
+            let mut count = 0;
+            while cpu.pc() != 0xC003 {
+                cpu.step(&mut mem);
+                count += 1;
+            }
+            assert_eq!(count, cycles);
+        }
+    }
+
+    #[test]
+    fn taken_branches_take_their_own_cycles() {
+        // Each branch is taken to a NOP at 0xC010. The branch should move the PC on its own last
+        // cycle, and the NOP a cycle after.
+        let branches: [(&[u8], usize); 4] = [
+            (&[0x28, 0x0E], 3),       // JR Z
+            (&[0xCA, 0x10, 0xC0], 4), // JP Z
+            (&[0xCC, 0x10, 0xC0], 6), // CALL Z
+            (&[0xC8], 5),             // RET Z
+        ];
+        for &(program, cycles) in &branches {
+            let mut cpu = SM83::new();
+            let mut mem = Peripherals::new_fake();
+            for (offset, &byte) in program.iter().enumerate() {
+                mem.write(0xC000 + offset as u16, byte);
+            }
+            mem.write(0xC010, 0x00);
+            mem.write(0xD000, 0x10);
+            mem.write(0xD001, 0xC0);
+            cpu.regs.set16(Reg16::SP, 0xD000);
+            cpu.set_pc(0xC000);
+            cpu.regs.set_flag(Flag::Zero, true);
+            // The first step runs the placeholder op, and decodes the branch.
+            cpu.step(&mut mem);
+            let mut moves = vec![];
+            for count in 1..=cycles + 1 {
+                let pc = cpu.pc();
+                cpu.step(&mut mem);
+                if cpu.pc() != pc {
+                    moves.push((count, cpu.pc()));
+                }
+            }
+            assert_eq!(
+                moves,
+                vec![(cycles, 0xC010), (cycles + 1, 0xC011)],
+                "{:02X?}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn executes_everything_decoded() {
+        // Catches ops the decoder produces that `execute_op` has no case for.
+        let codes = (0..=0xFFu16)
+            .filter(|&code| code != 0xCB)
+            .map(|code| [code as u8, 0x00])
+            .chain((0..=0xFFu16).map(|code| [0xCB, code as u8]));
+        for bytes in codes {
+            let mut cpu = SM83::new();
+            let mut mem = Peripherals::new_fake();
+            mem.write(0xC000, bytes[0]);
+            mem.write(0xC001, bytes[1]);
+            cpu.regs.set16(Reg16::PC, 0xC000);
+            cpu.regs.set16(Reg16::SP, 0xD000);
+            let (op, size, cycles) = decode::decode(&mem, 0xC000);
+            let unknown = matches!(op, Op::Unknown(_));
+            let next = NextOp {
+                delay_cycles: cycles,
+                pc_offset: size as u16,
+                op,
+            };
+            cpu.execute_op(&mut mem, &next);
+            assert_eq!(
+                cpu.take_unknown_op().is_some(),
+                unknown,
+                "0x{:02X} 0x{:02X}",
+                bytes[0],
+                bytes[1]
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_op() {
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        cpu.regs.set16(Reg16::PC, 0x150);
+        let op = NextOp {
+            delay_cycles: 0,
+            pc_offset: 1,
+            op: Op::Unknown(0xD3),
+        };
+        assert_eq!(cpu.execute_op(&mut mem, &op), 0x150);
+        assert_eq!(cpu.take_unknown_op(), Some(0x150));
+        assert_eq!(cpu.take_unknown_op(), None);
+
+        // Nothing runs once the CPU has locked up, not even interrupts.
+        assert!(cpu.locked());
+        cpu.interrupt_enable = true;
+        mem.write(0xFFFF, 0x01);
+        mem.write(0xFF0F, 0x01);
+        for _ in 0..10 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(cpu.pc(), 0x150);
+        cpu.set_pc(0x100);
+        assert!(!cpu.locked());
+    }
+
+    #[test]
+    fn recent_instructions() {
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        // INC A; JR -3, in work RAM.
+        for (offset, &byte) in [0x3C, 0x18, 0xFD].iter().enumerate() {
+            mem.write(0xC000 + offset as u16, byte);
+        }
+        cpu.set_pc(0xC000);
+        for _ in 0..200 {
+            cpu.step(&mut mem);
+        }
+        let recent = cpu.recent();
+        assert_eq!(recent.len(), RECENT_INSTRUCTIONS);
+        let first_inc = recent
+            .iter()
+            .position(|executed| executed.pc == 0xC000)
+            .unwrap();
+        let (inc, jump) = (&recent[first_inc], &recent[first_inc + 1]);
+        assert_eq!(jump.pc, 0xC001);
+        assert!(matches!(jump.op, Op::JumpRelative(0xC000)));
+        // Each has the registers from before it ran.
+        assert_eq!(inc.regs.read16(Reg16::PC), 0xC000);
+        assert_eq!(
+            jump.regs.read8(Reg8::A),
+            inc.regs.read8(Reg8::A).wrapping_add(1)
+        );
+    }
+
+    #[test]
+    fn runs_switched_work_ram_bank() {
+        let mut cpu = SM83::new();
+        let mut mem = Peripherals::new_fake();
+        mem.skip_boot(Model::Cgb);
+        // DEC A; JR -3 in bank 2, and INC A; JR -3 in bank 1, both at 0xD000.
+        for &(bank, op) in &[(2, 0x3D), (1, 0x3C)] {
+            mem.write(0xFF70, bank);
+            for (offset, &byte) in [op, 0x18, 0xFD].iter().enumerate() {
+                mem.write(0xD000 + offset as u16, byte);
+            }
+        }
+        cpu.set_pc(0xD000);
+        cpu.regs.set8(Reg8::A, 0x80);
+        for _ in 0..100 {
+            cpu.step(&mut mem);
+        }
+        let a = cpu.regs.read8(Reg8::A);
+        assert!(a > 0x80);
+
+        // Once bank 2 is switched in, its code runs instead of the decodes cached from bank 1.
+        mem.write(0xFF70, 2);
+        for _ in 0..100 {
+            cpu.step(&mut mem);
+        }
+        assert!(cpu.regs.read8(Reg8::A) < a);
+    }
+
+    #[test]
+    fn runs_game_genie_patches() {
+        let mut rom = vec![0; 0x8000];
+        // INC A; JR -3.
+        rom[0x150..0x153].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        let mut mem = Peripherals::new_headless(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = SM83::new();
+        cpu.set_pc(0x150);
+        cpu.regs.set8(Reg8::A, 0x80);
+        let run = |cpu: &mut SM83, mem: &mut Peripherals| {
+            let before = cpu.regs.read8(Reg8::A);
+            for _ in 0..100 {
+                cpu.step(mem);
+            }
+            cpu.regs.read8(Reg8::A).wrapping_sub(before) as i8
+        };
+        assert!(run(&mut cpu, &mut mem) > 0);
+
+        // The INC A was decoded before the code turned it into DEC A, and after it's taken off.
+        mem.add_cheat("3D1-50F").unwrap();
+        assert!(run(&mut cpu, &mut mem) < 0);
+        assert!(mem.remove_cheat("3D1-50F"));
+        assert!(run(&mut cpu, &mut mem) > 0);
+    }
 }
@@ -0,0 +1,66 @@
+//! Instruction traces in the format used by Game Boy Doctor
+//! (https://github.com/robert/gameboy-doctor), so runs can be diffed against its reference logs.
+use cpu::registers::{Reg16, Reg8, Registers};
+use cpu::sm83::Executed;
+use peripherals::Peripherals;
+
+/// Formats the state before the instruction at PC runs, along with the four bytes at PC.
+pub fn doctor_line(regs: &Registers, mem: &Peripherals) -> String {
+    let pc = regs.read16(Reg16::PC);
+    let pcmem: Vec<String> = (0..4)
+        .map(|offset| format!("{:02X}", mem.peek(pc.wrapping_add(offset))))
+        .collect();
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        regs.read8(Reg8::A),
+        regs.read16(Reg16::AF) & 0xFF,
+        regs.read8(Reg8::B),
+        regs.read8(Reg8::C),
+        regs.read8(Reg8::D),
+        regs.read8(Reg8::E),
+        regs.read8(Reg8::H),
+        regs.read8(Reg8::L),
+        regs.read16(Reg16::SP),
+        pc,
+        pcmem.join(",")
+    )
+}
+
+/// Formats one of the CPU's recent instructions, with the registers from before it ran.
+pub fn recent_line(executed: &Executed) -> String {
+    let regs = &executed.regs;
+    format!(
+        "0x{:04X}  {:<24} AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X}",
+        executed.pc,
+        executed.op.to_string(),
+        regs.read16(Reg16::AF),
+        regs.read16(Reg16::BC),
+        regs.read16(Reg16::DE),
+        regs.read16(Reg16::HL),
+        regs.read16(Reg16::SP)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doctor_format() {
+        let mut regs = Registers::new();
+        let mut mem = Peripherals::new_fake();
+        regs.set16(Reg16::AF, 0x01B0);
+        regs.set16(Reg16::BC, 0x0013);
+        regs.set16(Reg16::DE, 0x00D8);
+        regs.set16(Reg16::HL, 0x014D);
+        regs.set16(Reg16::SP, 0xFFFE);
+        regs.set16(Reg16::PC, 0xC000);
+        for (offset, val) in [0x00, 0xC3, 0x13, 0x02].iter().enumerate() {
+            mem.write(0xC000 + offset as u16, *val);
+        }
+        assert_eq!(
+            doctor_line(&regs, &mem),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:C000 PCMEM:00,C3,13,02"
+        );
+    }
+}
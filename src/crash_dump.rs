@@ -0,0 +1,159 @@
+//! Crash dumps, written when the emulator panics or the CPU locks up, so the machine can be looked
+//! over in the debugger afterwards. Unlike a save state, a dump doesn't depend on the emulator's
+//! internals, just on what the CPU could see.
+//!
+//! A dump starts with `WWCD` and a format version, then has sections until the end of the file.
+//! Everything is little-endian. Each section is a four byte tag, a u32 length, and that many bytes:
+//!
+//! - `NOTE`: what went wrong, as UTF-8.
+//! - `REGS`: AF, BC, DE, HL, SP, and PC, as u16s.
+//! - `MEM `: a u16 address, then the bytes from there on. There's one for each of `REGIONS`.
+//! - `WBNK`: the work RAM bank mapped at 0xD000, as a u8. Dumps from before it was added don't
+//!   have it.
+//!
+//! Sections with other tags are skipped, so later versions can add them.
+use cpu::registers::Reg16;
+use std::io;
+
+const MAGIC: &[u8; 4] = b"WWCD";
+const VERSION: u32 = 1;
+
+/// The registers in the `REGS` section, in order.
+pub const REGS: [Reg16; 6] = [
+    Reg16::AF,
+    Reg16::BC,
+    Reg16::DE,
+    Reg16::HL,
+    Reg16::SP,
+    Reg16::PC,
+];
+
+/// What a dump keeps of memory, as start addresses and lengths: VRAM, the mapped work RAM, OAM,
+/// the I/O registers, HRAM, and IE.
+pub const REGIONS: [(u16, usize); 6] = [
+    (0x8000, 0x2000),
+    (0xC000, 0x2000),
+    (0xFE00, 0xA0),
+    (0xFF00, 0x80),
+    (0xFF80, 0x7F),
+    (0xFFFF, 1),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct CrashDump {
+    pub note: String,
+    /// In the order of `REGS`.
+    pub regs: [u16; 6],
+    /// Start addresses, and the bytes from there.
+    pub regions: Vec<(u16, Vec<u8>)>,
+    /// The work RAM bank the 0xD000 region came from.
+    pub wram_bank: Option<u8>,
+}
+
+impl CrashDump {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        push_section(&mut bytes, b"NOTE", self.note.as_bytes());
+        let regs: Vec<u8> = self.regs.iter().flat_map(|reg| reg.to_le_bytes()).collect();
+        push_section(&mut bytes, b"REGS", &regs);
+        for (address, data) in &self.regions {
+            let mut region = address.to_le_bytes().to_vec();
+            region.extend_from_slice(data);
+            push_section(&mut bytes, b"MEM ", &region);
+        }
+        if let Some(bank) = self.wram_bank {
+            push_section(&mut bytes, b"WBNK", &[bank]);
+        }
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(invalid("not a crash dump"));
+        }
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported crash dump version {}",
+                version
+            )));
+        }
+        let mut dump = Self {
+            note: String::new(),
+            regs: [0; 6],
+            regions: vec![],
+            wram_bank: None,
+        };
+        let mut regs = false;
+        let mut rest = &bytes[8..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(invalid("truncated section header"));
+            }
+            let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let data = rest
+                .get(8..8 + len)
+                .ok_or_else(|| invalid("truncated section"))?;
+            match &rest[..4] {
+                b"NOTE" => dump.note = String::from_utf8_lossy(data).into_owned(),
+                b"REGS" if data.len() == 12 => {
+                    for (reg, pair) in dump.regs.iter_mut().zip(data.chunks(2)) {
+                        *reg = u16::from_le_bytes([pair[0], pair[1]]);
+                    }
+                    regs = true;
+                }
+                b"REGS" => return Err(invalid("REGS section is the wrong size")),
+                b"MEM " if data.len() >= 2 => {
+                    let address = u16::from_le_bytes([data[0], data[1]]);
+                    if usize::from(address) + data.len() - 2 > 0x10000 {
+                        return Err(invalid("MEM section runs past the end of memory"));
+                    }
+                    dump.regions.push((address, data[2..].to_vec()));
+                }
+                b"MEM " => return Err(invalid("MEM section has no address")),
+                b"WBNK" if data.len() == 1 => dump.wram_bank = Some(data[0]),
+                b"WBNK" => return Err(invalid("WBNK section is the wrong size")),
+                _ => {}
+            }
+            rest = &rest[8 + len..];
+        }
+        if !regs {
+            return Err(invalid("no registers"));
+        }
+        Ok(dump)
+    }
+}
+
+fn push_section(bytes: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(tag);
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dump = CrashDump {
+            note: "CPU locked up at 0x0150".to_string(),
+            regs: [0x01B0, 0x0013, 0x00D8, 0x014D, 0xFFFE, 0x0150],
+            regions: vec![(0xFF80, vec![1, 2, 3]), (0xFFFF, vec![0x1F])],
+            wram_bank: Some(3),
+        };
+        let mut bytes = dump.to_bytes();
+        // Sections that aren't known are skipped.
+        push_section(&mut bytes, b"XTRA", &[0xAA; 5]);
+        assert_eq!(CrashDump::parse(&bytes).unwrap(), dump);
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(CrashDump::parse(&bytes).is_err());
+        assert!(CrashDump::parse(b"WWSS\x01\x00\x00\x00").is_err());
+    }
+}
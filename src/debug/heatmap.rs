@@ -0,0 +1,175 @@
+//! Counts reads and writes to each address, to see which parts of memory a game uses most.
+//! Only the program's own reads count. Instruction fetches, which are mostly answered from the
+//! decode cache rather than memory, and the debugger's reads don't.
+use peripherals::{HookId, Peripherals};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Names, first addresses, and last addresses of the memory map's regions.
+const REGIONS: &[(&str, u16, u16)] = &[
+    ("ROM0", 0x0000, 0x3FFF),
+    ("ROMX", 0x4000, 0x7FFF),
+    ("VRAM", 0x8000, 0x9FFF),
+    ("SRAM", 0xA000, 0xBFFF),
+    ("WRAM", 0xC000, 0xDFFF),
+    ("ECHO", 0xE000, 0xFDFF),
+    ("OAM", 0xFE00, 0xFE9F),
+    ("UNUSED", 0xFEA0, 0xFEFF),
+    ("IO", 0xFF00, 0xFF7F),
+    ("HRAM", 0xFF80, 0xFFFE),
+    ("IE", 0xFFFF, 0xFFFF),
+];
+
+// Regions `save` writes per-address counts for.
+const DETAILED: &[&str] = &["VRAM", "WRAM"];
+
+// Shared with the hooks, which have to be Send.
+struct Counts {
+    reads: Vec<AtomicU64>,
+    writes: Vec<AtomicU64>,
+}
+
+impl Counts {
+    fn new() -> Self {
+        Self {
+            reads: (0..0x10000).map(|_| AtomicU64::new(0)).collect(),
+            writes: (0..0x10000).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn get(&self, address: u16) -> (u64, u64) {
+        let address = usize::from(address);
+        (
+            self.reads[address].load(Ordering::Relaxed),
+            self.writes[address].load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Heatmap {
+    // From the last time counting was started, so they can be reported after it's stopped.
+    counts: Option<Arc<Counts>>,
+    hooks: Vec<HookId>,
+}
+
+impl Heatmap {
+    /// Clears the counts and starts counting.
+    pub fn start(&mut self, peripherals: &mut Peripherals) {
+        self.stop(peripherals);
+        let counts = Arc::new(Counts::new());
+        let reads = counts.clone();
+        self.hooks.push(
+            peripherals.add_read_hook(0x0000..=0xFFFF, move |address, _| {
+                reads.reads[usize::from(address)].fetch_add(1, Ordering::Relaxed);
+            }),
+        );
+        let writes = counts.clone();
+        self.hooks.push(
+            peripherals.add_write_hook(0x0000..=0xFFFF, move |address, _| {
+                writes.writes[usize::from(address)].fetch_add(1, Ordering::Relaxed);
+            }),
+        );
+        self.counts = Some(counts);
+    }
+
+    /// Stops counting, keeping the counts so far.
+    pub fn stop(&mut self, peripherals: &mut Peripherals) {
+        for id in self.hooks.drain(..) {
+            peripherals.remove_hook(id);
+        }
+    }
+
+    /// Totals the reads and writes in each region.
+    pub fn report(&self) -> String {
+        let counts = match self.counts {
+            Some(ref counts) => counts,
+            None => return "Nothing counted yet".to_string(),
+        };
+        let mut lines = vec![format!(
+            "{:<6} {:<13} {:>12} {:>12}",
+            "Region", "", "Reads", "Writes"
+        )];
+        for &(name, start, end) in REGIONS {
+            let (reads, writes) = (start..=end).fold((0, 0), |(reads, writes), address| {
+                let (r, w) = counts.get(address);
+                (reads + r, writes + w)
+            });
+            lines.push(format!(
+                "{:<6} 0x{:04X}-0x{:04X} {:>12} {:>12}",
+                name, start, end, reads, writes
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Writes the counts for each address in VRAM and WRAM that was accessed to `path`, as CSV.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "address,region,reads,writes")?;
+        if let Some(ref counts) = self.counts {
+            for &(name, start, end) in REGIONS.iter().filter(|r| DETAILED.contains(&r.0)) {
+                for address in start..=end {
+                    let (reads, writes) = counts.get(address);
+                    if reads != 0 || writes != 0 {
+                        writeln!(out, "0x{:04X},{},{},{}", address, name, reads, writes)?;
+                    }
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::decode;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn counts_regions() {
+        let mut peripherals = Peripherals::new_fake();
+        let mut heatmap = Heatmap::default();
+        peripherals.write(0xC000, 1);
+        heatmap.start(&mut peripherals);
+        peripherals.write(0xC000, 2);
+        peripherals.write(0xC001, 3);
+        peripherals.read(0xC000);
+        peripherals.read(0xFF80);
+        heatmap.stop(&mut peripherals);
+        peripherals.read(0xC000);
+
+        let report = heatmap.report();
+        assert!(report.contains("WRAM   0xC000-0xDFFF            1            2"));
+        assert!(report.contains("HRAM   0xFF80-0xFFFE            1            0"));
+        assert!(report.contains("VRAM   0x8000-0x9FFF            0            0"));
+
+        let path = env::temp_dir().join(format!("wolfwig_heatmap_{}.csv", process::id()));
+        heatmap.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "address,region,reads,writes\n0xC000,WRAM,1,1\n0xC001,WRAM,0,1\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_fetches_and_peeks() {
+        let mut peripherals = Peripherals::new_fake();
+        let mut heatmap = Heatmap::default();
+        heatmap.start(&mut peripherals);
+        decode::decode(&peripherals, 0xC000);
+        peripherals.peek(0xC000);
+        heatmap.stop(&mut peripherals);
+
+        assert!(heatmap
+            .report()
+            .contains("WRAM   0xC000-0xDFFF            0            0"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Formatting for the `x` command, which dumps memory like gdb's `x/`, and for `xram`.
+use peripherals::Peripherals;
+
+const ROW_BYTES: usize = 16;
+// More than this would only go round the address space again.
+const MAX_BYTES: u32 = 0x10000;
+
+/// Dumps `count` units starting at `start`, 16 bytes to a row with an ASCII gutter. Units are
+/// bytes, or little-endian 16-bit words if `words` is set. At most the whole address space is
+/// dumped, however big `count` is.
+pub fn hexdump(mem: &Peripherals, start: u16, count: u32, words: bool) -> String {
+    let unit = if words { 2 } else { 1 };
+    let len = count
+        .checked_mul(unit)
+        .map_or(MAX_BYTES, |len| len.min(MAX_BYTES));
+    let bytes: Vec<u8> = (0..len)
+        .map(|offset| mem.peek(start.wrapping_add(offset as u16)))
+        .collect();
+    format_rows(&bytes, words, |offset| {
+        format!("0x{:04X}", start.wrapping_add(offset as u16))
+    })
+}
+
+/// Dumps up to `count` bytes of `bytes` from `start`, labelled with their offsets. Used for
+/// memory that isn't all mapped at once, like banked cartridge RAM.
+pub fn hexdump_offsets(bytes: &[u8], start: usize, count: usize) -> String {
+    let end = bytes.len().min(start.saturating_add(count));
+    let bytes = bytes.get(start..end).unwrap_or(&[]);
+    format_rows(bytes, false, |offset| format!("0x{:05X}", start + offset))
+}
+
+fn format_rows(bytes: &[u8], words: bool, label: impl Fn(usize) -> String) -> String {
+    bytes
+        .chunks(ROW_BYTES)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = if words {
+                bytes
+                    .chunks(2)
+                    .map(|word| format!("{:02X}{:02X}", word.get(1).unwrap_or(&0), word[0]))
+                    .collect()
+            } else {
+                bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+            };
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| {
+                    if (0x20..0x7F).contains(&byte) {
+                        char::from(byte)
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            // Pad short rows so that the gutters line up.
+            let width = if words { 39 } else { 47 };
+            format!(
+                "{}: {:<width$} |{}|",
+                label(row * ROW_BYTES),
+                hex.join(" "),
+                ascii,
+                width = width
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_and_words() {
+        let mut mem = Peripherals::new_fake();
+        for (i, byte) in b"Hello, wolfwig!\x00\x01".iter().enumerate() {
+            mem.write(0xC000 + i as u16, *byte);
+        }
+        assert_eq!(
+            hexdump(&mem, 0xC000, 18, false),
+            "0xC000: 48 65 6C 6C 6F 2C 20 77 6F 6C 66 77 69 67 21 00 |Hello, wolfwig!.|\n\
+             0xC010: 01 00                                           |..|"
+        );
+        assert_eq!(
+            hexdump(&mem, 0xC000, 2, true),
+            "0xC000: 6548 6C6C                               |Hell|"
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let mut ram = vec![0; 0x2000];
+        ram[0x1FFF] = b'!';
+        assert_eq!(
+            hexdump_offsets(&ram, 0x1FFE, 16),
+            "0x01FFE: 00 21                                           |.!|"
+        );
+        assert_eq!(hexdump_offsets(&ram, 0x2000, 16), "");
+    }
+
+    #[test]
+    fn huge_counts() {
+        let mem = Peripherals::new_fake();
+        let rows = 0x10000 / ROW_BYTES;
+        assert_eq!(hexdump(&mem, 0, u32::MAX, true).lines().count(), rows);
+        assert_eq!(hexdump(&mem, 0, 0x20000, false).lines().count(), rows);
+    }
+}
@@ -0,0 +1,177 @@
+//! Execution history for stepping backwards. The debugger snapshots the machine every so often,
+//! and logs every instruction it runs. Going back restores the nearest snapshot at or before the
+//! target instruction, and replays forward from there.
+use std::collections::VecDeque;
+use std::io;
+
+use Wolfwig;
+
+// Cycles between snapshots.
+const SNAPSHOT_INTERVAL: u64 = 10_000;
+// Snapshots kept, which limits how far back execution can go.
+const MAX_SNAPSHOTS: usize = 128;
+
+struct Checkpoint {
+    // Instruction the snapshot was taken at.
+    position: u64,
+    cycle: u64,
+    state: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct History {
+    checkpoints: VecDeque<Checkpoint>,
+    // PC and cycle of every instruction since the oldest checkpoint.
+    log: VecDeque<(u16, u64)>,
+    // Position of the first instruction in the log.
+    first: u64,
+    // Position of the current instruction.
+    position: u64,
+    // Cycles run by the debugger.
+    cycle: u64,
+}
+
+impl History {
+    /// Records one cycle of execution. `new_instruction` is whether the PC moved.
+    pub fn cycle(&mut self, wolfwig: &Wolfwig, new_instruction: bool) {
+        self.cycle += 1;
+        if !new_instruction {
+            return;
+        }
+        self.position += 1;
+        let due = self
+            .checkpoints
+            .back()
+            .is_none_or(|last| self.cycle - last.cycle >= SNAPSHOT_INTERVAL);
+        if due {
+            if self.checkpoints.is_empty() {
+                self.log.clear();
+                self.first = self.position;
+            }
+            self.checkpoints.push_back(Checkpoint {
+                position: self.position,
+                cycle: self.cycle,
+                state: wolfwig.snapshot(),
+            });
+            if self.checkpoints.len() > MAX_SNAPSHOTS {
+                self.checkpoints.pop_front();
+                let oldest = self.checkpoints[0].position;
+                self.log.drain(..(oldest - self.first) as usize);
+                self.first = oldest;
+            }
+        }
+        self.log.push_back((wolfwig.pc(), self.cycle));
+    }
+
+    /// Forgets everything, for when the machine state is changed by something that replaying
+    /// wouldn't repeat.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.log.clear();
+    }
+
+    /// Cycles run by the debugger, up to the current instruction.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Number of instructions that can be stepped back over.
+    pub fn available(&self) -> u64 {
+        if self.log.is_empty() {
+            0
+        } else {
+            self.position - self.first
+        }
+    }
+
+    /// How many instructions back the most recent one at a PC matching `hit` is, not counting the
+    /// current instruction.
+    pub fn last_hit<F: Fn(u16) -> bool>(&self, hit: F) -> Option<u64> {
+        self.log
+            .iter()
+            .rev()
+            .skip(1)
+            .position(|&(pc, _)| hit(pc))
+            .map(|back| back as u64 + 1)
+    }
+
+    /// Goes back `count` instructions, or as far as the history allows. Returns how many
+    /// instructions it went back.
+    pub fn rewind(&mut self, wolfwig: &mut Wolfwig, count: u64) -> io::Result<u64> {
+        let count = count.min(self.available());
+        if count == 0 {
+            return Ok(0);
+        }
+        let target = self.position - count;
+        let (pc, cycle) = self.log[(target - self.first) as usize];
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.position <= target)
+            .expect("The oldest checkpoint starts the log");
+        wolfwig.restore(&checkpoint.state)?;
+        wolfwig.set_replaying(true);
+        for _ in checkpoint.cycle..cycle {
+            wolfwig.step();
+        }
+        wolfwig.set_replaying(false);
+        // Replaying can trip watchpoints that already fired.
+        wolfwig.peripherals.take_watch_hit();
+
+        // What came after the target will be recorded again as execution goes forward.
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|checkpoint| checkpoint.position > target)
+        {
+            self.checkpoints.pop_back();
+        }
+        self.log.truncate((target - self.first) as usize + 1);
+        self.position = target;
+        self.cycle = cycle;
+        if wolfwig.pc() != pc {
+            // Most likely input that arrived while running forward, which isn't replayed.
+            return Err(io::Error::other(format!(
+                "Replay diverged: expected PC 0x{:04X}, but it is 0x{:04X}",
+                pc,
+                wolfwig.pc()
+            )));
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use Model;
+
+    #[test]
+    fn replay_sends_nothing_twice() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10B].copy_from_slice(&[
+            0x3E, 0x41, // LD A,0x41
+            0xE0, 0x01, // LDH (0x01),A
+            0x3E, 0x81, // LD A,0x81
+            0xE0, 0x02, // LDH (0x02),A
+            0x00, //       NOP
+            0x18, 0xFD, // JR -3
+        ]);
+        let mut wolfwig = Wolfwig::new_headless(rom, Model::Dmg);
+        let (tx, rx) = mpsc::channel();
+        wolfwig.peripherals.connect_serial_channel(tx);
+        let mut history = History::default();
+        for _ in 0..3000 {
+            let pc = wolfwig.pc();
+            wolfwig.step();
+            history.cycle(&wolfwig, wolfwig.pc() != pc);
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0x41]);
+
+        assert_eq!(history.rewind(&mut wolfwig, 2).unwrap(), 2);
+        assert_eq!(rx.try_iter().count(), 0);
+        assert_eq!(history.cycles(), history.log.back().unwrap().1);
+    }
+}
@@ -2,12 +2,36 @@
 /// is mostly designed for debugging the emulator itself while it's under development.
 use Wolfwig;
 
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
 use cpu::decode;
+use cpu::decode::Op;
 use cpu::registers;
+use peripherals::{Access, Peripherals};
 use std::collections::HashSet;
-use std::io::{stdin, stdout, Write};
 use std::iter::Iterator;
-use std::process;
+use std::path::{Path, PathBuf};
+
+// Where to stop when running until a function returns.
+#[derive(Clone, Copy)]
+enum Until {
+    // Stepping over a call. Stops when execution is back at the instruction after it, with the
+    // stack no deeper than it was, so recursive calls don't stop early.
+    Return { pc: u16, sp: u16 },
+    // Stops after the return that pops the stack above where it was.
+    Finish { sp: u16 },
+}
+
+mod heatmap;
+mod hexdump;
+mod history;
+mod profile;
+mod prompt;
+mod symbols;
+mod tui;
+
+pub use self::tui::Tui;
 
 pub struct Debug {
     wolfwig: Wolfwig,
@@ -18,20 +42,73 @@ pub struct Debug {
     steps: u32,
     breakpoints: HashSet<u16>,
     verbose: bool,
+    // Whether to stop at instructions the CPU doesn't know how to run.
+    break_on_unknown: bool,
     frame: u32,
     wait_for_frame: bool,
+    until: Option<Until>,
+    // Where `x` carries on from if it isn't given an address.
+    examine_next: u16,
+    history: history::History,
+    profile: profile::Profile,
+    heatmap: heatmap::Heatmap,
+    symbols: symbols::Symbols,
+    editor: Editor<prompt::DebugHelper>,
 }
 
 const HELP: &str = "Available commands:
  [n]ext n     -- Runs the next n instructions, default 1 if nothing is provided
+ next-over    -- Like next, but runs a CALL or RST until it returns
+ [fin]ish     -- Runs until the current function returns
+ rs n         -- Steps back n instructions, default 1. Also reverse-step.
+ rc           -- Runs backwards to the last breakpoint. Also reverse-continue.
  [f]rame      -- Runs until the start of the next frame
  [b]reakpoint -- Sets a breakpoint
- [i]nfo       -- lists breakpoins
+ watch a      -- Breaks when the program writes to address a
+ rwatch a     -- Breaks when the program reads from address a
+ unwatch a    -- Removes watchpoints on address a
+ [i]nfo       -- lists breakpoins and watchpoints
+ cheat c      -- `cheat add code` applies a GameShark code (01VVLLHH) every VBlank, or
+                 patches the ROM with a Game Genie code (ABC-DEF-GHI). `cheat remove code`
+                 stops applying it, and `cheat list` lists them.
  [d]elete     -- deletes a breakpoint
  [r]un n      -- Run freely, until breakpoint, n times. Default 1.
+ disas a n    -- Disassembles n instructions starting at address a. Defaults to 10
+                 instructions from the PC.
  [p]rint      -- register name prints specific register, 0xNNNN prints memory address,
                  blank prints all registers.
+ backtrace    -- Shows return addresses of calls and interrupts that haven't returned. Also bt.
+ set r v      -- Sets register r to v. `set [0xNNNN] v` writes v to memory instead.
+ x/nu a       -- Dumps n units of memory starting at address a, where the unit u is b for
+                 bytes or w for 16-bit words. Defaults to 16 bytes after the last dump.
+ xram o n     -- Dumps n bytes of cartridge RAM from offset o, counting across every bank.
+                 Defaults to the first 16 bytes.
+ [o]am n      -- lists all 40 OAM entries, or shows entry n along with its tile.
+ [pal]ettes   -- decodes BGP, OBP0, and OBP1.
+ status       -- Shows the frame, scanline, dot within the line, and total machine cycles.
+ io r         -- Decodes the bitfields of I/O register r (by name or address), or of all of
+                 them.
+ [a]pu        -- shows each audio channel's frequency, volume, and duty.
+ colors c     -- Draws the screen in green or gray.
+ settings c   -- `settings save` saves the colors, speed, and cheats for whenever this game is
+                 opened, and `settings load` applies them again.
+ profile c    -- `profile start` counts executed PCs, `profile stop` pauses, and
+                 `profile report [n]` lists the n hottest addresses, default 20.
+ opcodes c    -- `opcodes start` counts how many times each opcode runs, `opcodes stop`
+                 pauses, and `opcodes report [n]` lists the n most run, or all of them.
+ heatmap c    -- `heatmap start` counts reads and writes to each address, `heatmap stop`
+                 pauses, `heatmap report` totals them for each region, and `heatmap save file`
+                 writes the count for each address in VRAM and WRAM as CSV.
+ symbols file -- Loads labels from a .sym file, used to name addresses in profile reports.
  [v]erbose   -- enable verbose printing of instruction stream
+ break-unknown -- Toggles stopping at opcodes the CPU doesn't know how to execute.
+ [rec]ord     -- `record [file]` starts recording frames (.gif, or raw RGB24 otherwise),
+                 `record stop` stops. Recordings go in the save directory by default.
+ save file    -- Saves the state of the emulator to file, by default the ROM's .state file in
+                 the save directory.
+ load file    -- Restores a state saved with `save`. It has to be from the same ROM.
+ crash file   -- Loads the registers and memory from a crash dump, written when the emulator
+                 panics or the CPU locks up, for looking around in.
  [q]uit       -- quit";
 
 fn to_int32(s: &str) -> Option<u32> {
@@ -56,8 +133,59 @@ fn next_as_int32(iter: &mut Iterator<Item = &str>) -> Option<u32> {
     None
 }
 
+fn to_reg8(s: &str) -> Option<registers::Reg8> {
+    use cpu::registers::Reg8::*;
+    match s {
+        "A" => Some(A),
+        "B" => Some(B),
+        "C" => Some(C),
+        "D" => Some(D),
+        "E" => Some(E),
+        "H" => Some(H),
+        "L" => Some(L),
+        _ => None,
+    }
+}
+
+fn to_reg16(s: &str) -> Option<registers::Reg16> {
+    use cpu::registers::Reg16::*;
+    match s {
+        "AF" => Some(AF),
+        "BC" => Some(BC),
+        "DE" => Some(DE),
+        "HL" => Some(HL),
+        "SP" => Some(SP),
+        "PC" => Some(PC),
+        _ => None,
+    }
+}
+
+/// Decodes `count` instructions starting at `start`, returning each one's address along with a
+/// line showing its bytes and mnemonic.
+fn disassemble(mem: &Peripherals, start: u16, count: usize) -> Vec<(u16, String)> {
+    let mut lines = vec![];
+    let mut addr = start;
+    for _ in 0..count {
+        let (op, size, _) = decode::decode(mem, addr);
+        let bytes: Vec<String> = (0..size as u16)
+            .map(|offset| format!("{:02X}", mem.peek(addr.wrapping_add(offset))))
+            .collect();
+        lines.push((
+            addr,
+            format!("0x{:04X}: {:<9} {}", addr, bytes.join(" "), op),
+        ));
+        match addr.checked_add(size as u16) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+    lines
+}
+
 impl Debug {
     pub fn new(wolfwig: Wolfwig) -> Self {
+        let mut editor = Editor::new();
+        editor.set_helper(Some(prompt::DebugHelper));
         Self {
             wolfwig,
             cycle: 0,
@@ -67,14 +195,72 @@ impl Debug {
             steps: 0,
             breakpoints: HashSet::new(),
             verbose: false,
+            break_on_unknown: false,
             frame: 0,
             wait_for_frame: false,
+            until: None,
+            examine_next: 0,
+            history: history::History::default(),
+            profile: profile::Profile::default(),
+            heatmap: heatmap::Heatmap::default(),
+            symbols: symbols::Symbols::default(),
+            editor,
         }
     }
 
     pub fn step(&mut self) -> u16 {
+        // Drop any hits from the debugger's own reads.
+        self.wolfwig.peripherals.take_watch_hit();
         self.wolfwig.step();
+        if let Some(hit) = self.wolfwig.peripherals.take_watch_hit() {
+            println!("{}", hit);
+            self.stop();
+        }
+        if let Some(pc) = self.wolfwig.take_unknown_op() {
+            if self.wolfwig.locked() {
+                // The PC never moves again, so this is the last chance to stop.
+                println!(
+                    "Locked up on the illegal opcode at 0x{:04X}. Set the PC to continue.",
+                    pc
+                );
+                self.stop();
+                self.prompt();
+            } else if self.break_on_unknown {
+                println!("Could not execute the instruction at 0x{:04X}", pc);
+                self.stop();
+            }
+        }
         self.pc = self.wolfwig.pc();
+        self.history.cycle(&self.wolfwig, self.pc != self.last_pc);
+        if self.pc != self.last_pc {
+            self.profile.record(self.pc);
+            if self.break_on_unknown {
+                if let (Op::Unknown(code), _, _) =
+                    decode::decode(&self.wolfwig.peripherals, self.pc)
+                {
+                    println!("Unknown opcode 0x{:02X} at 0x{:04X}", code, self.pc);
+                    self.stop();
+                }
+            }
+        }
+        if self.pc != self.last_pc {
+            if let Some(until) = self.until {
+                if self.reached(until) || self.breakpoints.contains(&self.pc) {
+                    self.until = None;
+                } else {
+                    if self.verbose {
+                        let (op, _, _) = decode::decode(&self.wolfwig.peripherals, self.pc);
+                        println!(
+                            "PC: 0x{:02X} Cycle: 0x{:04X} Op: {}",
+                            self.pc, self.cycle, op
+                        );
+                    }
+                    self.last_pc = self.pc;
+                    self.cycle += 1;
+                    return self.pc;
+                }
+            }
+        }
         if self.pc != self.last_pc && self.run != 0 {
             if self.breakpoints.contains(&self.pc) {
                 self.run -= 1;
@@ -106,12 +292,139 @@ impl Debug {
         self.pc
     }
 
+    pub fn frame(&self) -> u32 {
+        self.wolfwig.frame()
+    }
+
+    /// Whether the debugger's been quit, or the window closed.
+    pub fn shutting_down(&self) -> bool {
+        self.wolfwig.shutting_down()
+    }
+
+    /// Stops at the prompt when the current instruction is unknown, or the CPU fails to execute
+    /// one.
+    pub fn set_break_on_unknown(&mut self, enabled: bool) {
+        self.break_on_unknown = enabled;
+    }
+
+    // Cancels whatever is running, so the prompt comes up at the next instruction.
+    fn stop(&mut self) {
+        self.run = 0;
+        self.steps = 0;
+        self.wait_for_frame = false;
+        self.until = None;
+    }
+
+    fn reached(&self, until: Until) -> bool {
+        let sp = self.wolfwig.sp();
+        match until {
+            Until::Return { pc, sp: call_sp } => self.pc == pc && sp >= call_sp,
+            Until::Finish { sp: start_sp } => {
+                let (op, _, _) = decode::decode(&self.wolfwig.peripherals, self.last_pc);
+                let returned = matches!(
+                    op,
+                    Op::Return | Op::ReturnAndEnableInterrupts | Op::ConditionalReturn(_)
+                );
+                returned && sp > start_sp
+            }
+        }
+    }
+
+    fn disassemble(&self, start: u16, count: u32) {
+        for (addr, line) in disassemble(&self.wolfwig.peripherals, start, count as usize) {
+            let marker = if addr == self.pc { ">" } else { " " };
+            println!("{} {}", marker, line);
+        }
+    }
+
+    // Goes back `count` instructions, replaying from the nearest snapshot.
+    fn reverse(&mut self, count: u64) {
+        match self.history.rewind(&mut self.wolfwig, count) {
+            Ok(0) => {
+                println!("No recorded history to go back through");
+                return;
+            }
+            Ok(back) if back < count => {
+                println!(
+                    "Went back {} instructions, to the start of the history",
+                    back
+                )
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("{}", err);
+                self.history.clear();
+            }
+        }
+        self.pc = self.wolfwig.pc();
+        self.last_pc = self.pc;
+        self.cycle = self.history.cycles() as usize;
+        self.until = None;
+        let (op, _, _) = decode::decode(&self.wolfwig.peripherals, self.pc);
+        // The cycle's shown as it was when the instruction was first reached, before the step
+        // that reached it was counted.
+        println!(
+            "PC: 0x{:02X} Cycle: 0x{:04X} Op: {}",
+            self.pc,
+            self.cycle.saturating_sub(1),
+            op
+        );
+    }
+
+    // Handles `x/nu a`, where `format` is everything after the `x`.
+    fn examine(&mut self, format: &str, addr: Option<&str>) {
+        let format = format.trim_start_matches('/');
+        let (count, unit) = format.split_at(
+            format
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(format.len()),
+        );
+        // More than the whole address space would only repeat itself.
+        let count = if count.is_empty() {
+            16
+        } else {
+            count.parse::<u32>().unwrap_or(16).min(0x10000)
+        };
+        let words = match unit {
+            "" | "b" => false,
+            "w" => true,
+            _ => {
+                println!("Unknown unit {}, expected b or w", unit);
+                return;
+            }
+        };
+        if let Some(addr) = addr {
+            match to_int32(addr) {
+                Some(addr) if addr <= 0xFFFF => self.examine_next = addr as u16,
+                _ => {
+                    println!("Could not parse address {}", addr);
+                    return;
+                }
+            }
+        }
+        println!(
+            "{}",
+            hexdump::hexdump(&self.wolfwig.peripherals, self.examine_next, count, words)
+        );
+        let unit = if words { 2 } else { 1 };
+        let len = count.checked_mul(unit).unwrap_or(0x10000);
+        self.examine_next = self.examine_next.wrapping_add(len as u16);
+    }
+
     fn prompt(&mut self) {
         loop {
-            let mut buf = String::new();
-            print!("> ");
-            stdout().flush().expect("Could not flush stdout");
-            stdin().read_line(&mut buf).unwrap();
+            let buf = match self.editor.readline("> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => {
+                    self.wolfwig.quit();
+                    return;
+                }
+                Err(err) => panic!("Could not read debugger command: {}", err),
+            };
+            if !buf.trim().is_empty() {
+                self.editor.add_history_entry(buf.as_str());
+            }
             let mut split = buf.trim_end().split(' ');
             match split.next() {
                 Some("r") | Some("run") => {
@@ -128,6 +441,25 @@ impl Debug {
                     };
                     break;
                 }
+                Some("next-over") => {
+                    let (op, size, _) = decode::decode(&self.wolfwig.peripherals, self.pc);
+                    match op {
+                        Op::Call(_) | Op::ConditionalCall(_, _) | Op::Reset(_) => {
+                            self.until = Some(Until::Return {
+                                pc: self.pc.wrapping_add(size as u16),
+                                sp: self.wolfwig.sp(),
+                            });
+                        }
+                        _ => {}
+                    }
+                    break;
+                }
+                Some("fin") | Some("finish") => {
+                    self.until = Some(Until::Finish {
+                        sp: self.wolfwig.sp(),
+                    });
+                    break;
+                }
                 Some("f") | Some("frame") => {
                     self.frame = self.wolfwig.peripherals.ppu.frame + 1;
                     self.wait_for_frame = true;
@@ -143,7 +475,63 @@ impl Debug {
                         self.breakpoints.remove(&(pc as u16));
                     }
                 }
-                Some("i") | Some("info") => println!("{:?}", self.breakpoints),
+                Some("disas") => {
+                    let addr = next_as_int32(&mut split).map_or(self.pc, |addr| addr as u16);
+                    let count = next_as_int32(&mut split).unwrap_or(10);
+                    self.disassemble(addr, count);
+                }
+                Some("watch") => {
+                    if let Some(addr) = next_as_int32(&mut split) {
+                        self.wolfwig.peripherals.watch(Access::Write, addr as u16);
+                    }
+                }
+                Some("rwatch") => {
+                    if let Some(addr) = next_as_int32(&mut split) {
+                        self.wolfwig.peripherals.watch(Access::Read, addr as u16);
+                    }
+                }
+                Some("unwatch") => {
+                    if let Some(addr) = next_as_int32(&mut split) {
+                        self.wolfwig.peripherals.unwatch(addr as u16);
+                    }
+                }
+                Some("cheat") => match (split.next(), split.next()) {
+                    (Some("add"), Some(code)) => {
+                        if let Err(err) = self.wolfwig.peripherals.add_cheat(code) {
+                            println!("{}", err);
+                        }
+                    }
+                    (Some("remove"), Some(code)) => {
+                        if !self.wolfwig.peripherals.remove_cheat(code) {
+                            println!("No cheat {}", code);
+                        }
+                    }
+                    (Some("list"), None) => {
+                        println!("{}", self.wolfwig.peripherals.describe_cheats())
+                    }
+                    _ => println!("Usage: cheat add <code>|remove <code>|list"),
+                },
+                Some("colors") => match split.next().map(str::parse) {
+                    Some(Ok(colors)) => self.wolfwig.set_colors(colors),
+                    Some(Err(err)) => println!("{}", err),
+                    None => println!("Usage: colors green|gray"),
+                },
+                Some("settings") => match split.next() {
+                    Some("save") => match self.wolfwig.save_game_settings() {
+                        Ok(path) => println!("Saved settings to {}", path.display()),
+                        Err(err) => println!("Could not save settings: {}", err),
+                    },
+                    Some("load") => {
+                        if let Err(err) = self.wolfwig.load_game_settings() {
+                            println!("Could not load settings: {}", err);
+                        }
+                    }
+                    _ => println!("Usage: settings save|load"),
+                },
+                Some("i") | Some("info") => {
+                    println!("{:?}", self.breakpoints);
+                    println!("{}", self.wolfwig.peripherals.describe_watches());
+                }
                 Some("h") | Some("help") => println!("{}", HELP),
                 Some("p") | Some("print") => match split.next() {
                     Some("A") => self.wolfwig.print_reg8(registers::Reg8::A),
@@ -161,7 +549,7 @@ impl Debug {
                     Some("PC") => self.wolfwig.print_reg16(registers::Reg16::PC),
                     Some(val) => match to_int32(val) {
                         Some(addr) if addr <= 0xFFFF => {
-                            println!("0x{:02X}", self.wolfwig.peripherals.read(addr as u16))
+                            println!("0x{:02X}", self.wolfwig.peripherals.peek(addr as u16))
                         }
                         Some(addr) => println!("Addr 0x{:X} too large", addr),
                         None => {
@@ -169,11 +557,16 @@ impl Debug {
                             if let (Some(start), Some(end)) =
                                 (next_as_int32(&mut range), next_as_int32(&mut range))
                             {
-                                print!("[");
-                                for addr in start..(end + 1) {
-                                    print!(" 0x{:02X}", self.wolfwig.peripherals.read(addr as u16));
-                                }
-                                println!(" ]");
+                                let count = (end + 1).saturating_sub(start);
+                                println!(
+                                    "{}",
+                                    hexdump::hexdump(
+                                        &self.wolfwig.peripherals,
+                                        start as u16,
+                                        count,
+                                        false
+                                    )
+                                );
                             } else {
                                 println!("Could not parse {}", val);
                             }
@@ -187,8 +580,203 @@ impl Debug {
                         )
                     }
                 },
+                Some("xram") => {
+                    let start = next_as_int32(&mut split).unwrap_or(0) as usize;
+                    let count = next_as_int32(&mut split).unwrap_or(16) as usize;
+                    let ram = self.wolfwig.peripherals.cartridge_ram();
+                    if ram.is_empty() {
+                        println!("The cartridge has no RAM");
+                    } else {
+                        let dirty = self.wolfwig.peripherals.cartridge_ram_dirty();
+                        println!(
+                            "{} KiB{}",
+                            ram.len() / 1024,
+                            if dirty {
+                                ", written since last saved"
+                            } else {
+                                ""
+                            }
+                        );
+                        println!("{}", hexdump::hexdump_offsets(ram, start, count));
+                    }
+                }
+                Some(cmd) if cmd == "x" || cmd.starts_with("x/") => {
+                    self.examine(&cmd[1..], split.next())
+                }
+                Some("rs") | Some("reverse-step") => {
+                    let count = next_as_int32(&mut split).unwrap_or(1);
+                    self.reverse(u64::from(count));
+                }
+                Some("rc") | Some("reverse-continue") => {
+                    let breakpoints = &self.breakpoints;
+                    match self.history.last_hit(|pc| breakpoints.contains(&pc)) {
+                        Some(count) => self.reverse(count),
+                        None => {
+                            println!("No breakpoint was hit in the recorded history");
+                            let count = self.history.available();
+                            self.reverse(count);
+                        }
+                    }
+                }
+                Some("profile") => match split.next() {
+                    Some("start") => {
+                        self.profile.start();
+                        println!("Profiling started");
+                    }
+                    Some("stop") => {
+                        self.profile.running = false;
+                        println!("Profiling stopped");
+                    }
+                    Some("report") => {
+                        let count = next_as_int32(&mut split).unwrap_or(20);
+                        println!("{}", self.profile.report(&self.symbols, count as usize));
+                    }
+                    _ => println!("Usage: profile start|stop|report [n]"),
+                },
+                Some("opcodes") => match split.next() {
+                    Some("start") => {
+                        self.wolfwig.start_counting_opcodes();
+                        println!("Counting opcodes");
+                    }
+                    Some("stop") => {
+                        self.wolfwig.stop_counting_opcodes();
+                        println!("Stopped counting opcodes");
+                    }
+                    Some("report") => {
+                        let count = next_as_int32(&mut split).map(|count| count as usize);
+                        match self.wolfwig.opcode_report(count) {
+                            Some(report) => println!("{}", report),
+                            None => println!("Opcodes aren't being counted"),
+                        }
+                    }
+                    _ => println!("Usage: opcodes start|stop|report [n]"),
+                },
+                Some("heatmap") => match split.next() {
+                    Some("start") => {
+                        self.heatmap.start(&mut self.wolfwig.peripherals);
+                        println!("Counting memory accesses");
+                    }
+                    Some("stop") => {
+                        self.heatmap.stop(&mut self.wolfwig.peripherals);
+                        println!("Stopped counting memory accesses");
+                    }
+                    Some("report") => println!("{}", self.heatmap.report()),
+                    Some("save") => match split.next() {
+                        Some(path) => match self.heatmap.save(Path::new(path)) {
+                            Ok(()) => println!("Wrote the heatmap to {}", path),
+                            Err(err) => println!("Could not write {}: {}", path, err),
+                        },
+                        None => println!("Usage: heatmap save file"),
+                    },
+                    _ => println!("Usage: heatmap start|stop|report|save file"),
+                },
+                Some("symbols") => match split.next() {
+                    Some(file) => match symbols::Symbols::load(Path::new(file)) {
+                        Ok(symbols) => {
+                            println!("Loaded {} symbols from {}", symbols.count(), file);
+                            self.symbols = symbols;
+                        }
+                        Err(err) => println!("Could not load symbols from {}: {}", file, err),
+                    },
+                    None => println!("Usage: symbols <file>"),
+                },
+                Some("bt") | Some("backtrace") => self.wolfwig.print_backtrace(),
+                Some("set") => match (split.next(), next_as_int32(&mut split)) {
+                    (Some(dest), Some(val)) => {
+                        // Replaying history wouldn't repeat the change.
+                        self.history.clear();
+                        if let Some(reg) = to_reg8(dest) {
+                            self.wolfwig.set_reg8(reg, val as u8);
+                        } else if let Some(reg) = to_reg16(dest) {
+                            self.wolfwig.set_reg16(reg, val as u16);
+                            if let registers::Reg16::PC = reg {
+                                self.pc = val as u16;
+                            }
+                        } else if dest.starts_with('[') && dest.ends_with(']') {
+                            match to_int32(&dest[1..dest.len() - 1]) {
+                                Some(addr) if addr <= 0xFFFF => {
+                                    self.wolfwig.write(addr as u16, val as u8)
+                                }
+                                _ => println!("Could not parse address {}", dest),
+                            }
+                        } else {
+                            println!("Unknown register {}", dest);
+                        }
+                    }
+                    _ => println!("Usage: set <register or [address]> <value>"),
+                },
+                Some("o") | Some("oam") => match next_as_int32(&mut split) {
+                    Some(index) => self.wolfwig.print_sprite(index as usize),
+                    None => self.wolfwig.print_oam(),
+                },
+                Some("pal") | Some("palettes") => self.wolfwig.print_palettes(),
+                Some("status") => self.wolfwig.print_status(),
+                Some("io") => self.wolfwig.print_io(split.next()),
+                Some("a") | Some("apu") => self.wolfwig.print_apu(),
                 Some("v") | Some("verbose") => self.verbose = !self.verbose,
-                Some("q") | Some("quit") => process::exit(0),
+                Some("break-unknown") => {
+                    self.break_on_unknown = !self.break_on_unknown;
+                    println!(
+                        "Breaking on unknown opcodes {}",
+                        if self.break_on_unknown { "on" } else { "off" }
+                    );
+                }
+                Some("rec") | Some("record") => match split.next() {
+                    Some("stop") => self.wolfwig.stop_recording(),
+                    file => {
+                        let path = file
+                            .map(PathBuf::from)
+                            .unwrap_or_else(|| self.wolfwig.capture_path("gif"));
+                        if let Err(err) = self.wolfwig.start_recording(&path) {
+                            println!("Could not record to {}: {}", path.display(), err);
+                        } else {
+                            println!("Recording to {}", path.display());
+                        }
+                    }
+                },
+                Some("save") => {
+                    let path = split
+                        .next()
+                        .map(PathBuf::from)
+                        .unwrap_or_else(|| self.wolfwig.save_path("state"));
+                    if let Err(err) = self.wolfwig.save_state(&path) {
+                        println!("Could not save state to {}: {}", path.display(), err);
+                    } else {
+                        println!("Saved state to {}", path.display());
+                    }
+                }
+                Some("crash") => match split.next() {
+                    Some(path) => match self.wolfwig.load_crash_dump(Path::new(path)) {
+                        Ok(note) => {
+                            self.pc = self.wolfwig.pc();
+                            self.last_pc = self.pc;
+                            self.until = None;
+                            self.history.clear();
+                            println!("Loaded crash dump: {}", note);
+                        }
+                        Err(err) => println!("Could not load crash dump {}: {}", path, err),
+                    },
+                    None => println!("Usage: crash file"),
+                },
+                Some("load") => {
+                    let path = split
+                        .next()
+                        .map(PathBuf::from)
+                        .unwrap_or_else(|| self.wolfwig.save_path("state"));
+                    if let Err(err) = self.wolfwig.load_state(&path) {
+                        println!("Could not load state from {}: {}", path.display(), err);
+                    } else {
+                        self.pc = self.wolfwig.pc();
+                        self.last_pc = self.pc;
+                        self.until = None;
+                        self.history.clear();
+                        println!("Loaded state from {}", path.display());
+                    }
+                }
+                Some("q") | Some("quit") => {
+                    self.wolfwig.quit();
+                    return;
+                }
                 cmd => println!(
                     "Unrecognized command: {:?}. Type 'help' for valid comamnds",
                     cmd
@@ -0,0 +1,70 @@
+//! Counts how often each PC is executed, to find where the program spends its time.
+use std::collections::HashMap;
+
+use super::symbols::Symbols;
+
+#[derive(Default)]
+pub struct Profile {
+    counts: HashMap<u16, u64>,
+    total: u64,
+    pub running: bool,
+}
+
+impl Profile {
+    /// Clears the counts and starts counting.
+    pub fn start(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+        self.running = true;
+    }
+
+    pub fn record(&mut self, pc: u16) {
+        if self.running {
+            *self.counts.entry(pc).or_insert(0) += 1;
+            self.total += 1;
+        }
+    }
+
+    /// Lists the `count` most executed addresses, most executed first.
+    pub fn report(&self, symbols: &Symbols, count: usize) -> String {
+        if self.total == 0 {
+            return "No instructions profiled".to_string();
+        }
+        let mut hottest: Vec<(u16, u64)> = self.counts.iter().map(|(&pc, &n)| (pc, n)).collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut lines = vec![format!("{} instructions profiled", self.total)];
+        for (pc, n) in hottest.into_iter().take(count) {
+            let percent = n as f64 * 100.0 / self.total as f64;
+            let mut line = format!("0x{:04X} {:>10} {:>6.2}%", pc, n, percent);
+            if let Some(name) = symbols.describe(pc) {
+                line.push_str(&format!("  {}", name));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_hottest_first() {
+        let mut profile = Profile::default();
+        profile.record(0x0100);
+        profile.start();
+        for &pc in &[0x0150, 0x0200, 0x0200, 0x0201, 0x0200] {
+            profile.record(pc);
+        }
+        profile.running = false;
+        profile.record(0x0150);
+        let symbols = Symbols::parse("00:0200 Loop");
+        assert_eq!(
+            profile.report(&symbols, 2),
+            "5 instructions profiled\n\
+             0x0200          3  60.00%  Loop\n\
+             0x0150          1  20.00%"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Line editing for the debugger prompt: history, Ctrl-R search, and tab completion of command
+//! and register names.
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+const COMMANDS: &[&str] = &[
+    "a",
+    "apu",
+    "b",
+    "backtrace",
+    "break-unknown",
+    "breakpoint",
+    "bt",
+    "cheat",
+    "colors",
+    "crash",
+    "d",
+    "delete",
+    "disas",
+    "f",
+    "fin",
+    "finish",
+    "frame",
+    "heatmap",
+    "help",
+    "i",
+    "info",
+    "io",
+    "load",
+    "n",
+    "next",
+    "next-over",
+    "o",
+    "oam",
+    "opcodes",
+    "p",
+    "pal",
+    "palettes",
+    "print",
+    "profile",
+    "q",
+    "quit",
+    "r",
+    "rc",
+    "rec",
+    "record",
+    "reverse-continue",
+    "reverse-step",
+    "rs",
+    "run",
+    "rwatch",
+    "save",
+    "set",
+    "settings",
+    "status",
+    "symbols",
+    "unwatch",
+    "v",
+    "verbose",
+    "watch",
+    "x",
+    "xram",
+];
+
+const REGISTERS: &[&str] = &[
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "PC",
+];
+
+pub struct DebugHelper;
+
+impl Completer for DebugHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |space| space + 1);
+        let word = &line[start..pos];
+        // The first word is a command, and anything after it might be a register.
+        let names = if start == 0 { COMMANDS } else { REGISTERS };
+        let candidates = names
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .map(|name| name.to_string())
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for DebugHelper {
+    type Hint = String;
+}
+
+impl Highlighter for DebugHelper {}
+
+impl Validator for DebugHelper {}
+
+impl Helper for DebugHelper {}
@@ -0,0 +1,74 @@
+//! Symbols from the `.sym` files that RGBDS and other assemblers write, with one `bank:address
+//! name` label per line. Banks are ignored, so labels in different switchable banks can collide,
+//! and the first one loaded wins.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses a symbol file, skipping comments and lines that aren't labels.
+    pub fn parse(text: &str) -> Self {
+        let mut labels = BTreeMap::new();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let (location, name) = match (fields.next(), fields.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue,
+            };
+            let addr = match location.split(':').nth(1) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                labels.entry(addr).or_insert_with(|| name.to_string());
+            }
+        }
+        Self { labels }
+    }
+
+    pub fn count(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Names an address by the closest label at or before it, like `Main+0x12`.
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (&label_addr, name) = self.labels.range(..=addr).next_back()?;
+        if label_addr == addr {
+            Some(name.clone())
+        } else {
+            Some(format!("{}+0x{:X}", name, addr - label_addr))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_describe() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             00:0150 Start\n\
+             00:0200 Main ; the main loop\n\
+             01:4000 Banked\n\
+             not a label\n",
+        );
+        assert_eq!(symbols.count(), 3);
+        assert_eq!(symbols.describe(0x0100), None);
+        assert_eq!(symbols.describe(0x0150), Some("Start".to_string()));
+        assert_eq!(symbols.describe(0x0212), Some("Main+0x12".to_string()));
+        assert_eq!(symbols.describe(0x4001), Some("Banked+0x1".to_string()));
+    }
+}
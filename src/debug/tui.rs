@@ -0,0 +1,297 @@
+//! A full-screen terminal frontend for the debugger, with panes for the code around the PC, the
+//! registers, a memory dump, and breakpoints that update as the emulator runs.
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, stdout, Stdout};
+use std::time::Duration;
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph};
+use tui::{Frame, Terminal};
+
+use cpu::registers::Reg16;
+use peripherals::Peripherals;
+use Wolfwig;
+
+// Number of recently executed instructions shown above the PC.
+const HISTORY: usize = 4;
+
+const KEYS: &str = "s: step  c: continue/stop  b: breakpoint at PC  up/down/pgup/pgdn: memory  \
+                    q: quit";
+
+type Backend = CrosstermBackend<Stdout>;
+
+pub struct Tui {
+    wolfwig: Wolfwig,
+    terminal: Terminal<Backend>,
+    breakpoints: BTreeSet<u16>,
+    running: bool,
+    // Address at the top of the memory pane.
+    memory_base: u16,
+    // PCs of the last few instructions, oldest first.
+    history: VecDeque<u16>,
+    last_pc: u16,
+    message: String,
+}
+
+impl Tui {
+    /// Takes over the terminal until this is dropped.
+    pub fn new(wolfwig: Wolfwig) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        out.execute(EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(out))?;
+        let last_pc = wolfwig.pc();
+        Ok(Self {
+            wolfwig,
+            terminal,
+            breakpoints: BTreeSet::new(),
+            running: false,
+            memory_base: 0xC000,
+            history: VecDeque::new(),
+            last_pc,
+            message: String::new(),
+        })
+    }
+
+    /// Runs until the user quits.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            if self.wolfwig.shutting_down() {
+                return Ok(());
+            }
+            if self.running {
+                self.run_frame();
+                self.draw()?;
+                if event::poll(Duration::from_millis(0))? && !self.handle(event::read()?) {
+                    return Ok(());
+                }
+            } else {
+                self.draw()?;
+                if !self.handle(event::read()?) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Returns false once the user quits.
+    fn handle(&mut self, event: Event) -> bool {
+        let code = match event {
+            Event::Key(key) => key.code,
+            _ => return true,
+        };
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('s') => {
+                self.running = false;
+                self.step_instruction();
+            }
+            KeyCode::Char('c') => {
+                self.running = !self.running;
+                self.message.clear();
+            }
+            KeyCode::Char('b') => {
+                let pc = self.wolfwig.pc();
+                if !self.breakpoints.remove(&pc) {
+                    self.breakpoints.insert(pc);
+                }
+            }
+            KeyCode::Up => self.memory_base = self.memory_base.wrapping_sub(0x10),
+            KeyCode::Down => self.memory_base = self.memory_base.wrapping_add(0x10),
+            KeyCode::PageUp => self.memory_base = self.memory_base.wrapping_sub(0x100),
+            KeyCode::PageDown => self.memory_base = self.memory_base.wrapping_add(0x100),
+            _ => {}
+        }
+        true
+    }
+
+    // Runs one cycle. Returns true if the PC moved onto a new instruction.
+    fn step_cycle(&mut self) -> bool {
+        // Drop any hits from the debugger's own reads.
+        self.wolfwig.peripherals.take_watch_hit();
+        self.wolfwig.step();
+        if let Some(hit) = self.wolfwig.peripherals.take_watch_hit() {
+            self.message = hit.to_string();
+            self.running = false;
+        }
+        if let Some(pc) = self.wolfwig.take_unknown_op() {
+            if self.wolfwig.locked() {
+                self.message = format!("Locked up on the illegal opcode at 0x{:04X}", pc);
+                self.running = false;
+            }
+        }
+        let pc = self.wolfwig.pc();
+        if pc == self.last_pc {
+            return false;
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.last_pc);
+        self.last_pc = pc;
+        true
+    }
+
+    fn step_instruction(&mut self) {
+        // A halted CPU never moves on, so give up after a frame.
+        for _ in 0..Peripherals::CYCLES_PER_FRAME {
+            if self.step_cycle() {
+                return;
+            }
+        }
+    }
+
+    // Runs until the end of the frame or a breakpoint. Also stops after a frame's worth of
+    // cycles, since frames don't end while the LCD is off.
+    fn run_frame(&mut self) {
+        let frame = self.wolfwig.frame();
+        for _ in 0..Peripherals::CYCLES_PER_FRAME {
+            if self.step_cycle() && self.breakpoints.contains(&self.last_pc) {
+                self.message = format!("Breakpoint at 0x{:04X}", self.last_pc);
+                self.running = false;
+            }
+            if !self.running || self.wolfwig.frame() != frame {
+                return;
+            }
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let code = self.code_lines();
+        let registers = self.register_lines();
+        let breakpoints: Vec<Spans> = self
+            .breakpoints
+            .iter()
+            .map(|addr| Spans::from(format!("0x{:04X}", addr)))
+            .collect();
+        let status = if self.running { "Running" } else { "Stopped" };
+        let status = format!("{}. {}  {}", status, self.message, KEYS);
+        let memory_base = self.memory_base;
+        let mem = &self.wolfwig.peripherals;
+        self.terminal.draw(|f| {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(10), Constraint::Length(1)].as_ref())
+                .split(f.size());
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(40), Constraint::Min(60)].as_ref())
+                .split(rows[0]);
+            let right = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(8), Constraint::Min(4)].as_ref())
+                .split(columns[1]);
+            let top_right = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(30), Constraint::Length(16)].as_ref())
+                .split(right[0]);
+
+            pane(f, columns[0], "Code", code);
+            pane(f, top_right[0], "Registers", registers);
+            pane(f, top_right[1], "Breakpoints", breakpoints);
+            let lines = right[1].height.saturating_sub(2);
+            pane(f, right[1], "Memory", memory_lines(mem, memory_base, lines));
+            f.render_widget(Paragraph::new(status), rows[1]);
+        })?;
+        Ok(())
+    }
+
+    // Recently run instructions, then the PC, then as many upcoming instructions as fit.
+    fn code_lines(&self) -> Vec<Spans<'static>> {
+        let mem = &self.wolfwig.peripherals;
+        let mut lines: Vec<Spans> = self
+            .history
+            .iter()
+            .map(|&addr| {
+                let (_, line) = super::disassemble(mem, addr, 1).remove(0);
+                Spans::from(Span::styled(
+                    format!("  {}", line),
+                    Style::default().fg(Color::DarkGray),
+                ))
+            })
+            .collect();
+        let height = self.terminal.size().map_or(24, |size| size.height) as usize;
+        let pc = self.wolfwig.pc();
+        for (addr, line) in super::disassemble(mem, pc, height) {
+            let marker = if self.breakpoints.contains(&addr) {
+                "*"
+            } else {
+                " "
+            };
+            let style = if addr == pc {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            lines.push(Spans::from(Span::styled(
+                format!("{} {}", marker, line),
+                style,
+            )));
+        }
+        lines
+    }
+
+    fn register_lines(&self) -> Vec<Spans<'static>> {
+        let af = self.wolfwig.reg16(Reg16::AF);
+        let flags: String = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+            .iter()
+            .map(|&(bit, name)| if af & (1 << bit) != 0 { name } else { '-' })
+            .collect();
+        vec![
+            Spans::from(format!(
+                "AF 0x{:04X}  BC 0x{:04X}",
+                af,
+                self.wolfwig.reg16(Reg16::BC)
+            )),
+            Spans::from(format!(
+                "DE 0x{:04X}  HL 0x{:04X}",
+                self.wolfwig.reg16(Reg16::DE),
+                self.wolfwig.reg16(Reg16::HL)
+            )),
+            Spans::from(format!(
+                "SP 0x{:04X}  PC 0x{:04X}",
+                self.wolfwig.sp(),
+                self.wolfwig.pc()
+            )),
+            Spans::from(format!("Flags {}", flags)),
+            Spans::from(format!(
+                "Frame {}  LY {}",
+                self.wolfwig.frame(),
+                self.wolfwig.peripherals.read(0xFF44)
+            )),
+        ]
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        // Nothing else can be done if the terminal can't be restored.
+        let _ = terminal::disable_raw_mode();
+        let _ = self.terminal.backend_mut().execute(LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+fn pane(f: &mut Frame<Backend>, area: Rect, title: &str, lines: Vec<Spans>) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title.to_string());
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn memory_lines(mem: &Peripherals, base: u16, count: u16) -> Vec<Spans<'static>> {
+    (0..count)
+        .map(|line| {
+            let addr = base.wrapping_add(line * 0x10);
+            let bytes: Vec<String> = (0..0x10)
+                .map(|offset| format!("{:02X}", mem.read(addr.wrapping_add(offset))))
+                .collect();
+            Spans::from(format!("0x{:04X}: {}", addr, bytes.join(" ")))
+        })
+        .collect()
+}
@@ -1,51 +1,681 @@
 #[macro_use]
-extern crate log;
-#[macro_use]
 extern crate bitflags;
+#[macro_use]
+extern crate tracing;
 
+extern crate crossterm;
+extern crate gif;
+extern crate png;
+extern crate rustyline;
 extern crate sdl2;
+extern crate tui;
 
-use std::io::{self, stdout, Write};
-use std::path::Path;
+use savestate::Snapshot;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, stdout, LineWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::thread;
 
 pub mod debug;
 
 mod cpu;
+mod crash_dump;
+mod model;
 mod peripherals;
+mod replay;
+mod savestate;
+mod settings;
 mod util;
 
+pub use cpu::bus::Bus;
+pub use cpu::registers::{Flag, Reg16, Reg8, Registers};
+pub use cpu::sm83::{CallFrame, Executed, SM83};
+pub use cpu::Cpu;
+pub use model::Model;
+pub use peripherals::{
+    Clock, Colors, CycleClock, FrameStats, FrameTimeSummary, Frontend, MockClock, RealClock,
+};
+pub use replay::Divergence;
+pub use savestate::Metadata as StateInfo;
+pub use savestate::{Reader as StateReader, Writer as StateWriter};
+
+/// Options that have to be known when the emulator is constructed, since they change how the
+/// frontend is set up.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Present frames on the display's vsync. Gives smoother output on 60Hz displays, though
+    /// emulation still runs at the real 59.7Hz, so a frame is occasionally shown twice.
+    pub vsync: bool,
+    /// Open a second window showing the whole background map, for debugging scrolling.
+    pub bg_map_viewer: bool,
+    /// Open a window with an oscilloscope for each audio channel.
+    pub apu_viewer: bool,
+    /// Don't open an audio device. The sound registers still work, but nothing is played.
+    pub no_audio: bool,
+    /// Show the FPS and emulation speed in the window title.
+    pub show_speed: bool,
+    /// Model whose post-boot state is set up when there's no boot ROM to run.
+    pub model: Model,
+    /// Refuse to load ROMs with a bad header checksum, rather than warning about them.
+    pub strict: bool,
+    /// Where .sav files, save states, and captures go. It's created if it doesn't exist. Empty
+    /// means the working directory.
+    pub save_dir: PathBuf,
+    /// Save a state when the window is closed, and pick up from it the next time the same ROM is
+    /// opened.
+    pub auto_save: bool,
+}
+
+/// Where files the emulator writes go by default: the platform's per-user data directory, or
+/// the working directory if that can't be found.
+pub fn default_save_dir() -> PathBuf {
+    let var = |name| {
+        env::var_os(name)
+            .filter(|val| !val.is_empty())
+            .map(PathBuf::from)
+    };
+    let data_dir = if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    };
+    data_dir.map_or_else(PathBuf::new, |dir| dir.join("wolfwig"))
+}
+
+/// Number of save state slots. The F1-F10 hotkeys save to slots 1-9 and 0, or load with shift.
+pub const SAVE_SLOTS: u8 = 10;
+
+fn check_slot(slot: u8) -> Result<(), io::Error> {
+    if slot < SAVE_SLOTS {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No save slot {}, they go up to {}", slot, SAVE_SLOTS - 1),
+        ))
+    }
+}
+
+/// Describes a ROM from its header: the cartridge type and whether it's supported, and whether
+/// the sizes and checksum in the header match the file. Doesn't open a window.
+pub fn rom_info(rom: &Path) -> Result<String, io::Error> {
+    peripherals::rom_info(rom)
+}
+
+/// How fast to run, relative to the real hardware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    /// A whole multiple of the real speed. `Times(1)` is normal speed.
+    Times(u32),
+    /// As fast as the host can go.
+    Uncapped,
+}
+
+impl Speed {
+    /// Parses a multiplier from the command line, where 0 means uncapped.
+    pub fn from_multiplier(multiplier: u32) -> Self {
+        match multiplier {
+            0 => Speed::Uncapped,
+            n => Speed::Times(n),
+        }
+    }
+}
+
+/// Where bytes sent out of the serial port go.
+pub enum SerialSink {
+    /// Printed to stdout as characters.
+    Stdout,
+    /// Written to a file, which is created or truncated.
+    File(PathBuf),
+    /// Passed to a callback, which runs on its own thread.
+    Callback(Box<dyn FnMut(u8) + Send>),
+}
+
 ///! Wolfwig is the main object in the emulator that owns everything.
 ///! TODO(slongfield): Write some actual documentation.
 pub struct Wolfwig {
     pub peripherals: peripherals::Peripherals,
-    cpu: cpu::sm83::SM83,
+    cpu: Box<dyn Cpu>,
+    state_hashes: Option<replay::StateHashes>,
+    // Frame that state hashes were last taken at.
+    hashed_frame: u32,
+    divergence: Option<Divergence>,
+    // Where to write a Game Boy Doctor trace, and whether it's started. It starts at 0x0100,
+    // once the boot ROM is done, since that's where the reference logs start.
+    doctor_trace: Option<LineWriter<File>>,
+    doctor_started: bool,
+    io_log: Option<peripherals::IoLog>,
+    opcode_counts: Option<cpu::opcode_counts::OpcodeCounts>,
+    // Whether frame times go in the end-of-run report.
+    report_frame_stats: bool,
+    // Whether to save a state to resume from on shutdown.
+    auto_save: bool,
+    // Set once something other than the window, like the debugger, asks to shut down.
+    quit: bool,
+    // Set while the debugger replays history.
+    replaying: bool,
 }
 
 impl Wolfwig {
-    pub fn from_files(bootrom: &Path, rom: &Path) -> Result<Self, io::Error> {
-        let peripherals = peripherals::Peripherals::from_files(bootrom, rom)?;
+    /// Without a boot ROM, starts at the cartridge's entry point in the state `options.model`'s
+    /// boot ROM would have left. The frontend, which polls input and shows frames, has to be run on
+    /// this thread, while the emulator can be sent to another. Without a display, it sets
+    /// `SDL_VIDEODRIVER` to SDL's dummy driver for the rest of the process.
+    pub fn from_files(
+        bootrom: Option<&Path>,
+        rom: &Path,
+        options: &Options,
+    ) -> Result<(Self, Frontend), io::Error> {
+        let (peripherals, frontend) = peripherals::Peripherals::from_files(bootrom, rom, options)?;
+        let mut wolfwig = Self::new(peripherals);
+        if bootrom.is_none() {
+            wolfwig.skip_boot(options.model);
+        }
+        if let Err(err) = wolfwig.load_game_settings() {
+            warn!(
+                "Could not apply {}: {}",
+                wolfwig.peripherals.settings_path().display(),
+                err
+            );
+        }
+        if options.auto_save {
+            wolfwig.auto_save = true;
+            // A state that can't be loaded, say from an older version, shouldn't stop the game.
+            if let Err(err) = wolfwig.load_auto_save() {
+                warn!("{}", err);
+            }
+        }
+        Ok((wolfwig, frontend))
+    }
+
+    /// Runs a ROM without a window, sound, or input, starting in the state `model`'s boot ROM
+    /// would have left. Meant for running test ROMs, whose results come out of the serial port.
+    pub fn new_headless(rom: Vec<u8>, model: Model) -> Self {
+        let mut wolfwig = Self::new(peripherals::Peripherals::new_headless(rom));
+        wolfwig.skip_boot(model);
+        wolfwig
+    }
 
-        Ok(Self {
+    /// Swaps in another CPU core, which carries on from the registers the current one has. What
+    /// the old core was part way through is dropped.
+    pub fn with_cpu(mut self, mut cpu: Box<dyn Cpu>) -> Self {
+        *cpu.regs_mut() = *self.cpu.regs();
+        cpu.set_pc(self.cpu.pc());
+        self.cpu = cpu;
+        self
+    }
+
+    fn new(peripherals: peripherals::Peripherals) -> Self {
+        Self {
             peripherals,
-            cpu: cpu::sm83::SM83::new(),
-        })
+            cpu: Box::new(cpu::sm83::SM83::new()),
+            state_hashes: None,
+            hashed_frame: 0,
+            divergence: None,
+            doctor_trace: None,
+            doctor_started: false,
+            io_log: None,
+            opcode_counts: None,
+            report_frame_stats: false,
+            auto_save: false,
+            quit: false,
+            replaying: false,
+        }
+    }
+
+    fn skip_boot(&mut self, model: Model) {
+        self.peripherals.skip_boot(model);
+        let header_checksum = self.peripherals.read(0x014D);
+        let registers = model.registers(header_checksum);
+        for (reg, &val) in [
+            cpu::registers::Reg16::AF,
+            cpu::registers::Reg16::BC,
+            cpu::registers::Reg16::DE,
+            cpu::registers::Reg16::HL,
+        ]
+        .iter()
+        .zip(registers.iter())
+        {
+            self.cpu.regs_mut().set16(*reg, val);
+        }
+        self.cpu.regs_mut().set16(cpu::registers::Reg16::SP, 0xFFFE);
+        self.cpu.set_pc(0x0100);
     }
 
     pub fn step(&mut self) -> bool {
+        if self.shutting_down() {
+            return false;
+        }
+        if let Some(action) = self.peripherals.take_slot_action() {
+            self.run_slot_action(action);
+        }
+        if self.peripherals.paused() {
+            self.peripherals.step_idle();
+            return false;
+        }
+        if self.cpu.stopped() {
+            // The system clock is halted in STOP, so only input is polled until a button wakes
+            // the CPU back up.
+            self.peripherals.step_idle();
+            return self.cpu.step(&mut self.peripherals);
+        }
         self.peripherals.step();
-        self.cpu.step(&mut self.peripherals)
+        if self.doctor_trace.is_some() && self.cpu.at_instruction() && !self.replaying {
+            self.trace_instruction();
+        }
+        if let Some(ref mut counts) = self.opcode_counts {
+            if self.cpu.at_instruction() && !self.replaying {
+                counts.record(&self.peripherals, self.cpu.pc());
+            }
+        }
+        let pc = self.pc();
+        let stopped = self.cpu.step(&mut self.peripherals);
+        if let Some(ref mut log) = self.io_log {
+            if self.replaying {
+                log.discard();
+            } else if let Err(err) = log.flush(&self.peripherals, pc) {
+                error!("Could not write I/O log: {}", err);
+                self.io_log = None;
+            }
+        }
+        if self.state_hashes.is_some() && self.frame() != self.hashed_frame && !self.replaying {
+            self.check_state_hash();
+        }
+        stopped
+    }
+
+    /// Runs as normal, but without output that's already happened being repeated: traces, logs,
+    /// and state hashes are left alone, and so is everything `Peripherals::set_muted` covers.
+    /// Meant for the debugger going back over history it's already run.
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+        self.peripherals.set_muted(replaying);
+    }
+
+    /// Stops the emulator, as closing the window does. Whatever's running it should stop once
+    /// `shutting_down` is set, and drop it.
+    pub fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    /// Whether the window's been closed or `quit` called. Steps do nothing from then on.
+    pub fn shutting_down(&self) -> bool {
+        self.quit || self.peripherals.shutdown_requested()
+    }
+
+    /// Prints the end-of-run report, and writes out the .sav file and the state to resume from
+    /// with auto-save. Every way out of the emulator comes through here, when it's dropped.
+    fn shut_down(&mut self) {
+        if let Some(report) = self.end_of_run_report() {
+            println!("{}", report);
+        }
+        if let Err(err) = self.peripherals.flush_battery() {
+            error!("Could not save cartridge RAM: {}", err);
+        }
+        if self.auto_save {
+            let path = self.auto_save_path();
+            if let Err(err) = self.save_state(&path) {
+                error!("Could not save state to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Where auto-save keeps the state to pick up from next time.
+    pub fn auto_save_path(&self) -> PathBuf {
+        self.save_path("resume")
+    }
+
+    /// Loads the state auto-save wrote when this ROM was last closed. Returns whether there was
+    /// one.
+    pub fn load_auto_save(&mut self) -> Result<bool, io::Error> {
+        let path = self.auto_save_path();
+        match self.load_state(&path) {
+            Ok(()) => {
+                info!("Resumed from {}", path.display());
+                Ok(true)
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(io::Error::new(
+                err.kind(),
+                format!("Could not resume from {}: {}", path.display(), err),
+            )),
+        }
+    }
+
+    fn run_slot_action(&mut self, action: peripherals::SlotAction) {
+        match action {
+            peripherals::SlotAction::Save(slot) => match self.save_slot(slot) {
+                Ok(()) => info!("Saved state to slot {}", slot),
+                Err(err) => error!("Could not save state to slot {}: {}", slot, err),
+            },
+            peripherals::SlotAction::Load(slot) => match self.load_slot(slot) {
+                Ok(()) => info!("Loaded state from slot {}", slot),
+                Err(err) => error!("Could not load state from slot {}: {}", slot, err),
+            },
+        }
+    }
+
+    fn trace_instruction(&mut self) {
+        if !self.doctor_started {
+            if self.pc() != 0x100 {
+                return;
+            }
+            self.doctor_started = true;
+        }
+        let line = cpu::trace::doctor_line(self.cpu.regs(), &self.peripherals);
+        if let Some(ref mut trace) = self.doctor_trace {
+            if let Err(err) = writeln!(trace, "{}", line) {
+                error!("Could not write trace: {}", err);
+                self.doctor_trace = None;
+            }
+        }
+    }
+
+    /// Captures the whole machine state. Connections to the outside, like the link cable and any
+    /// recording, are left out.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let metadata = StateInfo {
+            frame: self.frame(),
+            saved_at: peripherals::unix_time(),
+        };
+        let mut w = savestate::Writer::new(self.peripherals.rom_hash(), metadata);
+        self.peripherals.save(&mut w);
+        self.cpu.save_state(&mut w);
+        w.into_bytes()
+    }
+
+    /// Restores state captured by `snapshot`, which has to be from the same ROM. If it can't be,
+    /// the machine is left as it was.
+    pub fn restore(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        // A state that's cut short or corrupt fails partway through loading, so what was there is
+        // kept to put back.
+        let before = self.snapshot();
+        self.load_snapshot(state).inspect_err(|_| {
+            self.load_snapshot(&before)
+                .expect("Could not roll back a failed restore");
+        })
+    }
+
+    fn load_snapshot(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut r = savestate::Reader::new(state, self.peripherals.rom_hash())?;
+        self.peripherals.load(&mut r)?;
+        self.cpu.load_state(&mut r, &self.peripherals)?;
+        r.finish()
+    }
+
+    pub fn save_state(&self, path: &Path) -> Result<(), io::Error> {
+        fs::write(path, self.snapshot())
+    }
+
+    pub fn load_state(&mut self, path: &Path) -> Result<(), io::Error> {
+        let state = fs::read(path)?;
+        self.restore(&state)
+    }
+
+    /// Writes the registers and memory to a crash dump in the save directory, along with `note`
+    /// saying what went wrong, and returns where it went.
+    pub fn write_crash_dump(&self, note: &str) -> Result<PathBuf, io::Error> {
+        let path = self.capture_path("crash");
+        fs::write(&path, self.crash_dump(note).to_bytes())?;
+        Ok(path)
+    }
+
+    fn crash_dump(&self, note: &str) -> crash_dump::CrashDump {
+        let regs = self.cpu.regs();
+        let mut dump = crash_dump::CrashDump {
+            note: note.to_string(),
+            regs: [0; 6],
+            regions: vec![],
+            wram_bank: Some(self.peripherals.mem.wram_bank()),
+        };
+        for (val, reg) in dump.regs.iter_mut().zip(crash_dump::REGS.iter()) {
+            *val = regs.read16(*reg);
+        }
+        for &(start, len) in &crash_dump::REGIONS {
+            let data = (0..len)
+                .map(|offset| self.peripherals.peek(start + offset as u16))
+                .collect();
+            dump.regions.push((start, data));
+        }
+        dump
+    }
+
+    /// Loads the registers and memory from a crash dump, and returns its note. It's meant for
+    /// looking around in, since the rest of the machine's state isn't in the dump, and I/O
+    /// registers are written back like the CPU would so not all of them come out the same.
+    pub fn load_crash_dump(&mut self, path: &Path) -> Result<String, io::Error> {
+        let dump = crash_dump::CrashDump::parse(&fs::read(path)?)?;
+        self.apply_crash_dump(&dump);
+        Ok(dump.note)
+    }
+
+    fn apply_crash_dump(&mut self, dump: &crash_dump::CrashDump) {
+        // The bank has to be switched first, so that the work RAM goes back into the bank it
+        // came from.
+        if let Some(bank) = dump.wram_bank {
+            self.peripherals.poke(0xFF70, bank);
+        }
+        for (start, data) in &dump.regions {
+            for (offset, &val) in data.iter().enumerate() {
+                self.peripherals.poke(start + offset as u16, val);
+            }
+        }
+        for (&val, reg) in dump.regs.iter().zip(crash_dump::REGS.iter()) {
+            self.set_reg16(*reg, val);
+        }
+    }
+
+    /// Where numbered save state slot `slot` is kept, in the save directory.
+    pub fn slot_path(&self, slot: u8) -> PathBuf {
+        self.save_path(&format!("state{}", slot))
+    }
+
+    pub fn save_slot(&self, slot: u8) -> Result<(), io::Error> {
+        check_slot(slot)?;
+        self.save_state(&self.slot_path(slot))
+    }
+
+    pub fn load_slot(&mut self, slot: u8) -> Result<(), io::Error> {
+        check_slot(slot)?;
+        let path = self.slot_path(slot);
+        self.load_state(&path)
+    }
+
+    /// When the state in `slot` was saved, or None if nothing has been saved there.
+    pub fn slot_info(&self, slot: u8) -> Result<Option<StateInfo>, io::Error> {
+        check_slot(slot)?;
+        match fs::read(self.slot_path(slot)) {
+            Ok(state) => savestate::Reader::metadata(&state).map(Some),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes a line in Game Boy Doctor's format before every instruction, starting from the
+    /// end of the boot ROM.
+    pub fn trace_doctor(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.doctor_trace = Some(LineWriter::new(File::create(path)?));
+        self.doctor_started = false;
+        Ok(())
+    }
+
+    /// Starts counting how many times each opcode runs, from zero.
+    pub fn start_counting_opcodes(&mut self) {
+        self.opcode_counts = Some(cpu::opcode_counts::OpcodeCounts::new());
+    }
+
+    /// Stops counting opcodes, keeping the counts so far.
+    pub fn stop_counting_opcodes(&mut self) {
+        if let Some(ref mut counts) = self.opcode_counts {
+            counts.running = false;
+        }
+    }
+
+    /// Lists the `count` most run opcodes, or all that have run. None if they were never counted.
+    pub fn opcode_report(&self, count: Option<usize>) -> Option<String> {
+        self.opcode_counts
+            .as_ref()
+            .map(|counts| counts.report(count))
+    }
+
+    /// How long frames have taken to emulate, not counting the wait to pace them, and to present.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.peripherals.ppu.frame_stats()
+    }
+
+    /// Adds frame time statistics to the end-of-run report.
+    pub fn report_frame_stats(&mut self) {
+        self.report_frame_stats = true;
+    }
+
+    /// What to print when the emulator exits: opcode counts and frame times, if they were asked
+    /// for.
+    pub fn end_of_run_report(&self) -> Option<String> {
+        let mut sections = vec![];
+        if let Some(opcodes) = self.opcode_report(None) {
+            sections.push(opcodes);
+        }
+        if self.report_frame_stats {
+            sections.push(self.frame_stats().to_string());
+        }
+        if sections.is_empty() {
+            None
+        } else {
+            Some(sections.join("\n"))
+        }
+    }
+
+    /// Logs every write the program makes to the I/O registers, as JSON lines.
+    pub fn log_io_writes(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.io_log = Some(peripherals::IoLog::start(path, &mut self.peripherals)?);
+        Ok(())
+    }
+
+    fn check_state_hash(&mut self) {
+        let frame = self.frame();
+        self.hashed_frame = frame;
+        if !replay::StateHashes::due(frame) {
+            return;
+        }
+        let hash = self.state_hash();
+        let result = match self.state_hashes {
+            Some(ref mut hashes) => hashes.check(frame, hash),
+            None => return,
+        };
+        match result {
+            Ok(None) => {}
+            Ok(Some(divergence)) => {
+                if self.divergence.is_none() {
+                    error!(
+                        "Replay diverged at frame {}: expected {:016x}, got {:016x}",
+                        divergence.frame, divergence.expected, divergence.actual
+                    );
+                    self.divergence = Some(divergence);
+                }
+            }
+            Err(err) => {
+                error!("Could not write state hash: {}", err);
+                self.state_hashes = None;
+            }
+        }
+    }
+
+    /// Hash of the parts of the emulator state that games can observe: the screen, the CPU
+    /// registers, and work and high RAM.
+    pub fn state_hash(&self) -> u64 {
+        let mut state = self.frame_hash().to_le_bytes().to_vec();
+        for reg in &[
+            cpu::registers::Reg16::AF,
+            cpu::registers::Reg16::BC,
+            cpu::registers::Reg16::DE,
+            cpu::registers::Reg16::HL,
+            cpu::registers::Reg16::SP,
+            cpu::registers::Reg16::PC,
+        ] {
+            state.extend_from_slice(&self.cpu.regs().read16(*reg).to_le_bytes());
+        }
+        // Straight from RAM, since reads through the bus can be seen by hooks, and are blocked by
+        // OAM DMA.
+        state.extend_from_slice(self.peripherals.mem.wram());
+        state.extend_from_slice(self.peripherals.mem.high_ram());
+        util::fnv1a_64(&state)
+    }
+
+    /// Writes a hash of the emulator state every `replay::INTERVAL` frames to `path`. Together with
+    /// an input movie, this is a reference for `verify_state_hashes`.
+    pub fn record_state_hashes(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.state_hashes = Some(replay::StateHashes::record(path)?);
+        Ok(())
+    }
+
+    /// Checks the emulator state against hashes written by `record_state_hashes`. The first
+    /// mismatch is reported by `divergence`.
+    pub fn verify_state_hashes(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.state_hashes = Some(replay::StateHashes::verify(path)?);
+        Ok(())
+    }
+
+    /// The first frame where the state didn't match the verified hashes, if any.
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.divergence
+    }
+
+    /// Whether every hash being verified has been checked.
+    pub fn verification_finished(&self) -> bool {
+        self.state_hashes
+            .as_ref()
+            .is_some_and(|hashes| hashes.finished())
     }
 
     pub fn start_print_serial(&mut self) {
+        // Printing to stdout can't fail to set up.
+        self.set_serial_sink(SerialSink::Stdout).unwrap();
+    }
+
+    /// Sends every byte written out of the serial port to the sink, as well as to any sinks set
+    /// before. Useful for capturing the results of test ROMs.
+    pub fn set_serial_sink(&mut self, sink: SerialSink) -> io::Result<()> {
+        let mut write: Box<dyn FnMut(u8) -> io::Result<()> + Send> = match sink {
+            SerialSink::Stdout => Box::new(|byte| {
+                let mut out = stdout();
+                write!(out, "{}", char::from(byte))?;
+                out.flush()
+            }),
+            SerialSink::File(path) => {
+                let mut file = File::create(path)?;
+                Box::new(move |byte| {
+                    file.write_all(&[byte])?;
+                    file.flush()
+                })
+            }
+            SerialSink::Callback(mut callback) => Box::new(move |byte| {
+                callback(byte);
+                Ok(())
+            }),
+        };
         let (tx, rx) = mpsc::channel();
         self.peripherals.connect_serial_channel(tx);
-        thread::spawn(move || loop {
-            let received = rx.recv().unwrap();
-            print!("{}", char::from(received));
-            stdout().flush().expect("Could not flush stdout");
+        // A sink that can't be written is dropped, like the I/O log, rather than taking the
+        // emulator down with it. The serial port stops sending to it once the thread's gone.
+        thread::spawn(move || {
+            for byte in rx {
+                if let Err(err) = write(byte) {
+                    error!("Could not write serial output: {}", err);
+                    return;
+                }
+            }
         });
+        Ok(())
+    }
+
+    /// Connects the serial ports of two emulators in the same process, as if by a link cable.
+    /// Both then need to be stepped for transfers to finish.
+    pub fn connect_link(&mut self, other: &mut Wolfwig) {
+        self.peripherals.connect_link(&mut other.peripherals);
     }
 
     pub fn print_header(&self) {
@@ -53,22 +683,384 @@ impl Wolfwig {
     }
 
     pub fn print_registers(&self) {
-        println!("{}", self.cpu.regs);
+        println!("{}", self.cpu.regs());
     }
 
     pub fn pc(&self) -> u16 {
         self.cpu.pc()
     }
 
+    /// Whether the next cycle starts executing the instruction at the PC.
+    pub fn at_instruction(&self) -> bool {
+        self.cpu.at_instruction()
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.cpu.regs().read16(cpu::registers::Reg16::SP)
+    }
+
+    pub fn reg16(&self, reg: cpu::registers::Reg16) -> u16 {
+        self.cpu.regs().read16(reg)
+    }
+
+    /// Whether the CPU has hung on one of the undefined opcodes. Setting the PC gets it going
+    /// again.
+    pub fn locked(&self) -> bool {
+        self.cpu.locked()
+    }
+
+    /// PC of the last instruction the CPU couldn't execute, if there's been one since this was
+    /// last called.
+    pub fn take_unknown_op(&mut self) -> Option<u16> {
+        self.cpu.take_unknown_op()
+    }
+
     pub fn print_reg8(&self, reg: cpu::registers::Reg8) {
-        println!("0x{:02X}", self.cpu.regs.read8(reg));
+        println!("0x{:02X}", self.cpu.regs().read8(reg));
     }
 
     pub fn print_reg16(&self, reg: cpu::registers::Reg16) {
-        println!("0x{:02X}", self.cpu.regs.read16(reg));
+        println!("0x{:02X}", self.cpu.regs().read16(reg));
+    }
+
+    pub fn set_reg8(&mut self, reg: cpu::registers::Reg8, val: u8) {
+        self.cpu.regs_mut().set8(reg, val);
+    }
+
+    pub fn set_reg16(&mut self, reg: cpu::registers::Reg16, val: u16) {
+        match reg {
+            cpu::registers::Reg16::PC => self.cpu.set_pc(val),
+            reg => self.cpu.regs_mut().set16(reg, val),
+        }
+    }
+
+    /// Writes to memory as if from the CPU, so writes to registers have their usual effects.
+    pub fn write(&mut self, address: u16, val: u8) {
+        self.peripherals.write(address, val);
+    }
+
+    /// Prints the current PC and then each return address, innermost call first.
+    pub fn print_backtrace(&self) {
+        println!("#0  0x{:04X}", self.pc());
+        for (depth, frame) in self.cpu.call_stack().iter().rev().enumerate() {
+            let kind = if frame.interrupt { "interrupt" } else { "call" };
+            println!(
+                "#{:<2} 0x{:04X}  ({} to 0x{:04X} from 0x{:04X}, SP 0x{:04X})",
+                depth + 1,
+                frame.return_pc,
+                kind,
+                frame.target,
+                frame.call_pc,
+                frame.sp
+            );
+        }
+    }
+
+    /// Prints the last instructions the CPU ran to stderr, oldest first, each with the registers
+    /// from before it ran.
+    pub fn print_recent(&self) {
+        let recent = self.cpu.recent();
+        if recent.is_empty() {
+            return;
+        }
+        eprintln!("Last {} instructions:", recent.len());
+        for executed in &recent {
+            eprintln!("  {}", cpu::trace::recent_line(executed));
+        }
+    }
+
+    pub fn print_oam(&self) {
+        print!("{}", self.peripherals.ppu.describe_oam());
+    }
+
+    pub fn print_sprite(&self, index: usize) {
+        match self.peripherals.ppu.describe_sprite(index) {
+            Some(sprite) => print!("{}", sprite),
+            None => println!("No OAM entry {}", index),
+        }
+    }
+
+    pub fn print_palettes(&self) {
+        print!("{}", self.peripherals.ppu.describe_palettes());
+    }
+
+    pub fn print_status(&self) {
+        println!("{}", self.peripherals.describe_status());
+    }
+
+    pub fn print_io(&self, name: Option<&str>) {
+        println!("{}", self.peripherals.describe_io(name));
+    }
+
+    pub fn print_apu(&self) {
+        self.peripherals.print_apu();
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.peripherals.ppu.frame
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.peripherals.ppu.fps()
+    }
+
+    /// Emulation speed as a percentage of the real hardware.
+    pub fn speed(&self) -> f32 {
+        self.peripherals.ppu.speed()
+    }
+
+    /// Default path for captures (screenshots, recordings) taken at the current frame, in the
+    /// save directory.
+    pub fn capture_path(&self, extension: &str) -> PathBuf {
+        self.peripherals.capture_path(extension)
+    }
+
+    /// Where a file for this ROM with the given extension goes in the save directory, like
+    /// `state` for its save state.
+    pub fn save_path(&self, extension: &str) -> PathBuf {
+        self.peripherals.save_path(extension)
+    }
+
+    /// Writes the most recently rendered frame to `path` as a PNG.
+    pub fn screenshot(&self, path: &Path) -> Result<(), io::Error> {
+        self.peripherals.ppu.screenshot(path)
+    }
+
+    /// Starts recording frames to `path`. See `Recorder` for the supported formats.
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.peripherals.ppu.start_recording(path)
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.peripherals.ppu.stop_recording()
+    }
+
+    /// Writes every rendered frame into `dir`, with the frame's hash in the file name, so runs
+    /// can be compared between emulator versions.
+    pub fn dump_frames(&mut self, dir: &Path) -> Result<(), io::Error> {
+        self.peripherals.ppu.dump_frames(dir)
+    }
+
+    pub fn frame_hash(&self) -> u64 {
+        self.peripherals.ppu.frame_hash()
+    }
+
+    /// Freezes emulation. The window and audio device stay open, and `step` keeps polling for
+    /// input so that the pause hotkey can resume.
+    pub fn pause(&mut self) {
+        self.peripherals.pause();
+    }
+
+    pub fn resume(&mut self) {
+        self.peripherals.resume();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.peripherals.paused()
+    }
+
+    /// While paused, runs exactly one more frame and then pauses again.
+    pub fn advance_frame(&mut self) {
+        self.peripherals.advance_frame();
+    }
+
+    /// Replaces the clock frames are paced against, and the speed is measured and audio queued
+    /// by. `from_files` starts with a `RealClock`, and `new_headless` with a `CycleClock`, so that
+    /// nothing depends on how fast the host is.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.peripherals.set_clock(clock);
     }
 
     pub fn go_fast(&mut self) {
-        self.peripherals.go_fast();
+        self.set_speed(Speed::Uncapped);
+    }
+
+    /// Applies the settings saved for this game with `save_game_settings`, if there are any.
+    /// `from_files` does this already.
+    pub fn load_game_settings(&mut self) -> Result<(), io::Error> {
+        let path = self.peripherals.settings_path();
+        let settings = match settings::GameSettings::load(&path)? {
+            Some(settings) => settings,
+            None => return Ok(()),
+        };
+        info!("Applying settings from {}", path.display());
+        if let Some(colors) = settings.colors {
+            self.peripherals.ppu.set_colors(colors);
+        }
+        if let Some(speed) = settings.speed {
+            self.set_speed(speed);
+        }
+        for cheat in &settings.cheats {
+            self.peripherals.add_cheat(cheat)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the colors, speed, and cheats as this game's settings, which are applied whenever
+    /// it's opened. Returns where they were saved.
+    pub fn save_game_settings(&self) -> Result<PathBuf, io::Error> {
+        let path = self.peripherals.settings_path();
+        let settings = settings::GameSettings {
+            colors: Some(self.peripherals.ppu.colors()),
+            speed: Some(self.peripherals.speed()),
+            cheats: self.peripherals.cheat_codes(),
+        };
+        settings.save(&path)?;
+        Ok(path)
+    }
+
+    /// Changes the colors the four shades are drawn in.
+    pub fn set_colors(&mut self, colors: Colors) {
+        self.peripherals.ppu.set_colors(colors);
+    }
+
+    /// Sets how fast the emulator runs. This adjusts both the frame pacing and the audio.
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.peripherals.set_speed(speed);
+    }
+
+    /// Records the buttons held on every frame to `path`. Start this before the first step, so
+    /// the movie can be replayed from power on.
+    pub fn record_input(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.peripherals.record_input(path)
+    }
+
+    /// Replays an input movie written by `record_input`. Real input is ignored until it ends.
+    pub fn play_input(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.peripherals.play_input(path)
+    }
+
+    /// Sets how many frames turbo buttons are held, and then released, for.
+    pub fn set_turbo_period(&mut self, frames: u32) {
+        self.peripherals.set_turbo_period(frames);
+    }
+
+    /// Sets the speed used while the fast-forward hotkey is held.
+    pub fn set_fast_forward_speed(&mut self, speed: Speed) {
+        self.peripherals.set_fast_forward_speed(speed);
+    }
+}
+
+impl Drop for Wolfwig {
+    // A panic unwinds through here, so crash reports show what led up to it. The machine may be in
+    // a bad state then, so it isn't saved.
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.print_recent();
+            match self.write_crash_dump("The emulator panicked") {
+                Ok(path) => eprintln!("Wrote a crash dump to {}", path.display()),
+                Err(err) => eprintln!("Could not write a crash dump: {}", err),
+            }
+        } else {
+            self.shut_down();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn failed_restore_changes_nothing() {
+        let mut wolfwig = Wolfwig::new_headless(vec![0; 0x8000], Model::Dmg);
+        wolfwig.peripherals.write(0xC000, 0x12);
+        let state = wolfwig.snapshot();
+        wolfwig.peripherals.write(0xC000, 0x34);
+        wolfwig.set_reg16(Reg16::BC, 0x5678);
+        let before = wolfwig.state_hash();
+
+        // Cut off partway through, after work RAM has been loaded.
+        assert!(wolfwig.restore(&state[..state.len() - 16]).is_err());
+        assert_eq!(wolfwig.state_hash(), before);
+        assert_eq!(wolfwig.peripherals.read(0xC000), 0x34);
+        assert_eq!(wolfwig.reg16(Reg16::BC), 0x5678);
+
+        wolfwig.restore(&state).unwrap();
+        assert_eq!(wolfwig.peripherals.read(0xC000), 0x12);
+    }
+
+    #[test]
+    fn crash_dump_restores_work_ram_bank() {
+        let mut wolfwig = Wolfwig::new_headless(vec![0; 0x8000], Model::Cgb);
+        wolfwig.peripherals.write(0xFF70, 3);
+        wolfwig.peripherals.write(0xD000, 0x33);
+        let dump = wolfwig.crash_dump("test");
+
+        let mut loaded = Wolfwig::new_headless(vec![0; 0x8000], Model::Cgb);
+        loaded.apply_crash_dump(&dump);
+        assert_eq!(loaded.peripherals.read(0xFF70) & 0x7, 3);
+        assert_eq!(loaded.peripherals.read(0xD000), 0x33);
+        loaded.peripherals.write(0xFF70, 1);
+        assert_eq!(loaded.peripherals.read(0xD000), 0x00);
+    }
+
+    // Counts the cycles run by the core it wraps.
+    struct Counting {
+        core: SM83,
+        cycles: Arc<AtomicUsize>,
+    }
+
+    impl Cpu for Counting {
+        fn step(&mut self, mem: &mut dyn Bus) -> bool {
+            self.cycles.fetch_add(1, Ordering::Relaxed);
+            self.core.step(mem)
+        }
+
+        fn regs(&self) -> &Registers {
+            &self.core.regs
+        }
+
+        fn regs_mut(&mut self) -> &mut Registers {
+            &mut self.core.regs
+        }
+
+        fn set_pc(&mut self, pc: u16) {
+            self.core.set_pc(pc)
+        }
+
+        fn at_instruction(&self) -> bool {
+            self.core.at_instruction()
+        }
+
+        fn stopped(&self) -> bool {
+            self.core.stopped()
+        }
+
+        fn locked(&self) -> bool {
+            self.core.locked()
+        }
+
+        fn take_unknown_op(&mut self) -> Option<u16> {
+            self.core.take_unknown_op()
+        }
+
+        fn save_state(&self, w: &mut StateWriter) {
+            self.core.save_state(w)
+        }
+
+        fn load_state(&mut self, r: &mut StateReader, mem: &dyn Bus) -> io::Result<()> {
+            self.core.load_state(r, mem)
+        }
+    }
+
+    #[test]
+    fn swapped_in_core() {
+        let cycles = Arc::new(AtomicUsize::new(0));
+        let wolfwig = Wolfwig::new_headless(vec![0; 0x8000], Model::Dmg);
+        let mut wolfwig = wolfwig.with_cpu(Box::new(Counting {
+            core: SM83::new(),
+            cycles: cycles.clone(),
+        }));
+        // It starts where the boot ROM would have left off.
+        assert_eq!(wolfwig.pc(), 0x0100);
+        assert_eq!(wolfwig.reg16(Reg16::SP), 0xFFFE);
+        for _ in 0..10 {
+            wolfwig.step();
+        }
+        assert_eq!(cycles.load(Ordering::Relaxed), 10);
+        assert!(wolfwig.pc() > 0x0100);
     }
 }
@@ -1,10 +1,22 @@
-extern crate env_logger;
 extern crate structopt;
+extern crate tracing_subscriber;
 
 extern crate wolfwig;
 
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+// Cycles --expect_serial waits when no limit is given, about a minute of emulated time.
+const DEFAULT_SERIAL_BUDGET: u64 = 60 * 1_048_576;
+// Cycles between checks of the serial output, about a frame.
+const SERIAL_CHECK_INTERVAL: u64 = 17_556;
 
 /// The Wolfwig gameboy emulator.
 #[derive(StructOpt)]
@@ -13,44 +25,380 @@ struct Opt {
     #[structopt(short = "r", long = "rom", parse(from_os_str))]
     rom: PathBuf,
 
-    /// Bootrom
+    /// Bootrom. Without one, the cartridge starts straight away, in the state the --model's boot
+    /// ROM would have left.
     #[structopt(short = "b", long = "bootrom", parse(from_os_str))]
-    bootrom: PathBuf,
+    bootrom: Option<PathBuf>,
+
+    /// Refuse to load ROMs with a bad header checksum, which a real Game Boy wouldn't start.
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    /// Model to set up when skipping the boot ROM: dmg0, dmg, mgb, or cgb.
+    #[structopt(long = "model", default_value = "dmg")]
+    model: wolfwig::Model,
+
+    /// Where to put .sav files, save states, screenshots, and recordings. Defaults to a
+    /// `wolfwig` directory in the platform's per-user data directory, like ~/.local/share.
+    #[structopt(long = "save_dir", parse(from_os_str))]
+    save_dir: Option<PathBuf>,
+
+    /// Save a state when the window is closed, and resume from it the next time the same ROM is
+    /// opened.
+    #[structopt(long = "auto_save")]
+    auto_save: bool,
+
+    /// Print what's in the ROM's header and whether it's supported, then exit.
+    #[structopt(long = "info")]
+    info: bool,
 
     /// Should the emulator start in debug mode
     #[structopt(short = "d", long = "debug")]
     debug: bool,
 
+    /// In debug mode, stop at opcodes the CPU doesn't know how to execute.
+    #[structopt(long = "break_on_unknown")]
+    break_on_unknown: bool,
+
+    /// Start in the full-screen terminal debugger.
+    #[structopt(long = "tui")]
+    tui: bool,
+
     /// Should bytes printed sent out the serial port be printed to the console?
     #[structopt(short = "p", long = "print_serial")]
     print_serial: bool,
 
+    /// Write bytes sent out the serial port into this file.
+    #[structopt(long = "serial_out", parse(from_os_str))]
+    serial_out: Option<PathBuf>,
+
     /// Should the emulator go fast (i.e., ignore all speed limits?).
     #[structopt(short = "f", long = "go_fast")]
     go_fast: bool,
+
+    /// Speed multiplier used while Tab is held, which speeds up the audio to match. 0 runs as
+    /// fast as possible, without sound.
+    #[structopt(long = "fast_forward_speed", default_value = "4")]
+    fast_forward_speed: u32,
+
+    /// Number of frames turbo buttons (U and I) are held, and then released, for.
+    #[structopt(long = "turbo_period", default_value = "1")]
+    turbo_period: u32,
+
+    /// Pace frames using vsync rather than sleeping. Smoother on 60Hz displays.
+    #[structopt(long = "vsync")]
+    vsync: bool,
+
+    /// Open a debug window showing the full background map and the visible viewport.
+    #[structopt(long = "bg_map")]
+    bg_map: bool,
+
+    /// Open a debug window with a waveform and readout for each audio channel.
+    #[structopt(long = "apu_viewer")]
+    apu_viewer: bool,
+
+    /// Don't open an audio device, for machines without one or for benchmarking. Games still see
+    /// working sound registers.
+    #[structopt(long = "no_audio")]
+    no_audio: bool,
+
+    /// Show the FPS and emulation speed in the window title.
+    #[structopt(long = "show_speed")]
+    show_speed: bool,
+
+    /// Log how long each span ran for when it closes, like RUST_LOG=wolfwig[frame]=info for
+    /// the time spent on each frame, which is its busy and idle time together. Logging is
+    /// filtered with RUST_LOG.
+    #[structopt(long = "time_spans")]
+    time_spans: bool,
+
+    /// Write a PNG screenshot once the given frame has been rendered.
+    #[structopt(long = "screenshot_at_frame")]
+    screenshot_at_frame: Option<u32>,
+
+    /// Write every rendered frame into this directory, named by frame number and hash.
+    #[structopt(long = "dump_frames", parse(from_os_str))]
+    dump_frames: Option<PathBuf>,
+
+    /// Record the buttons held on every frame into this file.
+    #[structopt(long = "record_input", parse(from_os_str))]
+    record_input: Option<PathBuf>,
+
+    /// Replay buttons from a file written with --record_input.
+    #[structopt(long = "play_input", parse(from_os_str))]
+    play_input: Option<PathBuf>,
+
+    /// Write a hash of the emulator state every 60 frames into this file.
+    #[structopt(long = "record_hashes", parse(from_os_str))]
+    record_hashes: Option<PathBuf>,
+
+    /// Check the emulator state against hashes written with --record_hashes, and exit with an
+    /// error on the first mismatch. Meant to be used with --play_input.
+    #[structopt(long = "verify_hashes", parse(from_os_str))]
+    verify_hashes: Option<PathBuf>,
+
+    /// Write a trace of every instruction after the boot ROM into this file, in the format
+    /// Game Boy Doctor compares against.
+    #[structopt(long = "doctor_trace", parse(from_os_str))]
+    doctor_trace: Option<PathBuf>,
+
+    /// Write a line of JSON into this file for every write to the I/O registers, with the cycle,
+    /// frame, PC, address, value, and register name.
+    #[structopt(long = "io_log", parse(from_os_str))]
+    io_log: Option<PathBuf>,
+
+    /// Count how many times each opcode runs, and list them, most run first, on exit.
+    #[structopt(long = "count_opcodes")]
+    count_opcodes: bool,
+
+    /// On exit, print the fastest, average, 99th percentile, and slowest times frames took to
+    /// emulate and to present.
+    #[structopt(long = "frame_stats")]
+    frame_stats: bool,
+
+    /// Exit after this many frames have been rendered.
+    #[structopt(long = "max_frames")]
+    max_frames: Option<u32>,
+
+    /// Exit after this many machine cycles have been run.
+    #[structopt(long = "max_cycles")]
+    max_cycles: Option<u64>,
+
+    /// Exit with status 0 as soon as this text comes out of the serial port, or 1 if it hasn't
+    /// by --max_frames or --max_cycles. Without either, gives up after a minute of emulated time.
+    /// Not checked in debug mode.
+    #[structopt(long = "expect_serial")]
+    expect_serial: Option<String>,
+}
+
+// Watches the serial output for --expect_serial.
+struct SerialExpectation {
+    expected: String,
+    rx: mpsc::Receiver<u8>,
+    output: String,
+}
+
+impl SerialExpectation {
+    fn new(wolfwig: &mut wolfwig::Wolfwig, expected: String) -> Self {
+        let (tx, rx) = mpsc::channel();
+        wolfwig
+            .set_serial_sink(wolfwig::SerialSink::Callback(Box::new(move |byte| {
+                // Nothing is listening once the result is known.
+                let _ = tx.send(byte);
+            })))
+            .unwrap();
+        Self {
+            expected,
+            rx,
+            output: String::new(),
+        }
+    }
+
+    // Whether the expected text has come out yet. With `wait`, bytes still on their way get a
+    // moment to arrive.
+    fn found(&mut self, wait: bool) -> bool {
+        self.output.extend(self.rx.try_iter().map(char::from));
+        if wait {
+            while let Ok(byte) = self.rx.recv_timeout(Duration::from_millis(100)) {
+                self.output.push(char::from(byte));
+            }
+        }
+        self.output.contains(&self.expected)
+    }
+}
+
+// Whether a run bounded by --max_frames or --max_cycles is over.
+fn limit_reached(opt: &Opt, frame: u32, cycles: u64) -> bool {
+    opt.max_frames.is_some_and(|max| frame >= max)
+        || opt.max_cycles.is_some_and(|max| cycles >= max)
 }
 
 fn main() {
-    env_logger::init();
-    let opt = Opt::from_args();
-    let mut wolfwig = wolfwig::Wolfwig::from_files(&opt.bootrom, &opt.rom).unwrap();
+    let mut opt = Opt::from_args();
+    let span_events = if opt.time_spans {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(span_events)
+        .with_writer(io::stderr)
+        .init();
+    if opt.info {
+        match wolfwig::rom_info(&opt.rom) {
+            Ok(info) => print!("{}", info),
+            Err(err) => {
+                eprintln!("Could not read {}: {}", opt.rom.display(), err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    let options = wolfwig::Options {
+        vsync: opt.vsync,
+        bg_map_viewer: opt.bg_map,
+        apu_viewer: opt.apu_viewer,
+        no_audio: opt.no_audio,
+        show_speed: opt.show_speed,
+        model: opt.model,
+        strict: opt.strict,
+        save_dir: opt
+            .save_dir
+            .take()
+            .unwrap_or_else(wolfwig::default_save_dir),
+        auto_save: opt.auto_save,
+    };
+    let (mut wolfwig, mut frontend) =
+        match wolfwig::Wolfwig::from_files(opt.bootrom.as_deref(), &opt.rom, &options) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                eprintln!("Could not start {}: {}", opt.rom.display(), err);
+                process::exit(1);
+            }
+        };
     if opt.print_serial {
         wolfwig.start_print_serial()
     }
+    if let Some(path) = opt.serial_out.take() {
+        wolfwig
+            .set_serial_sink(wolfwig::SerialSink::File(path))
+            .unwrap();
+    }
+    let mut expectation = opt
+        .expect_serial
+        .take()
+        .map(|expected| SerialExpectation::new(&mut wolfwig, expected));
+    if expectation.is_some() && opt.max_frames.is_none() && opt.max_cycles.is_none() {
+        opt.max_cycles = Some(DEFAULT_SERIAL_BUDGET);
+    }
     if opt.go_fast {
         wolfwig.go_fast();
     }
+    wolfwig.set_turbo_period(opt.turbo_period);
+    wolfwig.set_fast_forward_speed(wolfwig::Speed::from_multiplier(opt.fast_forward_speed));
+    if let Some(ref dir) = opt.dump_frames {
+        wolfwig.dump_frames(dir).unwrap();
+    }
+    if let Some(ref path) = opt.record_input {
+        wolfwig.record_input(path).unwrap();
+    }
+    if let Some(ref path) = opt.play_input {
+        wolfwig.play_input(path).unwrap();
+    }
+    if let Some(ref path) = opt.doctor_trace {
+        wolfwig.trace_doctor(path).unwrap();
+    }
+    if let Some(ref path) = opt.io_log {
+        wolfwig.log_io_writes(path).unwrap();
+    }
+    if opt.count_opcodes {
+        wolfwig.start_counting_opcodes();
+    }
+    if opt.frame_stats {
+        wolfwig.report_frame_stats();
+    }
+    if let Some(ref path) = opt.record_hashes {
+        wolfwig.record_state_hashes(path).unwrap();
+    }
+    if let Some(ref path) = opt.verify_hashes {
+        wolfwig.verify_state_hashes(path).unwrap();
+    }
 
     wolfwig.print_header();
 
-    if opt.debug {
+    // SDL has to stay on this thread, so the emulator runs on another while this one polls input
+    // and shows frames. The frontend returns once the emulator is dropped at the end of the run,
+    // which is when it saves the game.
+    let emulation = thread::spawn(move || run(wolfwig, opt, expectation));
+    frontend.run();
+    match emulation.join() {
+        Ok(code) => process::exit(code),
+        Err(_) => process::exit(101),
+    }
+}
+
+// Runs until the window's closed, the debugger quits, or the run's limit is reached, and returns
+// the exit code.
+fn run(
+    mut wolfwig: wolfwig::Wolfwig,
+    mut opt: Opt,
+    mut expectation: Option<SerialExpectation>,
+) -> i32 {
+    if opt.tui {
+        let mut tui = wolfwig::debug::Tui::new(wolfwig).unwrap();
+        tui.run().unwrap();
+    } else if opt.debug {
         let mut debug = wolfwig::debug::Debug::new(wolfwig);
+        debug.set_break_on_unknown(opt.break_on_unknown);
+        let mut cycles = 0;
         loop {
             debug.step();
+            cycles += 1;
+            if debug.shutting_down() || limit_reached(&opt, debug.frame(), cycles) {
+                break;
+            }
         }
     } else {
+        let mut cycles = 0;
         loop {
             wolfwig.step();
+            if wolfwig.shutting_down() {
+                break;
+            }
+            cycles += 1;
+            if opt.screenshot_at_frame == Some(wolfwig.frame()) {
+                let path = wolfwig.capture_path("png");
+                if let Err(err) = wolfwig.screenshot(&path) {
+                    println!("Could not write screenshot {}: {}", path.display(), err);
+                }
+                opt.screenshot_at_frame = None;
+            }
+            if let Some(pc) = wolfwig.take_unknown_op() {
+                if wolfwig.locked() {
+                    eprintln!("CPU locked up on the illegal opcode at 0x{:04X}", pc);
+                } else {
+                    eprintln!("Could not execute the instruction at 0x{:04X}", pc);
+                }
+                wolfwig.print_recent();
+                if wolfwig.locked() {
+                    let note = format!("CPU locked up at 0x{:04X}", pc);
+                    match wolfwig.write_crash_dump(&note) {
+                        Ok(path) => eprintln!("Wrote a crash dump to {}", path.display()),
+                        Err(err) => eprintln!("Could not write a crash dump: {}", err),
+                    }
+                }
+            }
+            if let Some(divergence) = wolfwig.divergence() {
+                eprintln!(
+                    "Replay diverged at frame {}: expected state {:016x}, got {:016x}",
+                    divergence.frame, divergence.expected, divergence.actual
+                );
+                return 1;
+            }
+            if opt.verify_hashes.is_some() && wolfwig.verification_finished() {
+                println!("Replay matched through frame {}", wolfwig.frame());
+                break;
+            }
+            if let Some(ref mut expectation) = expectation {
+                if cycles % SERIAL_CHECK_INTERVAL == 0 && expectation.found(false) {
+                    println!("Found {:?} in the serial output", expectation.expected);
+                    return 0;
+                }
+            }
+            if limit_reached(&opt, wolfwig.frame(), cycles) {
+                if let Some(ref mut expectation) = expectation {
+                    if !expectation.found(true) {
+                        eprintln!(
+                            "{:?} didn't appear in the serial output after {} cycles",
+                            expectation.expected, cycles
+                        );
+                        return 1;
+                    }
+                }
+                break;
+            }
         }
     }
+    0
 }
@@ -0,0 +1,195 @@
+//! Game Boy models, for starting straight from the cartridge without a boot ROM. Each boot ROM
+//! leaves the registers in a slightly different state, which some games check to tell the models
+//! apart.
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Model {
+    /// The earliest DMG boot ROM.
+    Dmg0,
+    #[default]
+    Dmg,
+    /// The Game Boy Pocket.
+    Mgb,
+    /// The Game Boy Color. Only its registers, unmapped reads, and work RAM banks are emulated:
+    /// the rest is still a DMG, so games that check for it will try to use color features that
+    /// aren't there.
+    Cgb,
+}
+
+// I/O registers every boot ROM leaves the same. STAT and LY follow the PPU, which starts from the
+// top of the frame when LCDC turns the screen on.
+const POST_BOOT_IO: &[(u16, u8)] = &[
+    (0xFF00, 0xCF),
+    (0xFF05, 0x00),
+    (0xFF06, 0x00),
+    (0xFF07, 0xF8),
+    (0xFF0F, 0xE1),
+    // The sound has to be powered on before the rest of it can be written. The channels aren't
+    // triggered, since the boot sound is over by the time the cartridge starts.
+    (0xFF26, 0x80),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF40, 0x91),
+    (0xFF42, 0x00),
+    (0xFF43, 0x00),
+    (0xFF45, 0x00),
+    (0xFF47, 0xFC),
+    (0xFF4A, 0x00),
+    (0xFF4B, 0x00),
+    (0xFFFF, 0x00),
+];
+
+// The CGB boot ROM leaves the serial port on its internal clock, and it has registers the others
+// don't. The CGB-only registers that aren't emulated yet, like VBK and the palettes, read as
+// unmapped.
+const CGB_POST_BOOT_IO: &[(u16, u8)] = &[(0xFF02, 0x7F), (0xFF4D, 0x7E), (0xFF70, 0xF8)];
+const DMG_POST_BOOT_IO: &[(u16, u8)] = &[(0xFF02, 0x7E)];
+
+impl Model {
+    /// I/O registers the boot ROM leaves set, in the order they're written.
+    pub fn post_boot_io(self) -> Vec<(u16, u8)> {
+        let model_io = match self {
+            Model::Cgb => CGB_POST_BOOT_IO,
+            _ => DMG_POST_BOOT_IO,
+        };
+        POST_BOOT_IO.iter().chain(model_io).cloned().collect()
+    }
+
+    /// AF, BC, DE, and HL when the cartridge starts. The DMG boot ROMs leave the half-carry and
+    /// carry flags set unless the header checksum is zero.
+    pub fn registers(self, header_checksum: u8) -> [u16; 4] {
+        let carries = if header_checksum == 0 { 0x00 } else { 0x30 };
+        match self {
+            Model::Dmg0 => [0x0100, 0xFF13, 0x00C1, 0x8403],
+            Model::Dmg => [0x0180 | carries, 0x0013, 0x00D8, 0x014D],
+            Model::Mgb => [0xFF80 | carries, 0x0013, 0x00D8, 0x014D],
+            Model::Cgb => [0x1180, 0x0000, 0xFF56, 0x000D],
+        }
+    }
+
+    /// Wave RAM at power on. The boot ROMs don't touch it. On a DMG it's different on every unit,
+    /// and this is the pattern one of them starts with.
+    pub fn wave_ram(self) -> [u8; 16] {
+        match self {
+            Model::Cgb => [
+                0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+                0x00, 0xFF,
+            ],
+            _ => [
+                0x84, 0x40, 0x43, 0xAA, 0x2D, 0x78, 0x92, 0x3C, 0x60, 0x59, 0x59, 0xB0, 0x34, 0xB8,
+                0x2E, 0xDA,
+            ],
+        }
+    }
+
+    /// The full 16-bit divider, whose top byte is DIV. It differs between models because their
+    /// boot ROMs take different amounts of time.
+    pub fn divider(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1800,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Cgb => 0x1EA0,
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!(
+                "Unknown model {:?}, expected dmg0, dmg, mgb, or cgb",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Cgb => "cgb",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::Peripherals;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn names() {
+        for &model in &[Model::Dmg0, Model::Dmg, Model::Mgb, Model::Cgb] {
+            assert_eq!(model.to_string().parse(), Ok(model));
+        }
+        assert_eq!("DMG".parse(), Ok(Model::Dmg));
+        assert!("sgb".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn skip_boot() {
+        let mut mem = Peripherals::new_fake();
+        mem.skip_boot(Model::Dmg);
+        assert_eq!(mem.read(0xFF04), 0xAB);
+        assert_eq!(mem.read(0xFF40), 0x91);
+        assert_eq!(mem.read(0xFF47), 0xFC);
+        // The boot sound leaves channel one on.
+        assert_eq!(mem.read(0xFF26), 0xF1);
+        assert_eq!(mem.read(0xFF12), 0xF3);
+        assert_eq!(mem.read(0xFF30), 0x84);
+        assert_eq!(Model::Dmg.registers(0x00)[0], 0x0180);
+        assert_eq!(Model::Dmg.registers(0x4D)[0], 0x01B0);
+        assert_eq!(mem.read(0xFF02), 0x7E);
+        assert_eq!(mem.read(0xFF4D), 0xFF);
+    }
+
+    #[test]
+    fn skip_boot_cgb() {
+        let mut mem = Peripherals::new_fake();
+        mem.skip_boot(Model::Cgb);
+        assert_eq!(mem.read(0xFF02), 0x7F);
+        assert_eq!(mem.read(0xFF4D), 0x7E);
+        assert_eq!(mem.read(0xFF70), 0xF9);
+        assert_eq!(mem.read(0xFF30), 0x00);
+    }
+
+    #[test]
+    fn skip_boot_is_unwatched() {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let hook_writes = writes.clone();
+        let mut mem = Peripherals::new_fake();
+        mem.add_write_hook(0xFF00..=0xFFFF, move |_, _| {
+            hook_writes.fetch_add(1, Ordering::Relaxed);
+        });
+        mem.skip_boot(Model::Dmg);
+        assert_eq!(writes.load(Ordering::Relaxed), 0);
+    }
+}
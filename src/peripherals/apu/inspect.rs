@@ -0,0 +1,114 @@
+//! Human-readable dumps of APU state, for the debugger and the scope window.
+use peripherals::apu::{Apu, ChannelEnable, Envelope};
+use std::fmt::Write;
+
+// Percentages for NR32's output level.
+fn wave_level(level: u8) -> f32 {
+    match level & 0x3 {
+        0 => 0.0,
+        1 => 1.0,
+        2 => 0.5,
+        _ => 0.25,
+    }
+}
+
+fn envelope_volume(envelope: &Envelope) -> f32 {
+    if envelope.sweep == 0 {
+        f32::from(envelope.initial_volume) / 15.0
+    } else {
+        f32::from(envelope.current_volume) / 15.0
+    }
+}
+
+impl Apu {
+    // Frequency of the noise channel's LFSR clock.
+    fn noise_hz(&self) -> f32 {
+        let divisor = match self.channel_four.counter.ratio {
+            0 => 0.5,
+            ratio => f32::from(ratio),
+        };
+        524_288.0 / divisor / (2 << self.channel_four.counter.frequency) as f32
+    }
+
+    fn panning(&self, left: ChannelEnable, right: ChannelEnable) -> &'static str {
+        let enable = &self.control.channel_enable;
+        match (enable.contains(left), enable.contains(right)) {
+            (true, true) => "LR",
+            (true, false) => "L-",
+            (false, true) => "-R",
+            (false, false) => "--",
+        }
+    }
+
+    /// One line per channel, with its panning, frequency, volume, and duty or mode.
+    pub fn describe_channels(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "CH1 {} {:8.1} Hz  vol {:.2}  duty {:4.1}%",
+            self.panning(ChannelEnable::CH1_LEFT, ChannelEnable::CH1_RIGHT),
+            self.channel_one.frequency.hz(),
+            envelope_volume(&self.channel_one.envelope),
+            self.channel_one.length_pattern.duty_cycle() * 100.0
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "CH2 {} {:8.1} Hz  vol {:.2}  duty {:4.1}%",
+            self.panning(ChannelEnable::CH2_LEFT, ChannelEnable::CH2_RIGHT),
+            self.channel_two.frequency.hz(),
+            envelope_volume(&self.channel_two.envelope),
+            self.channel_two.length_pattern.duty_cycle() * 100.0
+        )
+        .unwrap();
+        // The wave channel steps through 32 samples per period, rather than the 8 steps of the
+        // square channels, so it plays at half the frequency for the same register value.
+        writeln!(
+            out,
+            "CH3 {} {:8.1} Hz  vol {:.2}  {}",
+            self.panning(ChannelEnable::CH3_LEFT, ChannelEnable::CH3_RIGHT),
+            self.channel_three.frequency.hz() / 2.0,
+            wave_level(self.channel_three.level),
+            if self.channel_three.enable {
+                "on"
+            } else {
+                "off"
+            }
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "CH4 {} {:8.1} Hz  vol {:.2}  {}-bit",
+            self.panning(ChannelEnable::CH4_LEFT, ChannelEnable::CH4_RIGHT),
+            self.noise_hz(),
+            envelope_volume(&self.channel_four.envelope),
+            if self.channel_four.counter.width {
+                7
+            } else {
+                15
+            }
+        )
+        .unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_channel_one() {
+        let mut apu = Apu::new_fake();
+        apu.control.channel_enable.set_enable(0b0001_0000);
+        // 2048 - 131072 / 1024 Hz
+        apu.channel_one.frequency.set_frequency_low(0x80);
+        apu.channel_one.frequency.set_frequency_high(0x07);
+        apu.channel_one.envelope.set_initial_volume(15);
+        apu.channel_one.length_pattern.set_duty(2);
+        assert_eq!(
+            apu.describe_channels().lines().next().unwrap(),
+            "CH1 L-   1024.0 Hz  vol 1.00  duty 50.0%"
+        );
+    }
+}
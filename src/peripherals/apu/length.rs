@@ -0,0 +1,141 @@
+//! Length counters, which turn a channel off after a set time. They count down on every other step
+//! of the frame sequencer, 256 times a second, but enabling them or triggering the channel halfway
+//! between those steps has some quirks games and test ROMs can see through NR52.
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
+
+pub struct LengthCounter {
+    // 64, or 256 for the wave channel.
+    max: u16,
+    // Steps left before the channel is turned off. 0 once it has been.
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Handles a write to the length bits of NRx1.
+    pub fn set_length(&mut self, length: u8) {
+        self.counter = self.max - u16::from(length);
+    }
+
+    pub fn remaining(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Handles the length enable and trigger bits of a write to NRx4, and returns whether the
+    /// channel is on afterwards. `between_steps` is whether the frame sequencer's next step won't
+    /// clock length counters, in which case enabling the counter clocks it once straight away.
+    pub fn write_control(
+        &mut self,
+        enable: bool,
+        trigger: bool,
+        between_steps: bool,
+        active: bool,
+    ) -> bool {
+        let mut active = active;
+        if enable && !self.enabled && between_steps && self.counter != 0 {
+            self.counter -= 1;
+            if self.counter == 0 && !trigger {
+                active = false;
+            }
+        }
+        self.enabled = enable;
+        if trigger {
+            active = true;
+            // A counter that's run out is reloaded, and gets the extra clock too.
+            if self.counter == 0 {
+                self.counter = self.max;
+                if enable && between_steps {
+                    self.counter -= 1;
+                }
+            }
+        }
+        active
+    }
+
+    /// Clocks the counter on one of the frame sequencer's length steps. Returns whether it ran
+    /// out, turning the channel off.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.counter);
+        w.bool(self.enabled);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.counter = r.u16()?.min(self.max);
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_down_when_enabled() {
+        let mut length = LengthCounter::new(64);
+        length.set_length(62);
+        assert!(length.write_control(false, true, false, false));
+        assert!(!length.clock());
+        assert_eq!(length.remaining(), 2);
+
+        assert!(length.write_control(true, false, false, true));
+        assert!(!length.clock());
+        assert!(length.clock());
+        // It stays off until it's reloaded or triggered.
+        assert!(!length.clock());
+        assert_eq!(length.remaining(), 0);
+    }
+
+    #[test]
+    fn enabling_between_steps() {
+        let mut length = LengthCounter::new(64);
+        length.set_length(62);
+        assert!(length.write_control(true, true, true, false));
+        assert_eq!(length.remaining(), 1);
+        // Enabling it again doesn't clock it, but disabling and enabling it does.
+        assert!(length.write_control(true, false, true, true));
+        assert_eq!(length.remaining(), 1);
+        length.write_control(false, false, true, true);
+        assert!(!length.write_control(true, false, true, true));
+        assert_eq!(length.remaining(), 0);
+    }
+
+    #[test]
+    fn trigger_reloads() {
+        let mut length = LengthCounter::new(256);
+        // Triggering with the counter run out reloads it.
+        assert!(length.write_control(false, true, true, false));
+        assert_eq!(length.remaining(), 256);
+
+        // Running out on the extra clock doesn't stop a trigger, which reloads it with the extra
+        // clock taken off.
+        let mut length = LengthCounter::new(64);
+        length.set_length(63);
+        length.write_control(false, false, true, false);
+        assert!(length.write_control(true, true, true, false));
+        assert_eq!(length.remaining(), 63);
+    }
+}
@@ -1,7 +1,21 @@
+use peripherals::clock::Clock;
+use peripherals::io_map::IoRegister;
+use peripherals::ppu::FrameSender;
+use savestate::{Reader, Snapshot, Writer};
 use std::cmp::min;
 ///! Model of the Audio Processing Unit
 use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
 use std::time;
+use Speed;
+
+mod inspect;
+mod length;
+mod scope;
+
+use self::length::LengthCounter;
+pub use self::scope::{HEIGHT as SCOPE_HEIGHT, WIDTH as SCOPE_WIDTH};
 
 pub struct Sweep {
     time: u8,
@@ -48,11 +62,8 @@ impl Sweep {
 pub struct LengthPattern {
     // Duty cycle, ranges from 0-4 (12.5%, 25%, 50%, 75%)
     duty: u8,
-    // Lengths, in units of 1/64ths of a second
+    // Lengths, in units of 1/256ths of a second
     length: u8,
-    // How much of the length has been played out.
-    length_sec: f32,
-    played_length: f32,
     modified: bool,
 }
 
@@ -61,8 +72,6 @@ impl LengthPattern {
         Self {
             duty: 0,
             length: 0,
-            length_sec: 0.0,
-            played_length: 1000.0,
             modified: false,
         }
     }
@@ -78,8 +87,6 @@ impl LengthPattern {
     }
     pub fn set_length(&mut self, val: u8) {
         self.length = val;
-        self.length_sec = (64.0 - val as f32) / 256.0;
-        self.played_length = 0.0;
         self.modified = true
     }
     fn duty_cycle(&self) -> f32 {
@@ -162,6 +169,12 @@ impl Envelope {
         }
     }
 
+    /// The channel's DAC is on as long as any of NRx2's top five bits are set. While it's off,
+    /// the channel can't be on.
+    pub fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.direction
+    }
+
     // Current output volume
     pub fn volume(&self) -> f32 {
         if (self.sweep == 0) {
@@ -270,6 +283,7 @@ pub struct ChannelOne {
     pub length_pattern: LengthPattern,
     pub envelope: Envelope,
     pub frequency: Frequency,
+    length_counter: LengthCounter,
     phase: f32,
     active: bool,
 }
@@ -281,6 +295,7 @@ impl ChannelOne {
             length_pattern: LengthPattern::new(),
             envelope: Envelope::new(),
             frequency: Frequency::new(),
+            length_counter: LengthCounter::new(64),
             phase: 0.0,
             active: false,
         }
@@ -290,16 +305,13 @@ impl ChannelOne {
         self.active as u8
     }
 
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
     fn get_samples(&mut self, nsamples: usize, device_freq: f32) -> Vec<f32> {
         let mut samples = vec![];
-        if self.frequency.start {
-            self.length_pattern.played_length = 0.0;
-            self.frequency.start = false;
-            if !self.frequency.use_counter {
-                self.length_pattern.length_sec = 1000.0
-            }
-        }
-        if self.length_pattern.played_length >= self.length_pattern.length_sec {
+        if !self.active {
             for _ in 0..nsamples {
                 samples.push(0.0)
             }
@@ -308,10 +320,10 @@ impl ChannelOne {
         let phase_inc = self.frequency.hz() / device_freq;
         if self.frequency.modified || self.length_pattern.modified {
             debug!(
-                "CH1: Playing {} hz tone for {} seconds? {}",
+                "CH1: Playing {} hz tone for {} steps? {}",
                 self.frequency.hz(),
-                self.length_pattern.length_sec,
-                self.frequency.use_counter
+                self.length_counter.remaining(),
+                self.length_counter.enabled()
             );
             self.frequency.modified = false;
             self.length_pattern.modified = false;
@@ -324,7 +336,6 @@ impl ChannelOne {
             }
             self.phase = (self.phase + phase_inc) % 1.0;
         }
-        self.length_pattern.played_length += (nsamples as f32) / device_freq;
         self.envelope.update(time::Duration::from_micros(
             (((nsamples * 1_000_000) as f32) / device_freq) as u64,
         ));
@@ -336,6 +347,7 @@ pub struct ChannelTwo {
     pub length_pattern: LengthPattern,
     pub envelope: Envelope,
     pub frequency: Frequency,
+    length_counter: LengthCounter,
     phase: f32,
     active: bool,
 }
@@ -346,6 +358,7 @@ impl ChannelTwo {
             length_pattern: LengthPattern::new(),
             envelope: Envelope::new(),
             frequency: Frequency::new(),
+            length_counter: LengthCounter::new(64),
             phase: 0.0,
             active: false,
         }
@@ -355,16 +368,13 @@ impl ChannelTwo {
         self.active as u8
     }
 
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
     fn get_samples(&mut self, nsamples: usize, device_freq: f32) -> Vec<f32> {
         let mut samples = vec![];
-        if self.frequency.start {
-            self.length_pattern.played_length = 0.0;
-            self.frequency.start = false;
-            if !self.frequency.use_counter {
-                self.length_pattern.length_sec = 1000.0
-            }
-        }
-        if self.length_pattern.played_length >= self.length_pattern.length_sec {
+        if !self.active {
             for _ in 0..nsamples {
                 samples.push(0.0)
             }
@@ -373,10 +383,10 @@ impl ChannelTwo {
         let phase_inc = self.frequency.hz() / device_freq;
         if self.frequency.modified || self.length_pattern.modified {
             debug!(
-                "CH2: Playing {} hz tone for {} seconds? {}",
+                "CH2: Playing {} hz tone for {} steps? {}",
                 self.frequency.hz(),
-                self.length_pattern.length_sec,
-                self.frequency.use_counter
+                self.length_counter.remaining(),
+                self.length_counter.enabled()
             );
             self.frequency.modified = false;
             self.length_pattern.modified = false;
@@ -389,7 +399,6 @@ impl ChannelTwo {
             }
             self.phase = (self.phase + phase_inc) % 1.0;
         }
-        self.length_pattern.played_length += (nsamples as f32) / device_freq;
         self.envelope.update(time::Duration::from_micros(
             (((nsamples * 1_000_000) as f32) / device_freq) as u64,
         ));
@@ -403,6 +412,7 @@ pub struct ChannelThree {
     pub level: u8,
     pub frequency: Frequency,
     pub table: Vec<u8>,
+    length_counter: LengthCounter,
     active: bool,
 }
 
@@ -416,6 +426,7 @@ impl ChannelThree {
             level: 0,
             frequency: Frequency::new(),
             table: vec![0; Self::TABLE_SIZE],
+            length_counter: LengthCounter::new(256),
             active: false,
         }
     }
@@ -461,6 +472,11 @@ impl ChannelThree {
     pub fn active(&self) -> u8 {
         self.active as u8
     }
+
+    /// NR30's enable bit only turns the DAC on. The channel is still turned on by triggering it.
+    pub fn dac_enabled(&self) -> bool {
+        self.enable
+    }
 }
 
 /// Channel Four is the noise channel, usually used for snares or other percussion.
@@ -470,6 +486,7 @@ pub struct ChannelFour {
     pub counter: PolyCounter,
     pub start: bool,
     pub stop_on_length: bool,
+    length_counter: LengthCounter,
     active: bool,
 }
 
@@ -481,6 +498,7 @@ impl ChannelFour {
             counter: PolyCounter::new(),
             start: false,
             stop_on_length: false,
+            length_counter: LengthCounter::new(64),
             active: false,
         }
     }
@@ -508,16 +526,50 @@ impl ChannelFour {
     pub fn active(&self) -> u8 {
         self.active as u8
     }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
 }
 
 pub struct Volume {
     pub left: u8,
     pub right: u8,
+    // Whether the cartridge's Vin pin is mixed into each side.
+    pub vin_left: bool,
+    pub vin_right: bool,
 }
 
 impl Volume {
     fn new() -> Self {
-        Self { left: 0, right: 0 }
+        Self {
+            left: 0,
+            right: 0,
+            vin_left: false,
+            vin_right: false,
+        }
+    }
+
+    pub fn set_vin_left(&mut self, val: u8) {
+        self.vin_left = val != 0
+    }
+
+    pub fn set_vin_right(&mut self, val: u8) {
+        self.vin_right = val != 0
+    }
+
+    pub fn vin_left(&self) -> u8 {
+        self.vin_left as u8
+    }
+
+    pub fn vin_right(&self) -> u8 {
+        self.vin_right as u8
+    }
+
+    /// What a sample from Vin adds to the left and right outputs.
+    pub fn mix_vin(&self, vin: f32) -> (f32, f32) {
+        let side = |enabled: bool| if enabled { vin } else { 0.0 };
+        (side(self.vin_left), side(self.vin_right))
     }
 
     pub fn set_left(&mut self, val: u8) {
@@ -589,6 +641,7 @@ impl Control {
     }
 }
 
+// Samples waiting to be played, shared between the APU and the audio device's callback.
 struct APUSamples {
     pub left: VecDeque<f32>,
     pub right: VecDeque<f32>,
@@ -597,19 +650,23 @@ struct APUSamples {
     update_samples: usize,
 }
 
-impl sdl2::audio::AudioCallback for APUSamples {
+// Plays the APU's samples, from SDL's audio thread.
+struct Playback(Arc<Mutex<APUSamples>>);
+
+impl sdl2::audio::AudioCallback for Playback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
+        let mut samples = self.0.lock().unwrap();
         for sample in out.iter_mut().step_by(2) {
-            if let Some(val) = self.left.pop_front() {
+            if let Some(val) = samples.left.pop_front() {
                 *sample = val;
             } else {
                 *sample = 0.0;
             }
         }
         for sample in out.iter_mut().skip(1).step_by(2) {
-            if let Some(val) = self.right.pop_front() {
+            if let Some(val) = samples.right.pop_front() {
                 *sample = val;
             } else {
                 *sample = 0.0;
@@ -618,26 +675,207 @@ impl sdl2::audio::AudioCallback for APUSamples {
     }
 }
 
+/// Keeps the audio device playing. Like the windows, it has to stay on the thread SDL was set up
+/// on, while the APU feeding it can be elsewhere.
+pub struct AudioOutput {
+    _device: sdl2::audio::AudioDevice<Playback>,
+}
+
 pub struct Apu {
     pub channel_one: ChannelOne,
     pub channel_two: ChannelTwo,
     pub channel_three: ChannelThree,
     pub channel_four: ChannelFour,
     pub control: Control,
-    device: Option<sdl2::audio::AudioDevice<APUSamples>>,
-    last_update: time::Instant,
+    samples: Option<Arc<Mutex<APUSamples>>>,
+    // When samples were last queued, by the clock passed to `step`.
+    last_update: time::Duration,
+    // The frame sequencer's next step, 0-7. Length counters are clocked on the even steps.
+    frame_step: u8,
+    scope: Option<scope::Scope>,
+    // Samples are generated against wall time rather than emulated time, so when running faster
+    // than normal, this many times more are generated and squeezed down to fit, which speeds the
+    // audio up to match. There's no telling how much faster uncapped is, so it's None and quiet.
+    speedup: Option<usize>,
+}
+
+// Cartridges can put their own audio on the Vin pin, but none that are emulated do.
+const VIN_SAMPLE: f32 = 0.0;
+
+// NRx1 of the pulse channels.
+fn read_length_pattern(length_pattern: &LengthPattern) -> u8 {
+    length_pattern.duty() << 6 | length_pattern.length()
+}
+
+fn write_length_pattern(length_pattern: &mut LengthPattern, val: u8) {
+    length_pattern.set_duty(val >> 6);
+    length_pattern.set_length(val & 0x3F);
+}
+
+// NRx2.
+fn read_envelope(envelope: &Envelope) -> u8 {
+    envelope.initial_volume() << 4 | envelope.direction() << 3 | envelope.sweep()
+}
+
+fn write_envelope(envelope: &mut Envelope, val: u8) {
+    envelope.set_initial_volume(val >> 4);
+    envelope.set_direction((val >> 3) & 0x1);
+    envelope.set_sweep(val & 0x7);
+}
+
+// NRx4.
+fn read_frequency_high(frequency: &Frequency) -> u8 {
+    frequency.start() << 7 | frequency.use_counter() << 6 | frequency.frequency_high()
+}
+
+fn write_frequency_high(frequency: &mut Frequency, val: u8) {
+    frequency.set_start(val >> 7);
+    frequency.set_use_counter((val >> 6) & 0x1);
+    frequency.set_frequency_high(val & 0x7);
+}
+
+// The length enable and trigger bits of NRx4, which every channel has.
+fn write_length_control(
+    length_counter: &mut LengthCounter,
+    active: &mut bool,
+    val: u8,
+    between_steps: bool,
+) {
+    *active =
+        length_counter.write_control(val & 0x40 != 0, val & 0x80 != 0, between_steps, *active);
+}
+
+// Bits of NR10-NR52 that read as 1, whatever was written. As well as the bits that don't exist,
+// that's the ones that are write-only: the lengths, the frequencies, and the trigger bits.
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+impl IoRegister for Apu {
+    fn read8(&self, address: u16) -> u8 {
+        match READ_MASKS.get(usize::from(address.wrapping_sub(0xFF10))) {
+            Some(&mask) => self.register(address) | mask,
+            None => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        let between_steps = self.between_length_steps();
+        match address {
+            0xFF10 => {
+                let sweep = &mut self.channel_one.sweep;
+                sweep.set_time((val >> 4) & 0x7);
+                sweep.set_direction((val >> 3) & 0x1);
+                sweep.set_shift(val & 0x7);
+            }
+            0xFF11 => {
+                write_length_pattern(&mut self.channel_one.length_pattern, val);
+                self.channel_one.length_counter.set_length(val & 0x3F);
+            }
+            0xFF12 => write_envelope(&mut self.channel_one.envelope, val),
+            0xFF13 => self.channel_one.frequency.set_frequency_low(val),
+            0xFF14 => {
+                let one = &mut self.channel_one;
+                write_frequency_high(&mut one.frequency, val);
+                write_length_control(&mut one.length_counter, &mut one.active, val, between_steps);
+            }
+            0xFF16 => {
+                write_length_pattern(&mut self.channel_two.length_pattern, val);
+                self.channel_two.length_counter.set_length(val & 0x3F);
+            }
+            0xFF17 => write_envelope(&mut self.channel_two.envelope, val),
+            0xFF18 => self.channel_two.frequency.set_frequency_low(val),
+            0xFF19 => {
+                let two = &mut self.channel_two;
+                write_frequency_high(&mut two.frequency, val);
+                write_length_control(&mut two.length_counter, &mut two.active, val, between_steps);
+            }
+            0xFF1A => self.channel_three.set_enable(val >> 7),
+            0xFF1B => {
+                self.channel_three.set_length(val);
+                self.channel_three.length_counter.set_length(val);
+            }
+            0xFF1C => self.channel_three.set_level((val >> 5) & 0x3),
+            0xFF1D => self.channel_three.frequency.set_frequency_low(val),
+            0xFF1E => {
+                let three = &mut self.channel_three;
+                write_frequency_high(&mut three.frequency, val);
+                write_length_control(
+                    &mut three.length_counter,
+                    &mut three.active,
+                    val,
+                    between_steps,
+                );
+            }
+            0xFF20 => {
+                self.channel_four.set_length(val & 0x3F);
+                self.channel_four.length_counter.set_length(val & 0x3F);
+            }
+            0xFF21 => write_envelope(&mut self.channel_four.envelope, val),
+            0xFF22 => {
+                let counter = &mut self.channel_four.counter;
+                counter.set_frequency(val >> 4);
+                counter.set_width((val >> 3) & 0x1);
+                counter.set_ratio(val & 0x7);
+            }
+            0xFF23 => {
+                let four = &mut self.channel_four;
+                four.set_start(val >> 7);
+                four.set_stop_on_length((val >> 6) & 0x1);
+                write_length_control(
+                    &mut four.length_counter,
+                    &mut four.active,
+                    val,
+                    between_steps,
+                );
+            }
+            0xFF24 => {
+                let volume = &mut self.control.volume;
+                volume.set_vin_left(val >> 7);
+                volume.set_left((val >> 4) & 0x7);
+                volume.set_vin_right((val >> 3) & 0x1);
+                volume.set_right(val & 0x7);
+            }
+            0xFF25 => self.control.channel_enable.set_enable(val),
+            0xFF26 => {
+                let enable = val >> 7 != 0;
+                if enable && !self.control.enable {
+                    // The frame sequencer starts over when the APU is turned on.
+                    self.frame_step = 0;
+                } else if !enable {
+                    self.channel_one.active = false;
+                    self.channel_two.active = false;
+                    self.channel_three.active = false;
+                    self.channel_four.active = false;
+                }
+                self.control.set_enable(val >> 7);
+            }
+            _ => {}
+        }
+        // Turning a DAC off turns its channel off, and triggering a channel doesn't turn it on
+        // while its DAC is off.
+        self.channel_one.active &= self.channel_one.dac_enabled();
+        self.channel_two.active &= self.channel_two.dac_enabled();
+        self.channel_three.active &= self.channel_three.dac_enabled();
+        self.channel_four.active &= self.channel_four.dac_enabled();
+    }
 }
 
 impl Apu {
-    pub fn new(audio: sdl2::AudioSubsystem) -> Self {
+    pub fn new(audio: sdl2::AudioSubsystem) -> Result<(Self, AudioOutput), String> {
         let desired_spec = sdl2::audio::AudioSpecDesired {
             freq: Some(44100),
             channels: Some(2),
             samples: None,
         };
 
-        let device = audio
-            .open_playback(None, &desired_spec, |spec| APUSamples {
+        let mut shared = None;
+        let device = audio.open_playback(None, &desired_spec, |spec| {
+            let samples = Arc::new(Mutex::new(APUSamples {
                 left: VecDeque::new(),
                 right: VecDeque::new(),
                 device_freq: spec.freq as f32,
@@ -645,21 +883,28 @@ impl Apu {
                     u64::from(spec.samples) * 1_000_000 / (spec.freq as u64),
                 ),
                 update_samples: usize::from(spec.samples),
-            })
-            .unwrap();
+            }));
+            shared = Some(samples.clone());
+            Playback(samples)
+        })?;
         device.resume();
 
-        Self {
+        let apu = Self {
             channel_one: ChannelOne::new(),
             channel_two: ChannelTwo::new(),
             channel_three: ChannelThree::new(),
             channel_four: ChannelFour::new(),
             control: Control::new(),
-            device: Some(device),
-            last_update: time::Instant::now(),
-        }
+            samples: shared,
+            last_update: time::Duration::from_secs(0),
+            frame_step: 0,
+            scope: None,
+            speedup: Some(1),
+        };
+        Ok((apu, AudioOutput { _device: device }))
     }
 
+    /// Without an audio device. The registers work as usual, but nothing is played.
     pub fn new_fake() -> Self {
         Self {
             channel_one: ChannelOne::new(),
@@ -667,23 +912,138 @@ impl Apu {
             channel_three: ChannelThree::new(),
             channel_four: ChannelFour::new(),
             control: Control::new(),
-            device: None,
-            last_update: time::Instant::now(),
+            samples: None,
+            last_update: time::Duration::from_secs(0),
+            frame_step: 0,
+            scope: None,
+            speedup: Some(1),
+        }
+    }
+
+    /// Reads a register with its write-only bits as they were written, for save states.
+    pub fn register(&self, address: u16) -> u8 {
+        let one = &self.channel_one;
+        let two = &self.channel_two;
+        let three = &self.channel_three;
+        let four = &self.channel_four;
+        match address {
+            0xFF10 => one.sweep.time() << 4 | one.sweep.direction() << 3 | one.sweep.shift(),
+            0xFF11 => read_length_pattern(&one.length_pattern),
+            0xFF12 => read_envelope(&one.envelope),
+            0xFF13 => one.frequency.frequency_low(),
+            0xFF14 => read_frequency_high(&one.frequency),
+            0xFF16 => read_length_pattern(&two.length_pattern),
+            0xFF17 => read_envelope(&two.envelope),
+            0xFF18 => two.frequency.frequency_low(),
+            0xFF19 => read_frequency_high(&two.frequency),
+            0xFF1A => three.enable() << 7,
+            0xFF1B => three.length(),
+            0xFF1C => three.level() << 5,
+            0xFF1D => three.frequency.frequency_low(),
+            0xFF1E => read_frequency_high(&three.frequency),
+            0xFF20 => four.length(),
+            0xFF21 => read_envelope(&four.envelope),
+            0xFF22 => {
+                four.counter.frequency() << 4 | four.counter.width() << 3 | four.counter.ratio()
+            }
+            0xFF23 => u8::from(four.start) << 7 | four.stop_on_length() << 6,
+            0xFF24 => {
+                let volume = &self.control.volume;
+                volume.vin_left() << 7
+                    | volume.left() << 4
+                    | volume.vin_right() << 3
+                    | volume.right()
+            }
+            0xFF25 => self.control.channel_enable.enable(),
+            0xFF26 => {
+                self.control.enable() << 7
+                    | four.active() << 3
+                    | three.active() << 2
+                    | two.active() << 1
+                    | one.active()
+            }
+            _ => 0xFF,
         }
     }
 
-    pub fn step(&mut self) {
-        if let Some(ref mut device) = self.device {
-            let mut samples = device.lock();
-            if time::Instant::now().duration_since(self.last_update) > samples.update_interval {
-                self.last_update = time::Instant::now();
+    /// The boot sound ends with channel one still on, its envelope having faded it out. Writing
+    /// NR14 to trigger it would start it over, so it's set up directly.
+    pub fn skip_boot(&mut self) {
+        let one = &mut self.channel_one;
+        one.active = one.dac_enabled();
+        one.envelope.current_volume = 0;
+    }
+
+    /// Advances the frame sequencer, which the divider clocks 512 times a second.
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.control.enable {
+            return;
+        }
+        if self.frame_step.is_multiple_of(2) {
+            if self.channel_one.length_counter.clock() {
+                self.channel_one.active = false;
+            }
+            if self.channel_two.length_counter.clock() {
+                self.channel_two.active = false;
+            }
+            if self.channel_three.length_counter.clock() {
+                self.channel_three.active = false;
+            }
+            if self.channel_four.length_counter.clock() {
+                self.channel_four.active = false;
+            }
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // Whether the frame sequencer's next step won't clock the length counters.
+    fn between_length_steps(&self) -> bool {
+        !self.frame_step.is_multiple_of(2)
+    }
+
+    /// Sends a rolling waveform of each channel to `window`, which should be `SCOPE_WIDTH` by
+    /// `SCOPE_HEIGHT`.
+    pub fn open_scope(&mut self, window: FrameSender) {
+        self.scope = Some(scope::Scope::new(window));
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speedup = match speed {
+            Speed::Times(n) => Some(n.max(1) as usize),
+            Speed::Uncapped => None,
+        };
+        self.flush();
+    }
+
+    /// Drops any samples that haven't been played yet.
+    pub fn flush(&mut self) {
+        if let Some(ref shared) = self.samples {
+            let mut samples = shared.lock().unwrap();
+            samples.left.clear();
+            samples.right.clear();
+        }
+    }
+
+    pub fn step(&mut self, clock: &dyn Clock) {
+        let speedup = match self.speedup {
+            Some(speedup) => speedup,
+            None => return,
+        };
+        if let Some(ref shared) = self.samples {
+            let mut samples = shared.lock().unwrap();
+            let now = clock.now();
+            if now > self.last_update + samples.update_interval {
+                self.last_update = now;
                 while samples.right.len() < 2 * samples.update_samples {
-                    let mut channel_one_samples = self
-                        .channel_one
-                        .get_samples(samples.update_samples, samples.device_freq);
-                    let mut channel_two_samples = self
-                        .channel_two
-                        .get_samples(samples.update_samples, samples.device_freq);
+                    let count = samples.update_samples * speedup;
+                    let channel_one_samples = squeeze(
+                        &self.channel_one.get_samples(count, samples.device_freq),
+                        speedup,
+                    );
+                    let channel_two_samples = squeeze(
+                        &self.channel_two.get_samples(count, samples.device_freq),
+                        speedup,
+                    );
                     for i in 0..samples.update_samples {
                         let mut left_sample = 0.0;
                         let mut right_sample = 0.0;
@@ -715,11 +1075,158 @@ impl Apu {
                         {
                             right_sample += 0.25 * channel_two_samples[i];
                         }
+                        let (vin_left, vin_right) = self.control.volume.mix_vin(VIN_SAMPLE);
+                        left_sample += 0.25 * vin_left;
+                        right_sample += 0.25 * vin_right;
                         samples.left.push_back(left_sample);
                         samples.right.push_back(right_sample);
                     }
+                    if let Some(ref mut scope) = self.scope {
+                        scope.push(0, &channel_one_samples);
+                        scope.push(1, &channel_two_samples);
+                    }
                 }
             }
         }
+        let now = clock.now();
+        if self.scope.as_ref().is_some_and(|scope| scope.due(now)) {
+            let title = self.describe_channels().replace('\n', " | ");
+            if let Some(ref mut scope) = self.scope {
+                scope.draw(&title, now);
+            }
+        }
+    }
+}
+
+// Resamples to `factor` times fewer samples, averaging each run of `factor` into one.
+fn squeeze(samples: &[f32], factor: usize) -> Vec<f32> {
+    samples
+        .chunks(factor)
+        .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
+        .collect()
+}
+
+// Register values are saved by `Peripherals`, and this is the state that isn't visible through them.
+impl Snapshot for Apu {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.frame_step);
+        self.channel_one.length_counter.save(w);
+        w.bool(self.channel_one.active);
+        self.channel_two.length_counter.save(w);
+        w.bool(self.channel_two.active);
+        self.channel_three.length_counter.save(w);
+        w.bool(self.channel_three.active);
+        self.channel_four.length_counter.save(w);
+        w.bool(self.channel_four.active);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.frame_step = r.u8()? % 8;
+        self.channel_one.length_counter.load(r)?;
+        self.channel_one.active = r.bool()?;
+        self.channel_two.length_counter.load(r)?;
+        self.channel_two.active = r.bool()?;
+        self.channel_three.length_counter.load(r)?;
+        self.channel_three.active = r.bool()?;
+        self.channel_four.length_counter.load(r)?;
+        self.channel_four.active = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_turns_channel_off() {
+        let mut apu = Apu::new_fake();
+        apu.write8(0xFF26, 0x80);
+        apu.write8(0xFF12, 0xF0);
+        apu.write8(0xFF17, 0xF0);
+        // Three steps of length, enabled and triggered just before a length step.
+        apu.write8(0xFF11, 61);
+        apu.write8(0xFF14, 0xC0);
+        assert_eq!(apu.read8(0xFF26) & 0x1, 1);
+        for _ in 0..4 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.read8(0xFF26) & 0x1, 1);
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read8(0xFF26) & 0x1, 0);
+
+        // Triggered between length steps, the first is taken straight away, so it's off after
+        // the next two.
+        apu.write8(0xFF16, 61);
+        apu.write8(0xFF19, 0xC0);
+        for _ in 0..3 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.read8(0xFF26) & 0x2, 0x2);
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read8(0xFF26) & 0x2, 0);
+    }
+
+    #[test]
+    fn dac_gates_channel() {
+        let mut apu = Apu::new_fake();
+        apu.write8(0xFF26, 0x80);
+        // With NR12 all zero, the DAC is off, and triggering does nothing.
+        apu.write8(0xFF14, 0x80);
+        assert_eq!(apu.read8(0xFF26) & 0x1, 0);
+        // Increasing from zero volume is enough to turn it on.
+        apu.write8(0xFF12, 0x08);
+        apu.write8(0xFF14, 0x80);
+        assert_eq!(apu.read8(0xFF26) & 0x1, 1);
+        apu.write8(0xFF12, 0x00);
+        assert_eq!(apu.read8(0xFF26) & 0x1, 0);
+
+        apu.write8(0xFF1A, 0x80);
+        apu.write8(0xFF1E, 0x80);
+        assert_eq!(apu.read8(0xFF26) & 0x4, 0x4);
+        apu.write8(0xFF1A, 0x00);
+        assert_eq!(apu.read8(0xFF26) & 0x4, 0);
+    }
+
+    #[test]
+    fn vin_bits() {
+        let mut apu = Apu::new_fake();
+        apu.write8(0xFF24, 0x8B);
+        assert_eq!(apu.read8(0xFF24), 0x8B);
+        assert_eq!(apu.control.volume.mix_vin(1.0), (1.0, 1.0));
+        apu.write8(0xFF24, 0x77);
+        assert_eq!(apu.read8(0xFF24), 0x77);
+        assert_eq!(apu.control.volume.mix_vin(1.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn write_only_bits_read_as_one() {
+        let mut apu = Apu::new_fake();
+        apu.write8(0xFF26, 0x80);
+        apu.write8(0xFF11, 0x80);
+        apu.write8(0xFF13, 0x12);
+        apu.write8(0xFF14, 0x45);
+        assert_eq!(apu.read8(0xFF11), 0xBF);
+        assert_eq!(apu.read8(0xFF13), 0xFF);
+        assert_eq!(apu.read8(0xFF14), 0xFF);
+        assert_eq!(apu.read8(0xFF15), 0xFF);
+        // They're still there for save states.
+        assert_eq!(apu.register(0xFF13), 0x12);
+        assert_eq!(apu.register(0xFF14), 0x45);
+    }
+
+    #[test]
+    fn squeezes_samples_for_speed() {
+        assert_eq!(
+            squeeze(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], 4),
+            vec![1.5, 5.5]
+        );
+        assert_eq!(squeeze(&[0.5, 0.25], 1), vec![0.5, 0.25]);
+
+        let mut apu = Apu::new_fake();
+        apu.set_speed(Speed::Times(4));
+        assert_eq!(apu.speedup, Some(4));
+        apu.set_speed(Speed::Uncapped);
+        assert_eq!(apu.speedup, None);
     }
 }
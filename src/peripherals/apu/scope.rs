@@ -0,0 +1,71 @@
+//! Oscilloscope debug window for the APU. Shows a rolling waveform for each channel that's
+//! synthesized, with all four channels' frequency, volume, and duty in the window title.
+use peripherals::ppu::FrameSender;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Only the square channels are synthesized. The wave and noise channels get strips once they are.
+const CHANNELS: usize = 2;
+// One sample per pixel, so at 44.1kHz this covers about 12ms.
+pub const WIDTH: usize = 512;
+const STRIP_HEIGHT: usize = 64;
+pub const HEIGHT: usize = STRIP_HEIGHT * CHANNELS;
+// No point redrawing faster than the main window.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(16);
+
+const COLORS: [(u8, u8, u8); CHANNELS] = [(255, 96, 96), (96, 255, 96)];
+
+pub struct Scope {
+    window: FrameSender,
+    history: Vec<VecDeque<f32>>,
+    last_draw: Duration,
+}
+
+impl Scope {
+    pub fn new(window: FrameSender) -> Self {
+        Self {
+            window,
+            history: vec![VecDeque::from(vec![0.0; WIDTH]); CHANNELS],
+            last_draw: Duration::from_secs(0),
+        }
+    }
+
+    /// Adds samples (0.0-1.0) for a channel, dropping the oldest ones off the left of the window.
+    pub fn push(&mut self, channel: usize, samples: &[f32]) {
+        let history = &mut self.history[channel];
+        history.extend(samples.iter().cloned());
+        while history.len() > WIDTH {
+            history.pop_front();
+        }
+    }
+
+    /// Whether it's time to redraw, with `now` from the APU's clock.
+    pub fn due(&self, now: Duration) -> bool {
+        now >= self.last_draw + REDRAW_INTERVAL
+    }
+
+    pub fn draw(&mut self, title: &str, now: Duration) {
+        self.last_draw = now;
+        self.window.set_title(title.to_string());
+        let history = &self.history;
+        self.window.send(|rgb| {
+            rgb.resize(WIDTH * HEIGHT * 3, 0);
+            for (channel, samples) in history.iter().enumerate() {
+                let top = channel * STRIP_HEIGHT + 1;
+                let span = (STRIP_HEIGHT - 2) as f32;
+                let mut last_y = None;
+                for (x, sample) in samples.iter().enumerate() {
+                    let y = top + (span * (1.0 - sample.clamp(0.0, 1.0))) as usize;
+                    // Join each sample to the last with a vertical line, so edges show up.
+                    let from = last_y.unwrap_or(y);
+                    for line in from.min(y)..=from.max(y) {
+                        let offset = (line * WIDTH + x) * 3;
+                        let (r, g, b) = COLORS[channel];
+                        rgb[offset..offset + 3].copy_from_slice(&[r, g, b]);
+                    }
+                    last_y = Some(y);
+                }
+            }
+        });
+    }
+}
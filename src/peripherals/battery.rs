@@ -0,0 +1,125 @@
+//! Writes .sav files on a thread of their own, so that a slow disk doesn't stall emulation while
+//! battery-backed RAM is saved during play.
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+struct Save {
+    path: PathBuf,
+    data: Vec<u8>,
+    // Gets the result, for saves that are waited on. Others just log errors.
+    done: Option<mpsc::Sender<io::Result<()>>>,
+}
+
+// The thread's only started on the first save, since most cartridges never have one.
+#[derive(Default)]
+pub struct BatteryWriter {
+    saves: Option<mpsc::Sender<Save>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl BatteryWriter {
+    /// Writes `data` to `path` in the background.
+    pub fn save(&mut self, path: PathBuf, data: Vec<u8>) {
+        self.send(Save {
+            path,
+            data,
+            done: None,
+        });
+    }
+
+    /// Writes `data` to `path` once any saves already in the background are done, and waits for
+    /// it to finish.
+    pub fn save_now(&mut self, path: PathBuf, data: Vec<u8>) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.send(Save {
+            path,
+            data,
+            done: Some(tx),
+        });
+        rx.recv()
+            .unwrap_or_else(|_| Err(io::Error::other("The .sav writer has stopped")))
+    }
+
+    fn send(&mut self, save: Save) {
+        if self.saves.is_none() {
+            let (tx, thread) = spawn();
+            self.saves = Some(tx);
+            self.thread = Some(thread);
+        }
+        let saves = self
+            .saves
+            .as_ref()
+            .expect("The .sav writer was just started");
+        if let Err(mpsc::SendError(save)) = saves.send(save) {
+            if let Some(done) = save.done {
+                let _ = done.send(Err(io::Error::other("The .sav writer has stopped")));
+            }
+        }
+    }
+}
+
+// Saves still in the background when the emulator goes away are finished first.
+impl Drop for BatteryWriter {
+    fn drop(&mut self) {
+        self.saves = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn spawn() -> (mpsc::Sender<Save>, thread::JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel::<Save>();
+    let thread = thread::spawn(move || {
+        for save in rx {
+            let result = fs::write(&save.path, &save.data);
+            match save.done {
+                Some(done) => {
+                    let _ = done.send(result);
+                }
+                None => {
+                    if let Err(err) = result {
+                        error!(
+                            "Could not save cartridge RAM to {}: {}",
+                            save.path.display(),
+                            err
+                        );
+                    }
+                }
+            }
+        }
+    });
+    (tx, thread)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn waits_for_background_saves() {
+        let path = env::temp_dir().join(format!("wolfwig_battery_writer_{}.sav", process::id()));
+        let mut writer = BatteryWriter::default();
+        for i in 0..10 {
+            writer.save(path.clone(), vec![i; 0x2000]);
+        }
+        writer.save_now(path.clone(), vec![0xAA; 4]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![0xAA; 4]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn finishes_saves_when_dropped() {
+        let path = env::temp_dir().join(format!("wolfwig_battery_dropped_{}.sav", process::id()));
+        let mut writer = BatteryWriter::default();
+        writer.save(path.clone(), vec![0x55; 0x8000]);
+        drop(writer);
+        assert_eq!(fs::read(&path).unwrap(), vec![0x55; 0x8000]);
+        fs::remove_file(path).unwrap();
+    }
+}
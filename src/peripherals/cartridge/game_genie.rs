@@ -0,0 +1,243 @@
+//! Game Genie codes, which patch the ROM as it's read. The Game Genie sits between the cartridge
+//! and the Game Boy, so it's a cartridge that wraps another one.
+use peripherals::cartridge::{Cartridge, RTC_TRAILER_BYTES};
+use savestate::{Reader, Snapshot, Writer};
+use std::fmt;
+use std::io;
+
+/// A code, written `ABC-DEF-GHI`: read AB from address 0xFCDE ^ 0xF000, but only if the ROM has
+/// `(GI >> 2 | GI << 6) ^ 0xBA` there. H is a check digit, and isn't checked. The six digit form,
+/// `ABC-DEF`, replaces the byte whatever it was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Code {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Code {
+    pub fn parse(code: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Bad Game Genie code {}: should look like ABC-DEF-GHI or ABC-DEF",
+                    code
+                ),
+            )
+        };
+        let groups: Vec<&str> = code.split('-').collect();
+        if !matches!(groups.len(), 2 | 3) || groups.iter().any(|group| group.len() != 3) {
+            return Err(invalid());
+        }
+        let digits = groups
+            .concat()
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let value = digits[0] << 4 | digits[1];
+        let address = u16::from(digits[5] ^ 0xF) << 12
+            | u16::from(digits[2]) << 8
+            | u16::from(digits[3]) << 4
+            | u16::from(digits[4]);
+        let compare = digits
+            .get(8)
+            .map(|&i| (digits[6] << 4 | i).rotate_right(2) ^ 0xBA);
+        Ok(Self {
+            address,
+            value,
+            compare,
+        })
+    }
+
+    fn patch(&self, address: u16, val: u8) -> Option<u8> {
+        match self.compare {
+            _ if address != self.address => None,
+            Some(compare) if compare != val => None,
+            _ => Some(self.value),
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let address = self.address ^ 0xF000;
+        write!(
+            f,
+            "{:02X}{:X}-{:03X}",
+            self.value,
+            address >> 8 & 0xF,
+            (address & 0xFF) << 4 | address >> 12
+        )?;
+        if let Some(compare) = self.compare {
+            let gi = (compare ^ 0xBA).rotate_left(2);
+            // H isn't checked, so any digit will do.
+            write!(f, "-{:X}{:X}{:X}", gi >> 4, 0xA, gi & 0xF)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct GameGenie {
+    cartridge: Box<dyn Cartridge>,
+    codes: Vec<Code>,
+}
+
+impl GameGenie {
+    pub fn new(cartridge: Box<dyn Cartridge>) -> Self {
+        Self {
+            cartridge,
+            codes: vec![],
+        }
+    }
+
+    /// Adds a code, unless it's already there.
+    pub fn add(&mut self, code: &str) -> io::Result<Code> {
+        let code = Code::parse(code)?;
+        if !self.codes.contains(&code) {
+            self.codes.push(code);
+        }
+        Ok(code)
+    }
+
+    /// Returns the code, if it was there to remove.
+    pub fn remove(&mut self, code: &str) -> Option<Code> {
+        let code = Code::parse(code).ok()?;
+        let before = self.codes.len();
+        self.codes.retain(|&other| other != code);
+        if self.codes.len() != before {
+            Some(code)
+        } else {
+            None
+        }
+    }
+
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+}
+
+impl Cartridge for GameGenie {
+    fn read(&self, address: u16) -> u8 {
+        let val = self.cartridge.read(address);
+        if address >= 0x8000 {
+            return val;
+        }
+        self.codes
+            .iter()
+            .find_map(|code| code.patch(address, val))
+            .unwrap_or(val)
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        self.cartridge.write(address, val)
+    }
+
+    fn rom_bank(&self) -> u16 {
+        self.cartridge.rom_bank()
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.cartridge.ram()
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.cartridge.ram_mut()
+    }
+
+    fn ram_dirty(&self) -> bool {
+        self.cartridge.ram_dirty()
+    }
+
+    fn set_ram_dirty(&mut self, dirty: bool) {
+        self.cartridge.set_ram_dirty(dirty)
+    }
+
+    fn step(&mut self, cycles: u64) {
+        self.cartridge.step(cycles)
+    }
+
+    fn next_tick(&self) -> Option<u64> {
+        self.cartridge.next_tick()
+    }
+
+    fn rtc_trailer(&self, now: u64) -> Option<[u8; RTC_TRAILER_BYTES]> {
+        self.cartridge.rtc_trailer(now)
+    }
+
+    fn load_rtc_trailer(&mut self, trailer: &[u8; RTC_TRAILER_BYTES], now: u64) {
+        self.cartridge.load_rtc_trailer(trailer, now)
+    }
+}
+
+// Codes are the player's choice rather than part of the game's state, so they aren't saved.
+impl Snapshot for GameGenie {
+    fn save(&self, w: &mut Writer) {
+        self.cartridge.save(w)
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.cartridge.load(r)
+    }
+}
+
+impl fmt::Display for GameGenie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.cartridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::cartridge;
+
+    #[test]
+    fn parse() {
+        let code = Code::parse("00A-17B-C49").unwrap();
+        assert_eq!(
+            code,
+            Code {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }
+        );
+        assert_eq!(Code::parse(&code.to_string()).unwrap(), code);
+        assert_eq!(
+            Code::parse("3E5-01F").unwrap(),
+            Code {
+                address: 0x0501,
+                value: 0x3E,
+                compare: None,
+            }
+        );
+
+        assert!(Code::parse("00A-17B-C4").is_err());
+        assert!(Code::parse("00A17BC49").is_err());
+        assert!(Code::parse("00A-17B-C4X").is_err());
+    }
+
+    #[test]
+    fn patches_reads() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4A17] = 0xC8;
+        rom[0x0501] = 0x12;
+        let mut genie = GameGenie::new(cartridge::new(vec![], rom));
+        genie.add("00A-17B-C49").unwrap();
+        genie.add("3E5-01F").unwrap();
+        assert_eq!(genie.read(0x4A17), 0x00);
+        assert_eq!(genie.read(0x0501), 0x3E);
+        assert_eq!(genie.read(0x0502), 0x00);
+
+        // Codes whose compare byte doesn't match leave the ROM alone.
+        genie.add("99A-18B-C49").unwrap();
+        assert_eq!(genie.read(0x4A18), 0x00);
+
+        assert_eq!(genie.remove("00A-17B-C49").unwrap().address, 0x4A17);
+        assert_eq!(genie.remove("00A-17B-C49"), None);
+        assert_eq!(genie.read(0x4A17), 0xC8);
+        assert_eq!(genie.codes().len(), 2);
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io;
 use std::str;
 use util;
 
@@ -16,7 +17,7 @@ const DESTINATION_CODE: (usize, usize) = (0x014A, 0x014A);
 const LICENSEE: (usize, usize) = (0x014B, 0x014B);
 const ROM_VERSION: (usize, usize) = (0x014C, 0x014C);
 const HEADER_CHECKSUM: (usize, usize) = (0x014D, 0x014D);
-const GLOBAL_CHECKSUM: (usize, usize) = (0x014E, 0x014E);
+const GLOBAL_CHECKSUM: (usize, usize) = (0x014E, 0x014F);
 const BIT_MASKS: [u8; 8] = [1 << 7, 1 << 6, 1 << 5, 1 << 4, 1 << 3, 1 << 2, 1 << 1, 1];
 
 #[derive(Debug)]
@@ -51,6 +52,26 @@ pub enum CartridgeType {
     HuC1RamBattery,
 }
 
+impl CartridgeType {
+    /// Whether the cartridge's RAM is kept by a battery, so should be saved between runs.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc1RamBattery
+                | CartridgeType::Mbc2Battery
+                | CartridgeType::RomRamBattery
+                | CartridgeType::Mmm01RamBattery
+                | CartridgeType::Mbc3TimerBattery
+                | CartridgeType::Mbc3TimerBatteryRam
+                | CartridgeType::Mbc3RamBattery
+                | CartridgeType::Mbc5RamBattery
+                | CartridgeType::Mbc5RumbleRamBattery
+                | CartridgeType::Mbc7SensorRumbleRamBattery
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+}
+
 pub struct Header {
     nintendo: Vec<u8>,
     title: String,
@@ -64,7 +85,10 @@ pub struct Header {
     destination_code: bool,
     rom_version: u8,
     header_checksum: u8,
-    global_checksum: u8,
+    global_checksum: u16,
+    // What the checksums should be, given the rest of the ROM.
+    expected_header_checksum: u8,
+    expected_global_checksum: u16,
 }
 
 impl Header {
@@ -83,11 +107,82 @@ impl Header {
             ram_size: bytes[RAM_SIZE.0],
             destination_code: bytes[DESTINATION_CODE.0] == 0,
             rom_version: bytes[ROM_VERSION.0],
-            // TODO(slongfield): Verify checksum validity.
             header_checksum: bytes[HEADER_CHECKSUM.0],
-            global_checksum: bytes[GLOBAL_CHECKSUM.0],
+            global_checksum: util::bytes_to_u16(&bytes[GLOBAL_CHECKSUM.0..(GLOBAL_CHECKSUM.1 + 1)]),
+            expected_header_checksum: header_checksum(bytes),
+            expected_global_checksum: global_checksum(bytes),
+        }
+    }
+
+    /// Size of the ROM the header declares, if the code is a known one.
+    pub fn rom_bytes(&self) -> Option<usize> {
+        match self.rom_size {
+            code @ 0x00..=0x08 => Some(0x8000 << code),
+            _ => None,
+        }
+    }
+
+    /// Size of the cartridge RAM the header declares, if the code is a known one.
+    pub fn ram_bytes(&self) -> Option<usize> {
+        match self.ram_size {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        }
+    }
+
+    /// Errors if the header checksum is wrong, which the boot ROM refuses to start.
+    pub fn check_header_checksum(&self) -> io::Result<()> {
+        if self.header_checksum == self.expected_header_checksum {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Header checksum is 0x{:02x}, but the header adds up to 0x{:02x}",
+                    self.header_checksum, self.expected_header_checksum
+                ),
+            ))
         }
     }
+
+    /// Whether the global checksum matches the ROM. Nothing on the hardware checks this one, so
+    /// plenty of homebrew gets it wrong.
+    pub fn global_checksum_ok(&self) -> bool {
+        self.global_checksum == self.expected_global_checksum
+    }
+
+    /// Names the game, for files that are kept about it, from its title and global checksum, like
+    /// `TETRIS_16BF`. Anything in the title that doesn't belong in a file name becomes `_`.
+    pub fn game_id(&self) -> String {
+        let title: String = self
+            .title
+            .trim_end_matches('\0')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_{:04X}", title, self.global_checksum)
+    }
+}
+
+/// The header checksum covers the title through the ROM version.
+fn header_checksum(bytes: &[u8]) -> u8 {
+    bytes[TITLE.0..(ROM_VERSION.1 + 1)]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+/// The global checksum is the sum of every byte in the ROM, except its own.
+fn global_checksum(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|&(addr, _)| !(GLOBAL_CHECKSUM.0..=GLOBAL_CHECKSUM.1).contains(&addr))
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(u16::from(byte)))
 }
 
 ///! Decodes the licensee codes.
@@ -206,7 +301,59 @@ impl fmt::Display for Header {
         writeln!(f, "RAM size: 0x{:02x}", self.ram_size)?;
         writeln!(f, "ROM version: 0x{:02x}", self.rom_version)?;
         writeln!(f, "Japan-only?: {}", self.destination_code)?;
-        writeln!(f, "Header checksum: 0x{:02x}", self.header_checksum)?;
-        writeln!(f, "Global checksum: 0x{:02x}", self.global_checksum)
+        writeln!(
+            f,
+            "Header checksum: 0x{:02x}{}",
+            self.header_checksum,
+            mismatch(self.header_checksum, self.expected_header_checksum)
+        )?;
+        writeln!(
+            f,
+            "Global checksum: 0x{:04x}{}",
+            self.global_checksum,
+            mismatch(self.global_checksum, self.expected_global_checksum)
+        )
+    }
+}
+
+fn mismatch<T: PartialEq + fmt::LowerHex>(found: T, expected: T) -> String {
+    if found == expected {
+        String::new()
+    } else {
+        format!(" (should be 0x{:x})", expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE.0..TITLE.0 + 4].copy_from_slice(b"TEST");
+        rom[0x200] = 0x12;
+        rom[HEADER_CHECKSUM.0] = header_checksum(&rom);
+        let global = global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM.0] = (global >> 8) as u8;
+        rom[GLOBAL_CHECKSUM.1] = global as u8;
+
+        let header = Header::new(&rom);
+        assert!(header.check_header_checksum().is_ok());
+        assert!(header.global_checksum_ok());
+
+        rom[TITLE.0] = b'B';
+        let header = Header::new(&rom);
+        assert!(header.check_header_checksum().is_err());
+        assert!(!header.global_checksum_ok());
+    }
+
+    #[test]
+    fn game_id() {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE.0..TITLE.0 + 8].copy_from_slice(b"MY GAME!");
+        rom[GLOBAL_CHECKSUM.0] = 0x16;
+        rom[GLOBAL_CHECKSUM.1] = 0xBF;
+        assert_eq!(Header::new(&rom).game_id(), "MY_GAME__16BF");
     }
 }
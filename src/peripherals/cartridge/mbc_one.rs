@@ -1,25 +1,33 @@
 ///!Model of an MBC1 cartridge.
 use peripherals::cartridge::header;
 use peripherals::cartridge::Cartridge;
+use savestate::{Reader, Snapshot, Writer};
 use std::fmt;
+use std::io;
 
 pub struct MbcOne {
     bootrom: Vec<u8>,
     rom: Vec<u8>,
     bootrom_disabled: bool,
     ram: Vec<u8>,
+    ram_dirty: bool,
+    // The RAM is cut off until 0x0A is written to 0x0000-0x1FFF.
+    ram_enabled: bool,
     rom_bank: u8,
     ram_bank: u8,
     rom_ram_mode: bool,
 }
 
 impl MbcOne {
-    pub fn new(bootrom: Vec<u8>, rom: Vec<u8>) -> Self {
+    /// `ram_bytes` is the RAM size from the header, zero if the cartridge has none.
+    pub fn new(bootrom: Vec<u8>, rom: Vec<u8>, ram_bytes: usize) -> Self {
         Self {
             bootrom,
             rom,
             bootrom_disabled: false,
-            ram: vec![0; 0x2000],
+            ram: vec![0; ram_bytes],
+            ram_dirty: false,
+            ram_enabled: false,
             rom_bank: 1,
             ram_bank: 0,
             rom_ram_mode: false,
@@ -27,6 +35,20 @@ impl MbcOne {
     }
 }
 
+impl MbcOne {
+    /// Where `address` in 0xA000-0xBFFF lands in the RAM, or None if it's past the end of it.
+    /// The RAM bank register only applies in RAM banking mode.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        let bank = if self.rom_ram_mode { self.ram_bank } else { 0 };
+        let offset = usize::from(bank) * 0x2000 + usize::from(address - 0xA000);
+        if offset < self.ram.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+}
+
 impl Cartridge for MbcOne {
     fn read(&self, address: u16) -> u8 {
         match address {
@@ -38,6 +60,11 @@ impl Cartridge for MbcOne {
                 let final_addr = addr + u16::from(self.rom_bank) * 0x4000;
                 *self.rom.get(final_addr as usize).unwrap_or(&0xFF)
             }
+            0xA000..=0xBFFF if !self.ram_enabled => 0xFF,
+            addr @ 0xA000..=0xBFFF => match self.ram_offset(addr) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
             0xFF50 => 0xFF,
             _ => 0xFF,
         }
@@ -45,6 +72,7 @@ impl Cartridge for MbcOne {
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
             0x2000..=0x3FFF => {
                 if val == 0 {
                     self.rom_bank = 1;
@@ -52,12 +80,62 @@ impl Cartridge for MbcOne {
                     self.rom_bank = val - 1;
                 }
             }
-            addr @ 0x4000..=0x5FFF => println!("Write of {} to ram bank {}", val, addr),
-            addr @ 0x6000..=0x7FFF => println!("Write of {} to bank sel {}", val, addr),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x03,
+            0x6000..=0x7FFF => self.rom_ram_mode = val & 0x01 != 0,
+            0xA000..=0xBFFF if !self.ram_enabled => {}
+            addr @ 0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.ram[offset] = val;
+                    self.ram_dirty = true;
+                }
+            }
             0xFF50 => self.bootrom_disabled = val != 0,
             _ => {}
         }
     }
+
+    fn rom_bank(&self) -> u16 {
+        u16::from(self.rom_bank)
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    fn set_ram_dirty(&mut self, dirty: bool) {
+        self.ram_dirty = dirty;
+    }
+}
+
+impl Snapshot for MbcOne {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.bootrom_disabled);
+        w.bytes(&self.ram);
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+        w.u8(self.ram_bank);
+        w.bool(self.rom_ram_mode);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.bootrom_disabled = r.bool()?;
+        r.bytes_into(&mut self.ram)?;
+        // The RAM no longer matches whatever was last saved of it.
+        self.ram_dirty = true;
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+        self.ram_bank = r.u8()?;
+        self.rom_ram_mode = r.bool()?;
+        Ok(())
+    }
 }
 
 impl fmt::Display for MbcOne {
@@ -66,3 +144,57 @@ impl fmt::Display for MbcOne {
         write!(f, "{}", header)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_banks() {
+        let mut cart = MbcOne::new(vec![], vec![0; 0x8000], 0x8000);
+        cart.write(0x0000, 0x0A);
+        cart.write(0x6000, 0x01);
+        for bank in 0..4 {
+            cart.write(0x4000, bank);
+            cart.write(0xA000, bank + 1);
+        }
+        assert_eq!(cart.ram()[0x6000], 4);
+        // Simple banking mode always uses bank 0.
+        cart.write(0x6000, 0x00);
+        assert_eq!(cart.read(0xA000), 1);
+    }
+
+    #[test]
+    fn ram_size() {
+        let mut cart = MbcOne::new(vec![], vec![0; 0x8000], 0x800);
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA7FF, 0x42);
+        cart.write(0xA800, 0x42);
+        assert_eq!(cart.read(0xA7FF), 0x42);
+        assert_eq!(cart.read(0xA800), 0xFF);
+        assert_eq!(cart.ram().len(), 0x800);
+
+        let mut cart = MbcOne::new(vec![], vec![0; 0x8000], 0);
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        assert!(!cart.ram_dirty());
+    }
+
+    #[test]
+    fn ram_needs_enabling() {
+        let mut cart = MbcOne::new(vec![], vec![0; 0x8000], 0x2000);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        assert!(!cart.ram_dirty());
+
+        cart.write(0x1FFF, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0x42);
+
+        cart.write(0x0000, 0x00);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        cart.write(0xA000, 0x43);
+        assert_eq!(cart.ram()[0], 0x42);
+    }
+}
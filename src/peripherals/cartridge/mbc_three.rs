@@ -0,0 +1,242 @@
+//! Model of an MBC3 cartridge, with up to 2MiB of ROM, 32KiB of RAM, and in some, a real-time
+//! clock whose registers are banked in over the RAM.
+use peripherals::cartridge::header;
+use peripherals::cartridge::rtc::Rtc;
+use peripherals::cartridge::{Cartridge, RTC_TRAILER_BYTES};
+use savestate::{Reader, Snapshot, Writer};
+use std::fmt;
+use std::io;
+
+pub struct MbcThree {
+    bootrom: Vec<u8>,
+    rom: Vec<u8>,
+    bootrom_disabled: bool,
+    ram: Vec<u8>,
+    ram_dirty: bool,
+    // Both the RAM and the clock are cut off until 0x0A is written to 0x0000-0x1FFF, so that
+    // they aren't corrupted when the power goes.
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x00-0x03 selects a RAM bank, and 0x08-0x0C a clock register.
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+}
+
+impl MbcThree {
+    /// `ram_bytes` is the RAM size from the header, and `has_rtc` whether it says there's a clock.
+    pub fn new(bootrom: Vec<u8>, rom: Vec<u8>, ram_bytes: usize, has_rtc: bool) -> Self {
+        Self {
+            bootrom,
+            rom,
+            bootrom_disabled: false,
+            ram: vec![0; ram_bytes],
+            ram_dirty: false,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: if has_rtc { Some(Rtc::default()) } else { None },
+        }
+    }
+
+    /// Where `address` in 0xA000-0xBFFF lands in the RAM, or None if it's past the end of it.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        let offset = usize::from(self.ram_bank) * 0x2000 + usize::from(address - 0xA000);
+        if offset < self.ram.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+}
+
+impl Cartridge for MbcThree {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            addr @ 0x000..=0xFF if !self.bootrom_disabled => {
+                *self.bootrom.get(addr as usize).unwrap_or(&0xFF)
+            }
+            addr @ 0..=0x3FFF => *self.rom.get(addr as usize).unwrap_or(&0xFF),
+            addr @ 0x4000..=0x7FFF => {
+                let offset = usize::from(self.rom_bank) * 0x4000 + usize::from(addr - 0x4000);
+                *self.rom.get(offset).unwrap_or(&0xFF)
+            }
+            0xA000..=0xBFFF if !self.ram_enabled => 0xFF,
+            addr @ 0xA000..=0xBFFF => match (self.ram_bank, &self.rtc) {
+                (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_bank),
+                (0x00..=0x03, _) => match self.ram_offset(addr) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xFF,
+                },
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (val & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.latch(val);
+                }
+            }
+            0xA000..=0xBFFF if !self.ram_enabled => {}
+            addr @ 0xA000..=0xBFFF => match (self.ram_bank, &mut self.rtc) {
+                (0x08..=0x0C, Some(rtc)) => {
+                    rtc.write(self.ram_bank, val);
+                    // The clock is saved along with the RAM.
+                    self.ram_dirty = true;
+                }
+                (0x00..=0x03, _) => {
+                    if let Some(offset) = self.ram_offset(addr) {
+                        self.ram[offset] = val;
+                        self.ram_dirty = true;
+                    }
+                }
+                _ => {}
+            },
+            0xFF50 => self.bootrom_disabled = val != 0,
+            _ => {}
+        }
+    }
+
+    fn step(&mut self, cycles: u64) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.step(cycles);
+        }
+    }
+
+    fn next_tick(&self) -> Option<u64> {
+        self.rtc.as_ref().and_then(|rtc| rtc.next_tick())
+    }
+
+    fn rom_bank(&self) -> u16 {
+        u16::from(self.rom_bank)
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    fn set_ram_dirty(&mut self, dirty: bool) {
+        self.ram_dirty = dirty;
+    }
+
+    fn rtc_trailer(&self, now: u64) -> Option<[u8; RTC_TRAILER_BYTES]> {
+        self.rtc.as_ref().map(|rtc| rtc.trailer(now))
+    }
+
+    fn load_rtc_trailer(&mut self, trailer: &[u8; RTC_TRAILER_BYTES], now: u64) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load_trailer(trailer, now);
+        }
+    }
+}
+
+impl Snapshot for MbcThree {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.bootrom_disabled);
+        w.bytes(&self.ram);
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+        w.u8(self.ram_bank);
+        if let Some(ref rtc) = self.rtc {
+            rtc.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.bootrom_disabled = r.bool()?;
+        r.bytes_into(&mut self.ram)?;
+        // The RAM no longer matches whatever was last saved of it.
+        self.ram_dirty = true;
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+        self.ram_bank = r.u8()?;
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MbcThree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = header::Header::new(&self.rom);
+        write!(f, "{}", header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banks() {
+        let mut rom = vec![0; 0x80 * 0x4000];
+        rom[0x7F * 0x4000] = 0x7F;
+        rom[0x4000] = 0x01;
+        let mut cart = MbcThree::new(vec![], rom, 0x8000, true);
+        cart.write(0x2000, 0x7F);
+        assert_eq!(cart.read(0x4000), 0x7F);
+        cart.write(0x2000, 0x00);
+        assert_eq!(cart.read(0x4000), 0x01);
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x03);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.ram()[0x6000], 0x42);
+
+        // Clock registers are banked over the RAM.
+        cart.write(0x4000, 0x09);
+        cart.write(0xA000, 17);
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0xA000), 17);
+        assert_eq!(cart.ram()[0x6000], 0x42);
+        assert_eq!(cart.rtc_trailer(0).unwrap()[4], 17);
+
+        let cart = MbcThree::new(vec![], vec![0; 0x8000], 0x2000, false);
+        assert!(cart.rtc_trailer(0).is_none());
+    }
+
+    #[test]
+    fn ram_and_clock_need_enabling() {
+        let mut cart = MbcThree::new(vec![], vec![0; 0x8000], 0x2000, true);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        assert_eq!(cart.ram()[0], 0);
+        assert!(!cart.ram_dirty());
+
+        cart.write(0x1FFF, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0x42);
+        cart.write(0x4000, 0x08);
+        cart.write(0xA000, 30);
+
+        cart.write(0x0000, 0x00);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        cart.write(0xA000, 10);
+        assert_eq!(cart.rtc_trailer(0).unwrap()[0], 30);
+    }
+
+    #[test]
+    fn boot_rom_ends_before_header() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x42;
+        let cart = MbcThree::new(vec![0; 0x100], rom, 0, false);
+        assert_eq!(cart.read(0xFF), 0);
+        assert_eq!(cart.read(0x100), 0x42);
+    }
+}
@@ -1,20 +1,186 @@
 pub mod header;
 
+mod game_genie;
 mod mbc_one;
+mod mbc_three;
 mod rom_cart;
+mod rtc;
 
+pub use self::game_genie::GameGenie;
+pub use self::rtc::TRAILER_BYTES as RTC_TRAILER_BYTES;
+use savestate::Snapshot;
 use std::fmt;
+use std::io;
+
+/// Whether `new` can make a cartridge of this type.
+pub fn supported(cartridge_type: &header::CartridgeType) -> bool {
+    matches!(
+        cartridge_type,
+        header::CartridgeType::Rom
+            | header::CartridgeType::Mbc1
+            | header::CartridgeType::Mbc1Ram
+            | header::CartridgeType::Mbc1RamBattery
+            | header::CartridgeType::Mbc3
+            | header::CartridgeType::Mbc3Ram
+            | header::CartridgeType::Mbc3RamBattery
+            | header::CartridgeType::Mbc3TimerBattery
+            | header::CartridgeType::Mbc3TimerBatteryRam
+    )
+}
+
+/// Describes a ROM from its header, and checks the header against the rest of the file.
+pub fn info(rom: &[u8]) -> io::Result<String> {
+    if rom.len() < 0x150 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("ROM is only {} bytes, too short for a header", rom.len()),
+        ));
+    }
+    let header = header::Header::new(rom);
+    let mut info = header.to_string();
+    match header.rom_bytes() {
+        Some(size) if size == rom.len() => info += &format!("ROM: {} KiB\n", size / 1024),
+        Some(size) => {
+            info += &format!(
+                "ROM: header says {} KiB, but the file is {} bytes\n",
+                size / 1024,
+                rom.len()
+            )
+        }
+        None => info += "ROM: unknown size code\n",
+    }
+    match header.ram_bytes() {
+        Some(size) => info += &format!("RAM: {} KiB\n", size / 1024),
+        None => info += "RAM: unknown size code\n",
+    }
+    if let Err(err) = header.check_header_checksum() {
+        info += &format!("{}\n", err);
+    }
+    let support = if supported(&header.cartridge_type) {
+        "supported"
+    } else {
+        "not supported"
+    };
+    info += &format!("Mapper: {:?} is {}\n", header.cartridge_type, support);
+    Ok(info)
+}
 
 pub fn new(bootrom: Vec<u8>, rom: Vec<u8>) -> Box<Cartridge> {
     let header = header::Header::new(&rom);
+    let ram_bytes = || {
+        header.ram_bytes().unwrap_or_else(|| {
+            warn!("Unknown RAM size code in header, assuming no RAM");
+            0
+        })
+    };
     match header.cartridge_type {
         header::CartridgeType::Rom => Box::new(rom_cart::RomCart::new(bootrom, rom)),
-        header::CartridgeType::Mbc1 => Box::new(mbc_one::MbcOne::new(bootrom, rom)),
+        header::CartridgeType::Mbc1
+        | header::CartridgeType::Mbc1Ram
+        | header::CartridgeType::Mbc1RamBattery => {
+            Box::new(mbc_one::MbcOne::new(bootrom, rom, ram_bytes()))
+        }
+        header::CartridgeType::Mbc3
+        | header::CartridgeType::Mbc3Ram
+        | header::CartridgeType::Mbc3RamBattery => {
+            Box::new(mbc_three::MbcThree::new(bootrom, rom, ram_bytes(), false))
+        }
+        header::CartridgeType::Mbc3TimerBattery | header::CartridgeType::Mbc3TimerBatteryRam => {
+            Box::new(mbc_three::MbcThree::new(bootrom, rom, ram_bytes(), true))
+        }
         other => panic!("Unhandled cartridge type: {:?}", other),
     }
 }
 
-pub trait Cartridge: fmt::Display {
+/// Cartridges save their banking state and RAM in save states, but not the ROM itself.
+pub trait Cartridge: fmt::Display + Snapshot + Send {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, val: u8);
+
+    /// Bank currently mapped into 0x4000-0x7FFF.
+    fn rom_bank(&self) -> u16 {
+        1
+    }
+
+    /// External RAM, every bank of it, or nothing if the cartridge has none.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Like `ram`, for loading saved RAM. Doesn't mark the RAM dirty.
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// Whether the RAM has changed since it was last marked clean, so needs saving.
+    fn ram_dirty(&self) -> bool {
+        false
+    }
+
+    fn set_ram_dirty(&mut self, _dirty: bool) {}
+
+    /// Runs anything on the cartridge that keeps time, for `cycles` machine cycles.
+    fn step(&mut self, _cycles: u64) {}
+
+    /// Cycles until anything that keeps time changes, so `step` needs calling. None if there's
+    /// nothing that does, or it's stopped.
+    fn next_tick(&self) -> Option<u64> {
+        None
+    }
+
+    /// The clock, as the trailer that goes after the RAM in .sav files, as of `now` in seconds
+    /// since the Unix epoch. None if the cartridge has no clock.
+    fn rtc_trailer(&self, _now: u64) -> Option<[u8; RTC_TRAILER_BYTES]> {
+        None
+    }
+
+    /// Restores the clock from a trailer, and catches it up to `now`.
+    fn load_rtc_trailer(&mut self, _trailer: &[u8; RTC_TRAILER_BYTES], _now: u64) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_rom() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x01;
+        let described = info(&rom).unwrap();
+        assert!(described.contains("ROM: 32 KiB"), "{}", described);
+        assert!(
+            described.contains("Mapper: Mbc1 is supported"),
+            "{}",
+            described
+        );
+
+        rom.truncate(0x4000);
+        let described = info(&rom).unwrap();
+        assert!(
+            described.contains("but the file is 16384 bytes"),
+            "{}",
+            described
+        );
+        assert!(info(&rom[..0x100]).is_err());
+    }
+
+    #[test]
+    fn ram_dirty() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut cart = new(vec![], rom);
+        assert!(!cart.ram_dirty());
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA001, 0x42);
+        assert_eq!(cart.ram()[1], 0x42);
+        assert!(cart.ram_dirty());
+        cart.set_ram_dirty(false);
+        cart.ram_mut()[2] = 0x17;
+        assert_eq!(cart.read(0xA002), 0x17);
+        assert!(!cart.ram_dirty());
+
+        let rom_only = new(vec![], vec![0; 0x8000]);
+        assert!(rom_only.ram().is_empty());
+    }
 }
@@ -1,7 +1,9 @@
 ///!Pure ROM cartridge.
 use peripherals::cartridge::header;
 use peripherals::cartridge::Cartridge;
+use savestate::{Reader, Snapshot, Writer};
 use std::fmt;
+use std::io;
 
 pub struct RomCart {
     bootrom: Vec<u8>,
@@ -37,6 +39,17 @@ impl Cartridge for RomCart {
     }
 }
 
+impl Snapshot for RomCart {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.bootrom_disabled);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.bootrom_disabled = r.bool()?;
+        Ok(())
+    }
+}
+
 impl fmt::Display for RomCart {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let header = header::Header::new(&self.rom);
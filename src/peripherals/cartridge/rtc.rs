@@ -0,0 +1,232 @@
+//! The real-time clock in MBC3 cartridges. It counts emulated time while the emulator runs, and
+//! wall-clock time in between: .sav files get the clock's registers and the time they were
+//! written in a 48-byte trailer, the same one other emulators use, and the clock catches up on the
+//! time since then when it's loaded.
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
+
+// Machine cycles per second. The clock runs off its own crystal, but that runs at the same rate.
+const CYCLES_PER_SECOND: u32 = 1_048_576;
+
+/// Length of the trailer after the RAM in a .sav file.
+pub const TRAILER_BYTES: usize = 48;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // Nine bits.
+    days: u16,
+    halt: bool,
+    // Set when the day counter overflows, until the game clears it.
+    carry: bool,
+}
+
+impl Registers {
+    // Registers 0x08-0x0C, in order.
+    fn to_bytes(self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8 & 1 | u8::from(self.halt) << 6 | u8::from(self.carry) << 7,
+        ]
+    }
+
+    fn from_bytes(bytes: [u8; 5]) -> Self {
+        Self {
+            seconds: bytes[0] & 0x3F,
+            minutes: bytes[1] & 0x3F,
+            hours: bytes[2] & 0x1F,
+            days: u16::from(bytes[3]) | u16::from(bytes[4] & 1) << 8,
+            halt: bytes[4] & 0x40 != 0,
+            carry: bytes[4] & 0x80 != 0,
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        if self.halt {
+            return;
+        }
+        let total = u64::from(self.seconds)
+            + 60 * (u64::from(self.minutes)
+                + 60 * (u64::from(self.hours) + 24 * u64::from(self.days)))
+            + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+}
+
+#[derive(Default)]
+pub struct Rtc {
+    live: Registers,
+    // What reads see, copied from `live` when the game latches the clock.
+    latched: Registers,
+    cycles: u32,
+    // Latching takes a write of 0 and then 1.
+    latch_armed: bool,
+}
+
+impl Rtc {
+    /// Reads register 0x08-0x0C, as of the last latch.
+    pub fn read(&self, register: u8) -> u8 {
+        match self
+            .latched
+            .to_bytes()
+            .get(usize::from(register.wrapping_sub(0x08)))
+        {
+            Some(&val) => val,
+            None => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, val: u8) {
+        let mut bytes = self.live.to_bytes();
+        if let Some(byte) = bytes.get_mut(usize::from(register.wrapping_sub(0x08))) {
+            *byte = val;
+        }
+        if register == 0x08 {
+            // Writing the seconds restarts the second.
+            self.cycles = 0;
+        }
+        self.live = Registers::from_bytes(bytes);
+    }
+
+    /// Handles a write to 0x6000-0x7FFF.
+    pub fn latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.latched = self.live;
+        }
+        self.latch_armed = val == 0;
+    }
+
+    /// Runs the clock for `cycles` machine cycles. Time doesn't count while it's halted.
+    pub fn step(&mut self, cycles: u64) {
+        if self.live.halt {
+            return;
+        }
+        let total = u64::from(self.cycles) + cycles;
+        self.cycles = (total % u64::from(CYCLES_PER_SECOND)) as u32;
+        self.live.advance(total / u64::from(CYCLES_PER_SECOND));
+    }
+
+    /// Cycles until the seconds next tick over, or None while the clock's halted.
+    pub fn next_tick(&self) -> Option<u64> {
+        if self.live.halt {
+            None
+        } else {
+            Some(u64::from(CYCLES_PER_SECOND - self.cycles))
+        }
+    }
+
+    /// The live and latched registers, as little-endian words, followed by `now` as a Unix time.
+    pub fn trailer(&self, now: u64) -> [u8; TRAILER_BYTES] {
+        let mut trailer = [0; TRAILER_BYTES];
+        let (live, latched) = (self.live.to_bytes(), self.latched.to_bytes());
+        for (word, &val) in trailer.chunks_mut(4).zip(live.iter().chain(latched.iter())) {
+            word[0] = val;
+        }
+        trailer[40..].copy_from_slice(&now.to_le_bytes());
+        trailer
+    }
+
+    /// Restores the clock from a trailer written by `trailer`, and advances it by the time since.
+    pub fn load_trailer(&mut self, trailer: &[u8; TRAILER_BYTES], now: u64) {
+        let mut bytes = [0; 10];
+        for (byte, word) in bytes.iter_mut().zip(trailer.chunks(4)) {
+            *byte = word[0];
+        }
+        self.live = Registers::from_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]]);
+        self.latched = Registers::from_bytes([bytes[5], bytes[6], bytes[7], bytes[8], bytes[9]]);
+        let mut saved = [0; 8];
+        saved.copy_from_slice(&trailer[40..]);
+        self.live
+            .advance(now.saturating_sub(u64::from_le_bytes(saved)));
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.live.to_bytes());
+        w.bytes(&self.latched.to_bytes());
+        w.u32(self.cycles);
+        w.bool(self.latch_armed);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        let mut bytes = [0; 5];
+        r.bytes_into(&mut bytes)?;
+        self.live = Registers::from_bytes(bytes);
+        r.bytes_into(&mut bytes)?;
+        self.latched = Registers::from_bytes(bytes);
+        self.cycles = r.u32()?;
+        self.latch_armed = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latch_and_tick() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        assert_eq!(rtc.next_tick(), Some(u64::from(CYCLES_PER_SECOND)));
+        rtc.step(u64::from(CYCLES_PER_SECOND) - 1);
+        assert_eq!(rtc.next_tick(), Some(1));
+        rtc.step(1);
+        // Reads don't change until the clock is latched.
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch(1);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch(0);
+        rtc.latch(1);
+        assert_eq!(
+            (0x08..=0x0C).map(|reg| rtc.read(reg)).collect::<Vec<_>>(),
+            vec![0, 0, 0, 0, 0x80]
+        );
+
+        rtc.write(0x0C, 0x40);
+        assert_eq!(rtc.next_tick(), None);
+        rtc.step(u64::from(CYCLES_PER_SECOND));
+        rtc.latch(0);
+        rtc.latch(1);
+        assert_eq!(rtc.read(0x08), 0);
+    }
+
+    #[test]
+    fn trailer() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x09, 30);
+        rtc.latch(0);
+        rtc.latch(1);
+        let trailer = rtc.trailer(1_000_000);
+        assert_eq!(trailer[4], 30);
+        assert_eq!(trailer[24], 30);
+
+        let mut loaded = Rtc::default();
+        loaded.load_trailer(&trailer, 1_000_000 + 3 * 3600 + 45);
+        loaded.latch(0);
+        loaded.latch(1);
+        assert_eq!(
+            (0x08..=0x0A)
+                .map(|reg| loaded.read(reg))
+                .collect::<Vec<_>>(),
+            vec![45, 30, 3]
+        );
+    }
+}
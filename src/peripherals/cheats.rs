@@ -0,0 +1,127 @@
+//! Cheat codes. GameShark codes poke a value into RAM once a frame, at the start of VBlank, so
+//! whatever the game wrote there in the meantime gets overwritten.
+use std::fmt;
+use std::io;
+
+/// A GameShark code, written `01VVLLHH`: write the value VV to address 0xHHLL. Only the 01 type,
+/// which writes to whatever RAM is mapped in, is supported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameShark {
+    pub address: u16,
+    pub value: u8,
+}
+
+impl GameShark {
+    pub fn parse(code: &str) -> io::Result<Self> {
+        let invalid = |why: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Bad GameShark code {}: {}", code, why),
+            )
+        };
+        if code.len() != 8 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid("should be 8 hex digits"));
+        }
+        let [kind, value, low, high] = u32::from_str_radix(code, 16).unwrap().to_be_bytes();
+        if kind != 0x01 {
+            return Err(invalid(&format!("type {:02X} isn't supported", kind)));
+        }
+        Ok(Self {
+            address: u16::from_le_bytes([low, high]),
+            value,
+        })
+    }
+}
+
+impl fmt::Display for GameShark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [low, high] = self.address.to_le_bytes();
+        write!(f, "01{:02X}{:02X}{:02X}", self.value, low, high)
+    }
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    game_shark: Vec<GameShark>,
+}
+
+impl Cheats {
+    /// Adds a code, unless it's already there.
+    pub fn add(&mut self, code: &str) -> io::Result<GameShark> {
+        let code = GameShark::parse(code)?;
+        if !self.game_shark.contains(&code) {
+            self.game_shark.push(code);
+        }
+        Ok(code)
+    }
+
+    /// Returns whether the code was there to remove.
+    pub fn remove(&mut self, code: &str) -> bool {
+        let before = self.game_shark.len();
+        if let Ok(code) = GameShark::parse(code) {
+            self.game_shark.retain(|&other| other != code);
+        }
+        self.game_shark.len() != before
+    }
+
+    /// The writes to make this frame, in the order the codes were added.
+    pub fn game_shark(&self) -> &[GameShark] {
+        &self.game_shark
+    }
+
+    /// A line for each code.
+    pub fn describe(&self) -> Vec<String> {
+        self.game_shark
+            .iter()
+            .map(|code| {
+                format!(
+                    "{}: writes 0x{:02X} to 0x{:04X}",
+                    code, code.value, code.address
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_game_shark() {
+        let code = GameShark::parse("0163E1D9").unwrap();
+        assert_eq!(
+            code,
+            GameShark {
+                address: 0xD9E1,
+                value: 0x63
+            }
+        );
+        assert_eq!(code.to_string(), "0163E1D9");
+        assert_eq!(GameShark::parse("0163e1d9").unwrap(), code);
+
+        assert!(GameShark::parse("0163E1").is_err());
+        assert!(GameShark::parse("0163E1DX").is_err());
+        assert!(GameShark::parse("9163E1D9").is_err());
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut cheats = Cheats::default();
+        cheats.add("01FF00C0").unwrap();
+        cheats.add("01FF00C0").unwrap();
+        cheats.add("010180FF").unwrap();
+        assert_eq!(cheats.game_shark().len(), 2);
+        assert!(cheats.add("nonsense").is_err());
+
+        assert!(cheats.remove("01ff00c0"));
+        assert!(!cheats.remove("01FF00C0"));
+        assert_eq!(
+            cheats.game_shark(),
+            &[GameShark {
+                address: 0xFF80,
+                value: 0x01
+            }]
+        );
+    }
+}
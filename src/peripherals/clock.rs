@@ -0,0 +1,119 @@
+//! Where the emulator gets the time from, for pacing frames, measuring the speed, and feeding the
+//! audio device. Games can't see any of that, but what's shown and played can, so headless runs
+//! and tests use a clock that only moves with emulation, or only when it's told to.
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Machine cycles per emulated second.
+const CYCLES_PER_SECOND: u64 = 1_048_576;
+
+pub trait Clock: Send {
+    /// Time since the clock was created.
+    fn now(&self) -> Duration;
+
+    /// Waits for `duration` to pass.
+    fn sleep(&mut self, duration: Duration);
+
+    /// Called for every machine cycle that's emulated.
+    fn tick(&mut self) {}
+}
+
+/// The host's clock. Time passes however fast the emulator runs.
+pub struct RealClock {
+    start: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Emulated time: a second passes for every second of machine cycles. Sleeping doesn't make any
+/// time pass, so nothing waits on it.
+#[derive(Default)]
+pub struct CycleClock {
+    cycles: u64,
+}
+
+impl Clock for CycleClock {
+    fn now(&self) -> Duration {
+        let nanos = u128::from(self.cycles) * 1_000_000_000 / u128::from(CYCLES_PER_SECOND);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+/// Time only passes when it's advanced, or slept through. Clones share the same time, so a test
+/// can keep one to move the time of another that's been handed to the emulator.
+#[derive(Clone, Default)]
+pub struct MockClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_clock() {
+        let mut clock = CycleClock::default();
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_secs(0));
+        for _ in 0..CYCLES_PER_SECOND / 2 {
+            clock.tick();
+        }
+        assert_eq!(clock.now(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn mock_clock() {
+        let mut clock = MockClock::default();
+        let handle = clock.clone();
+        clock.tick();
+        assert_eq!(clock.now(), Duration::from_secs(0));
+        clock.sleep(Duration::from_millis(5));
+        handle.advance(Duration::from_millis(10));
+        assert_eq!(clock.now(), Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,44 @@
+//! The parts of the emulator that talk to SDL: input, the windows, and the audio device. SDL has to
+//! be used from the thread it was set up on, so these stay there, while `Peripherals` and the rest
+//! of the emulator can be sent off to run on another thread.
+use peripherals::apu::AudioOutput;
+use peripherals::joypad::{InputPump, SdlEvents};
+use peripherals::ppu::Presenter;
+use std::time::Duration;
+
+// Longest to go without polling input while waiting for a frame. Frames don't come while the
+// LCD is off or emulation is paused, and this keeps the window responsive then.
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+pub struct Frontend {
+    input: InputPump<SdlEvents>,
+    presenter: Presenter,
+    // Held so the device stays open.
+    _audio: Option<AudioOutput>,
+}
+
+impl Frontend {
+    pub fn new(
+        input: InputPump<SdlEvents>,
+        presenter: Presenter,
+        audio: Option<AudioOutput>,
+    ) -> Self {
+        Self {
+            input,
+            presenter,
+            _audio: audio,
+        }
+    }
+
+    /// Polls input and presents frames until the emulator is dropped, which happens when the
+    /// thread running it finishes.
+    pub fn run(&mut self) {
+        loop {
+            // SDL input isn't scripted against cycles, so the cycle doesn't matter.
+            self.input.pump(0);
+            if !self.presenter.wait_and_present(POLL_INTERVAL) {
+                return;
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 ///! Interrupt handler peripheral.
+use peripherals::io_map::IoRegister;
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
 
 struct Flag {
     enable: bool,
@@ -166,6 +169,83 @@ impl Interrupt {
     }
 }
 
+impl Interrupt {
+    // The flags in bit order, from VBlank in bit 0 to the joypad in bit 4.
+    fn flags(&self) -> [&Flag; 5] {
+        [
+            &self.vblank,
+            &self.lcd_stat,
+            &self.timer,
+            &self.serial,
+            &self.joypad,
+        ]
+    }
+
+    fn flags_mut(&mut self) -> [&mut Flag; 5] {
+        [
+            &mut self.vblank,
+            &mut self.lcd_stat,
+            &mut self.timer,
+            &mut self.serial,
+            &mut self.joypad,
+        ]
+    }
+}
+
+impl IoRegister for Interrupt {
+    fn read8(&self, address: u16) -> u8 {
+        let bits = |get: fn(&Flag) -> bool| {
+            self.flags()
+                .iter()
+                .enumerate()
+                .fold(0, |val, (bit, flag)| val | u8::from(get(flag)) << bit)
+        };
+        match address {
+            // IF only has a bit for each interrupt.
+            0xFF0F => 0xE0 | bits(|flag| flag.trigger),
+            // IE keeps whatever's written to its top bits, even though they do nothing.
+            0xFFFF => self.unused << 5 | bits(|flag| flag.enable),
+            _ => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        match address {
+            0xFF0F => {
+                for (bit, flag) in self.flags_mut().iter_mut().enumerate() {
+                    flag.trigger = val & (1 << bit) != 0;
+                }
+            }
+            0xFFFF => {
+                for (bit, flag) in self.flags_mut().iter_mut().enumerate() {
+                    flag.enable = val & (1 << bit) != 0;
+                }
+                self.unused = val >> 5;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Snapshot for Interrupt {
+    fn save(&self, w: &mut Writer) {
+        for flag in &self.flags() {
+            w.bool(flag.enable);
+            w.bool(flag.trigger);
+        }
+        w.u8(self.unused);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        for flag in self.flags_mut().iter_mut() {
+            flag.enable = r.bool()?;
+            flag.trigger = r.bool()?;
+        }
+        self.unused = r.u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,104 @@
+//! A log of the program's writes to the I/O registers, as JSON lines, so that traces from hardware
+//! or other emulators can be diffed against this one's. Each line is one write, like
+//!
+//! ```text
+//! {"cycle":70224,"frame":1,"pc":"0x0150","address":"0xFF40","value":"0x91","register":"LCDC"}
+//! ```
+//!
+//! `cycle` counts machine cycles since power on, and `pc` is the instruction that made the write.
+//! `register` is null for addresses without a name, like wave RAM.
+use peripherals::{io_registers, Peripherals};
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+
+pub struct IoLog {
+    out: LineWriter<File>,
+    // Writes seen by the hooks, waiting to be logged with the PC of the instruction that made them.
+    writes: mpsc::Receiver<(u16, u8)>,
+}
+
+impl IoLog {
+    /// Starts logging writes to the I/O registers in `peripherals` into a file at `path`.
+    pub fn start(path: &Path, peripherals: &mut Peripherals) -> io::Result<Self> {
+        let out = LineWriter::new(File::create(path)?);
+        let (tx, writes) = mpsc::channel();
+        let ie_tx = tx.clone();
+        peripherals.add_write_hook(0xFF00..=0xFF7F, move |address, val| {
+            let _ = tx.send((address, val));
+        });
+        peripherals.add_write_hook(0xFFFF..=0xFFFF, move |address, val| {
+            let _ = ie_tx.send((address, val));
+        });
+        Ok(Self { out, writes })
+    }
+
+    /// Logs the writes made since the last call, which were all made by the instruction at `pc`.
+    pub fn flush(&mut self, peripherals: &Peripherals, pc: u16) -> io::Result<()> {
+        for (address, val) in self.writes.try_iter() {
+            let line = line(
+                peripherals.cycles(),
+                peripherals.ppu.frame,
+                pc,
+                address,
+                val,
+            );
+            writeln!(self.out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the writes made since the last call without logging them.
+    pub fn discard(&mut self) {
+        self.writes.try_iter().for_each(drop);
+    }
+}
+
+fn line(cycle: u64, frame: u32, pc: u16, address: u16, val: u8) -> String {
+    let register = match io_registers::at(address) {
+        Some(register) => format!("\"{}\"", register.name),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"cycle\":{},\"frame\":{},\"pc\":\"0x{:04X}\",\"address\":\"0x{:04X}\",\
+         \"value\":\"0x{:02X}\",\"register\":{}}}",
+        cycle, frame, pc, address, val, register
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn logs_io_writes() {
+        let path = env::temp_dir().join(format!("wolfwig_io_log_{}.jsonl", process::id()));
+        let mut peripherals = Peripherals::new_fake();
+        let mut log = IoLog::start(&path, &mut peripherals).unwrap();
+        peripherals.write(0xC000, 0x12);
+        peripherals.write(0xFF47, 0xE4);
+        peripherals.write(0xFF30, 0x01);
+        log.flush(&peripherals, 0x0150).unwrap();
+        peripherals.write(0xFFFF, 0x01);
+        log.flush(&peripherals, 0x0152).unwrap();
+        drop(log);
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "{\"cycle\":0,\"frame\":0,\"pc\":\"0x0150\",\"address\":\"0xFF47\",\
+                 \"value\":\"0xE4\",\"register\":\"BGP\"}",
+                "{\"cycle\":0,\"frame\":0,\"pc\":\"0x0150\",\"address\":\"0xFF30\",\
+                 \"value\":\"0x01\",\"register\":null}",
+                "{\"cycle\":0,\"frame\":0,\"pc\":\"0x0152\",\"address\":\"0xFFFF\",\
+                 \"value\":\"0x01\",\"register\":\"IE\"}",
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
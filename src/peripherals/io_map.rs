@@ -0,0 +1,245 @@
+//! Where each bit of the I/O registers at 0xFF00-0xFF7F and 0xFFFF is read from and written to.
+//! Reads and writes both go through this table, so a register's bits can't be wired up one way
+//! for reads and another for writes. Bits that nothing reads from read as 1.
+//!
+//! Most registers belong to a device that decodes them itself, through `IoRegister`. Adding a
+//! register to one of those only needs a line here, and the device's own `read8` and `write8`.
+use super::Peripherals;
+
+/// A device that packs and unpacks its own I/O registers. Bits a register doesn't have read as 1,
+/// and writes to them, or to read-only bits, are ignored.
+pub trait IoRegister {
+    fn read8(&self, address: u16) -> u8;
+    fn write8(&mut self, address: u16, val: u8);
+}
+
+/// Bits `high` down to `low` of a register. `read` returns the field's value, unshifted, and
+/// `write` is given it the same way. Either can be missing for read-only or write-only bits.
+pub struct Bits {
+    pub high: u8,
+    pub low: u8,
+    pub read: Option<fn(&Peripherals) -> u8>,
+    pub write: Option<fn(&mut Peripherals, u8)>,
+}
+
+pub struct MappedRegister {
+    pub address: u16,
+    pub bits: &'static [Bits],
+}
+
+impl Bits {
+    const fn mask(&self) -> u8 {
+        (((1u16 << (self.high - self.low + 1)) - 1) << self.low) as u8
+    }
+}
+
+impl MappedRegister {
+    /// Bits that read as 1 whatever was written to them.
+    pub const fn read_mask(&self) -> u8 {
+        let mut mask = 0xFF;
+        let mut i = 0;
+        while i < self.bits.len() {
+            if self.bits[i].read.is_some() {
+                mask &= !self.bits[i].mask();
+            }
+            i += 1;
+        }
+        mask
+    }
+
+    pub fn read(&self, peripherals: &Peripherals) -> u8 {
+        let mut val = self.read_mask();
+        for bits in self.bits {
+            if let Some(read) = bits.read {
+                val |= (read(peripherals) << bits.low) & bits.mask();
+            }
+        }
+        val
+    }
+
+    pub fn write(&self, peripherals: &mut Peripherals, val: u8) {
+        for bits in self.bits {
+            if let Some(write) = bits.write {
+                write(peripherals, (val & bits.mask()) >> bits.low);
+            }
+        }
+    }
+}
+
+// Builds the table from lines like `0xFF00 => [5..5 => rw(getter, setter), ...]`, where the getter
+// and setter are paths from `Peripherals`. `r` and `w` map only one direction, and `io` hands the
+// whole register to a device's `IoRegister`.
+macro_rules! io_map {
+    ($( $address:literal => [ $( $high:literal .. $low:literal => $kind:ident($($path:tt)*) ),* ] ),* $(,)*) => {
+        pub const IO_MAP: &[MappedRegister] = &[
+            $(
+                MappedRegister {
+                    address: $address,
+                    bits: &[ $( io_map!(@bits $address, $high, $low, $kind($($path)*)) ),* ],
+                },
+            )*
+        ];
+    };
+    (@bits $address:literal, $high:literal, $low:literal, rw($($get:ident).+, $($set:ident).+)) => {
+        Bits {
+            high: $high,
+            low: $low,
+            read: Some(|p: &Peripherals| u8::from(p.$($get).+())),
+            write: Some(|p: &mut Peripherals, val: u8| p.$($set).+(val)),
+        }
+    };
+    (@bits $address:literal, $high:literal, $low:literal, r($($get:ident).+)) => {
+        Bits {
+            high: $high,
+            low: $low,
+            read: Some(|p: &Peripherals| u8::from(p.$($get).+())),
+            write: None,
+        }
+    };
+    (@bits $address:literal, $high:literal, $low:literal, w($($set:ident).+)) => {
+        Bits {
+            high: $high,
+            low: $low,
+            read: None,
+            write: Some(|p: &mut Peripherals, val: u8| p.$($set).+(val)),
+        }
+    };
+    (@bits $address:literal, $high:literal, $low:literal, io($device:ident)) => {
+        Bits {
+            high: $high,
+            low: $low,
+            read: Some(|p: &Peripherals| p.$device.read8($address)),
+            write: Some(|p: &mut Peripherals, val: u8| p.$device.write8($address, val)),
+        }
+    };
+}
+
+io_map! {
+    0xFF00 => [
+        5..5 => rw(joypad.select_button, joypad.set_select_button),
+        4..4 => rw(joypad.select_direction, joypad.set_select_direction),
+        3..0 => r(joypad.state)
+    ],
+    0xFF01 => [7..0 => io(serial)],
+    0xFF02 => [7..0 => io(serial)],
+    0xFF04 => [7..0 => io(timer)],
+    0xFF05 => [7..0 => io(timer)],
+    0xFF06 => [7..0 => io(timer)],
+    0xFF07 => [7..0 => io(timer)],
+    0xFF0F => [7..0 => io(interrupt)],
+    0xFF10 => [7..0 => io(apu)],
+    0xFF11 => [7..0 => io(apu)],
+    0xFF12 => [7..0 => io(apu)],
+    0xFF13 => [7..0 => io(apu)],
+    0xFF14 => [7..0 => io(apu)],
+    0xFF16 => [7..0 => io(apu)],
+    0xFF17 => [7..0 => io(apu)],
+    0xFF18 => [7..0 => io(apu)],
+    0xFF19 => [7..0 => io(apu)],
+    0xFF1A => [7..0 => io(apu)],
+    0xFF1B => [7..0 => io(apu)],
+    0xFF1C => [7..0 => io(apu)],
+    0xFF1D => [7..0 => io(apu)],
+    0xFF1E => [7..0 => io(apu)],
+    0xFF20 => [7..0 => io(apu)],
+    0xFF21 => [7..0 => io(apu)],
+    0xFF22 => [7..0 => io(apu)],
+    0xFF23 => [7..0 => io(apu)],
+    0xFF24 => [7..0 => io(apu)],
+    0xFF25 => [7..0 => io(apu)],
+    0xFF26 => [7..0 => io(apu)],
+    0xFF40 => [7..0 => io(ppu)],
+    0xFF41 => [7..0 => io(ppu)],
+    0xFF42 => [7..0 => io(ppu)],
+    0xFF43 => [7..0 => io(ppu)],
+    0xFF44 => [7..0 => io(ppu)],
+    0xFF45 => [7..0 => io(ppu)],
+    0xFF46 => [7..0 => io(ppu)],
+    0xFF47 => [7..0 => io(ppu)],
+    0xFF48 => [7..0 => io(ppu)],
+    0xFF49 => [7..0 => io(ppu)],
+    0xFF4A => [7..0 => io(ppu)],
+    0xFF4B => [7..0 => io(ppu)],
+    0xFF4D => [7..0 => io(mem)],
+    0xFF70 => [7..0 => io(mem)],
+    0xFFFF => [7..0 => io(interrupt)],
+}
+
+const UNMAPPED: u8 = 0xFF;
+
+// Position in IO_MAP of the register at each address, by the address's low byte.
+const fn build_index() -> [u8; 0x100] {
+    let mut index = [UNMAPPED; 0x100];
+    let mut i = 0;
+    while i < IO_MAP.len() {
+        index[(IO_MAP[i].address & 0xFF) as usize] = i as u8;
+        i += 1;
+    }
+    index
+}
+
+static INDEX: [u8; 0x100] = build_index();
+
+/// The register at `address`, or None if nothing is there.
+pub fn find(address: u16) -> Option<&'static MappedRegister> {
+    match address {
+        0xFF00..=0xFF7F | 0xFFFF => match INDEX[(address & 0xFF) as usize] {
+            UNMAPPED => None,
+            i => Some(&IO_MAP[usize::from(i)]),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bits that don't exist in each register, which read as 1 whatever's written.
+    const RESERVED: &[(u16, u8)] = &[
+        (0xFF00, 0xC0),
+        (0xFF02, 0x7E),
+        (0xFF07, 0xF8),
+        (0xFF0F, 0xE0),
+        (0xFF10, 0x80),
+        (0xFF14, 0x38),
+        (0xFF19, 0x38),
+        (0xFF1A, 0x7F),
+        (0xFF1C, 0x9F),
+        (0xFF1E, 0x38),
+        (0xFF20, 0xC0),
+        (0xFF23, 0xBF),
+        (0xFF26, 0x70),
+        (0xFF41, 0x80),
+    ];
+
+    #[test]
+    fn lookup() {
+        assert_eq!(find(0xFF00).unwrap().read_mask(), 0xC0);
+        assert_eq!(find(0xFF41).unwrap().read_mask(), 0x00);
+        assert!(find(0xFF03).is_none());
+        assert!(find(0xFF80).is_none());
+    }
+
+    #[test]
+    fn unmapped_bits_read_as_one() {
+        let mut mem = Peripherals::new_fake();
+        for &(address, reserved) in RESERVED {
+            mem.write(address, 0x00);
+            assert_eq!(mem.read(address) & reserved, reserved, "{:04X}", address);
+        }
+        // Everything else written reads back, outside of registers with read-only bits.
+        for register in IO_MAP {
+            if [0xFF00, 0xFF04, 0xFF26, 0xFF41, 0xFF44].contains(&register.address) {
+                continue;
+            }
+            mem.write(register.address, 0xFF);
+            assert_eq!(mem.read(register.address), 0xFF, "{:04X}", register.address);
+        }
+        mem.write(0xFF46, 0xC1);
+        assert_eq!(mem.read(0xFF46), 0xC1);
+        // Reads see the bits where they were written.
+        mem.write(0xFF00, 0x10);
+        assert_eq!(mem.read(0xFF00) & 0x30, 0x10);
+    }
+}
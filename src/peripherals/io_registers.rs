@@ -0,0 +1,244 @@
+//! Names and bitfields of the memory-mapped I/O registers, for inspecting them by name rather than
+//! as raw hex.
+
+/// A named range of bits within a register, from `high` down to `low` inclusive.
+pub struct Field {
+    pub name: &'static str,
+    pub high: u8,
+    pub low: u8,
+}
+
+pub struct RegisterInfo {
+    pub name: &'static str,
+    pub address: u16,
+    /// Fields from the most significant bit down. Registers that are a single value have none.
+    pub fields: &'static [Field],
+}
+
+const fn field(name: &'static str, high: u8, low: u8) -> Field {
+    Field { name, high, low }
+}
+
+const fn reg(name: &'static str, address: u16, fields: &'static [Field]) -> RegisterInfo {
+    RegisterInfo {
+        name,
+        address,
+        fields,
+    }
+}
+
+const INTERRUPTS: &[Field] = &[
+    field("JOYPAD", 4, 4),
+    field("SERIAL", 3, 3),
+    field("TIMER", 2, 2),
+    field("STAT", 1, 1),
+    field("VBLANK", 0, 0),
+];
+const PULSE_LENGTH: &[Field] = &[field("DUTY", 7, 6), field("LENGTH", 5, 0)];
+const ENVELOPE: &[Field] = &[
+    field("VOLUME", 7, 4),
+    field("DIRECTION", 3, 3),
+    field("PACE", 2, 0),
+];
+const PERIOD_HIGH: &[Field] = &[
+    field("TRIGGER", 7, 7),
+    field("LENGTH_ENABLE", 6, 6),
+    field("PERIOD_HIGH", 2, 0),
+];
+const PALETTE: &[Field] = &[
+    field("ID3", 7, 6),
+    field("ID2", 5, 4),
+    field("ID1", 3, 2),
+    field("ID0", 1, 0),
+];
+
+pub const IO_REGISTERS: &[RegisterInfo] = &[
+    reg(
+        "P1",
+        0xFF00,
+        &[
+            field("SELECT_BUTTONS", 5, 5),
+            field("SELECT_DPAD", 4, 4),
+            field("INPUT", 3, 0),
+        ],
+    ),
+    reg("SB", 0xFF01, &[]),
+    reg(
+        "SC",
+        0xFF02,
+        &[field("START", 7, 7), field("INTERNAL_CLOCK", 0, 0)],
+    ),
+    reg("DIV", 0xFF04, &[]),
+    reg("TIMA", 0xFF05, &[]),
+    reg("TMA", 0xFF06, &[]),
+    reg(
+        "TAC",
+        0xFF07,
+        &[field("ENABLE", 2, 2), field("CLOCK", 1, 0)],
+    ),
+    reg("IF", 0xFF0F, INTERRUPTS),
+    reg(
+        "NR10",
+        0xFF10,
+        &[
+            field("PACE", 6, 4),
+            field("DIRECTION", 3, 3),
+            field("STEP", 2, 0),
+        ],
+    ),
+    reg("NR11", 0xFF11, PULSE_LENGTH),
+    reg("NR12", 0xFF12, ENVELOPE),
+    reg("NR13", 0xFF13, &[]),
+    reg("NR14", 0xFF14, PERIOD_HIGH),
+    reg("NR21", 0xFF16, PULSE_LENGTH),
+    reg("NR22", 0xFF17, ENVELOPE),
+    reg("NR23", 0xFF18, &[]),
+    reg("NR24", 0xFF19, PERIOD_HIGH),
+    reg("NR30", 0xFF1A, &[field("DAC", 7, 7)]),
+    reg("NR31", 0xFF1B, &[]),
+    reg("NR32", 0xFF1C, &[field("VOLUME", 6, 5)]),
+    reg("NR33", 0xFF1D, &[]),
+    reg("NR34", 0xFF1E, PERIOD_HIGH),
+    reg("NR41", 0xFF20, &[field("LENGTH", 5, 0)]),
+    reg("NR42", 0xFF21, ENVELOPE),
+    reg(
+        "NR43",
+        0xFF22,
+        &[
+            field("SHIFT", 7, 4),
+            field("WIDTH", 3, 3),
+            field("DIVIDER", 2, 0),
+        ],
+    ),
+    reg(
+        "NR44",
+        0xFF23,
+        &[field("TRIGGER", 7, 7), field("LENGTH_ENABLE", 6, 6)],
+    ),
+    reg(
+        "NR50",
+        0xFF24,
+        &[
+            field("VIN_LEFT", 7, 7),
+            field("LEFT_VOLUME", 6, 4),
+            field("VIN_RIGHT", 3, 3),
+            field("RIGHT_VOLUME", 2, 0),
+        ],
+    ),
+    reg(
+        "NR51",
+        0xFF25,
+        &[
+            field("CH4_LEFT", 7, 7),
+            field("CH3_LEFT", 6, 6),
+            field("CH2_LEFT", 5, 5),
+            field("CH1_LEFT", 4, 4),
+            field("CH4_RIGHT", 3, 3),
+            field("CH3_RIGHT", 2, 2),
+            field("CH2_RIGHT", 1, 1),
+            field("CH1_RIGHT", 0, 0),
+        ],
+    ),
+    reg(
+        "NR52",
+        0xFF26,
+        &[
+            field("ENABLE", 7, 7),
+            field("CH4_ON", 3, 3),
+            field("CH3_ON", 2, 2),
+            field("CH2_ON", 1, 1),
+            field("CH1_ON", 0, 0),
+        ],
+    ),
+    reg(
+        "LCDC",
+        0xFF40,
+        &[
+            field("ENABLE", 7, 7),
+            field("WINDOW_MAP", 6, 6),
+            field("WINDOW", 5, 5),
+            field("TILE_DATA", 4, 4),
+            field("BG_MAP", 3, 3),
+            field("OBJ_SIZE", 2, 2),
+            field("OBJ", 1, 1),
+            field("BG", 0, 0),
+        ],
+    ),
+    reg(
+        "STAT",
+        0xFF41,
+        &[
+            field("LYC_INT", 6, 6),
+            field("MODE2_INT", 5, 5),
+            field("MODE1_INT", 4, 4),
+            field("MODE0_INT", 3, 3),
+            field("LYC_EQUAL", 2, 2),
+            field("MODE", 1, 0),
+        ],
+    ),
+    reg("SCY", 0xFF42, &[]),
+    reg("SCX", 0xFF43, &[]),
+    reg("LY", 0xFF44, &[]),
+    reg("LYC", 0xFF45, &[]),
+    reg("DMA", 0xFF46, &[]),
+    reg("BGP", 0xFF47, PALETTE),
+    reg("OBP0", 0xFF48, PALETTE),
+    reg("OBP1", 0xFF49, PALETTE),
+    reg("WY", 0xFF4A, &[]),
+    reg("WX", 0xFF4B, &[]),
+    reg("IE", 0xFFFF, INTERRUPTS),
+];
+
+/// Finds a register by name (in any case) or by address, like "lcdc" or "0xFF40".
+pub fn find(name: &str) -> Option<&'static RegisterInfo> {
+    let address = if name.starts_with("0x") || name.starts_with("0X") {
+        u16::from_str_radix(&name[2..], 16).ok()
+    } else {
+        None
+    };
+    IO_REGISTERS
+        .iter()
+        .find(|reg| reg.name.eq_ignore_ascii_case(name) || Some(reg.address) == address)
+}
+
+/// The register at `address`, if it has a name.
+pub fn at(address: u16) -> Option<&'static RegisterInfo> {
+    IO_REGISTERS.iter().find(|reg| reg.address == address)
+}
+
+impl RegisterInfo {
+    pub fn describe(&self, val: u8) -> String {
+        let mut description = format!("{:<4} (0x{:04X}) = 0x{:02X}", self.name, self.address, val);
+        if !self.fields.is_empty() {
+            description.push(':');
+        }
+        for field in self.fields {
+            let width = field.high - field.low + 1;
+            let mask = ((1u16 << width) - 1) as u8;
+            description += &format!(" {}={}", field.name, (val >> field.low) & mask);
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_fields() {
+        let lcdc = find("lcdc").unwrap();
+        assert_eq!(
+            lcdc.describe(0x91),
+            "LCDC (0xFF40) = 0x91: ENABLE=1 WINDOW_MAP=0 WINDOW=0 TILE_DATA=1 BG_MAP=0 OBJ_SIZE=0 \
+             OBJ=0 BG=1"
+        );
+        let stat = find("0xFF41").unwrap();
+        assert_eq!(
+            stat.describe(0x87),
+            "STAT (0xFF41) = 0x87: LYC_INT=0 MODE2_INT=0 MODE1_INT=0 MODE0_INT=0 LYC_EQUAL=1 MODE=3"
+        );
+        assert_eq!(find("LY").unwrap().describe(0x90), "LY   (0xFF44) = 0x90");
+        assert!(find("NOPE").is_none());
+    }
+}
@@ -11,9 +11,12 @@ pub struct State {
     pub down: bool,
     pub left: bool,
     pub right: bool,
-    // This is set true if a button is pressed. Should be cleared by the joypad controller when
-    // read.
-    pub keydown: bool,
+    // Autofire versions of A and B. While held, the button toggles every `turbo_period` frames.
+    pub turbo_a: bool,
+    pub turbo_b: bool,
+    // Held down to run faster than normal. Unlike the hotkeys, this isn't one-shot.
+    pub fast_forward: bool,
+    pub hotkeys: Hotkeys,
 }
 
 impl State {
@@ -28,12 +31,55 @@ impl State {
             down: false,
             left: false,
             right: false,
-            keydown: false,
+            turbo_a: false,
+            turbo_b: false,
+            fast_forward: false,
+            hotkeys: Hotkeys::default(),
         }
     }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::A => self.a = pressed,
+            Button::B => self.b = pressed,
+            Button::Start => self.start = pressed,
+            Button::Select => self.select = pressed,
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::Left => self.left = pressed,
+            Button::Right => self.right = pressed,
+        }
+    }
+}
+
+/// A button on the emulated joypad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Frontend hotkeys, which don't go to the emulated joypad. These are one-shot, and are cleared
+/// once they've been read.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Hotkeys {
+    pub screenshot: bool,
+    pub record: bool,
+    pub pause: bool,
+    pub frame_advance: bool,
+    // Save state slots, 0-9, to save to or load from.
+    pub save_slot: Option<u8>,
+    pub load_slot: Option<u8>,
 }
 
 pub trait EventHandler {
-    fn get_state(&mut self) -> State;
-    fn clear_keydown(&mut self);
+    /// Returns the current state. `cycle` is the number of machine cycles run since power on.
+    fn get_state(&mut self, cycle: u64) -> State;
+    fn clear_hotkeys(&mut self);
 }
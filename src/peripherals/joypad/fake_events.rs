@@ -1,18 +1,58 @@
 ///! Fake event stream, for testing.
-use peripherals::joypad::events::{EventHandler, State};
-// TODO(slongfield): Add a back channel for injecting events.
+use peripherals::joypad::events::{Button, EventHandler, State};
+use std::sync::mpsc::{self, Receiver, Sender};
 
-pub struct FakeEvents {}
+/// A scripted button press or release, for driving the fake joypad from tests.
+#[derive(Clone, Copy, Debug)]
+pub struct FakeInput {
+    /// Machine cycle to apply the input at. It's seen at the first poll at or after this cycle.
+    pub cycle: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+pub struct FakeEvents {
+    inputs: Option<Receiver<FakeInput>>,
+    // Inputs that have been received, but whose cycle hasn't come up yet.
+    pending: Vec<FakeInput>,
+    state: State,
+}
 
 impl FakeEvents {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            inputs: None,
+            pending: vec![],
+            state: State::new(),
+        }
+    }
+
+    /// A fake event stream that applies inputs sent on the returned channel.
+    pub fn with_inputs() -> (Self, Sender<FakeInput>) {
+        let (tx, rx) = mpsc::channel();
+        let mut events = Self::new();
+        events.inputs = Some(rx);
+        (events, tx)
     }
 }
 
 impl EventHandler for FakeEvents {
-    fn get_state(&mut self) -> State {
-        State::new()
+    fn get_state(&mut self, cycle: u64) -> State {
+        if let Some(ref inputs) = self.inputs {
+            self.pending.extend(inputs.try_iter());
+        }
+        // Apply in cycle order, keeping the send order for inputs on the same cycle.
+        self.pending.sort_by_key(|input| input.cycle);
+        let ready = self
+            .pending
+            .iter()
+            .take_while(|input| input.cycle <= cycle)
+            .count();
+        for input in self.pending.drain(..ready) {
+            self.state.set_button(input.button, input.pressed);
+        }
+        self.state
     }
-    fn clear_keydown(&mut self) {}
+
+    fn clear_hotkeys(&mut self) {}
 }
@@ -1,66 +1,186 @@
 ///! Joypad is the joypad peripheral
 use peripherals::interrupt::Interrupt;
-use sdl2::EventPump;
-use std::process;
+use savestate::{Reader, Snapshot, Writer};
+use sdl2::{EventPump, GameControllerSubsystem};
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
 
 mod events;
 mod fake_events;
+mod movie;
 mod sdl_events;
 
+#[cfg(test)]
+pub use self::events::Button;
+pub use self::fake_events::{FakeEvents, FakeInput};
+pub use self::sdl_events::SdlEvents;
+
+/// Polls the frontend for input, and sends it on to the joypad. This is kept apart from the
+/// joypad since SDL has to be polled from the thread it was set up on, which needn't be the one
+/// running the emulator.
+pub struct InputPump<E> {
+    events: E,
+    states: Sender<events::State>,
+}
+
+impl<E: events::EventHandler> InputPump<E> {
+    /// Polls for input. `cycle` is the number of machine cycles run since power on.
+    pub fn pump(&mut self, cycle: u64) {
+        let state = self.events.get_state(cycle);
+        self.events.clear_hotkeys();
+        // The joypad owns the other end, so this only fails while it's being dropped.
+        let _ = self.states.send(state);
+    }
+}
+
 pub struct Joypad {
-    events: Box<events::EventHandler>,
+    states: Receiver<events::State>,
     select_button: bool,
     select_direction: bool,
     state: u8,
-    counter: usize,
+    // Set when a selected line goes low, until the interrupt is requested on the next step.
+    line_fell: bool,
+    // Most recently polled buttons, kept so the state can be recomputed when turbo toggles.
+    held: events::State,
+    // Buttons the game sees, packed as in `held_buttons`. Usually these are just the held buttons,
+    // but they come from the movie during playback.
+    buttons: u8,
+    movie: Option<movie::Movie>,
+    hotkeys: events::Hotkeys,
+    fast_forward: bool,
+    // Set once the window has been closed. Unlike the hotkeys, it stays set.
+    shutdown: bool,
+    // Number of frames turbo buttons stay pressed, and then released, for.
+    turbo_period: u32,
+    turbo_frames: u32,
+    turbo_pressed: bool,
 }
 
 impl Joypad {
-    // How frequenlty to check for new updates, in cycles. This is a janky hack, needed
-    // because the
-    // SDL event polling can't be moved to a different thread, and is kind of slow.
-    // TODO(slongfield): Figure out a beter solution. Maybe move _all_ of the SDL
-    // stuff into a separate thread?
-    const UPDATE_INTERVAL: usize = 100;
-
-    pub fn new_sdl(events: EventPump) -> Self {
-        let events = Box::new(sdl_events::SdlEvents::new(events));
-        Self {
-            events,
+    pub fn new_sdl(
+        events: EventPump,
+        controllers: Option<GameControllerSubsystem>,
+    ) -> (Self, InputPump<SdlEvents>) {
+        Self::with_events(SdlEvents::new(events, controllers))
+    }
+
+    pub fn new_fake() -> (Self, InputPump<FakeEvents>) {
+        Self::with_events(FakeEvents::new())
+    }
+
+    /// A fake joypad whose buttons are driven by inputs sent on the returned channel.
+    pub fn new_fake_with_inputs() -> (Self, InputPump<FakeEvents>, Sender<FakeInput>) {
+        let (events, inputs) = FakeEvents::with_inputs();
+        let (joypad, pump) = Self::with_events(events);
+        (joypad, pump, inputs)
+    }
+
+    fn with_events<E: events::EventHandler>(events: E) -> (Self, InputPump<E>) {
+        let (tx, rx) = mpsc::channel();
+        let joypad = Self {
+            states: rx,
             select_button: true,
             select_direction: true,
             state: 0xF,
-            counter: 0,
+            line_fell: false,
+            held: events::State::new(),
+            buttons: 0,
+            movie: None,
+            hotkeys: events::Hotkeys::default(),
+            fast_forward: false,
+            shutdown: false,
+            turbo_period: 1,
+            turbo_frames: 0,
+            turbo_pressed: true,
+        };
+        let pump = InputPump { events, states: tx };
+        (joypad, pump)
+    }
+
+    /// Whether a selected line fell since the interrupt was last requested.
+    pub fn line_fell(&self) -> bool {
+        self.line_fell
+    }
+
+    /// Requests the joypad interrupt, if a line fell.
+    pub fn request_interrupt(&mut self, interrupt: &mut Interrupt) {
+        if self.line_fell {
+            self.line_fell = false;
+            interrupt.set_joypad_trigger(1);
         }
     }
 
-    pub fn new_fake() -> Self {
-        let events = Box::new(fake_events::FakeEvents::new());
-        Self {
-            events,
-            select_button: true,
-            select_direction: true,
-            state: 0xF,
-            counter: 0,
+    /// Called at the end of each PPU frame, so that turbo buttons toggle in step with the game
+    /// rather than with the input polling.
+    pub fn end_frame(&mut self) {
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_period {
+            self.turbo_frames = 0;
+            self.turbo_pressed = !self.turbo_pressed;
+            if self.movie.is_none() && (self.held.turbo_a || self.held.turbo_b) {
+                self.buttons = self.held_buttons();
+                self.latch();
+            }
+        }
+        if self.movie.is_some() {
+            self.next_movie_frame();
         }
     }
 
-    pub fn step(&mut self, interrupt: &mut Interrupt) {
-        self.counter += 1;
-        if self.counter == Self::UPDATE_INTERVAL {
-            debug!("Updating state.");
-            self.update(interrupt);
+    /// Starts recording the buttons held on each frame to `path`.
+    pub fn record_movie(&mut self, path: &Path, rom_hash: u64) -> Result<(), io::Error> {
+        self.movie = Some(movie::Movie::record(path, rom_hash)?);
+        Ok(())
+    }
+
+    /// Starts replaying buttons from `path`, ignoring the real input until it runs out.
+    pub fn play_movie(&mut self, path: &Path, rom_hash: u64) -> Result<(), io::Error> {
+        self.movie = Some(movie::Movie::play(path, rom_hash)?);
+        Ok(())
+    }
+
+    // While a movie is recording or playing, the buttons only change at frame boundaries, so that
+    // playback sees exactly what was recorded no matter when input happened to be polled.
+    fn next_movie_frame(&mut self) {
+        let held = self.held_buttons();
+        let next = match self.movie {
+            Some(ref mut movie) => movie.next_frame(held),
+            None => return,
+        };
+        match next {
+            Ok(Some(buttons)) => {
+                self.buttons = buttons;
+                self.latch();
+            }
+            Ok(None) => {
+                info!("Input movie finished");
+                self.movie = None;
+            }
+            Err(err) => {
+                error!("Could not record input: {}", err);
+                self.movie = None;
+            }
         }
     }
 
+    /// Sets how many frames turbo buttons are held, and then released, for. The default of 1
+    /// toggles every frame, which is as fast as most games can read input.
+    pub fn set_turbo_period(&mut self, frames: u32) {
+        self.turbo_period = frames.max(1);
+    }
+
     pub fn set_select_direction(&mut self, val: u8) {
         debug!("Setting select direction to {}", val);
-        self.select_direction = val != 0
+        self.select_direction = val != 0;
+        self.latch();
     }
 
     pub fn set_select_button(&mut self, val: u8) {
         debug!("Setting select button to {}", val);
-        self.select_button = val != 0
+        self.select_button = val != 0;
+        self.latch();
     }
 
     pub fn select_direction(&self) -> bool {
@@ -75,34 +195,190 @@ impl Joypad {
         self.state
     }
 
-    pub fn update(&mut self, interrupt: &mut Interrupt) {
-        if self.events.get_state().keydown {}
-        let state = self.events.get_state();
+    /// Returns the hotkeys that have been pressed since the last call.
+    pub fn take_hotkeys(&mut self) -> events::Hotkeys {
+        mem::take(&mut self.hotkeys)
+    }
+
+    /// Whether the window has been closed, or Escape pressed.
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown
+    }
 
-        if state.shutdown {
-            process::exit(0);
+    /// Whether the fast-forward key is currently held.
+    pub fn fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Applies any input that's been pumped since the last update. Returns whether there was any.
+    pub fn update(&mut self) -> bool {
+        let mut updated = false;
+        while let Ok(state) = self.states.try_recv() {
+            self.apply(state);
+            updated = true;
         }
+        updated
+    }
 
-        if state.keydown {
-            interrupt.set_joypad_trigger(1);
+    fn apply(&mut self, state: events::State) {
+        self.shutdown |= state.shutdown;
+
+        self.hotkeys.screenshot |= state.hotkeys.screenshot;
+        self.hotkeys.record |= state.hotkeys.record;
+        self.hotkeys.pause |= state.hotkeys.pause;
+        self.hotkeys.frame_advance |= state.hotkeys.frame_advance;
+        self.hotkeys.save_slot = state.hotkeys.save_slot.or(self.hotkeys.save_slot);
+        self.hotkeys.load_slot = state.hotkeys.load_slot.or(self.hotkeys.load_slot);
+        self.fast_forward = state.fast_forward;
+
+        self.held = state;
+        if self.movie.is_none() {
+            self.buttons = self.held_buttons();
+            self.latch();
         }
+    }
 
+    // Buttons that are held, including turbo, with the directions in the high nibble and the
+    // rest in the low nibble, in the same order as the register.
+    fn held_buttons(&self) -> u8 {
+        let state = self.held;
+        let a = state.a || (state.turbo_a && self.turbo_pressed);
+        let b = state.b || (state.turbo_b && self.turbo_pressed);
+        u8::from(state.down) << 7
+            | u8::from(state.up) << 6
+            | u8::from(state.left) << 5
+            | u8::from(state.right) << 4
+            | u8::from(state.start) << 3
+            | u8::from(state.select) << 2
+            | u8::from(b) << 1
+            | u8::from(a)
+    }
+
+    // Computes the register value from the buttons and the selected lines. The joypad interrupt is
+    // requested when any of the lines goes from high to low, whether that's from a button being
+    // pressed, or from selecting a matrix where one's already held.
+    fn latch(&mut self) {
+        let old = self.state;
         self.state = 0;
         if !self.select_direction {
-            self.state |= u8::from(state.down) << 3;
-            self.state |= u8::from(state.up) << 2;
-            self.state |= u8::from(state.left) << 1;
-            self.state |= u8::from(state.right);
+            self.state |= self.buttons >> 4;
         }
         if !self.select_button {
-            self.state |= u8::from(state.start) << 3;
-            self.state |= u8::from(state.select) << 2;
-            self.state |= u8::from(state.b) << 1;
-            self.state |= u8::from(state.a);
+            self.state |= self.buttons & 0xF;
         }
         // It's active low, so invert
         self.state = !self.state;
-        self.events.clear_keydown();
-        self.counter = 0;
+        if old & !self.state & 0xF != 0 {
+            self.line_fell = true;
+        }
+    }
+}
+
+// Buttons the game sees are saved, but held buttons come from the real input, so they're left
+// alone.
+impl Snapshot for Joypad {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.select_button);
+        w.bool(self.select_direction);
+        w.u8(self.buttons);
+        w.u32(self.turbo_frames);
+        w.bool(self.turbo_pressed);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.select_button = r.bool()?;
+        self.select_direction = r.bool()?;
+        self.buttons = r.u8()?;
+        self.turbo_frames = r.u32()?;
+        self.turbo_pressed = r.bool()?;
+        self.latch();
+        self.line_fell = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injected_inputs() {
+        let (mut joypad, mut pump, inputs) = Joypad::new_fake_with_inputs();
+        let mut interrupt = Interrupt::new();
+        interrupt.set_joypad_enable(1);
+        joypad.set_select_button(0);
+        inputs
+            .send(FakeInput {
+                cycle: 150,
+                button: Button::A,
+                pressed: true,
+            })
+            .unwrap();
+        pump.pump(100);
+        joypad.update();
+        assert_eq!(joypad.state() & 0xF, 0xF);
+        assert!(!joypad.line_fell());
+        pump.pump(200);
+        joypad.update();
+        assert_eq!(joypad.state() & 0xF, 0xE);
+        joypad.request_interrupt(&mut interrupt);
+        assert_eq!(interrupt.get_interrupt_pc(), Some(0x60));
+    }
+
+    #[test]
+    fn interrupts_on_selected_lines() {
+        let (mut joypad, _pump) = Joypad::new_fake();
+        let mut interrupt = Interrupt::new();
+        interrupt.set_joypad_enable(1);
+        joypad.set_select_direction(0);
+        let mut state = events::State::new();
+        state.a = true;
+        joypad.apply(state);
+        joypad.request_interrupt(&mut interrupt);
+        // A is on the buttons matrix, which isn't selected.
+        assert_eq!(joypad.state() & 0xF, 0xF);
+        assert!(interrupt.get_interrupt_pc().is_none());
+
+        // Selecting it pulls A's line low.
+        joypad.set_select_button(0);
+        joypad.request_interrupt(&mut interrupt);
+        assert_eq!(interrupt.get_interrupt_pc(), Some(0x60));
+
+        // Holding it, or pressing another button on the same line, isn't another edge.
+        interrupt.set_joypad_trigger(0);
+        state.right = true;
+        joypad.apply(state);
+        joypad.request_interrupt(&mut interrupt);
+        assert!(interrupt.get_interrupt_pc().is_none());
+    }
+
+    #[test]
+    fn turbo_toggles_each_period() {
+        let (mut joypad, _pump) = Joypad::new_fake();
+        joypad.set_select_button(0);
+        let mut state = events::State::new();
+        state.turbo_a = true;
+        joypad.apply(state);
+        let a_pressed = |joypad: &Joypad| joypad.state() & 0x1 == 0;
+        assert!(a_pressed(&joypad));
+        for &pressed in &[false, true, false, true] {
+            joypad.end_frame();
+            assert_eq!(a_pressed(&joypad), pressed);
+        }
+        joypad.set_turbo_period(3);
+        for &pressed in &[true, true, false, false, false, true] {
+            joypad.end_frame();
+            assert_eq!(a_pressed(&joypad), pressed);
+        }
+    }
+
+    #[test]
+    fn shutdown_sticks() {
+        let (mut joypad, _pump) = Joypad::new_fake();
+        let mut state = events::State::new();
+        state.shutdown = true;
+        joypad.apply(state);
+        joypad.apply(events::State::new());
+        assert!(joypad.shutdown_requested());
     }
 }
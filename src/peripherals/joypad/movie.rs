@@ -0,0 +1,95 @@
+//! Input movies: the buttons held on every frame, written out so that a run can be replayed
+//! exactly. Since nothing else feeds into the emulator, replaying the same movie against the same
+//! ROM from power on gives the same result.
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"WWMV";
+const VERSION: u8 = 1;
+// Magic, version, and the hash of the ROM the movie was recorded against.
+const HEADER_SIZE: usize = 4 + 1 + 8;
+
+pub enum Movie {
+    Recording(BufWriter<File>),
+    Playing { frames: Vec<u8>, next: usize },
+}
+
+impl Movie {
+    pub fn record(path: &Path, rom_hash: u64) -> Result<Self, io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.write_all(&rom_hash.to_le_bytes())?;
+        Ok(Movie::Recording(file))
+    }
+
+    pub fn play(path: &Path, rom_hash: u64) -> Result<Self, io::Error> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a wolfwig input movie",
+            ));
+        }
+        if data[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported input movie version {}", data[4]),
+            ));
+        }
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&data[5..HEADER_SIZE]);
+        if u64::from_le_bytes(hash) != rom_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Input movie was recorded with a different ROM",
+            ));
+        }
+        Ok(Movie::Playing {
+            frames: data.split_off(HEADER_SIZE),
+            next: 0,
+        })
+    }
+
+    /// Called once per frame with the buttons that are held. Returns the buttons the game should
+    /// see for the next frame, or None once playback has run out.
+    pub fn next_frame(&mut self, held: u8) -> Result<Option<u8>, io::Error> {
+        match self {
+            Movie::Recording(file) => {
+                file.write_all(&[held])?;
+                Ok(Some(held))
+            }
+            Movie::Playing { frames, next } => {
+                let buttons = frames.get(*next).cloned();
+                *next += 1;
+                Ok(buttons)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn round_trip() {
+        let path = env::temp_dir().join(format!("wolfwig_movie_round_trip_{}.wwmv", process::id()));
+        {
+            let mut movie = Movie::record(&path, 0x1234).unwrap();
+            assert_eq!(movie.next_frame(0x01).unwrap(), Some(0x01));
+            assert_eq!(movie.next_frame(0x80).unwrap(), Some(0x80));
+        }
+        assert!(Movie::play(&path, 0x4321).is_err());
+        let mut movie = Movie::play(&path, 0x1234).unwrap();
+        assert_eq!(movie.next_frame(0xFF).unwrap(), Some(0x01));
+        assert_eq!(movie.next_frame(0xFF).unwrap(), Some(0x80));
+        assert_eq!(movie.next_frame(0xFF).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
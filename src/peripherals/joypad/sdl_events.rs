@@ -1,27 +1,89 @@
+use sdl2::controller::{Button, GameController};
 use sdl2::event::Event as SdlEvent;
-use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
+use sdl2::keyboard::{Keycode, LSHIFTMOD, RSHIFTMOD};
+use sdl2::{EventPump, GameControllerSubsystem};
+use std::collections::HashMap;
 
-use peripherals::joypad::events::{EventHandler, State};
+use peripherals::joypad::events::{EventHandler, Hotkeys, State};
 
 pub struct SdlEvents {
     events: EventPump,
+    // None if controllers couldn't be set up, in which case there's only the keyboard.
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // Open controllers, by joystick instance id. Dropping one closes it.
+    controllers: HashMap<i32, GameController>,
+    // Keyboard and controller state are tracked separately, so that letting go of a button on
+    // one doesn't release it if it's still held on the other.
     state: State,
+    pad: State,
 }
 
 ///! `EventHandler` for sdl
 impl SdlEvents {
-    pub fn new(events: EventPump) -> Self {
+    pub fn new(events: EventPump, controller_subsystem: Option<GameControllerSubsystem>) -> Self {
         Self {
             state: State::new(),
+            pad: State::new(),
+            controller_subsystem,
+            controllers: HashMap::new(),
             events,
         }
     }
 }
 
+// Maps a controller button onto the pad state, by position rather than by label, so the right
+// face button is A and the bottom one is B, like on the Game Boy.
+fn set_button(pad: &mut State, button: Button, pressed: bool) {
+    match button {
+        Button::DPadUp => pad.up = pressed,
+        Button::DPadDown => pad.down = pressed,
+        Button::DPadLeft => pad.left = pressed,
+        Button::DPadRight => pad.right = pressed,
+        Button::B => pad.a = pressed,
+        Button::A => pad.b = pressed,
+        Button::Start => pad.start = pressed,
+        Button::Back => pad.select = pressed,
+        _ => {}
+    }
+}
+
+// The save state slot for a function key: F1-F9 are slots 1-9, and F10 is slot 0.
+fn save_slot(code: Keycode) -> Option<u8> {
+    let keys = [
+        Keycode::F10,
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+    ];
+    keys.iter()
+        .position(|&key| key == code)
+        .map(|slot| slot as u8)
+}
+
+// Buttons held on either the keyboard or a controller.
+fn merge(keyboard: State, pad: State) -> State {
+    State {
+        start: keyboard.start || pad.start,
+        select: keyboard.select || pad.select,
+        a: keyboard.a || pad.a,
+        b: keyboard.b || pad.b,
+        up: keyboard.up || pad.up,
+        down: keyboard.down || pad.down,
+        left: keyboard.left || pad.left,
+        right: keyboard.right || pad.right,
+        ..keyboard
+    }
+}
+
 impl EventHandler for SdlEvents {
     // TODO(slongfield): This is still the root of performance problems.
-    fn get_state(&mut self) -> State {
+    fn get_state(&mut self, _cycle: u64) -> State {
         for event in self.events.poll_iter() {
             // TODO(slongfield): Make this configurable. Currently optimized for my
             // Kinesis keyboard, but that's somewhat of an uncommon layout.
@@ -31,9 +93,9 @@ impl EventHandler for SdlEvents {
                 }
                 SdlEvent::KeyDown {
                     keycode: Some(code),
+                    keymod,
                     ..
                 } => {
-                    let mut set_keydown = true;
                     debug!("Got keydown {:?}", code);
                     match code {
                         Keycode::Escape => self.state.shutdown = true,
@@ -43,13 +105,52 @@ impl EventHandler for SdlEvents {
                         Keycode::D => self.state.right = true,
                         Keycode::J => self.state.b = true,
                         Keycode::K => self.state.a = true,
+                        Keycode::U => self.state.turbo_b = true,
+                        Keycode::I => self.state.turbo_a = true,
                         Keycode::Backspace => self.state.select = true,
                         Keycode::Space => self.state.start = true,
-                        _ => set_keydown = false,
+                        Keycode::Tab => self.state.fast_forward = true,
+                        Keycode::P | Keycode::Pause => self.state.hotkeys.pause = true,
+                        Keycode::N => self.state.hotkeys.frame_advance = true,
+                        Keycode::F11 => self.state.hotkeys.record = true,
+                        Keycode::F12 => self.state.hotkeys.screenshot = true,
+                        _ => {
+                            // Shift loads the slot rather than saving it.
+                            if let Some(slot) = save_slot(code) {
+                                if keymod.intersects(LSHIFTMOD | RSHIFTMOD) {
+                                    self.state.hotkeys.load_slot = Some(slot);
+                                } else {
+                                    self.state.hotkeys.save_slot = Some(slot);
+                                }
+                            }
+                        }
+                    }
+                }
+                SdlEvent::ControllerDeviceAdded { which, .. } => {
+                    if let Some(ref subsystem) = self.controller_subsystem {
+                        match subsystem.open(which) {
+                            Ok(controller) => {
+                                info!("Connected controller {}", controller.name());
+                                self.controllers
+                                    .insert(controller.instance_id(), controller);
+                            }
+                            Err(err) => error!("Could not open controller {}: {}", which, err),
+                        }
                     }
-                    if set_keydown {
-                        self.state.keydown = true;
+                }
+                SdlEvent::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controller) = self.controllers.remove(&which) {
+                        info!("Disconnected controller {}", controller.name());
                     }
+                    if self.controllers.is_empty() {
+                        self.pad = State::new();
+                    }
+                }
+                SdlEvent::ControllerButtonDown { button, .. } => {
+                    set_button(&mut self.pad, button, true)
+                }
+                SdlEvent::ControllerButtonUp { button, .. } => {
+                    set_button(&mut self.pad, button, false)
                 }
                 SdlEvent::KeyUp {
                     keycode: Some(code),
@@ -63,8 +164,11 @@ impl EventHandler for SdlEvents {
                         Keycode::D => self.state.right = false,
                         Keycode::J => self.state.b = false,
                         Keycode::K => self.state.a = false,
+                        Keycode::U => self.state.turbo_b = false,
+                        Keycode::I => self.state.turbo_a = false,
                         Keycode::Backspace => self.state.select = false,
                         Keycode::Space => self.state.start = false,
+                        Keycode::Tab => self.state.fast_forward = false,
                         _ => {}
                     }
                 }
@@ -72,10 +176,10 @@ impl EventHandler for SdlEvents {
             }
         }
 
-        self.state
+        merge(self.state, self.pad)
     }
 
-    fn clear_keydown(&mut self) {
-        self.state.keydown = false;
+    fn clear_hotkeys(&mut self) {
+        self.state.hotkeys = Hotkeys::default();
     }
 }
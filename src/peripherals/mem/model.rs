@@ -1,14 +1,20 @@
+use peripherals::io_map::IoRegister;
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
 pub struct Memory {
-    // External RAM, in cartrige, may be switchable?
-    // 0xA000-0xBFFF
-    xram: [u8; 0x2000],
-    // Working RAM bank 0
-    // 0xC000-0xCFFF,
-    wram0: [u8; 0x1000],
-    // TODO(slongfield): Switchable banks.
-    // Bank 1 in DMG mode, banks 1-7 in CGB mode
-    // 0xD000-0xDFFF
-    wram1_n: [u8; 0x1000],
+    // Working RAM, 0xC000-0xDFFF. Bank 0 is always at 0xC000-0xCFFF. 0xD000-0xDFFF is bank 1
+    // in DMG mode, and any of banks 1-7 in CGB mode.
+    wram: Vec<u8>,
+    // SVBK, which picks the bank at 0xD000. Only there in CGB mode.
+    wram_bank: u8,
+    // KEY1's prepare bit, which asks for a speed switch on the next STOP. Only there in CGB mode,
+    // and nothing switches speed yet.
+    speed_switch: bool,
+    cgb: bool,
     // High RAM. 0xFF80-0xFFFE
     high_ram: [u8; 0x17f],
 }
@@ -16,21 +22,54 @@ pub struct Memory {
 impl Memory {
     pub fn new() -> Self {
         Self {
-            xram: [0; 0x2000],
-            wram0: [0; 0x1000],
-            wram1_n: [0; 0x1000],
+            wram: vec![0; WRAM_BANK_SIZE * WRAM_BANKS],
+            wram_bank: 1,
+            speed_switch: false,
+            cgb: false,
             high_ram: [0; 0x17f],
         }
     }
 
+    /// Turns on work RAM banking and KEY1, which only the CGB has.
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        if !cgb {
+            self.wram_bank = 1;
+            self.speed_switch = false;
+        }
+    }
+
+    /// The work RAM bank mapped at 0xD000.
+    pub fn wram_bank(&self) -> u8 {
+        self.wram_bank
+    }
+
+    /// Every bank of work RAM, whichever is mapped.
+    pub fn wram(&self) -> &[u8] {
+        &self.wram
+    }
+
+    /// High RAM, 0xFF80-0xFFFE.
+    pub fn high_ram(&self) -> &[u8] {
+        &self.high_ram[..0x7F]
+    }
+
+    // Index into work RAM for 0xC000-0xFDFF. 0xE000-0xFDFF echoes 0xC000-0xDDFF, so the top 512
+    // bytes of the switchable bank have no echo.
+    fn wram_offset(&self, address: usize) -> usize {
+        match (address - 0xC000) & 0x1FFF {
+            offset @ 0x0000..=0x0FFF => offset,
+            offset => usize::from(self.wram_bank) * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE,
+        }
+    }
+
     pub fn write(&mut self, address: u16, val: u8) {
         let address = address as usize;
         match address {
-            addr @ 0xA000..=0xBFFF => self.xram[addr - 0xA000] = val,
-            addr @ 0xC000..=0xCFFF => self.wram0[addr - 0xC000] = val,
-            addr @ 0xD000..=0xDFFF => self.wram1_n[addr - 0xD000] = val,
-            addr @ 0xE000..=0xFDFF => self.write((addr - 0x2000) as u16, val),
-            addr @ 0xFEA0..=0xFEFF => info!("Write to unmapped memory region: {}", addr),
+            addr @ 0xC000..=0xFDFF => {
+                let offset = self.wram_offset(addr);
+                self.wram[offset] = val
+            }
             addr @ 0xFF80..=0xFFFE => self.high_ram[addr - 0xFF80] = val,
             addr => panic!("Attempted to write mem to unmapped address: {:#04X}!", addr),
         }
@@ -39,9 +78,7 @@ impl Memory {
     pub fn read(&self, address: u16) -> u8 {
         let address = address as usize;
         match address {
-            addr @ 0xA000..=0xBFFF => self.xram[addr - 0xA000],
-            addr @ 0xC000..=0xCFFF => self.wram0[addr - 0xC000],
-            addr @ 0xD000..=0xDFFF => self.wram1_n[addr - 0xD000],
+            addr @ 0xC000..=0xFDFF => self.wram[self.wram_offset(addr)],
             addr @ 0xFF80..=0xFFFE => self.high_ram[addr - 0xFF80],
             addr => panic!(
                 "Attempted to read mem from unmapped address: {:#04X}!",
@@ -51,6 +88,45 @@ impl Memory {
     }
 }
 
+impl IoRegister for Memory {
+    fn read8(&self, address: u16) -> u8 {
+        match address {
+            // Always at normal speed.
+            0xFF4D if self.cgb => 0x7E | self.speed_switch as u8,
+            0xFF70 if self.cgb => 0xF8 | self.wram_bank,
+            _ => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        if !self.cgb {
+            return;
+        }
+        match address {
+            0xFF4D => self.speed_switch = val & 0x1 != 0,
+            // Bank 0 is always at 0xC000, so selecting it selects bank 1.
+            0xFF70 => self.wram_bank = (val & 0x7).max(1),
+            _ => {}
+        }
+    }
+}
+
+impl Snapshot for Memory {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.wram);
+        w.u8(self.wram_bank);
+        w.bool(self.speed_switch);
+        w.bytes(&self.high_ram);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.bytes_into(&mut self.wram)?;
+        self.wram_bank = (r.u8()? & 0x7).max(1);
+        self.speed_switch = r.bool()?;
+        r.bytes_into(&mut self.high_ram)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -69,4 +145,49 @@ mod tests {
         assert_eq!(mem.read(0xC042), 17);
     }
 
+    #[test]
+    fn shadow_ram_boundaries() {
+        let mut mem = Memory::new();
+        mem.write(0xC000, 1);
+        mem.write(0xDDFF, 2);
+        mem.write(0xDE00, 3);
+        mem.write(0xDFFF, 4);
+        assert_eq!(mem.read(0xE000), 1);
+        assert_eq!(mem.read(0xFDFF), 2);
+        mem.write(0xFDFF, 5);
+        assert_eq!(mem.read(0xDDFF), 5);
+        mem.write(0xEFFF, 6);
+        assert_eq!(mem.read(0xCFFF), 6);
+        mem.write(0xF000, 7);
+        assert_eq!(mem.read(0xD000), 7);
+        assert_eq!(mem.read(0xDE00), 3);
+        assert_eq!(mem.read(0xDFFF), 4);
+    }
+
+    #[test]
+    fn wram_banks() {
+        let mut mem = Memory::new();
+        mem.write8(0xFF70, 2);
+        assert_eq!(mem.read8(0xFF70), 0xFF);
+        mem.write(0xD000, 1);
+
+        mem.set_cgb(true);
+        for bank in 2..8 {
+            mem.write8(0xFF70, bank);
+            mem.write(0xD000, bank);
+        }
+        assert_eq!(mem.read(0xD000), 7);
+        assert_eq!(mem.read(0xF000), 7);
+        mem.write8(0xFF70, 0);
+        assert_eq!(mem.read8(0xFF70), 0xF9);
+        assert_eq!(mem.read(0xD000), 1);
+        mem.write8(0xFF70, 3);
+        assert_eq!(mem.read(0xD000), 3);
+        mem.write(0xFDFF, 9);
+        assert_eq!(mem.read(0xDDFF), 9);
+        mem.write8(0xFF70, 4);
+        assert_eq!(mem.read(0xDDFF), 0);
+        assert_eq!(mem.read(0xC000), 0);
+    }
+
 }
@@ -1,23 +1,54 @@
+use cpu::bus::Bus;
+use model::Model;
+use peripherals::cartridge::Cartridge;
+use savestate::{Reader, Snapshot, Writer};
 use sdl2;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::io::{self, Read};
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use util;
+use Options;
+use Speed;
 
 mod apu;
+mod battery;
 mod cartridge;
+mod cheats;
+mod clock;
+mod frontend;
 mod interrupt;
+mod io_log;
+mod io_map;
+mod io_registers;
 mod joypad;
+
+#[cfg(test)]
+pub use self::joypad::{Button, FakeInput};
 pub mod mem;
 mod ppu;
+mod scheduler;
 mod serial;
+mod spans;
 mod timer;
+mod watch;
+
+pub use self::clock::{Clock, CycleClock, MockClock, RealClock};
+pub use self::frontend::Frontend;
+pub use self::io_log::IoLog;
+pub use self::ppu::{Colors, FrameStats, FrameTimeSummary};
+pub use self::watch::{Access, HookId, WatchHit};
 
 #[derive(Debug, Clone)]
 pub struct Dma {
     pub enabled: bool,
     pub source: u16,
     pub dest: u16,
+    // The byte being copied, which is what the CPU sees if it reads the bus DMA is using.
+    pub bus: u8,
 }
 
 impl Dma {
@@ -26,6 +57,7 @@ impl Dma {
             enabled: false,
             source: 0,
             dest: 0,
+            bus: 0xFF,
         }
     }
 }
@@ -33,13 +65,104 @@ impl Dma {
 pub struct Peripherals {
     pub mem: mem::model::Memory,
     apu: apu::Apu,
-    cartridge: Box<cartridge::Cartridge>,
+    // Game Genie codes patch the cartridge's ROM on the way out, so it's always plugged in.
+    cartridge: cartridge::GameGenie,
     dma: Dma,
     interrupt: interrupt::Interrupt,
     joypad: joypad::Joypad,
+    // Fake input, scripted against `cycles`, is pumped here. Real input is pumped by
+    // the `Frontend`, on whichever thread SDL was set up on.
+    input: Option<joypad::InputPump<joypad::FakeEvents>>,
+    // Machine cycles run since power on.
+    cycles: u64,
+    // What frames are paced against and audio is queued by.
+    clock: Box<dyn Clock>,
+    scheduler: scheduler::Scheduler,
+    // The cycles the serial port and cartridge have been run up to. Between their events, they
+    // fall behind, and are caught up with the cycles since when they next run.
+    serial_stepped: u64,
+    cartridge_stepped: u64,
+    // Bumped for each 256-byte page whenever it's written to, so cached decodes can tell when
+    // the code they came from has changed.
+    code_versions: Vec<u32>,
     pub ppu: ppu::Ppu,
     serial: serial::Serial,
     timer: timer::Timer,
+    // The current frame's spans.
+    spans: spans::Spans,
+    speed: Speed,
+    // Speed to use while the fast-forward hotkey is held.
+    fast_forward_speed: Speed,
+    fast_forwarding: bool,
+    paused: bool,
+    // Set while advancing a single frame from pause. Emulation pauses again once the PPU reaches
+    // this frame.
+    pause_at_frame: Option<u32>,
+    // A save state hotkey that's been pressed. States cover the CPU too, so they're handled by
+    // whatever's stepping it.
+    slot_action: Option<SlotAction>,
+    // Identifies the ROM, so that files tied to it (like input movies) can be checked.
+    rom_hash: u64,
+    // Which Game Boy is being emulated. Only changes what unmapped reads return, so far.
+    model: Model,
+    watch: watch::Watch,
+    cheats: cheats::Cheats,
+    // Where .sav files, save states, and captures go, and the name of the ROM's file without its
+    // extension, which they're named after.
+    save_dir: PathBuf,
+    rom_name: String,
+    // Names the game for its settings file, from the header.
+    game_id: String,
+    // Whether the cartridge RAM is battery-backed, so is kept in a .sav file.
+    battery: bool,
+    battery_writer: battery::BatteryWriter,
+    // Set while the debugger replays history. Output that already happened, like serial bytes,
+    // recorded frames, and battery saves, isn't repeated.
+    muted: bool,
+}
+
+/// Describes the ROM in a file, without setting anything else up.
+pub fn rom_info(rom: &Path) -> Result<String, io::Error> {
+    cartridge::info(&read_rom_from_file(rom)?)
+}
+
+/// Saving to or loading from a numbered save state slot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotAction {
+    Save(u8),
+    Load(u8),
+}
+
+/// Seconds since the Unix epoch, which cartridge clocks and save states are saved against.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn sdl_error(context: &str, err: String) -> io::Error {
+    io::Error::other(format!("{}: {}", context, err))
+}
+
+// Starts SDL's video. Without a display, that fails, so it falls back to SDL's dummy driver. The
+// window is never shown then, but everything else works as usual.
+fn init_video(sdl: &sdl2::Sdl) -> Result<sdl2::VideoSubsystem, io::Error> {
+    let video =
+        with_dummy_video(|| sdl.video()).map_err(|err| sdl_error("Could not start video", err))?;
+    info!("Using SDL video driver {}", video.current_video_driver());
+    Ok(video)
+}
+
+// Runs `start`, and if it fails, runs it again with SDL_VIDEODRIVER set to SDL's dummy driver.
+fn with_dummy_video<T, F>(mut start: F) -> Result<T, String>
+where
+    F: FnMut() -> Result<T, String>,
+{
+    start().or_else(|err| {
+        warn!("Could not start video, so nothing will be shown: {}", err);
+        env::set_var("SDL_VIDEODRIVER", "dummy");
+        start()
+    })
 }
 
 fn read_rom_from_file(filename: &Path) -> Result<Vec<u8>, io::Error> {
@@ -50,65 +173,156 @@ fn read_rom_from_file(filename: &Path) -> Result<Vec<u8>, io::Error> {
     Ok(buffer)
 }
 
-// Macro for fanning writes from a register out to various setters.
-macro_rules! write_reg {
-    ($val:ident: $( $msb:literal .. $lsb:literal =>
-                    $self:ident.$mod:ident$(.$field:ident)+),* ) => {{
-        $(
-            $self.$mod$(.$field)+(($val & ((1 << ($msb-$lsb+1)) - 1 << $lsb)) >> $lsb);
-        )*
-    }}
-}
-
-// Macro for fanning reads from a reigster in from various getters. Unmapped bits are read as 1.
-macro_rules! read_reg {
-    ( $( $msb:literal .. $lsb:literal => $self:ident.$mod:ident$(.$field:ident)+),* ) => {{
-        let mut val = 0xFF;
-        $(
-            val &= !(((1 << ($msb-$lsb+1)) - 1) << $lsb);
-            val |= (u8::from($self.$mod$(.$field)+()) & ((1 << ($msb-$lsb+1)) - 1)) << $lsb;
-        )*
-            val
-    }}
-}
-
 impl Peripherals {
-    pub fn from_files(bootrom: &Path, rom: &Path) -> Result<Self, io::Error> {
-        let bootrom = read_rom_from_file(bootrom)?;
+    /// Without a boot ROM, `skip_boot` has to set things up the way it would have. The returned
+    /// frontend has to stay on this thread, but the peripherals can be sent to another.
+    pub fn from_files(
+        bootrom: Option<&Path>,
+        rom: &Path,
+        options: &Options,
+    ) -> Result<(Self, Frontend), io::Error> {
+        let bootrom = match bootrom {
+            Some(path) => read_rom_from_file(path)?,
+            None => vec![],
+        };
+        let rom_name = rom.file_stem().map_or_else(
+            || "rom".to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
         let rom = read_rom_from_file(rom)?;
-        let sdl = sdl2::init().unwrap();
-        let video_subsystem = sdl.video().unwrap();
-        let ppu = ppu::Ppu::new_sdl(video_subsystem);
-        let events = sdl.event_pump().unwrap();
-        let joypad = joypad::Joypad::new_sdl(events);
-        let audio_subsystem = sdl.audio().unwrap();
-        let apu = apu::Apu::new(audio_subsystem);
+        if !options.save_dir.as_os_str().is_empty() {
+            fs::create_dir_all(&options.save_dir)?;
+        }
+        let header = cartridge::header::Header::new(&rom);
+        if let Err(err) = header.check_header_checksum() {
+            if options.strict {
+                return Err(err);
+            }
+            warn!("{}. A real Game Boy wouldn't start this ROM.", err);
+        }
+        if !header.global_checksum_ok() {
+            warn!("The ROM's global checksum is wrong");
+        }
+        let sdl = sdl2::init().map_err(|err| sdl_error("Could not start SDL", err))?;
+        let video_subsystem = init_video(&sdl)?;
+        let mut presenter = ppu::Presenter::default();
+        let ppu = ppu::Ppu::new_sdl(&video_subsystem, &mut presenter, options)
+            .map_err(|err| sdl_error("Could not open the window", err))?;
+        let events = sdl
+            .event_pump()
+            .map_err(|err| sdl_error("Could not read input", err))?;
+        let controllers = sdl
+            .game_controller()
+            .map_err(|err| warn!("Controllers won't work: {}", err))
+            .ok();
+        let (joypad, input) = joypad::Joypad::new_sdl(events, controllers);
+        let (mut apu, audio) = if options.no_audio {
+            (apu::Apu::new_fake(), None)
+        } else {
+            match sdl.audio().and_then(apu::Apu::new) {
+                Ok((apu, audio)) => (apu, Some(audio)),
+                Err(err) => {
+                    warn!(
+                        "Could not start audio, so there won't be any sound: {}",
+                        err
+                    );
+                    (apu::Apu::new_fake(), None)
+                }
+            }
+        };
+        if options.apu_viewer {
+            let window = presenter
+                .add_sdl_window(
+                    &video_subsystem,
+                    "Wolfwig APU",
+                    apu::SCOPE_WIDTH,
+                    apu::SCOPE_HEIGHT,
+                    1,
+                )
+                .map_err(|err| sdl_error("Could not open the APU viewer", err))?;
+            apu.open_scope(window);
+        }
         let interrupt = interrupt::Interrupt::new();
         let timer = timer::Timer::new();
         let dma = Dma::new();
-        let cartridge = cartridge::new(bootrom, rom);
-        Ok(Self {
+        let rom_hash = util::fnv1a_64(&rom);
+        let mut mem = mem::model::Memory::new();
+        mem.set_cgb(options.model == Model::Cgb);
+        let battery = header.cartridge_type.has_battery();
+        let cartridge = cartridge::GameGenie::new(cartridge::new(bootrom, rom));
+        let mut peripherals = Self {
             apu,
             cartridge,
             dma,
             interrupt,
             joypad,
-            mem: mem::model::Memory::new(),
+            input: None,
+            cycles: 0,
+            clock: Box::new(RealClock::new()),
+            scheduler: scheduler::Scheduler::new(0),
+            serial_stepped: 0,
+            cartridge_stepped: 0,
+            code_versions: vec![0; 0x100],
+            mem,
             ppu,
             serial: serial::Serial::new(None),
             timer,
-        })
+            spans: spans::Spans::new(0),
+            speed: Speed::Times(1),
+            fast_forward_speed: Speed::Times(4),
+            fast_forwarding: false,
+            paused: false,
+            pause_at_frame: None,
+            slot_action: None,
+            rom_hash,
+            model: options.model,
+            watch: watch::Watch::default(),
+            cheats: cheats::Cheats::default(),
+            save_dir: options.save_dir.clone(),
+            rom_name,
+            game_id: header.game_id(),
+            battery,
+            battery_writer: battery::BatteryWriter::default(),
+            muted: false,
+        };
+        peripherals.load_battery()?;
+        Ok((peripherals, Frontend::new(input, presenter, audio)))
+    }
+
+    /// Runs a ROM without a window, sound, or input, and as fast as possible. There's no boot ROM,
+    /// so `skip_boot` has to be called before running.
+    pub fn new_headless(rom: Vec<u8>) -> Self {
+        let (joypad, input) = joypad::Joypad::new_fake();
+        let mut peripherals = Self::new_fake_with_joypad(joypad, input);
+        peripherals.rom_hash = util::fnv1a_64(&rom);
+        peripherals.game_id = cartridge::header::Header::new(&rom).game_id();
+        peripherals.cartridge = cartridge::GameGenie::new(cartridge::new(vec![], rom));
+        peripherals.set_speed(Speed::Uncapped);
+        peripherals
     }
 
     ///! Fake for testing.
     pub fn new_fake() -> Self {
+        let (joypad, input) = joypad::Joypad::new_fake();
+        Self::new_fake_with_joypad(joypad, input)
+    }
+
+    /// Fake for testing, with joypad input scripted through the returned channel.
+    pub fn new_fake_with_inputs() -> (Self, mpsc::Sender<joypad::FakeInput>) {
+        let (joypad, input, inputs) = joypad::Joypad::new_fake_with_inputs();
+        (Self::new_fake_with_joypad(joypad, input), inputs)
+    }
+
+    fn new_fake_with_joypad(
+        joypad: joypad::Joypad,
+        input: joypad::InputPump<joypad::FakeEvents>,
+    ) -> Self {
         let ppu = ppu::Ppu::new_fake();
-        let joypad = joypad::Joypad::new_fake();
         let apu = apu::Apu::new_fake();
         let interrupt = interrupt::Interrupt::new();
         let timer = timer::Timer::new();
         let dma = Dma::new();
-        let cartridge = cartridge::new(vec![0; 0x100], vec![0; 0x1000]);
+        let cartridge = cartridge::GameGenie::new(cartridge::new(vec![0; 0x100], vec![0; 0x1000]));
         Self {
             mem: mem::model::Memory::new(),
             serial: serial::Serial::new(None),
@@ -116,23 +330,153 @@ impl Peripherals {
             apu,
             ppu,
             joypad,
+            input: Some(input),
+            cycles: 0,
+            clock: Box::new(CycleClock::default()),
+            scheduler: scheduler::Scheduler::new(0),
+            serial_stepped: 0,
+            cartridge_stepped: 0,
+            code_versions: vec![0; 0x100],
             interrupt,
             timer,
+            spans: spans::Spans::new(0),
             dma,
+            speed: Speed::Times(1),
+            fast_forward_speed: Speed::Times(4),
+            fast_forwarding: false,
+            paused: false,
+            pause_at_frame: None,
+            slot_action: None,
+            rom_hash: 0,
+            model: Model::Dmg,
+            watch: watch::Watch::default(),
+            cheats: cheats::Cheats::default(),
+            save_dir: PathBuf::new(),
+            rom_name: "rom".to_string(),
+            game_id: "rom".to_string(),
+            battery: false,
+            battery_writer: battery::BatteryWriter::default(),
+            muted: false,
+        }
+    }
+
+    // How often to check for input while paused.
+    const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.paused = true;
+            // The audio device stays open, and plays silence once its queue is empty.
+            self.apu.flush();
         }
     }
 
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.pause_at_frame = None;
+    }
+
+    /// Runs exactly one more PPU frame, then pauses again. Does nothing if not paused.
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.pause_at_frame = Some(self.ppu.frame + 1);
+        }
+    }
+
+    /// Replaces the clock frames are paced against. Windowed runs start with the host's clock,
+    /// and headless and fake ones with emulated time.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        self.joypad.shutdown_requested()
+    }
+
+    /// Returns the save state hotkey pressed since the last call, if any.
+    pub fn take_slot_action(&mut self) -> Option<SlotAction> {
+        self.slot_action.take()
+    }
+
+    /// Stands in for `step` while paused, or while the CPU is in STOP. Nothing is emulated, but
+    /// input is still checked, so that the resume hotkey or a button press that ends STOP gets
+    /// seen.
+    pub fn step_idle(&mut self) {
+        let started = Instant::now();
+        self.poll_input();
+        self.clock.sleep(Self::PAUSED_POLL_INTERVAL);
+        self.ppu.exclude_from_frame_time(started.elapsed());
+    }
+
+    // Machine cycles in a frame, while the LCD's on.
+    pub const CYCLES_PER_FRAME: u32 = 17_556;
+
+    // How often battery-backed RAM is written out, if it's changed. It's also written when the
+    // window is closed, so this is about how much play a crash can lose.
+    const BATTERY_FLUSH_FRAMES: u32 = 60;
+
+    // Fake input is pumped once per frame, and while idle. Real input comes in whenever the
+    // frontend sends it, and is picked up on the next step.
+    fn poll_input(&mut self) {
+        if let Some(ref mut input) = self.input {
+            input.pump(self.cycles);
+        }
+        self.joypad.update();
+        self.schedule_joypad();
+        self.handle_hotkeys();
+    }
+
+    /// Runs one machine cycle. The PPU and timer run every cycle, since they raise interrupts on
+    /// exact cycles, and their registers can be read at any time. Everything else only runs when
+    /// the scheduler says it next has something to do.
     pub fn step(&mut self) {
-        self.apu.step();
-        self.joypad.step(&mut self.interrupt);
-        self.ppu.step(&mut self.interrupt, &mut self.dma);
-        self.serial.step();
+        self.cycles += 1;
+        self.clock.tick();
+        let frame = self.ppu.frame;
+        let in_vblank = self.ppu.in_vblank();
+        self.ppu
+            .step(&mut self.interrupt, &mut self.dma, &mut *self.clock);
+        if self.ppu.in_vblank() && !in_vblank {
+            self.apply_cheats();
+        }
+        if self.ppu.frame != frame {
+            self.spans = spans::Spans::new(self.ppu.frame);
+            let span = self.spans.frame.clone();
+            let _frame = span.enter();
+            self.poll_input();
+            self.joypad.end_frame();
+            self.schedule_joypad();
+            if self.ppu.frame.is_multiple_of(Self::BATTERY_FLUSH_FRAMES) && !self.muted {
+                if let Some(saved) = self.battery_to_save() {
+                    self.battery_writer.save(self.save_path("sav"), saved);
+                }
+            }
+        }
+        if self.pause_at_frame == Some(self.ppu.frame) {
+            self.pause_at_frame = None;
+            self.pause();
+        }
+        if self.scheduler.pending(self.cycles) {
+            self.run_events();
+        }
+        let divider = self.timer.internal_divider();
         self.timer.step(&mut self.interrupt);
+        // The APU's frame sequencer is clocked by bit 12 of the divider falling.
+        if divider & !self.timer.internal_divider() & 1 << 12 != 0 {
+            let _apu = self.spans.apu.enter();
+            self.apu.clock_frame_sequencer();
+        }
         if self.dma.enabled {
             // Disable dma for read
             self.dma.enabled = false;
             for index in 0..4 {
                 let data = self.read(self.dma.source + index);
+                self.dma.bus = data;
                 let addr = self.dma.dest + index;
                 self.write(addr, data);
             }
@@ -140,342 +484,237 @@ impl Peripherals {
         }
     }
 
+    // Cycles between checks on whether the audio device needs more samples. It asks for them
+    // every few milliseconds, so this is plenty often.
+    const AUDIO_CHECK_CYCLES: u64 = 256;
+
+    // Cycles between checks for input from the frontend, about a twentieth of a millisecond.
+    const INPUT_CHECK_CYCLES: u64 = 64;
+
+    fn run_events(&mut self) {
+        while let Some(event) = self.scheduler.pop(self.cycles) {
+            match event {
+                scheduler::Event::Serial => {
+                    let _serial = self.spans.serial.enter();
+                    let cycles = self.cycles - self.serial_stepped;
+                    self.serial_stepped = self.cycles;
+                    self.serial.step(cycles, &mut self.interrupt);
+                    // Once it's idle, writing its registers wakes it back up.
+                    if let Some(next) = self.serial.next_step() {
+                        self.scheduler
+                            .schedule(scheduler::Event::Serial, self.cycles + next);
+                    }
+                }
+                scheduler::Event::Audio => {
+                    let _apu = self.spans.apu.enter();
+                    self.apu.step(&*self.clock);
+                    self.scheduler.schedule(
+                        scheduler::Event::Audio,
+                        self.cycles + Self::AUDIO_CHECK_CYCLES,
+                    );
+                }
+                scheduler::Event::Input => {
+                    if self.joypad.update() {
+                        self.schedule_joypad();
+                        self.handle_hotkeys();
+                    }
+                    self.scheduler.schedule(
+                        scheduler::Event::Input,
+                        self.cycles + Self::INPUT_CHECK_CYCLES,
+                    );
+                }
+                scheduler::Event::Cartridge => self.step_cartridge(),
+                scheduler::Event::Joypad => self.joypad.request_interrupt(&mut self.interrupt),
+            }
+        }
+    }
+
+    // Catches the cartridge's clock up to the current cycle, and schedules it for when it next
+    // ticks. Cartridges without a clock are never scheduled.
+    fn step_cartridge(&mut self) {
+        self.cartridge.step(self.cycles - self.cartridge_stepped);
+        self.cartridge_stepped = self.cycles;
+        match self.cartridge.next_tick() {
+            Some(next) => self
+                .scheduler
+                .schedule(scheduler::Event::Cartridge, self.cycles + next),
+            None => self.scheduler.cancel(scheduler::Event::Cartridge),
+        }
+    }
+
+    // The joypad interrupt is requested on the cycle after a line falls.
+    fn schedule_joypad(&mut self) {
+        if self.joypad.line_fell() {
+            self.scheduler
+                .schedule(scheduler::Event::Joypad, self.cycles + 1);
+        }
+    }
+
+    fn apply_cheats(&mut self) {
+        for i in 0..self.cheats.game_shark().len() {
+            let code = self.cheats.game_shark()[i];
+            self.write_unwatched(code.address, code.value);
+        }
+    }
+
+    fn handle_hotkeys(&mut self) {
+        if self.joypad.fast_forward() != self.fast_forwarding {
+            self.fast_forwarding = !self.fast_forwarding;
+            self.apply_speed();
+        }
+        let hotkeys = self.joypad.take_hotkeys();
+        if hotkeys.pause {
+            if self.paused {
+                self.resume();
+            } else {
+                self.pause();
+            }
+        }
+        if hotkeys.frame_advance {
+            self.advance_frame();
+        }
+        if let Some(slot) = hotkeys.save_slot {
+            self.slot_action = Some(SlotAction::Save(slot));
+        }
+        if let Some(slot) = hotkeys.load_slot {
+            self.slot_action = Some(SlotAction::Load(slot));
+        }
+        if hotkeys.screenshot {
+            let path = self.capture_path("png");
+            if let Err(err) = self.ppu.screenshot(&path) {
+                error!("Could not write screenshot {}: {}", path.display(), err);
+            }
+        }
+        if hotkeys.record {
+            if self.ppu.recording() {
+                self.ppu.stop_recording();
+            } else {
+                let path = self.capture_path("gif");
+                if let Err(err) = self.ppu.start_recording(&path) {
+                    error!("Could not start recording {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
     pub fn write(&mut self, address: u16, val: u8) {
+        self.watch.write(address, val);
+        self.write_unwatched(address, val);
+    }
+
+    fn write_unwatched(&mut self, address: u16, val: u8) {
+        self.code_written(address);
+        if let 0xFF01..=0xFF02 = address {
+            self.scheduler
+                .schedule(scheduler::Event::Serial, self.cycles + 1);
+        }
         if self.dma.enabled {
             if let addr @ 0xFF80..=0xFFFE = address {
                 self.mem.write(addr, val);
             }
         } else {
             match address {
-                addr @ 0x0000..=0x7FFF | addr @ 0xFF50 => self.cartridge.write(addr, val),
+                // The clock has to be caught up before it's written, and writes can stop or start
+                // it.
+                addr @ 0x0000..=0x7FFF | addr @ 0xA000..=0xBFFF | addr @ 0xFF50 => {
+                    self.step_cartridge();
+                    self.cartridge.write(addr, val);
+                    self.step_cartridge();
+                }
                 addr @ 0x8000..=0x9FFF | addr @ 0xFE00..=0xFE9F => self.ppu.write(addr, val),
-                0xFF40 => self.ppu.control.set_control(val),
-                0xFF41 => write_reg!(val:
-                                     6..6 => self.ppu.status.set_lyc_interrupt,
-                                     5..5 => self.ppu.status.set_mode2_interrupt,
-                                     4..4 => self.ppu.status.set_mode1_interrupt,
-                                     3..3 => self.ppu.status.set_mode0_interrupt
-                ),
-                0xFF42 => self.ppu.set_scroll_y(val),
-                0xFF43 => self.ppu.set_scroll_x(val),
-                0xFF44 => self.ppu.set_lcd_y(val),
-                0xFF45 => self.ppu.set_lcd_y_compare(val),
-                0xFF46 => self.ppu.set_dma(val),
-                0xFF47 => write_reg!(val:
-                                     7..6 => self.ppu.bg_palette.set_color3,
-                                     5..4 => self.ppu.bg_palette.set_color2,
-                                     3..2 => self.ppu.bg_palette.set_color1,
-                                     1..0 => self.ppu.bg_palette.set_color0
-                ),
-                0xFF48 => write_reg!(val:
-                                     7..6 => self.ppu.obj0_palette.set_color3,
-                                     5..4 => self.ppu.obj0_palette.set_color2,
-                                     3..2 => self.ppu.obj0_palette.set_color1,
-                                     1..0 => self.ppu.obj0_palette.set_color0
-                ),
-                0xFF49 => write_reg!(val:
-                                     7..6 => self.ppu.obj1_palette.set_color3,
-                                     5..4 => self.ppu.obj1_palette.set_color2,
-                                     3..2 => self.ppu.obj1_palette.set_color1,
-                                     1..0 => self.ppu.obj1_palette.set_color0
-                ),
-                0xFF4A => self.ppu.set_window_y(val),
-                0xFF4B => self.ppu.set_window_x(val),
-                addr @ 0xA000..=0xBFFF
-                | addr @ 0xC000..=0xCFFF
-                | addr @ 0xD000..=0xDFFF
-                | addr @ 0xFF80..=0xFFFE => self.mem.write(addr, val),
-                // Echo RAM, maps back onto 0xC000-0XDDFF
-                addr @ 0xE000..=0xFDFF => self.write(addr - 0x2000, val),
+                // Work RAM, and its echo at 0xE000-0xFDFF.
+                addr @ 0xC000..=0xFDFF | addr @ 0xFF80..=0xFFFE => self.mem.write(addr, val),
                 addr @ 0xFEA0..=0xFEFF => info!("Write to unmapped memory region: {:#04X}", addr),
-                // I/O registers.
-                0xFF00 => {
-                    write_reg!(val:
-                               5..5 => self.joypad.set_select_button,
-                               4..4 => self.joypad.set_select_direction
-                    );
-                    self.joypad.update(&mut self.interrupt);
-                }
-                0xFF01 => self.serial.set_data(val),
-                0xFF02 => self.serial.set_start((1 << 7) & val != 0),
-                0xFF04 => self.timer.set_divider(),
-                0xFF05 => self.timer.set_counter(val),
-                0xFF06 => self.timer.set_modulo(val),
-                0xFF07 => write_reg!(val:
-                                     2..2 => self.timer.set_start,
-                                     1..0 => self.timer.set_input_clock
-                ),
-                0xFF0F => write_reg!(val:
-                                     4..4 => self.interrupt.set_joypad_trigger,
-                                     3..3 => self.interrupt.set_serial_trigger,
-                                     2..2 => self.interrupt.set_timer_trigger,
-                                     1..1 => self.interrupt.set_lcd_stat_trigger,
-                                     0..0 => self.interrupt.set_vblank_trigger
-                ),
-                0xFF10 => write_reg!(val:
-                                     6..4 => self.apu.channel_one.sweep.set_time,
-                                     3..3 => self.apu.channel_one.sweep.set_direction,
-                                     2..0 => self.apu.channel_one.sweep.set_shift
-                ),
-                0xFF11 => write_reg!(val:
-                                     7..6 => self.apu.channel_one.length_pattern.set_duty,
-                                     5..0 => self.apu.channel_one.length_pattern.set_length
-                ),
-                0xFF12 => write_reg!(val:
-                                     7..4 => self.apu.channel_one.envelope.set_initial_volume,
-                                     3..3 => self.apu.channel_one.envelope.set_direction,
-                                     2..0 => self.apu.channel_one.envelope.set_sweep
-                ),
-                0xFF13 => self.apu.channel_one.frequency.set_frequency_low(val),
-                0xFF14 => write_reg!(val:
-                                     7..7 => self.apu.channel_one.frequency.set_start,
-                                     6..6 => self.apu.channel_one.frequency.set_use_counter,
-                                     2..0 => self.apu.channel_one.frequency.set_frequency_high
-                ),
-                0xFF16 => write_reg!(val:
-                                     7..6 => self.apu.channel_two.length_pattern.set_duty,
-                                     5..0 => self.apu.channel_two.length_pattern.set_length
-                ),
-                0xFF17 => write_reg!(val:
-                                     7..4 => self.apu.channel_two.envelope.set_initial_volume,
-                                     3..3 => self.apu.channel_two.envelope.set_direction,
-                                     2..0 => self.apu.channel_two.envelope.set_sweep
-                ),
-                0xFF18 => self.apu.channel_two.frequency.set_frequency_low(val),
-                0xFF19 => write_reg!(val:
-                                     7..7 => self.apu.channel_two.frequency.set_start,
-                                     6..6 => self.apu.channel_two.frequency.set_use_counter,
-                                     2..0 => self.apu.channel_two.frequency.set_frequency_high
-                ),
-                0xFF1A => write_reg!(val:
-                                     7..7 => self.apu.channel_three.set_enable
-                ),
-                0xFF1B => self.apu.channel_three.set_length(val),
-                0xFF1C => write_reg!(val:
-                                     6..5 => self.apu.channel_three.set_level
-                ),
-                0xFF1D => self.apu.channel_three.frequency.set_frequency_low(val),
-                0xFF1E => write_reg!(val:
-                                     7..7 => self.apu.channel_three.frequency.set_start,
-                                     6..6 => self.apu.channel_three.frequency.set_use_counter,
-                                     2..0 => self.apu.channel_three.frequency.set_frequency_high
-                ),
                 addr @ 0xFF30..=0xFF3F => self
                     .apu
                     .channel_three
-                    .set_table(usize::from(0xFF30 - addr), val),
-                0xFF20 => write_reg!(val:
-                                     5..0 => self.apu.channel_four.set_length
-                ),
-                0xFF21 => write_reg!(val:
-                                     7..4 => self.apu.channel_four.envelope.set_initial_volume,
-                                     3..3 => self.apu.channel_four.envelope.set_direction,
-                                     2..0 => self.apu.channel_four.envelope.set_sweep
-                ),
-                0xFF22 => write_reg!(val:
-                                     7..4 => self.apu.channel_four.counter.set_frequency,
-                                     3..3 => self.apu.channel_four.counter.set_width,
-                                     2..0 => self.apu.channel_four.counter.set_ratio
-                ),
-                0xFF23 => write_reg!(val:
-                                     7..7 => self.apu.channel_four.set_start,
-                                     6..6 => self.apu.channel_four.set_stop_on_length
-                ),
-                0xFF24 => write_reg!(val:
-                                     6..4 => self.apu.control.volume.set_left,
-                                     2..0 => self.apu.control.volume.set_right
-                ),
-                0xFF25 => self.apu.control.channel_enable.set_enable(val),
-                0xFF26 => write_reg!(val:
-                                     7..7 => self.apu.control.set_enable
-                ),
-                0xFF03 | 0xFF08..=0xFF0E | 0xFF4C..=0xFF4F | 0xFF50..=0xFF7F => {
-                    info!("Write to unmapped I/O reg!")
-                }
-                0xFFFF => write_reg!(val:
-                                     7..5 => self.interrupt.set_unused,
-                                     4..4 => self.interrupt.set_joypad_enable,
-                                     3..3 => self.interrupt.set_serial_enable,
-                                     2..2 => self.interrupt.set_timer_enable,
-                                     1..1 => self.interrupt.set_lcd_stat_enable,
-                                     0..0 => self.interrupt.set_vblank_enable
-                ),
-                _ => {}
+                    .set_table(usize::from(addr - 0xFF30), val),
+                addr @ 0xFF00..=0xFF7F | addr @ 0xFFFF => match io_map::find(addr) {
+                    Some(register) => register.write(self, val),
+                    None => info!("Write to unmapped I/O reg!"),
+                },
             }
         }
+        if address == 0xFF00 {
+            self.schedule_joypad();
+        }
     }
 
     pub fn read(&self, address: u16) -> u8 {
+        let val = self.read_unwatched(address);
+        self.watch.read(address, val);
+        val
+    }
+
+    fn read_unwatched(&self, address: u16) -> u8 {
         if self.dma.enabled {
             match address {
                 addr @ 0xFF80..=0xFFFE => self.mem.read(addr),
-                _ => 0xFF,
+                addr => self.open_bus(addr),
             }
         } else {
             match address {
-                addr @ 0x0000..=0x7FFF | addr @ 0xFF50 => self.cartridge.read(addr),
+                addr @ 0x0000..=0x7FFF | addr @ 0xA000..=0xBFFF | addr @ 0xFF50 => {
+                    self.cartridge.read(addr)
+                }
                 addr @ 0x8000..=0x9FFF | addr @ 0xFE00..=0xFE9F => self.ppu.read(addr),
-                0xFF40 => self.ppu.control.bits(),
-                0xFF41 => read_reg!(
-                    6..6 => self.ppu.status.lyc_interrupt,
-                    5..5 => self.ppu.status.mode2_interrupt,
-                    4..4 => self.ppu.status.mode1_interrupt,
-                    3..3 => self.ppu.status.mode0_interrupt,
-                    2..2 => self.ppu.lcd_y_compare,
-                    1..0 => self.ppu.status.mode
-                ),
-                0xFF42 => self.ppu.scroll_y(),
-                0xFF43 => self.ppu.scroll_x(),
-                0xFF44 => self.ppu.lcd_y(),
-                0xFF45 => self.ppu.lcd_y_compare(),
-                0xFF46 => 0xFF, // TODO(slongfield): What does DMA read do?
-                0xFF47 => read_reg!(
-                    7..6 => self.ppu.bg_palette.color3,
-                    5..4 => self.ppu.bg_palette.color2,
-                    3..2 => self.ppu.bg_palette.color1,
-                    1..0 => self.ppu.bg_palette.color0
-                ),
-                0xFF48 => read_reg!(
-                    7..6 => self.ppu.obj0_palette.color3,
-                    5..4 => self.ppu.obj0_palette.color2,
-                    3..2 => self.ppu.obj0_palette.color1,
-                    1..0 => self.ppu.obj0_palette.color0
-                ),
-                0xFF49 => read_reg!(
-                    7..6 => self.ppu.obj1_palette.color3,
-                    5..4 => self.ppu.obj1_palette.color2,
-                    3..2 => self.ppu.obj1_palette.color1,
-                    1..0 => self.ppu.obj1_palette.color0
-                ),
-                0xFF4A => self.ppu.window_y(),
-                0xFF4B => self.ppu.window_x(),
-                addr @ 0xA000..=0xBFFF
-                | addr @ 0xC000..=0xCFFF
-                | addr @ 0xD000..=0xDFFF
-                | addr @ 0xFF80..=0xFFFE => self.mem.read(addr),
-                // Echo RAM, maps back onto 0xC000-0XDDFF
-                addr @ 0xE000..=0xFDFF => self.read(addr - 0x2000),
+                // Work RAM, and its echo at 0xE000-0xFDFF.
+                addr @ 0xC000..=0xFDFF | addr @ 0xFF80..=0xFFFE => self.mem.read(addr),
                 addr @ 0xFEA0..=0xFEFF => {
                     info!("Read from unmapped memory region: {:#04X}", addr);
-                    0
-                }
-                0xFF00 => read_reg!(
-                    5..5 => self.joypad.select_direction,
-                    4..4 => self.joypad.select_button,
-                    3..0 => self.joypad.state
-                ),
-                0xFF01 => self.serial.data(),
-                0xFF02 => read_reg!(7..7 => self.serial.start),
-                0xFF04 => self.timer.divider(),
-                0xFF05 => self.timer.counter(),
-                0xFF06 => self.timer.modulo(),
-                0xFF07 => read_reg!(
-                    2..2 => self.timer.start,
-                    1..0 => self.timer.input_clock
-                ),
-                0xFF0F => read_reg!(
-                    4..4 => self.interrupt.joypad_trigger,
-                    3..3 => self.interrupt.serial_trigger,
-                    2..2 => self.interrupt.timer_trigger,
-                    1..1 => self.interrupt.lcd_stat_trigger,
-                    0..0 => self.interrupt.vblank_trigger
-                ),
-                0xFF10 => read_reg!(
-                    6..4 => self.apu.channel_one.sweep.time,
-                    3..3 => self.apu.channel_one.sweep.direction,
-                    2..0 => self.apu.channel_one.sweep.shift
-                ),
-                0xFF11 => read_reg!(
-                    7..6 => self.apu.channel_one.length_pattern.duty,
-                    5..0 => self.apu.channel_one.length_pattern.length
-                ),
-                0xFF12 => read_reg!(
-                    7..4 => self.apu.channel_one.envelope.initial_volume,
-                    3..3 => self.apu.channel_one.envelope.direction,
-                    2..0 => self.apu.channel_one.envelope.sweep
-                ),
-                0xFF13 => self.apu.channel_one.frequency.frequency_low(),
-                0xFF14 => read_reg!(
-                    7..7 => self.apu.channel_one.frequency.start,
-                    6..6 => self.apu.channel_one.frequency.use_counter,
-                    2..0 => self.apu.channel_one.frequency.frequency_high
-                ),
-                0xFF16 => read_reg!(
-                    7..6 => self.apu.channel_two.length_pattern.duty,
-                    5..0 => self.apu.channel_two.length_pattern.length
-                ),
-                0xFF17 => read_reg!(
-                    7..4 => self.apu.channel_two.envelope.initial_volume,
-                    3..3 => self.apu.channel_two.envelope.direction,
-                    2..0 => self.apu.channel_two.envelope.sweep
-                ),
-                0xFF18 => self.apu.channel_two.frequency.frequency_low(),
-                0xFF19 => read_reg!(
-                    7..7 => self.apu.channel_two.frequency.start,
-                    6..6 => self.apu.channel_two.frequency.use_counter,
-                    2..0 => self.apu.channel_two.frequency.frequency_high
-                ),
-                0xFF1A => read_reg!(
-                    7..7 => self.apu.channel_three.enable
-                ),
-                0xFF1B => self.apu.channel_three.length(),
-                0xFF1C => read_reg!(
-                    6..5 => self.apu.channel_three.level
-                ),
-                0xFF1D => self.apu.channel_three.frequency.frequency_low(),
-                0xFF1E => read_reg!(
-                    7..7 => self.apu.channel_three.frequency.start,
-                    6..6 => self.apu.channel_three.frequency.use_counter,
-                    2..0 => self.apu.channel_three.frequency.frequency_high
-                ),
-                addr @ 0xFF30..=0xFF3F => self.apu.channel_three.table(usize::from(0xFF30 - addr)),
-                0xFF20 => read_reg!(
-                    5..0 => self.apu.channel_four.length
-                ),
-                0xFF21 => read_reg!(
-                    7..4 => self.apu.channel_four.envelope.initial_volume,
-                    3..3 => self.apu.channel_four.envelope.direction,
-                    2..0 => self.apu.channel_four.envelope.sweep
-                ),
-                0xFF22 => read_reg!(
-                    7..4 => self.apu.channel_four.counter.frequency,
-                    3..3 => self.apu.channel_four.counter.width,
-                    2..0 => self.apu.channel_four.counter.ratio
-                ),
-                0xFF23 => read_reg!(
-                    6..6 => self.apu.channel_four.stop_on_length
-                ),
-                0xFF24 => read_reg!(
-                    6..4 => self.apu.control.volume.left,
-                    2..0 => self.apu.control.volume.right
-                ),
-                0xFF25 => self.apu.control.channel_enable.enable(),
-                0xFF26 => read_reg!(
-                    7..7 => self.apu.control.enable,
-                    3..3 => self.apu.channel_four.active,
-                    2..2 => self.apu.channel_three.active,
-                    1..1 => self.apu.channel_two.active,
-                    0..0 => self.apu.channel_one.active
-                ),
-                0xFF03
-                | 0xFF08..=0xFF0E
-                | 0xFF15
-                | 0xFF1F
-                | 0xFF27..=0xFF2F
-                | 0xFF4C..=0xFF4F
-                | 0xFF50..=0xFF7F => {
-                    info!("Read from unmapped I/O reg!");
-                    0xFF
+                    self.open_bus(addr)
                 }
-                0xFFFF => read_reg!(
-                    7..5 => self.interrupt.unused,
-                    4..4 => self.interrupt.joypad_enable,
-                    3..3 => self.interrupt.serial_enable,
-                    2..2 => self.interrupt.timer_enable,
-                    1..1 => self.interrupt.lcd_stat_enable,
-                    0..0 => self.interrupt.vblank_enable
-                ),
+                addr @ 0xFF30..=0xFF3F => self.apu.channel_three.table(usize::from(addr - 0xFF30)),
+                addr @ 0xFF00..=0xFF7F | addr @ 0xFFFF => match io_map::find(addr) {
+                    Some(register) => register.read(self),
+                    None => {
+                        info!("Read from unmapped I/O reg!");
+                        self.open_bus(addr)
+                    }
+                },
+            }
+        }
+    }
+
+    /// Reads without tripping watchpoints, and with VRAM and OAM readable even while the PPU has
+    /// them locked.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.ppu.peek(address),
+            _ => self.read_unwatched(address),
+        }
+    }
+
+    /// Writes without tripping watchpoints, and with VRAM and OAM writable even while the PPU has
+    /// them locked. DIV and DMA are left alone, since writing them resets the timer or starts a
+    /// transfer rather than setting them.
+    pub fn poke(&mut self, address: u16, val: u8) {
+        match address {
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => {
+                self.code_written(address);
+                self.ppu.poke(address, val);
+            }
+            0xFF04 | 0xFF46 => {}
+            _ => self.write_unwatched(address, val),
+        }
+    }
+
+    /// What reading `address` returns when nothing drives the data bus.
+    fn open_bus(&self, address: u16) -> u8 {
+        if self.dma.enabled {
+            // DMA holds the bus, so the CPU reads whatever it's copying.
+            return self.dma.bus;
+        }
+        match (address, self.model) {
+            // The CGB repeats the high nibble of the address's low byte here. The DMG reads 0,
+            // or 0xFF while the PPU has OAM locked, which isn't emulated.
+            (0xFEA0..=0xFEFF, Model::Cgb) => {
+                let nibble = (address as u8) & 0xF0;
+                nibble | (nibble >> 4)
             }
+            (0xFEA0..=0xFEFF, _) => 0x00,
+            // The data bus has pull-ups, so anything else nothing answers reads as 0xFF.
+            _ => 0xFF,
         }
     }
 
@@ -487,6 +726,329 @@ impl Peripherals {
         self.interrupt.disable_interrupt()
     }
 
+    /// Machine cycles run since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Where emulation is up to: the frame, the PPU's position within it, and the machine cycles
+    /// run in total.
+    pub fn describe_status(&self) -> String {
+        format!(
+            "Frame: {} LY: {} Dot: {} Mode: {} Cycles: {}",
+            self.ppu.frame,
+            self.ppu.lcd_y(),
+            self.ppu.dot(),
+            self.ppu.status.mode(),
+            self.cycles
+        )
+    }
+
+    /// Decodes one I/O register, found by name or address, or all of them.
+    pub fn describe_io(&self, name: Option<&str>) -> String {
+        match name {
+            Some(name) => match io_registers::find(name) {
+                Some(reg) => reg.describe(self.read(reg.address)),
+                None => format!("Unknown I/O register {}", name),
+            },
+            None => io_registers::IO_REGISTERS
+                .iter()
+                .map(|reg| reg.describe(self.read(reg.address)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    // Marks code decoded from the page containing `address` as stale. Writes to ROM only switch
+    // banks, which cached decodes are keyed by instead.
+    fn code_written(&mut self, address: u16) {
+        let pages = match address {
+            0x0000..=0x7FFF => return,
+            // Unmapping the boot ROM changes what's at the bottom of memory.
+            0xFF50 => 0x00..=0x01,
+            // Switching work RAM banks changes what's at 0xD000-0xDFFF, and its echo.
+            0xFF70 => {
+                for page in (0xD0..=0xDF).chain(0xF0..=0xFD) {
+                    self.code_versions[page] = self.code_versions[page].wrapping_add(1);
+                }
+                return;
+            }
+            // Work RAM can also be run from its echo.
+            0xC000..=0xDDFF | 0xE000..=0xFDFF => {
+                let page = (address >> 8) as usize;
+                let echo = page ^ 0x20;
+                self.code_versions[echo] = self.code_versions[echo].wrapping_add(1);
+                page..=page
+            }
+            _ => {
+                let page = (address >> 8) as usize;
+                page..=page
+            }
+        };
+        for page in pages {
+            self.code_versions[page] = self.code_versions[page].wrapping_add(1);
+        }
+    }
+
+    /// Counts writes to the page containing `address`, or None if what's there can change without
+    /// being written to, like I/O registers, so decodes from there can't be cached.
+    pub fn code_version(&self, address: u16) -> Option<u32> {
+        match address {
+            0xA000..=0xBFFF | 0xFE00..=0xFF7F | 0xFFFF => None,
+            _ => Some(self.code_versions[(address >> 8) as usize]),
+        }
+    }
+
+    /// The ROM bank mapped at `address`, or 0 outside the switchable bank.
+    pub fn rom_bank_at(&self, address: u16) -> u16 {
+        match address {
+            0x4000..=0x7FFF => self.cartridge.rom_bank(),
+            _ => 0,
+        }
+    }
+
+    /// The cartridge's external RAM, across all of its banks.
+    pub fn cartridge_ram(&self) -> &[u8] {
+        self.cartridge.ram()
+    }
+
+    /// For loading saved cartridge RAM.
+    pub fn cartridge_ram_mut(&mut self) -> &mut [u8] {
+        self.cartridge.ram_mut()
+    }
+
+    /// Whether the cartridge RAM has been written since `mark_cartridge_ram_saved`.
+    pub fn cartridge_ram_dirty(&self) -> bool {
+        self.cartridge.ram_dirty()
+    }
+
+    pub fn mark_cartridge_ram_saved(&mut self) {
+        self.cartridge.set_ram_dirty(false);
+    }
+
+    /// Unmaps the boot ROM, and leaves the I/O registers how the model's boot ROM would have.
+    pub fn skip_boot(&mut self, model: Model) {
+        self.model = model;
+        self.mem.set_cgb(model == Model::Cgb);
+        // The boot ROM's writes aren't the program's, so the debugger doesn't see them.
+        self.write_unwatched(0xFF50, 1);
+        for (addr, val) in model.post_boot_io() {
+            self.write_unwatched(addr, val);
+        }
+        for (addr, &val) in (0xFF30..).zip(model.wave_ram().iter()) {
+            self.write_unwatched(addr, val);
+        }
+        self.apu.skip_boot();
+        self.timer.set_internal_divider(model.divider());
+    }
+
+    /// Where a file for this ROM with the given extension goes, like its .sav file. It's named
+    /// after both the ROM's file and its contents, since the save directory is shared, and
+    /// different ROMs can have files with the same name.
+    pub fn save_path(&self, extension: &str) -> PathBuf {
+        self.save_dir.join(format!(
+            "{}-{:016x}.{}",
+            self.rom_name, self.rom_hash, extension
+        ))
+    }
+
+    /// Where the game's settings are kept. Different dumps of a game share them, as long as the
+    /// header is the same.
+    pub fn settings_path(&self) -> PathBuf {
+        self.save_dir.join(format!("{}.cfg", self.game_id))
+    }
+
+    /// Where the next screenshot or recording goes.
+    pub fn capture_path(&self, extension: &str) -> PathBuf {
+        self.save_dir.join(self.ppu.capture_name(extension))
+    }
+
+    fn load_battery(&mut self) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        let path = self.save_path("sav");
+        let saved = match fs::read(&path) {
+            Ok(saved) => saved,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let ram = self.cartridge.ram_mut();
+        let len = ram.len();
+        if saved.len() < len {
+            warn!(
+                "{} is {} bytes, but the cartridge has {}",
+                path.display(),
+                saved.len(),
+                len
+            );
+        }
+        let copied = saved.len().min(len);
+        ram[..copied].copy_from_slice(&saved[..copied]);
+        // Clocks are saved after the RAM. Older saves, or ones from emulators without the clock,
+        // just leave it where it was.
+        if let Some(trailer) = saved.get(len..len + cartridge::RTC_TRAILER_BYTES) {
+            let mut rtc = [0; cartridge::RTC_TRAILER_BYTES];
+            rtc.copy_from_slice(trailer);
+            self.cartridge.load_rtc_trailer(&rtc, unix_time());
+            // The loaded clock replaces whatever it was behind by.
+            self.cartridge_stepped = self.cycles;
+            self.step_cartridge();
+        }
+        info!("Loaded cartridge RAM from {}", path.display());
+        Ok(())
+    }
+
+    /// Writes battery-backed cartridge RAM to the ROM's .sav file, if it's changed since it was
+    /// last written, and waits for it and any saves made during play to finish.
+    pub fn flush_battery(&mut self) -> io::Result<()> {
+        match self.battery_to_save() {
+            Some(saved) => self.battery_writer.save_now(self.save_path("sav"), saved),
+            None => Ok(()),
+        }
+    }
+
+    // The contents of the .sav file, if the RAM's changed since it was last saved. The RAM then
+    // counts as saved.
+    fn battery_to_save(&mut self) -> Option<Vec<u8>> {
+        if !self.battery || !self.cartridge.ram_dirty() {
+            return None;
+        }
+        let mut saved = self.cartridge.ram().to_vec();
+        if let Some(rtc) = self.cartridge.rtc_trailer(unix_time()) {
+            saved.extend_from_slice(&rtc);
+        }
+        self.cartridge.set_ram_dirty(false);
+        Some(saved)
+    }
+
+    /// Keeps the emulation running as normal, but stops it sending anything to the outside:
+    /// serial output, recordings, frame dumps, and battery saves.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.serial.set_muted(muted);
+        self.ppu.set_muted(muted);
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Watches for the program accessing an address.
+    pub fn watch(&mut self, access: Access, address: u16) {
+        self.watch.watch(access, address);
+    }
+
+    pub fn unwatch(&mut self, address: u16) {
+        self.watch.unwatch(address);
+    }
+
+    pub fn describe_watches(&self) -> String {
+        self.watch.describe()
+    }
+
+    /// The last access to a watched address since this was last called.
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch.take_hit()
+    }
+
+    /// Calls `hook` with the address and value of every read the program makes in `range`.
+    /// Instruction fetches, and reads made by the debugger or while saving state, aren't seen.
+    pub fn add_read_hook<F>(&mut self, range: RangeInclusive<u16>, hook: F) -> HookId
+    where
+        F: FnMut(u16, u8) + Send + 'static,
+    {
+        self.watch.add_hook(Access::Read, range, Box::new(hook))
+    }
+
+    /// Calls `hook` with the address and value of every write the program makes in `range`,
+    /// before the write happens.
+    pub fn add_write_hook<F>(&mut self, range: RangeInclusive<u16>, hook: F) -> HookId
+    where
+        F: FnMut(u16, u8) + Send + 'static,
+    {
+        self.watch.add_hook(Access::Write, range, Box::new(hook))
+    }
+
+    /// Returns whether there was a hook to remove.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.watch.remove_hook(id)
+    }
+
+    /// Adds a GameShark code, which from then on is applied at the start of every VBlank, or a
+    /// Game Genie code, with dashes, which patches ROM reads.
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), io::Error> {
+        if code.contains('-') {
+            let code = self.cartridge.add(code)?;
+            self.rom_patched(code.address);
+            Ok(())
+        } else {
+            self.cheats.add(code).map(|_| ())
+        }
+    }
+
+    /// Returns whether the code was there to remove.
+    pub fn remove_cheat(&mut self, code: &str) -> bool {
+        if code.contains('-') {
+            match self.cartridge.remove(code) {
+                Some(code) => {
+                    self.rom_patched(code.address);
+                    true
+                }
+                None => false,
+            }
+        } else {
+            self.cheats.remove(code)
+        }
+    }
+
+    // Marks code decoded from a page of ROM as stale, as a Game Genie code patching it has come or
+    // gone, which writes to ROM can't do.
+    fn rom_patched(&mut self, address: u16) {
+        let page = (address >> 8) as usize;
+        self.code_versions[page] = self.code_versions[page].wrapping_add(1);
+    }
+
+    /// Every cheat code that's on, in the form they were added in.
+    pub fn cheat_codes(&self) -> Vec<String> {
+        self.cheats
+            .game_shark()
+            .iter()
+            .map(|code| code.to_string())
+            .chain(self.cartridge.codes().iter().map(|code| code.to_string()))
+            .collect()
+    }
+
+    pub fn describe_cheats(&self) -> String {
+        let mut lines = self.cheats.describe();
+        for code in self.cartridge.codes() {
+            let compare = match code.compare {
+                Some(compare) => format!(" if it was 0x{:02X}", compare),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "{}: reads 0x{:02X} from 0x{:04X}{}",
+                code, code.value, code.address, compare
+            ));
+        }
+        if lines.is_empty() {
+            return "No cheats".to_string();
+        }
+        lines.join("\n")
+    }
+
+    /// Connects the serial ports of two emulators, as if by a link cable.
+    pub fn connect_link(&mut self, other: &mut Peripherals) {
+        let (link, other_link) = serial::Link::pair();
+        self.serial.connect_link(link);
+        other.serial.connect_link(other_link);
+        self.scheduler
+            .schedule(scheduler::Event::Serial, self.cycles + 1);
+        other
+            .scheduler
+            .schedule(scheduler::Event::Serial, other.cycles + 1);
+    }
+
     pub fn connect_serial_channel(&mut self, tx: mpsc::Sender<u8>) {
         self.serial.connect_channel(tx);
     }
@@ -495,7 +1057,480 @@ impl Peripherals {
         println!("{}", self.cartridge);
     }
 
-    pub fn go_fast(&mut self) {
-        self.ppu.go_fast();
+    pub fn print_apu(&self) {
+        print!("{}", self.apu.describe_channels());
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.apply_speed();
+    }
+
+    pub fn record_input(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.joypad.record_movie(path, self.rom_hash)
+    }
+
+    pub fn play_input(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.joypad.play_movie(path, self.rom_hash)
+    }
+
+    /// Whether any joypad line is low, in the currently selected matrix.
+    pub fn joypad_pressed(&self) -> bool {
+        self.joypad.state() & 0xF != 0xF
+    }
+
+    pub fn set_turbo_period(&mut self, frames: u32) {
+        self.joypad.set_turbo_period(frames);
+    }
+
+    pub fn set_fast_forward_speed(&mut self, speed: Speed) {
+        self.fast_forward_speed = speed;
+        self.apply_speed();
+    }
+
+    fn apply_speed(&mut self) {
+        let speed = if self.fast_forwarding {
+            self.fast_forward_speed
+        } else {
+            self.speed
+        };
+        self.ppu.set_speed(speed);
+        self.apu.set_speed(speed);
+    }
+}
+
+// Audio is generated against wall time, so the APU's internal state isn't worth saving. Its
+// registers are saved and written back instead, with the trigger bits cleared so that loading
+// doesn't restart every channel.
+const APU_REGISTERS: &[u16] = &[
+    0xFF26, 0xFF10, 0xFF11, 0xFF12, 0xFF13, 0xFF14, 0xFF16, 0xFF17, 0xFF18, 0xFF19, 0xFF1A, 0xFF1B,
+    0xFF1C, 0xFF1D, 0xFF1E, 0xFF20, 0xFF21, 0xFF22, 0xFF23, 0xFF24, 0xFF25, 0xFF30, 0xFF31, 0xFF32,
+    0xFF33, 0xFF34, 0xFF35, 0xFF36, 0xFF37, 0xFF38, 0xFF39, 0xFF3A, 0xFF3B, 0xFF3C, 0xFF3D, 0xFF3E,
+    0xFF3F,
+];
+
+impl Bus for Peripherals {
+    fn read(&self, address: u16) -> u8 {
+        Peripherals::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        Peripherals::write(self, address, val)
+    }
+
+    fn fetch(&self, address: u16) -> u8 {
+        self.read_unwatched(address)
+    }
+
+    fn get_interrupt(&self) -> Option<u16> {
+        Peripherals::get_interrupt(self)
+    }
+
+    fn disable_interrupt(&mut self) {
+        Peripherals::disable_interrupt(self)
+    }
+
+    fn joypad_pressed(&self) -> bool {
+        Peripherals::joypad_pressed(self)
+    }
+
+    fn code_version(&self, address: u16) -> Option<u32> {
+        Peripherals::code_version(self, address)
+    }
+
+    fn rom_bank_at(&self, address: u16) -> u16 {
+        Peripherals::rom_bank_at(self, address)
+    }
+}
+
+impl Snapshot for Peripherals {
+    fn save(&self, w: &mut Writer) {
+        self.mem.save(w);
+        self.cartridge.save(w);
+        self.ppu.save(w);
+        self.timer.save(w);
+        self.interrupt.save(w);
+        self.serial.save(w);
+        self.joypad.save(w);
+        // Write-only bits read back as 1, so they're taken from the APU directly.
+        let apu: Vec<u8> = APU_REGISTERS
+            .iter()
+            .map(|&addr| match addr {
+                0xFF10..=0xFF26 => self.apu.register(addr),
+                _ => self.read_unwatched(addr),
+            })
+            .collect();
+        w.bytes(&apu);
+        self.apu.save(w);
+        w.bool(self.dma.enabled);
+        w.u16(self.dma.source);
+        w.u16(self.dma.dest);
+        w.u8(self.dma.bus);
+        w.u64(self.cycles);
+        // How far behind the serial port and cartridge clock are, so they can be caught up the
+        // same after loading.
+        w.u64(self.cycles - self.serial_stepped);
+        w.u64(self.cycles - self.cartridge_stepped);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.mem.load(r)?;
+        self.cartridge.load(r)?;
+        self.ppu.load(r)?;
+        self.timer.load(r)?;
+        self.interrupt.load(r)?;
+        self.serial.load(r)?;
+        self.joypad.load(r)?;
+        let mut apu = vec![0; APU_REGISTERS.len()];
+        r.bytes_into(&mut apu)?;
+        // Registers are written with DMA off, and DMA is restored after.
+        self.dma.enabled = false;
+        for (&addr, &val) in APU_REGISTERS.iter().zip(apu.iter()) {
+            let val = match addr {
+                0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => val & 0x7F,
+                _ => val,
+            };
+            self.write_unwatched(addr, val);
+        }
+        self.apu.load(r)?;
+        self.dma.enabled = r.bool()?;
+        self.dma.source = r.u16()?;
+        self.dma.dest = r.u16()?;
+        self.dma.bus = r.u8()?;
+        self.cycles = r.u64()?;
+        self.serial_stepped = self.cycles.saturating_sub(r.u64()?);
+        self.cartridge_stepped = self.cycles.saturating_sub(r.u64()?);
+        // Events are scheduled by cycle, so they have to be worked out again from the new state.
+        self.scheduler = scheduler::Scheduler::new(self.cycles + 1);
+        // Everything may have changed, so nothing decoded before is valid.
+        for version in &mut self.code_versions {
+            *version = version.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::thread;
+
+    #[test]
+    fn runs_on_another_thread() {
+        let (mut mem, inputs) = Peripherals::new_fake_with_inputs();
+        inputs
+            .send(FakeInput {
+                cycle: 0,
+                button: Button::Start,
+                pressed: true,
+            })
+            .unwrap();
+        let mem = thread::spawn(move || {
+            // Select the buttons, rather than the directions.
+            mem.write(0xFF00, 0x10);
+            mem.step_idle();
+            mem
+        })
+        .join()
+        .unwrap();
+        assert_eq!(mem.read(0xFF00) & 0xF, 0x7);
+    }
+
+    #[test]
+    fn video_falls_back_to_dummy() {
+        let mut drivers = vec![];
+        let started = with_dummy_video(|| {
+            drivers.push(env::var("SDL_VIDEODRIVER").ok());
+            if drivers.len() == 1 {
+                Err("no display".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        env::remove_var("SDL_VIDEODRIVER");
+        assert_eq!(started, Ok(()));
+        assert_eq!(drivers.last(), Some(&Some("dummy".to_string())));
+        assert_eq!(drivers.len(), 2);
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut mem = Peripherals::new_fake();
+        mem.skip_boot(Model::Dmg);
+        assert_eq!(mem.read(0xFEA0), 0x00);
+        assert_eq!(mem.read(0xFF4C), 0xFF);
+        mem.skip_boot(Model::Cgb);
+        assert_eq!(mem.read(0xFED3), 0xDD);
+
+        mem.write(0xFF80, 0x17);
+        mem.dma.enabled = true;
+        mem.dma.bus = 0x42;
+        assert_eq!(mem.read(0xC000), 0x42);
+        assert_eq!(mem.read(0xFEA0), 0x42);
+        assert_eq!(mem.read(0xFF80), 0x17);
+    }
+
+    #[test]
+    fn poking_vram_invalidates_code() {
+        let mut mem = Peripherals::new_fake();
+        let version = mem.code_version(0x9800);
+        mem.poke(0x9800, 0x00);
+        assert_ne!(mem.code_version(0x9800), version);
+    }
+
+    #[test]
+    fn echo_ram() {
+        let mut mem = Peripherals::new_fake();
+        mem.write(0xFDFF, 0x42);
+        assert_eq!(mem.read(0xDDFF), 0x42);
+        assert_eq!(mem.read(0xFE00), 0x00);
+        let version = mem.code_version(0xDD00);
+        mem.write(0xFD00, 0x00);
+        assert_ne!(mem.code_version(0xDD00), version);
+
+        // Only HRAM can be reached during DMA, echo or not.
+        mem.dma.enabled = true;
+        mem.write(0xE000, 0x17);
+        mem.dma.enabled = false;
+        assert_eq!(mem.read(0xC000), 0x00);
+    }
+
+    #[test]
+    fn cheats_apply_at_vblank() {
+        let mut mem = Peripherals::new_fake();
+        mem.skip_boot(Model::Dmg);
+        mem.add_cheat("0163E1D9").unwrap();
+        while !mem.ppu.in_vblank() {
+            assert_eq!(mem.read(0xD9E1), 0x00);
+            mem.step();
+        }
+        assert_eq!(mem.read(0xD9E1), 0x63);
+
+        // Writes made during the frame last until the next VBlank.
+        mem.write(0xD9E1, 0x00);
+        while mem.ppu.in_vblank() {
+            mem.step();
+        }
+        assert_eq!(mem.read(0xD9E1), 0x00);
+        while !mem.ppu.in_vblank() {
+            mem.step();
+        }
+        assert_eq!(mem.read(0xD9E1), 0x63);
+    }
+
+    #[test]
+    fn battery_saves() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut mem = Peripherals::new_headless(rom.clone());
+        mem.battery = true;
+        mem.save_dir = env::temp_dir();
+        mem.rom_name = format!("wolfwig_battery_saves_{}", process::id());
+        mem.write(0x0000, 0x0A);
+        mem.write(0xA010, 0x42);
+        mem.flush_battery().unwrap();
+        assert!(!mem.cartridge_ram_dirty());
+
+        let mut loaded = Peripherals::new_headless(rom);
+        loaded.battery = true;
+        loaded.save_dir = mem.save_dir.clone();
+        loaded.rom_name = mem.rom_name.clone();
+        loaded.load_battery().unwrap();
+        assert_eq!(loaded.cartridge_ram()[0x10], 0x42);
+        fs::remove_file(mem.save_path("sav")).unwrap();
+    }
+
+    #[test]
+    fn save_paths_tell_roms_apart() {
+        let mut mem = Peripherals::new_headless(vec![0; 0x8000]);
+        mem.rom_name = "game".to_string();
+        let mut rom = vec![0; 0x8000];
+        rom[0x150] = 1;
+        let mut other = Peripherals::new_headless(rom);
+        other.rom_name = "game".to_string();
+        assert_ne!(mem.save_path("sav"), other.save_path("sav"));
+    }
+
+    #[test]
+    fn battery_saves_clock() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        let mut mem = Peripherals::new_headless(rom.clone());
+        mem.battery = true;
+        mem.save_dir = env::temp_dir();
+        mem.rom_name = format!("wolfwig_battery_saves_clock_{}", process::id());
+        mem.write(0x0000, 0x0A);
+        mem.write(0x4000, 0x0A);
+        mem.write(0xA000, 5);
+        mem.flush_battery().unwrap();
+        let path = mem.save_path("sav");
+        assert_eq!(
+            fs::read(&path).unwrap().len(),
+            0x2000 + cartridge::RTC_TRAILER_BYTES
+        );
+
+        let mut loaded = Peripherals::new_headless(rom);
+        loaded.battery = true;
+        loaded.save_dir = mem.save_dir.clone();
+        loaded.rom_name = mem.rom_name.clone();
+        loaded.load_battery().unwrap();
+        loaded.write(0x0000, 0x0A);
+        loaded.write(0x4000, 0x0A);
+        loaded.write(0x6000, 0x00);
+        loaded.write(0x6000, 0x01);
+        assert_eq!(loaded.read(0xA000), 5);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn paces_against_clock() {
+        let mut mem = Peripherals::new_fake();
+        let clock = MockClock::default();
+        mem.set_clock(Box::new(clock.clone()));
+        mem.write(0xFF40, 0x80);
+        let frame = mem.ppu.frame;
+        while mem.ppu.frame == frame {
+            mem.step();
+        }
+        // No time passed while the frame was emulated, so all of it was slept.
+        assert_eq!(clock.now(), Duration::from_micros(16_666));
+    }
+
+    // Steps until the PPU finishes the frame it's on.
+    fn run_frame(mem: &mut Peripherals) {
+        let frame = mem.ppu.frame;
+        while mem.ppu.frame == frame {
+            mem.step();
+        }
+    }
+
+    // Steps as the emulator's loop does, idling while paused.
+    fn run_or_idle(mem: &mut Peripherals, steps: u32) {
+        for _ in 0..steps {
+            if mem.paused() {
+                mem.step_idle();
+            } else {
+                mem.step();
+            }
+        }
+    }
+
+    #[test]
+    fn pause_stops_frames() {
+        let mut mem = Peripherals::new_fake();
+        mem.set_clock(Box::new(MockClock::default()));
+        mem.write(0xFF40, 0x80);
+        run_frame(&mut mem);
+        mem.pause();
+        let (frame, cycles) = (mem.ppu.frame, mem.cycles);
+        run_or_idle(&mut mem, Peripherals::CYCLES_PER_FRAME);
+        assert!(mem.paused());
+        assert_eq!((mem.ppu.frame, mem.cycles), (frame, cycles));
+
+        mem.resume();
+        run_or_idle(&mut mem, Peripherals::CYCLES_PER_FRAME);
+        assert_eq!(mem.ppu.frame, frame + 1);
+    }
+
+    #[test]
+    fn frame_advance_runs_one_frame() {
+        let mut mem = Peripherals::new_fake();
+        mem.set_clock(Box::new(MockClock::default()));
+        mem.write(0xFF40, 0x80);
+        run_frame(&mut mem);
+        // Advancing does nothing unless paused.
+        mem.advance_frame();
+        assert!(mem.pause_at_frame.is_none());
+
+        mem.pause();
+        let (frame, cycles) = (mem.ppu.frame, mem.cycles);
+        mem.advance_frame();
+        assert!(!mem.paused());
+        run_or_idle(&mut mem, 3 * Peripherals::CYCLES_PER_FRAME);
+        assert!(mem.paused());
+        assert_eq!(mem.ppu.frame, frame + 1);
+        assert_eq!(
+            mem.cycles,
+            cycles + u64::from(Peripherals::CYCLES_PER_FRAME)
+        );
+    }
+
+    #[test]
+    fn fast_forward_shortens_frames() {
+        let mut mem = Peripherals::new_fake();
+        let clock = MockClock::default();
+        mem.set_clock(Box::new(clock.clone()));
+        mem.write(0xFF40, 0x80);
+        run_frame(&mut mem);
+        let start = clock.now();
+        mem.fast_forwarding = true;
+        mem.apply_speed();
+        run_frame(&mut mem);
+        // The default fast-forward speed is 4x.
+        assert_eq!(clock.now() - start, Duration::from_micros(16_666 / 4));
+
+        let start = clock.now();
+        mem.fast_forwarding = false;
+        mem.apply_speed();
+        run_frame(&mut mem);
+        assert_eq!(clock.now() - start, Duration::from_micros(16_666));
+    }
+
+    #[test]
+    fn serial_wakes_on_write() {
+        let mut mem = Peripherals::new_fake();
+        let (tx, rx) = mpsc::channel();
+        mem.connect_serial_channel(tx);
+        for _ in 0..1000 {
+            mem.step();
+        }
+        mem.write(0xFF01, 0x42);
+        mem.write(0xFF02, 0x81);
+        // Eight bits, at 128 cycles each.
+        for _ in 0..8 * 128 - 1 {
+            mem.step();
+        }
+        assert_eq!(mem.read(0xFF02) & 0x80, 0x80);
+        mem.step();
+        assert_eq!(mem.read(0xFF02) & 0x80, 0);
+        assert_eq!(mem.read(0xFF0F) & 0x08, 0x08);
+        assert_eq!(rx.try_recv(), Ok(0x42));
+    }
+
+    #[test]
+    fn clock_only_wakes_each_second() {
+        // Runs a second, counting the cycles the scheduler had something to run.
+        fn run(mem: &mut Peripherals) -> u32 {
+            let mut wakes = 0;
+            for _ in 0..(1 << 20) {
+                if mem.scheduler.pending(mem.cycles + 1) {
+                    wakes += 1;
+                }
+                mem.step();
+            }
+            wakes
+        }
+        let mut rom = vec![0; 0x8000];
+        let mut without = Peripherals::new_headless(rom.clone());
+        without.step();
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        let mut mem = Peripherals::new_headless(rom);
+        mem.write(0x0000, 0x0A);
+        mem.write(0x4000, 0x08);
+        mem.step();
+        // Audio and input are checked periodically either way; the clock adds one tick.
+        assert_eq!(run(&mut mem), run(&mut without) + 1);
+        mem.write(0x6000, 0x00);
+        mem.write(0x6000, 0x01);
+        assert_eq!(mem.read(0xA000), 1);
     }
 }
@@ -9,5 +9,16 @@ pub enum Color {
 pub trait Display {
     fn clear(&mut self, color: Color);
     fn draw_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), String>;
+
+    /// Draws a whole frame, given as packed 8-bit RGB in rows `width` pixels wide.
+    fn draw_frame(&mut self, width: usize, rgb: &[u8]) -> Result<(), String> {
+        for (index, pixel) in rgb.chunks(3).enumerate() {
+            let color = Color::RGB(pixel[0], pixel[1], pixel[2]);
+            self.draw_pixel(index % width, index / width, color)?;
+        }
+        Ok(())
+    }
+
     fn show(&mut self);
+    fn set_title(&mut self, title: &str);
 }
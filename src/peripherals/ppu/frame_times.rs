@@ -0,0 +1,139 @@
+//! Statistics on how long frames take, to make slow frames and uneven pacing visible. Times go
+//! into buckets, so the 99th percentile is only as exact as a bucket's width, but a long run
+//! doesn't keep every frame's time around.
+use std::fmt;
+use std::time::Duration;
+
+const BUCKET: Duration = Duration::from_micros(10);
+// Enough buckets for 100ms. Frames slower than that all count as the slowest bucket.
+const BUCKETS: usize = 10_000;
+
+/// Summary of how long a run's frames took.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameTimeSummary {
+    pub frames: u64,
+    pub min: Duration,
+    pub avg: Duration,
+    /// 99% of frames took at most this long.
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for FrameTimeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "min {:.2}ms, avg {:.2}ms, p99 {:.2}ms, max {:.2}ms over {} frames",
+            ms(self.min),
+            ms(self.avg),
+            ms(self.p99),
+            ms(self.max),
+            self.frames
+        )
+    }
+}
+
+/// How long frames took to emulate, and to present if they were shown. Either is None without
+/// any frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameStats {
+    pub emulation: Option<FrameTimeSummary>,
+    pub presentation: Option<FrameTimeSummary>,
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.emulation {
+            Some(emulation) => writeln!(f, "Emulation:    {}", emulation)?,
+            None => writeln!(f, "Emulation:    no frames")?,
+        }
+        match self.presentation {
+            Some(presentation) => write!(f, "Presentation: {}", presentation),
+            None => write!(f, "Presentation: no frames"),
+        }
+    }
+}
+
+pub struct FrameTimes {
+    buckets: Vec<u64>,
+    frames: u64,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl FrameTimes {
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            frames: 0,
+            total: Duration::from_secs(0),
+            min: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+
+    pub fn record(&mut self, time: Duration) {
+        let bucket = (time.as_micros() / BUCKET.as_micros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        if self.frames == 0 || time < self.min {
+            self.min = time;
+        }
+        self.max = self.max.max(time);
+        self.frames += 1;
+        self.total += time;
+    }
+
+    pub fn summary(&self) -> Option<FrameTimeSummary> {
+        if self.frames == 0 {
+            return None;
+        }
+        // The first bucket that takes the count to 99% of the frames, rounded up to its end.
+        let needed = (self.frames * 99).div_ceil(100);
+        let mut seen = 0;
+        let mut p99 = self.max;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= needed {
+                p99 = (BUCKET * (bucket as u32 + 1)).min(self.max);
+                break;
+            }
+        }
+        Some(FrameTimeSummary {
+            frames: self.frames,
+            min: self.min,
+            avg: self.total / self.frames as u32,
+            p99,
+            max: self.max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes() {
+        let mut times = FrameTimes::new();
+        assert_eq!(times.summary(), None);
+        for _ in 0..98 {
+            times.record(Duration::from_micros(1_000));
+        }
+        times.record(Duration::from_micros(2_000));
+        times.record(Duration::from_millis(500));
+        let summary = times.summary().unwrap();
+        assert_eq!(summary.frames, 100);
+        assert_eq!(summary.min, Duration::from_micros(1_000));
+        assert_eq!(summary.avg, Duration::from_micros(6_000));
+        // The 99th frame is the 2ms one, whose bucket goes up to 2.01ms. The 500ms one is past the
+        // last bucket.
+        assert_eq!(summary.p99, Duration::from_micros(2_010));
+        assert_eq!(summary.max, Duration::from_millis(500));
+        assert_eq!(
+            summary.to_string(),
+            "min 1.00ms, avg 6.00ms, p99 2.01ms, max 500.00ms over 100 frames"
+        );
+    }
+}
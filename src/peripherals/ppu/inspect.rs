@@ -0,0 +1,147 @@
+//! Human-readable dumps of PPU state, for the debugger.
+use peripherals::ppu::{Colors, LCDControl, Palette, Ppu, SpriteFlags};
+use std::fmt::Write;
+
+const OAM_ENTRIES: usize = 40;
+
+impl Ppu {
+    /// One line per OAM entry: index, position on screen, tile number, and decoded flags.
+    pub fn describe_oam(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, " #   Y    X  tile  flags").unwrap();
+        for index in 0..OAM_ENTRIES {
+            out.push_str(&self.describe_oam_entry(index));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// A single OAM entry, along with its tile drawn out as color numbers (0 drawn as '.', since
+    /// it's transparent for sprites).
+    pub fn describe_sprite(&self, index: usize) -> Option<String> {
+        if index >= OAM_ENTRIES {
+            return None;
+        }
+        let mut out = self.describe_oam_entry(index);
+        out.push('\n');
+        let tile_number = self.oam[index * 4 + 2];
+        let (first_tile, height) = if self.control.contains(LCDControl::SPRITE_SIZE) {
+            (tile_number & 0xFE, 16)
+        } else {
+            (tile_number, 8)
+        };
+        for row in 0..height {
+            // Sprites always use the 0x8000 tile set.
+            let tile_addr = usize::from(first_tile + (row / 8) as u8) * 16;
+            for col in 0..8 {
+                match self.tiles.pixel(tile_addr, col, row % 8) {
+                    0 => out.push('.'),
+                    color => out.push(char::from(b'0' + color)),
+                }
+            }
+            out.push('\n');
+        }
+        Some(out)
+    }
+
+    fn describe_oam_entry(&self, index: usize) -> String {
+        let entry = &self.oam[index * 4..index * 4 + 4];
+        let flags = SpriteFlags::from_bits_truncate(entry[3]);
+        let mut names = String::new();
+        for (flag, name) in &[
+            (SpriteFlags::BG_PRIORITY, "behind-bg "),
+            (SpriteFlags::Y_FLIP, "y-flip "),
+            (SpriteFlags::X_FLIP, "x-flip "),
+        ] {
+            if flags.contains(*flag) {
+                names.push_str(name);
+            }
+        }
+        let palette = if flags.contains(SpriteFlags::PALETTE) {
+            1
+        } else {
+            0
+        };
+        // OAM positions are offset so that sprites can be partially scrolled off the top left.
+        format!(
+            "{:2} {:4} {:4}  0x{:02X}  {}OBP{}",
+            index,
+            i16::from(entry[0]) - 16,
+            i16::from(entry[1]) - 8,
+            entry[2],
+            names,
+            palette
+        )
+    }
+
+    /// The DMG palettes, as register values, the shade each color maps to, and a swatch of each
+    /// shade drawn with 24-bit terminal colors.
+    /// TODO(slongfield): Add CGB palette RAM once it exists.
+    pub fn describe_palettes(&self) -> String {
+        let mut out = String::new();
+        for (name, palette) in &[
+            ("BGP ", &self.bg_palette),
+            ("OBP0", &self.obj0_palette),
+            ("OBP1", &self.obj1_palette),
+        ] {
+            writeln!(out, "{}  {}", name, describe_palette(palette, self.colors)).unwrap();
+        }
+        out
+    }
+}
+
+fn describe_palette(palette: &Palette, colors: Colors) -> String {
+    let shades = [
+        palette.color0,
+        palette.color1,
+        palette.color2,
+        palette.color3,
+    ];
+    let register = shades
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (index, shade)| acc | (shade << (index * 2)));
+    let mut out = format!("0x{:02X} ", register);
+    for (index, shade) in shades.iter().enumerate() {
+        write!(out, " {}:{}", index, shade).unwrap();
+    }
+    out.push_str("  ");
+    for shade in &shades {
+        let (r, g, b) = colors.rgb(*shade);
+        write!(out, "\x1b[48;2;{};{};{}m  \x1b[0m", r, g, b).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_sprite() {
+        let mut ppu = Ppu::new_fake();
+        ppu.oam[4..8].copy_from_slice(&[16 + 10, 8 + 20, 1, 0b0010_0000]);
+        // Tile 1, first row: color 3 in the leftmost pixel, color 1 in the rightmost.
+        ppu.write_vram(16, 0b1000_0000);
+        ppu.write_vram(17, 0b1000_0001);
+        assert_eq!(
+            ppu.describe_sprite(1)
+                .unwrap()
+                .lines()
+                .take(2)
+                .collect::<Vec<_>>(),
+            vec![" 1   10   20  0x01  x-flip OBP0", "3......1"]
+        );
+        assert_eq!(ppu.describe_sprite(OAM_ENTRIES), None);
+    }
+
+    #[test]
+    fn describe_palette_register() {
+        let mut palette = Palette::new();
+        palette.set_color0(0);
+        palette.set_color1(1);
+        palette.set_color2(2);
+        palette.set_color3(3);
+        assert!(describe_palette(&palette, Colors::Green).starts_with("0xE4  0:0 1:1 2:2 3:3"));
+    }
+}
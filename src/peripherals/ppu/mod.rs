@@ -1,20 +1,49 @@
+use peripherals::clock::Clock;
 use peripherals::interrupt::Interrupt;
+use peripherals::ppu::tiles::TileCache;
 use peripherals::Dma;
 use sdl2;
-use std::thread;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::{Duration, Instant};
+use util;
+use Options;
+use Speed;
 
 mod display;
-mod fake_display;
+mod frame_times;
+mod inspect;
+mod presenter;
+mod recorder;
+mod registers;
+mod screenshot;
 mod sdl_display;
+mod snapshot;
+mod speed;
+mod tiles;
+
+pub use self::frame_times::{FrameStats, FrameTimeSummary};
+pub use self::presenter::{FrameSender, Presenter};
 
 const LINE_COUNT: u8 = 154;
 const VISIBLE_COUNT: u8 = 144;
 const PIXEL_WIDTH: usize = 160;
-const MODE0_CYCLES: u8 = 51;
-const MODE1_CYCLES: u8 = 114; // cycles per line
-const MODE2_CYCLES: u8 = 20;
-const MODE3_CYCLES: u8 = 43;
+const PIXEL_HEIGHT: usize = VISIBLE_COUNT as usize;
+// The background map is 32x32 tiles.
+const BG_MAP_SIZE: usize = 256;
+// The PPU runs off a 4MHz dot clock, with 456 dots to a line. OAM scan takes the first 80 dots of
+// the visible lines, and rendering the next 172. The rest is HBlank.
+const LINE_DOTS: u16 = 456;
+const OAM_DOTS: u16 = 80;
+const RENDER_DOTS: u16 = 172;
+// Dots per machine cycle. In CGB double speed, the CPU's cycles would get half as many.
+const DOTS_PER_CYCLE: u16 = 4;
+// OAM has room for 40 sprites, but only 10 can be drawn on each line.
+const OAM_SPRITES: usize = 40;
+const SPRITES_PER_LINE: usize = 10;
 
 bitflags! {
     pub struct LCDControl: u8 {
@@ -64,6 +93,56 @@ impl LCDControl {
     }
 }
 
+/// Colors the four shades are drawn in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colors {
+    /// Greens, like the DMG's screen.
+    Green,
+    /// White through black, like the Game Boy Pocket's.
+    Gray,
+}
+
+impl Colors {
+    // Maps a palette shade (0-3) onto the color that's drawn to the screen.
+    // TODO(slongfield): Adjust to taste.
+    pub fn rgb(self, shade: u8) -> (u8, u8, u8) {
+        match (self, shade) {
+            (Colors::Green, 0b00) => (155, 188, 15),
+            (Colors::Green, 0b01) => (48, 98, 48),
+            (Colors::Green, 0b10) => (139, 172, 15),
+            (Colors::Green, _) => (15, 56, 15),
+            (Colors::Gray, 0b00) => (255, 255, 255),
+            (Colors::Gray, 0b01) => (170, 170, 170),
+            (Colors::Gray, 0b10) => (85, 85, 85),
+            (Colors::Gray, _) => (0, 0, 0),
+        }
+    }
+}
+
+impl fmt::Display for Colors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Colors::Green => write!(f, "green"),
+            Colors::Gray => write!(f, "gray"),
+        }
+    }
+}
+
+impl FromStr for Colors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "green" => Ok(Colors::Green),
+            "gray" => Ok(Colors::Gray),
+            other => Err(format!(
+                "Unknown colors {:?}, expected green or gray",
+                other
+            )),
+        }
+    }
+}
+
 const HBLANK_MODE: u8 = 0;
 const VBLANK_MODE: u8 = 1;
 const OAM_MODE: u8 = 2;
@@ -172,24 +251,6 @@ impl Palette {
     }
 }
 
-#[derive(Debug)]
-struct Tile {
-    data: Vec<u8>,
-}
-
-impl Tile {
-    fn new(data: Vec<u8>) -> Self {
-        Self { data }
-    }
-
-    fn pixel(&self, x: usize, y: usize) -> u8 {
-        let x = 7 - x;
-        let high_bit = (self.data[y * 2] & (1 << x)) >> x;
-        let low_bit = (self.data[y * 2 + 1] & (1 << x)) >> x;
-        (high_bit << 1) | low_bit
-    }
-}
-
 bitflags! {
     pub struct SpriteFlags: u8 {
         const BG_PRIORITY = 0b1000_0000;
@@ -201,36 +262,39 @@ bitflags! {
 
 #[derive(Debug)]
 struct Sprite {
-    pub tile: Tile,
+    // Where the sprite's tile starts in VRAM. Sprites always use the 0x8000 tile set.
+    tile_addr: usize,
     x: usize,
     y: usize,
     pub flags: SpriteFlags,
 }
 
 impl Sprite {
-    fn new(tile: Tile, x: u8, y: u8, flags: u8) -> Self {
+    fn new(tile_number: u8, x: u8, y: u8, flags: u8) -> Self {
         Self {
-            tile: tile,
+            tile_addr: usize::from(tile_number) * 16,
             x: usize::from(x),
             y: usize::from(y),
             flags: SpriteFlags::from_bits_truncate(flags),
         }
     }
 
-    fn get_pixel(&self, x: usize, y: u8) -> u8 {
+    // The sprite's pixel at screen position (x, y), or 0 if it's transparent or not there.
+    fn get_pixel(&self, tiles: &TileCache, x: usize, y: u8) -> u8 {
+        // OAM positions are offset by (8, 16), so sprites can be partly off the top left.
         if self.x > x && self.x <= x + 8 {
-            // TODO(slonddgfield): Handle double-tall selfs.
+            // TODO(slongfield): Handle double-tall sprites.
             let tile_y = if self.flags.contains(SpriteFlags::Y_FLIP) {
-                7 - ((usize::from(y) - self.y + 16) % 8)
+                7 - ((usize::from(y) + 16 - self.y) % 8)
             } else {
-                (usize::from(y) - self.y + 16) % 8
+                (usize::from(y) + 16 - self.y) % 8
             };
             let tile_x = if self.flags.contains(SpriteFlags::X_FLIP) {
-                7 - (x - self.x) % 8
+                7 - (x + 8 - self.x)
             } else {
-                (x - self.x) % 8
+                x + 8 - self.x
             };
-            self.tile.pixel(tile_x, tile_y)
+            tiles.pixel(self.tile_addr, tile_x, tile_y)
         } else {
             u8::from(0)
         }
@@ -239,11 +303,14 @@ impl Sprite {
 
 // Pixel processing unit.
 pub struct Ppu {
-    display: Box<display::Display>,
-    wait_for_frame: bool,
+    // Where finished frames are sent to be shown, if anywhere.
+    screen: Option<FrameSender>,
+    pace: Speed,
     // Video RAM. TODO(slongfield): In CGB, should be switchable banks.
     // Ox8000-0x9FFF
     vram: [u8; 0x2000],
+    // The tile data in `vram`, decoded.
+    tiles: TileCache,
     // Sprite attribute table.
     // 0xFE00-0xFE9F
     oam: [u8; 0x100],
@@ -254,80 +321,163 @@ pub struct Ppu {
     scroll_y: u8,
     window_x: u8,
     window_y: u8,
+    // Set once LY has matched WY this frame. The window can only be drawn after that, however WY
+    // changes afterwards.
+    window_y_reached: bool,
+    // The window's own line counter, which only moves on lines the window is drawn on. Hiding it
+    // for a few lines and showing it again picks up where it left off.
+    window_line: u8,
     lcd_y: u8,
     lcd_y_compare: u8,
     pub bg_palette: Palette,
     pub obj0_palette: Palette,
     pub obj1_palette: Palette,
-    mode_cycle: u8,
+    // Dot within the current line.
+    line_dot: u16,
     sprites: Vec<Sprite>,
-    before: Instant,
+    // When the last frame ended and was last shown, by the clock passed to `step`.
+    before: Duration,
+    last_present: Duration,
+    // When emulating the current frame started, after pacing the last one, and how long frames
+    // took to emulate. This is always wall time, even when the clock passed to `step` isn't, since
+    // it's measuring the emulator rather than the Game Boy.
+    frame_started: Instant,
+    emulation_times: frame_times::FrameTimes,
     dma: Dma,
+    // The last value written to DMA, which reads back from it.
+    dma_page: u8,
     pub frame: u32,
+    // Shade of every pixel on the screen, after the palettes have been applied. Lines are rendered
+    // into it, and the display only gets the finished frame.
+    framebuffer: [u8; PIXEL_WIDTH * PIXEL_HEIGHT],
+    colors: Colors,
+    recorder: Option<recorder::Recorder>,
+    // Directory that every frame gets written to, for comparing between emulator versions.
+    frame_dump: Option<PathBuf>,
+    // Set while the debugger replays history, so frames already recorded or dumped aren't again.
+    muted: bool,
+    // Debug window showing the whole background map, with the visible area outlined.
+    bg_map: Option<FrameSender>,
+    speed: speed::SpeedMeter,
+    // If set, the FPS and emulation speed are shown in the window title.
+    show_speed: bool,
 }
 
 impl Ppu {
     // Number of microseconds between frames.
     const INTERVAL: u64 = 16_666;
 
-    pub fn new_sdl(video_subsystem: sdl2::VideoSubsystem) -> Self {
-        Self {
-            display: Box::new(sdl_display::SdlDisplay::new(video_subsystem)),
-            wait_for_frame: true,
+    /// Opens the windows, which are shown by `presenter`.
+    pub fn new_sdl(
+        video_subsystem: &sdl2::VideoSubsystem,
+        presenter: &mut Presenter,
+        options: &Options,
+    ) -> Result<Self, String> {
+        let display = sdl_display::SdlDisplay::new(video_subsystem.clone(), options.vsync)?;
+        let screen = presenter.add_window(Box::new(display), PIXEL_WIDTH);
+        let bg_map = if options.bg_map_viewer {
+            Some(presenter.add_sdl_window(
+                video_subsystem,
+                "Wolfwig BG Map",
+                BG_MAP_SIZE,
+                BG_MAP_SIZE,
+                2,
+            )?)
+        } else {
+            None
+        };
+        Ok(Self {
+            screen: Some(screen),
+            pace: Speed::Times(1),
             vram: [0; 0x2000],
+            tiles: TileCache::new(),
             oam: [0; 0x100],
             lcd_y: 0,
             scroll_x: 0,
             scroll_y: 0,
             window_x: 0,
             window_y: 0,
+            window_y_reached: false,
+            window_line: 0,
             lcd_y_compare: 0,
             control: LCDControl::new(),
             status: LCDStatus::new(),
             bg_palette: Palette::new(),
             obj0_palette: Palette::new(),
             obj1_palette: Palette::new(),
-            mode_cycle: 0,
+            line_dot: 0,
             sprites: vec![],
-            before: Instant::now(),
+            before: Duration::from_secs(0),
+            last_present: Duration::from_secs(0),
+            frame_started: Instant::now(),
+            emulation_times: frame_times::FrameTimes::new(),
             dma: Dma::new(),
+            dma_page: 0xFF,
             frame: 0,
-        }
+            framebuffer: [0; PIXEL_WIDTH * PIXEL_HEIGHT],
+            colors: Colors::Green,
+            recorder: None,
+            frame_dump: None,
+            muted: false,
+            bg_map,
+            speed: speed::SpeedMeter::new(),
+            show_speed: options.show_speed,
+        })
     }
 
     pub fn new_fake() -> Self {
         Self {
-            display: Box::new(fake_display::FakeDisplay::new()),
-            wait_for_frame: true,
+            screen: None,
+            pace: Speed::Times(1),
             vram: [0; 0x2000],
+            tiles: TileCache::new(),
             oam: [0; 0x100],
             lcd_y: 0,
             scroll_x: 0,
             scroll_y: 0,
             window_x: 0,
             window_y: 0,
+            window_y_reached: false,
+            window_line: 0,
             lcd_y_compare: 0,
             control: LCDControl::new(),
             status: LCDStatus::new(),
             bg_palette: Palette::new(),
             obj0_palette: Palette::new(),
             obj1_palette: Palette::new(),
-            mode_cycle: 0,
+            line_dot: 0,
             sprites: vec![],
-            before: Instant::now(),
+            before: Duration::from_secs(0),
+            last_present: Duration::from_secs(0),
+            frame_started: Instant::now(),
+            emulation_times: frame_times::FrameTimes::new(),
             dma: Dma::new(),
+            dma_page: 0xFF,
             frame: 0,
+            framebuffer: [0; PIXEL_WIDTH * PIXEL_HEIGHT],
+            colors: Colors::Green,
+            recorder: None,
+            frame_dump: None,
+            muted: false,
+            bg_map: None,
+            speed: speed::SpeedMeter::new(),
+            show_speed: false,
         }
     }
 
-    pub fn step(&mut self, interrupt: &mut Interrupt, dma: &mut Dma) {
+    pub fn step(&mut self, interrupt: &mut Interrupt, dma: &mut Dma, clock: &mut dyn Clock) {
         if self.control.contains(LCDControl::ENABLE) {
-            match self.status.mode {
-                HBLANK_MODE => self.mode0(interrupt),
-                VBLANK_MODE => self.mode1(interrupt),
-                OAM_MODE => self.mode2(interrupt),
-                RENDER_MODE => self.render_line(),
-                _ => unreachable!(),
+            let mode = self.status.mode;
+            for _ in 0..DOTS_PER_CYCLE {
+                self.step_dot(interrupt, clock);
+            }
+            if self.status.mode != mode {
+                trace!(
+                    frame = self.frame,
+                    mode = self.status.mode,
+                    line = self.lcd_y,
+                    "mode change"
+                );
             }
         }
         if dma.enabled {
@@ -343,6 +493,41 @@ impl Ppu {
         }
     }
 
+    pub fn in_vblank(&self) -> bool {
+        self.status.mode == VBLANK_MODE
+    }
+
+    pub fn colors(&self) -> Colors {
+        self.colors
+    }
+
+    /// Changes the colors frames are drawn in from now on. Recordings already going keep theirs.
+    pub fn set_colors(&mut self, colors: Colors) {
+        self.colors = colors;
+    }
+
+    /// Writes LCDC. Turning the LCD off stops the PPU at the top of the screen, and turning it on
+    /// starts the first line, so frames always line up with when it was turned on.
+    pub fn set_control(&mut self, val: u8) {
+        let was_enabled = self.control.contains(LCDControl::ENABLE);
+        self.control.set_control(val);
+        match (was_enabled, self.control.contains(LCDControl::ENABLE)) {
+            (true, false) => {
+                self.lcd_y = 0;
+                self.line_dot = 0;
+                self.status.mode = HBLANK_MODE;
+            }
+            (false, true) => {
+                self.lcd_y = 0;
+                self.line_dot = 0;
+                self.window_y_reached = false;
+                self.window_line = 0;
+                self.status.mode = OAM_MODE;
+            }
+            _ => {}
+        }
+    }
+
     pub fn set_lcd_y(&mut self, val: u8) {
         self.lcd_y = val & 0
     }
@@ -351,7 +536,17 @@ impl Ppu {
         self.lcd_y
     }
 
+    /// Dot (at 4MHz) within the current line, counting from the start of the OAM scan.
+    pub fn dot(&self) -> u16 {
+        self.line_dot
+    }
+
+    pub fn dma(&self) -> u8 {
+        self.dma_page
+    }
+
     pub fn set_dma(&mut self, val: u8) {
+        self.dma_page = val;
         self.dma.enabled = true;
         self.dma.source = u16::from(val) * 0x100;
         self.dma.dest = 0xFE00;
@@ -361,9 +556,7 @@ impl Ppu {
         match address {
             addr @ 0x8000..=0x9FFF => match self.status.mode {
                 HBLANK_MODE | VBLANK_MODE | OAM_MODE => {
-                    if let Some(old) = self.vram.get_mut((addr as usize) - 0x8000) {
-                        *old = val;
-                    }
+                    self.write_vram(usize::from(addr - 0x8000), val);
                 }
                 RENDER_MODE => {}
                 _ => unreachable!(),
@@ -381,6 +574,11 @@ impl Ppu {
         }
     }
 
+    fn write_vram(&mut self, offset: usize, val: u8) {
+        self.vram[offset] = val;
+        self.tiles.update(&self.vram, offset);
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         match address {
             addr @ 0x8000..=0x9FFF => match self.status.mode {
@@ -417,8 +615,103 @@ impl Ppu {
         }
     }
 
-    pub fn go_fast(&mut self) {
-        self.wait_for_frame = false;
+    /// Reads VRAM or OAM even while they're locked.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            addr @ 0x8000..=0x9FFF => self.vram[usize::from(addr - 0x8000)],
+            addr @ 0xFE00..=0xFE9F => self.oam[usize::from(addr - 0xFE00)],
+            _ => self.read(address),
+        }
+    }
+
+    /// Writes VRAM or OAM even while they're locked.
+    pub fn poke(&mut self, address: u16, val: u8) {
+        match address {
+            addr @ 0x8000..=0x9FFF => self.write_vram(usize::from(addr - 0x8000), val),
+            addr @ 0xFE00..=0xFE9F => self.oam[usize::from(addr - 0xFE00)] = val,
+            _ => self.write(address, val),
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.pace = speed;
+    }
+
+    /// Default file name for screenshots and recordings that start at the current frame.
+    pub fn capture_name(&self, extension: &str) -> String {
+        format!("wolfwig_{:06}.{}", self.frame, extension)
+    }
+
+    pub fn screenshot(&self, path: &Path) -> Result<(), io::Error> {
+        let mut rgb = Vec::with_capacity(self.framebuffer.len() * 3);
+        push_rgb(&self.framebuffer, self.colors, &mut rgb);
+        screenshot::write_png(path, PIXEL_WIDTH, PIXEL_HEIGHT, &rgb)?;
+        debug!("Wrote screenshot to {:?}", path);
+        Ok(())
+    }
+
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.recorder = Some(recorder::Recorder::new(
+            path,
+            PIXEL_WIDTH,
+            PIXEL_HEIGHT,
+            self.colors,
+        )?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            info!("Stopped recording after {} frames", recorder.frames());
+        }
+    }
+
+    /// Stops frames going to the recording or frame dump, without stopping either.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Writes every frame from here on out into `dir`, named by frame number and hash.
+    pub fn dump_frames(&mut self, dir: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(dir)?;
+        self.frame_dump = Some(dir.to_path_buf());
+        Ok(())
+    }
+
+    /// How long frames have taken to emulate, not counting the wait to pace them, and to present.
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats {
+            emulation: self.emulation_times.summary(),
+            presentation: self
+                .screen
+                .as_ref()
+                .and_then(|screen| screen.presentation_times()),
+        }
+    }
+
+    /// Leaves `time` spent not emulating, like while paused, out of the current frame's
+    /// emulation time.
+    pub fn exclude_from_frame_time(&mut self, time: Duration) {
+        self.frame_started += time;
+    }
+
+    /// Frames per second, measured over the last second of wall time.
+    pub fn fps(&self) -> f32 {
+        self.speed.fps()
+    }
+
+    /// Emulation speed as a percentage of the real hardware, measured over the last second.
+    pub fn speed(&self) -> f32 {
+        self.speed.speed()
+    }
+
+    /// Hash of the most recently rendered frame, stable across runs and platforms.
+    pub fn frame_hash(&self) -> u64 {
+        util::fnv1a_64(&self.framebuffer)
     }
 
     pub fn set_scroll_y(&mut self, val: u8) {
@@ -453,199 +746,258 @@ impl Ppu {
         self.window_x
     }
 
-    // HBlank, don't render anything, go to VBLANK or OAM mode at end of cycle.
-    fn mode0(&mut self, interrupt: &mut Interrupt) {
-        self.mode_cycle += 1;
-        if self.mode_cycle == MODE0_CYCLES {
-            self.lcd_y += 1;
-            self.update_ly_interrupt(interrupt);
-            self.mode_cycle = 0;
-            if self.lcd_y == VISIBLE_COUNT {
-                self.status.mode = VBLANK_MODE;
-            } else {
-                self.status.mode = OAM_MODE;
+    // Runs one dot: the work for the current mode, and then moving on to the next mode or line
+    // if it's done.
+    fn step_dot(&mut self, interrupt: &mut Interrupt, clock: &mut dyn Clock) {
+        match self.status.mode {
+            OAM_MODE if self.line_dot == 0 => {
+                // WY is compared once a line, at the start of the OAM scan.
+                self.window_y_reached |= self.lcd_y == self.window_y;
+                self.scan_sprites();
             }
+            RENDER_MODE if self.line_dot == OAM_DOTS => self.render_line(),
+            _ => {}
+        }
+        self.line_dot += 1;
+        match self.status.mode {
+            OAM_MODE if self.line_dot == OAM_DOTS => {
+                self.status.mode = RENDER_MODE;
+                self.update_mode_interrupt(interrupt);
+            }
+            RENDER_MODE if self.line_dot == OAM_DOTS + RENDER_DOTS => {
+                self.status.mode = HBLANK_MODE;
+                self.update_mode_interrupt(interrupt);
+            }
+            _ if self.line_dot == LINE_DOTS => self.next_line(interrupt, clock),
+            _ => {}
+        }
+    }
+
+    // Moves on to the next line at the end of the last one, going into VBlank after the last
+    // visible line, and back to the top after VBlank.
+    fn next_line(&mut self, interrupt: &mut Interrupt, clock: &mut dyn Clock) {
+        self.line_dot = 0;
+        self.lcd_y += 1;
+        if self.lcd_y == LINE_COUNT {
+            self.lcd_y = 0;
+        }
+        self.update_ly_interrupt(interrupt);
+        if self.lcd_y == VISIBLE_COUNT {
+            self.status.mode = VBLANK_MODE;
+            self.window_y_reached = false;
+            self.window_line = 0;
+            // VBlank's own interrupt is requested whether or not STAT's mode 1 source is.
+            interrupt.set_vblank_trigger(1);
+            self.update_mode_interrupt(interrupt);
+        } else if self.lcd_y < VISIBLE_COUNT {
+            self.status.mode = OAM_MODE;
             self.update_mode_interrupt(interrupt);
+            if self.lcd_y == 0 {
+                self.end_frame(clock);
+            }
         }
     }
 
-    // VBlank, don't render anything, go to OAM mode at end of cycles.
-    fn mode1(&mut self, interrupt: &mut Interrupt) {
-        self.mode_cycle += 1;
-        if self.mode_cycle == MODE1_CYCLES {
-            self.lcd_y += 1;
-            self.update_ly_interrupt(interrupt);
-            self.mode_cycle = 0;
-            if self.lcd_y == LINE_COUNT {
-                self.lcd_y = 0;
-                self.status.mode = OAM_MODE;
-                self.update_mode_interrupt(interrupt);
+    // Hands the completed frame off to the display and any captures, and waits for it to be
+    // time for the next frame.
+    fn end_frame(&mut self, clock: &mut dyn Clock) {
+        // When running faster than normal, only send some frames. The presenter couldn't show
+        // them all anyway, and converting them is wasted work.
+        let present = match self.pace {
+            Speed::Times(n) => self.frame.is_multiple_of(n.max(1)),
+            Speed::Uncapped => {
+                clock.now() >= self.last_present + Duration::from_micros(Self::INTERVAL)
+            }
+        };
+        if present {
+            if let Some(ref mut screen) = self.screen {
+                let (framebuffer, colors) = (&self.framebuffer, self.colors);
+                screen.send(|rgb| push_rgb(framebuffer, colors, rgb));
+            }
+            self.last_present = clock.now();
+        }
+        if self.speed.tick(clock.now()) && self.show_speed {
+            let title = format!(
+                "Wolfwig Gameboy Emulator - {:.1} FPS ({:.0}%)",
+                self.speed.fps(),
+                self.speed.speed()
+            );
+            if let Some(ref mut screen) = self.screen {
+                screen.set_title(title);
+            }
+        }
+        let mut record_failed = false;
+        if let (Some(ref mut recorder), false) = (&mut self.recorder, self.muted) {
+            if let Err(err) = recorder.add_frame(&self.framebuffer) {
+                error!("Could not record frame: {}", err);
+                record_failed = true;
+            }
+        }
+        if record_failed {
+            self.recorder = None;
+        }
+        if self.bg_map.is_some() {
+            self.show_bg_map();
+        }
+        if let (Some(dir), false) = (self.frame_dump.clone(), self.muted) {
+            let name = format!("frame_{:06}_{:016x}.png", self.frame, self.frame_hash());
+            if let Err(err) = self.screenshot(&dir.join(&name)) {
+                error!("Could not dump frame {}: {}", name, err);
+                self.frame_dump = None;
+            }
+        }
+        self.emulation_times.record(self.frame_started.elapsed());
+        let now = clock.now();
+        if let Speed::Times(n) = self.pace {
+            let interval = Duration::from_micros(Self::INTERVAL / u64::from(n.max(1)));
+            let dt = now.checked_sub(self.before).unwrap_or_default();
+            if dt < interval {
+                clock.sleep(interval - dt);
+            }
+            // The next frame is timed from after the sleep, or it would count towards that frame.
+            self.before = clock.now();
+        }
+        self.frame_started = Instant::now();
+        self.frame += 1;
+    }
 
-                self.display.show();
-                if self.wait_for_frame {
-                    let now = Instant::now();
-                    let dt = u64::from(now.duration_since(self.before).subsec_micros());
-                    if dt < Self::INTERVAL {
-                        thread::sleep(Duration::from_micros(Self::INTERVAL - dt));
+    // OAM mode, build sprite list.
+    // Shades of the whole 256x256 background map, with the BG palette applied.
+    fn bg_map(&self) -> Vec<u8> {
+        let mut map = vec![0; BG_MAP_SIZE * BG_MAP_SIZE];
+        for tile_y in 0..32 {
+            for tile_x in 0..32 {
+                let tile_number = self.vram[self.control.bg_tile_map() + tile_y * 32 + tile_x];
+                let base_addr = self.control.bg_tile_addr(tile_number);
+                for y in 0..8 {
+                    for x in 0..8 {
+                        map[(tile_y * 8 + y) * BG_MAP_SIZE + tile_x * 8 + x] =
+                            self.bg_palette.get_color(self.tiles.pixel(base_addr, x, y));
                     }
-                    self.before = now;
                 }
-                self.frame += 1;
             }
         }
+        map
     }
 
-    // OAM mode, build sprite list.
-    fn mode2(&mut self, interrupt: &mut Interrupt) {
-        if self.mode_cycle == 0 {
-            self.sprites = vec![];
-            for entry in self.oam.chunks(4) {
-                let y = *entry.get(0).unwrap_or(&0);
-                let x = *entry.get(1).unwrap_or(&0);
-                let tile_number = *entry.get(2).unwrap_or(&0);
-                // TODO(slongfield): Handle double-tall tiles.
-                let tile = Tile::new(
-                    (0..16)
-                        .map(|offset| {
-                            *self
-                                .vram
-                                .get(usize::from(tile_number) * 16 + offset)
-                                .unwrap_or(&0)
-                        })
-                        .collect::<Vec<u8>>(),
-                );
-                let flags = *entry.get(3).unwrap_or(&0);
-                // Only add the sprite if it'll be visibile.
-                if self.lcd_y + 8 < y && self.lcd_y + 16 >= y {
-                    self.sprites.push(Sprite::new(tile, x, y, flags));
+    // Sends the background map to its debug window, outlining the area that's currently scrolled
+    // onto the screen. The outline wraps around the edges the same way the scroll does.
+    fn show_bg_map(&mut self) {
+        let map = self.bg_map();
+        let left = usize::from(self.scroll_x);
+        let top = usize::from(self.scroll_y);
+        let right = (left + PIXEL_WIDTH - 1) % BG_MAP_SIZE;
+        let bottom = (top + PIXEL_HEIGHT - 1) % BG_MAP_SIZE;
+        let in_span =
+            |pos: usize, start: usize, len: usize| (pos + BG_MAP_SIZE - start) % BG_MAP_SIZE < len;
+        let colors = self.colors;
+        if let Some(ref mut bg_map) = self.bg_map {
+            bg_map.send(|rgb| {
+                for (index, shade) in map.iter().enumerate() {
+                    let x = index % BG_MAP_SIZE;
+                    let y = index / BG_MAP_SIZE;
+                    let outline = ((x == left || x == right) && in_span(y, top, PIXEL_HEIGHT))
+                        || ((y == top || y == bottom) && in_span(x, left, PIXEL_WIDTH));
+                    let (r, g, b) = if outline {
+                        (255, 0, 0)
+                    } else {
+                        colors.rgb(*shade)
+                    };
+                    rgb.extend_from_slice(&[r, g, b]);
+                }
+            });
+        }
+    }
+
+    // Finds the sprites on the current line. Only the first ten in OAM are drawn, and they're kept
+    // in priority order: on DMG, the lowest X first, with ties going to whichever is first in OAM.
+    fn scan_sprites(&mut self) {
+        self.sprites = vec![];
+        for entry in self.oam[..OAM_SPRITES * 4].chunks(4) {
+            let y = *entry.get(0).unwrap_or(&0);
+            let x = *entry.get(1).unwrap_or(&0);
+            // TODO(slongfield): Handle double-tall tiles.
+            let tile_number = *entry.get(2).unwrap_or(&0);
+            let flags = *entry.get(3).unwrap_or(&0);
+            // Only add the sprite if it'll be visibile.
+            if self.lcd_y + 8 < y && self.lcd_y + 16 >= y {
+                self.sprites.push(Sprite::new(tile_number, x, y, flags));
+                if self.sprites.len() == SPRITES_PER_LINE {
+                    break;
                 }
             }
-            // Sort by X, since smallest X gets highest priority, so want to draw it
-            // first.
-            self.sprites.sort_unstable_by(|a, b| (a.x).cmp(&b.x));
-        }
-        self.mode_cycle += 1;
-        if self.mode_cycle == MODE2_CYCLES {
-            self.mode_cycle = 0;
-            self.status.mode = RENDER_MODE;
-            self.update_mode_interrupt(interrupt);
         }
+        // A stable sort, so sprites with the same X stay in OAM order.
+        self.sprites.sort_by_key(|sprite| sprite.x);
     }
 
     // Render mode, draw a line.
     fn render_line(&mut self) {
-        if self.mode_cycle != 0 {
-            self.mode_cycle += 1;
-            if self.mode_cycle == MODE3_CYCLES {
-                self.mode_cycle = 0;
-                self.status.mode = HBLANK_MODE;
-            }
-            return;
-        }
         let mut pixels: [u8; PIXEL_WIDTH] = [0; PIXEL_WIDTH];
         // Set up the background.
         {
             let bg_y = usize::from(self.scroll_y.wrapping_add(self.lcd_y));
             let y_offset = (bg_y / 8) * 32;
-            let tiles = (0..32)
-                .map(|line_offset| {
-                    *self
-                        .vram
-                        .get(self.control.bg_tile_map() + y_offset + line_offset)
-                        .unwrap_or(&0)
-                })
-                .map(|tile_number| {
-                    let base_addr = self.control.bg_tile_addr(tile_number);
-                    Tile::new(
-                        (0..16)
-                            .map(|offset| *self.vram.get(base_addr + offset).unwrap_or(&0))
-                            .collect::<Vec<u8>>(),
-                    )
-                })
-                .collect::<Vec<Tile>>();
+            let map = &self.vram[self.control.bg_tile_map() + y_offset..][..32];
             for offset in 0..160 {
                 let x = usize::from(self.scroll_x.wrapping_add(offset));
-                let tile = tiles.get(x / 8).unwrap();
-                pixels[usize::from(offset)] = tile.pixel(x % 8, bg_y % 8);
+                let tile_addr = self.control.bg_tile_addr(map[x / 8]);
+                pixels[usize::from(offset)] = self.tiles.pixel(tile_addr, x % 8, bg_y % 8);
             }
         }
-        // Set up the window.
-        if self.control.contains(LCDControl::WINDOW_ENABLE) && self.lcd_y > self.window_y {
-            let w_y = usize::from(self.lcd_y.wrapping_sub(self.window_y));
+        // Set up the window. Its left edge is at WX - 7, so past 166 it's off the screen.
+        if self.control.contains(LCDControl::WINDOW_ENABLE)
+            && self.window_y_reached
+            && self.window_x <= 166
+        {
+            let w_y = usize::from(self.window_line);
             let y_offset = (w_y / 8) * 32;
-            let tiles = (0..32)
-                .map(|line_offset| {
-                    *self
-                        .vram
-                        .get(self.control.window_tile_map() + y_offset + line_offset)
-                        .unwrap_or(&0)
-                })
-                .map(|tile_number| {
-                    let base_addr = self.control.bg_tile_addr(tile_number);
-                    Tile::new(
-                        (0..16)
-                            .map(|offset| *self.vram.get(base_addr + offset).unwrap_or(&0))
-                            .collect::<Vec<u8>>(),
-                    )
-                })
-                .collect::<Vec<Tile>>();
-            for offset in 0..160 {
-                if offset > (self.window_x - 8) {
-                    let x = usize::from(offset.wrapping_sub(self.window_x - 8));
-                    let tile = tiles.get(x / 8).unwrap();
-                    pixels[usize::from(offset)] = tile.pixel(x % 8, w_y % 8);
+            let map = &self.vram[self.control.window_tile_map() + y_offset..][..32];
+            for offset in 0..160u8 {
+                if offset + 7 >= self.window_x {
+                    let x = usize::from(offset + 7 - self.window_x);
+                    let tile_addr = self.control.bg_tile_addr(map[x / 8]);
+                    pixels[usize::from(offset)] = self.tiles.pixel(tile_addr, x % 8, w_y % 8);
                 }
             }
+            self.window_line += 1;
         }
         // Set up the sprites and select colors.
         {
-            if !self.control.contains(LCDControl::SPRITE_ENABLE) || self.sprites.len() == 0 {
-                for pixel in pixels.iter_mut() {
-                    *pixel = self.bg_palette.get_color(*pixel);
+            let sprites_enabled = self.control.contains(LCDControl::SPRITE_ENABLE);
+            for (index, pixel) in pixels.iter_mut().enumerate() {
+                let bg = *pixel;
+                *pixel = self.bg_palette.get_color(bg);
+                if !sprites_enabled {
+                    continue;
                 }
-            } else {
-                for (index, pixel) in pixels.iter_mut().enumerate() {
-                    if self.control.contains(LCDControl::SPRITE_ENABLE) {
-                        // Get first sprite with a non-zero pixel
-                        if let Some(sprite) = self
-                            .sprites
-                            .iter()
-                            .find(|s| s.get_pixel(index, self.lcd_y) != 0)
-                        {
-                            if !sprite.flags.contains(SpriteFlags::BG_PRIORITY) || *pixel == 0 {
-                                if sprite.flags.contains(SpriteFlags::PALETTE) {
-                                    *pixel = self
-                                        .obj1_palette
-                                        .get_color(sprite.get_pixel(index, self.lcd_y));
-                                } else {
-                                    *pixel = self
-                                        .obj0_palette
-                                        .get_color(sprite.get_pixel(index, self.lcd_y));
-                                }
-                            }
+                // The highest priority sprite that isn't transparent here is the one that's
+                // drawn, even if the background then hides it and a lower priority sprite
+                // wouldn't have been.
+                let opaque = self
+                    .sprites
+                    .iter()
+                    .map(|sprite| (sprite, sprite.get_pixel(&self.tiles, index, self.lcd_y)))
+                    .find(|&(_, color)| color != 0);
+                if let Some((sprite, color)) = opaque {
+                    if !sprite.flags.contains(SpriteFlags::BG_PRIORITY) || bg == 0 {
+                        let palette = if sprite.flags.contains(SpriteFlags::PALETTE) {
+                            &self.obj1_palette
                         } else {
-                            *pixel = self.bg_palette.get_color(*pixel);
-                        }
-                    } else {
-                        *pixel = self.bg_palette.get_color(*pixel);
+                            &self.obj0_palette
+                        };
+                        *pixel = palette.get_color(color);
                     }
                 }
             }
         }
-        // Draw the line.
-        for (index, pixel) in pixels.iter().enumerate() {
-            // TODO(slongfield): Adjust to taste.
-            let color = match pixel {
-                0b00 => display::Color::RGB(155, 188, 15),
-                0b01 => display::Color::RGB(48, 98, 48),
-                0b10 => display::Color::RGB(139, 172, 15),
-                _ => display::Color::RGB(15, 56, 15),
-            };
-            self.display
-                .draw_pixel(index as usize, self.lcd_y as usize, color)
-                .expect("Could not draw rectangle");
+        let line_start = usize::from(self.lcd_y) * PIXEL_WIDTH;
+        if let Some(line) = self
+            .framebuffer
+            .get_mut(line_start..line_start + PIXEL_WIDTH)
+        {
+            line.copy_from_slice(&pixels);
         }
-        self.mode_cycle += 1;
     }
 
     pub fn check_lcd_y_compare(&self) -> bool {
@@ -677,7 +1029,6 @@ impl Ppu {
                 if self.status.mode1_interrupt {
                     interrupt.set_lcd_stat_trigger(1)
                 }
-                interrupt.set_vblank_trigger(1)
             }
             OAM_MODE => {
                 if self.status.mode2_interrupt {
@@ -689,3 +1040,239 @@ impl Ppu {
         }
     }
 }
+
+// Appends the shades in `shades` to `rgb`, as packed 8-bit RGB.
+fn push_rgb(shades: &[u8], colors: Colors, rgb: &mut Vec<u8>) {
+    for shade in shades {
+        let (r, g, b) = colors.rgb(*shade);
+        rgb.extend_from_slice(&[r, g, b]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::clock::CycleClock;
+    use peripherals::ppu::display::{Color, Display};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn vblank_timing() {
+        let mut ppu = Ppu::new_fake();
+        let mut interrupt = Interrupt::new();
+        let mut dma = Dma::new();
+        let mut clock = CycleClock::default();
+        interrupt.set_vblank_enable(1);
+        interrupt.set_lcd_stat_enable(1);
+        ppu.set_control(0x91);
+        let cycles_to_vblank = u32::from(VISIBLE_COUNT) * u32::from(LINE_DOTS / DOTS_PER_CYCLE);
+        for _ in 0..cycles_to_vblank - 1 {
+            ppu.step(&mut interrupt, &mut dma, &mut clock);
+        }
+        assert_eq!(ppu.lcd_y(), VISIBLE_COUNT - 1);
+        assert!(interrupt.get_interrupt_pc().is_none());
+        ppu.step(&mut interrupt, &mut dma, &mut clock);
+        // Without STAT's mode 1 source, only VBlank is requested.
+        assert_eq!(ppu.lcd_y(), VISIBLE_COUNT);
+        assert_eq!(ppu.dot(), 0);
+        assert!(ppu.in_vblank());
+        assert_eq!(interrupt.get_interrupt_pc(), Some(0x40));
+        interrupt.set_vblank_trigger(0);
+        assert!(interrupt.get_interrupt_pc().is_none());
+
+        // A frame later, it's at the same place, even after the LCD's been off for a while.
+        ppu.set_control(0x11);
+        for _ in 0..1000 {
+            ppu.step(&mut interrupt, &mut dma, &mut clock);
+        }
+        assert_eq!(ppu.lcd_y(), 0);
+        ppu.status.set_mode1_interrupt(1);
+        ppu.set_control(0x91);
+        for _ in 0..cycles_to_vblank {
+            ppu.step(&mut interrupt, &mut dma, &mut clock);
+        }
+        assert_eq!(ppu.lcd_y(), VISIBLE_COUNT);
+        assert!(interrupt.vblank_trigger());
+        assert_eq!(interrupt.get_interrupt_pc(), Some(0x40));
+        interrupt.set_vblank_trigger(0);
+        assert_eq!(interrupt.get_interrupt_pc(), Some(0x48));
+    }
+
+    #[test]
+    fn mode_timing() {
+        let mut ppu = Ppu::new_fake();
+        let mut interrupt = Interrupt::new();
+        let mut dma = Dma::new();
+        let mut clock = CycleClock::default();
+        ppu.status.set_mode0_interrupt(1);
+        ppu.set_control(0x91);
+        // The dot each mode starts on, and the mode, for the first line and the start of the
+        // second.
+        let modes = [
+            (OAM_DOTS, RENDER_MODE),
+            (OAM_DOTS + RENDER_DOTS, HBLANK_MODE),
+            (LINE_DOTS, OAM_MODE),
+        ];
+        let mut dot = 0;
+        for &(start, mode) in &modes {
+            while dot + DOTS_PER_CYCLE < start {
+                ppu.step(&mut interrupt, &mut dma, &mut clock);
+                dot += DOTS_PER_CYCLE;
+            }
+            assert_ne!(ppu.status.mode(), mode);
+            assert!(!interrupt.lcd_stat_trigger());
+            ppu.step(&mut interrupt, &mut dma, &mut clock);
+            dot += DOTS_PER_CYCLE;
+            assert_eq!(ppu.status.mode(), mode);
+            // Only HBLANK's STAT source is enabled.
+            assert_eq!(interrupt.lcd_stat_trigger(), mode == HBLANK_MODE);
+            interrupt.set_lcd_stat_trigger(0);
+        }
+        assert_eq!(ppu.lcd_y(), 1);
+        assert_eq!(ppu.dot(), 0);
+
+        run_to_line(&mut ppu, &mut interrupt, VISIBLE_COUNT);
+        assert_eq!(ppu.status.mode(), VBLANK_MODE);
+    }
+
+    // Runs the PPU until it's at the start of `line`.
+    fn run_to_line(ppu: &mut Ppu, interrupt: &mut Interrupt, line: u8) {
+        let mut dma = Dma::new();
+        let mut clock = CycleClock::default();
+        ppu.step(interrupt, &mut dma, &mut clock);
+        while ppu.lcd_y() != line || ppu.dot() != 0 {
+            ppu.step(interrupt, &mut dma, &mut clock);
+        }
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut ppu = Ppu::new_fake();
+        let mut interrupt = Interrupt::new();
+        // Tile 1 at 0x8010 has shade (row + 1) % 4 on each row, and fills the window's map.
+        for row in 0..8 {
+            let shade = (row + 1) % 4;
+            ppu.write_vram(0x10 + row * 2, if shade & 2 != 0 { 0xFF } else { 0x00 });
+            ppu.write_vram(0x10 + row * 2 + 1, if shade & 1 != 0 { 0xFF } else { 0x00 });
+        }
+        for tile in ppu.vram[0x1C00..0x2000].iter_mut() {
+            *tile = 1;
+        }
+        ppu.bg_palette.set_color1(1);
+        ppu.bg_palette.set_color2(2);
+        ppu.bg_palette.set_color3(3);
+        ppu.set_window_y(10);
+        ppu.set_window_x(7);
+        ppu.set_control(0xF1);
+
+        // Hide the window for a few lines, and move WY past where it's already been reached.
+        run_to_line(&mut ppu, &mut interrupt, 12);
+        ppu.set_control(0xD1);
+        ppu.set_window_y(100);
+        run_to_line(&mut ppu, &mut interrupt, 20);
+        ppu.set_control(0xF1);
+        run_to_line(&mut ppu, &mut interrupt, 22);
+
+        let shades: Vec<u8> = (8..22).map(|line| ppu.framebuffer[line * 160]).collect();
+        assert_eq!(shades, vec![0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0]);
+
+        // It starts over the next frame, where WY doesn't get reached.
+        run_to_line(&mut ppu, &mut interrupt, 0);
+        run_to_line(&mut ppu, &mut interrupt, 30);
+        assert_eq!(ppu.framebuffer[10 * 160], 0);
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let mut ppu = Ppu::new_fake();
+        let mut interrupt = Interrupt::new();
+        // Tiles 1 and 2 are solid shades 1 and 2, and tile 3 is shade 3 on its left half and
+        // transparent on its right.
+        for row in 0..8 {
+            ppu.write_vram(0x10 + row * 2 + 1, 0xFF);
+            ppu.write_vram(0x20 + row * 2, 0xFF);
+            ppu.write_vram(0x30 + row * 2, 0xF0);
+            ppu.write_vram(0x30 + row * 2 + 1, 0xF0);
+        }
+        ppu.obj0_palette.set_color1(1);
+        ppu.obj0_palette.set_color2(2);
+        ppu.obj0_palette.set_color3(3);
+        // All on line 20, as (x, tile). The last five fill up the line off the left of the
+        // screen, so the one after them isn't drawn.
+        let sprites = [
+            (20, 1),
+            (16, 2),
+            (20, 2),
+            (40, 3),
+            (44, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (60, 1),
+        ];
+        for (entry, &(x, tile)) in sprites.iter().enumerate() {
+            ppu.oam[entry * 4] = 36;
+            ppu.oam[entry * 4 + 1] = x;
+            ppu.oam[entry * 4 + 2] = tile;
+        }
+        ppu.set_control(0x93);
+        run_to_line(&mut ppu, &mut interrupt, 21);
+
+        let line = &ppu.framebuffer[20 * 160..21 * 160];
+        // The lower X wins where the first two overlap, and the first in OAM where X is the same.
+        assert_eq!(line[8..16], [2; 8]);
+        assert_eq!(line[16..20], [1; 4]);
+        // Lower priority sprites show through transparent pixels.
+        assert_eq!(line[32..36], [3; 4]);
+        assert_eq!(line[36..44], [1; 8]);
+        assert_eq!(line[52..60], [0; 8]);
+    }
+
+    // Counts how it's asked to draw.
+    #[derive(Default)]
+    struct Draws {
+        pixels: usize,
+        frames: Vec<(usize, usize)>,
+    }
+
+    struct Counting(Rc<RefCell<Draws>>);
+
+    impl Display for Counting {
+        fn clear(&mut self, _color: Color) {}
+
+        fn draw_pixel(&mut self, _x: usize, _y: usize, _color: Color) -> Result<(), String> {
+            self.0.borrow_mut().pixels += 1;
+            Ok(())
+        }
+
+        fn draw_frame(&mut self, width: usize, rgb: &[u8]) -> Result<(), String> {
+            self.0.borrow_mut().frames.push((width, rgb.len()));
+            Ok(())
+        }
+
+        fn show(&mut self) {}
+        fn set_title(&mut self, _title: &str) {}
+    }
+
+    #[test]
+    fn draws_whole_frames() {
+        let draws = Rc::new(RefCell::new(Draws::default()));
+        let mut presenter = Presenter::default();
+        let mut ppu = Ppu::new_fake();
+        ppu.screen = Some(presenter.add_window(Box::new(Counting(draws.clone())), PIXEL_WIDTH));
+        let mut interrupt = Interrupt::new();
+        ppu.set_control(0x91);
+        run_to_line(&mut ppu, &mut interrupt, VISIBLE_COUNT);
+        run_to_line(&mut ppu, &mut interrupt, 0);
+        assert!(presenter.present());
+        let draws = draws.borrow();
+        assert_eq!(
+            draws.frames,
+            vec![(PIXEL_WIDTH, PIXEL_WIDTH * PIXEL_HEIGHT * 3)]
+        );
+        assert_eq!(draws.pixels, 0);
+    }
+}
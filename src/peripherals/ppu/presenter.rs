@@ -0,0 +1,302 @@
+//! Gets finished frames from the PPU onto the screen. The PPU copies each frame it wants shown into
+//! a triple buffer and carries on, so it never waits on the display. The `Presenter`, on whichever
+//! thread owns the windows, uploads and presents the newest frame whenever it gets to it, and any
+//! frames it was too slow for are skipped.
+use peripherals::ppu::display::Display;
+use peripherals::ppu::frame_times::{FrameTimeSummary, FrameTimes};
+use peripherals::ppu::sdl_display::SdlDisplay;
+use sdl2;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+struct Slot {
+    // The most recently sent frame, as packed 8-bit RGB.
+    frame: Vec<u8>,
+    // Set until `frame` has been presented.
+    fresh: bool,
+    title: Option<String>,
+    // Set once the sender has been dropped.
+    closed: bool,
+    // How long drawing and showing each frame took.
+    presented: FrameTimes,
+}
+
+struct Shared {
+    slot: Mutex<Slot>,
+    changed: Condvar,
+}
+
+/// The PPU's end of a window.
+pub struct FrameSender {
+    // Filled in with the next frame, then swapped into the slot.
+    back: Vec<u8>,
+    shared: Arc<Shared>,
+}
+
+impl FrameSender {
+    /// Sends the frame `fill` writes, replacing any the presenter hasn't got to yet.
+    pub fn send<F>(&mut self, fill: F)
+    where
+        F: FnOnce(&mut Vec<u8>),
+    {
+        self.back.clear();
+        fill(&mut self.back);
+        let mut slot = self.shared.slot.lock().unwrap();
+        mem::swap(&mut slot.frame, &mut self.back);
+        slot.fresh = true;
+        self.shared.changed.notify_one();
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.shared.slot.lock().unwrap().title = Some(title);
+        self.shared.changed.notify_one();
+    }
+
+    /// How long the presenter has taken to draw and show frames, including any wait for vsync.
+    pub fn presentation_times(&self) -> Option<FrameTimeSummary> {
+        self.shared.slot.lock().unwrap().presented.summary()
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        if let Ok(mut slot) = self.shared.slot.lock() {
+            slot.closed = true;
+        }
+        self.shared.changed.notify_one();
+    }
+}
+
+struct Window {
+    shared: Arc<Shared>,
+    // The frame on screen.
+    front: Vec<u8>,
+    display: Box<dyn Display>,
+    width: usize,
+}
+
+impl Window {
+    // Shows the newest frame, if there's one that hasn't been shown. Returns whether the sender
+    // is still around.
+    fn present(&mut self) -> bool {
+        let (fresh, title, closed) = {
+            let mut slot = self.shared.slot.lock().unwrap();
+            let fresh = slot.fresh;
+            if fresh {
+                mem::swap(&mut slot.frame, &mut self.front);
+                slot.fresh = false;
+            }
+            (fresh, slot.title.take(), slot.closed)
+        };
+        if let Some(title) = title {
+            self.display.set_title(&title);
+        }
+        if fresh {
+            let started = Instant::now();
+            if let Err(err) = self.display.draw_frame(self.width, &self.front) {
+                error!("Could not draw frame: {}", err);
+            }
+            self.display.show();
+            self.shared
+                .slot
+                .lock()
+                .unwrap()
+                .presented
+                .record(started.elapsed());
+        }
+        !closed
+    }
+}
+
+/// Owns the windows frames are shown in.
+#[derive(Default)]
+pub struct Presenter {
+    windows: Vec<Window>,
+}
+
+impl Presenter {
+    /// Adds a window showing frames `width` pixels wide, and returns the sender for it.
+    pub fn add_window(&mut self, display: Box<dyn Display>, width: usize) -> FrameSender {
+        let shared = Arc::new(Shared {
+            slot: Mutex::new(Slot {
+                frame: vec![],
+                fresh: false,
+                title: None,
+                closed: false,
+                presented: FrameTimes::new(),
+            }),
+            changed: Condvar::new(),
+        });
+        self.windows.push(Window {
+            shared: shared.clone(),
+            front: vec![],
+            display,
+            width,
+        });
+        FrameSender {
+            back: vec![],
+            shared,
+        }
+    }
+
+    /// Opens a debug window, `scale` screen pixels to each of its pixels.
+    pub fn add_sdl_window(
+        &mut self,
+        video_subsystem: &sdl2::VideoSubsystem,
+        title: &str,
+        width: usize,
+        height: usize,
+        scale: u32,
+    ) -> Result<FrameSender, String> {
+        let display =
+            SdlDisplay::new_debug(video_subsystem, title, width as u32, height as u32, scale)?;
+        Ok(self.add_window(Box::new(display), width))
+    }
+
+    /// Presents whatever's come in since the last call, without waiting. Returns false once all
+    /// the senders have been dropped.
+    pub fn present(&mut self) -> bool {
+        let mut open = false;
+        for window in &mut self.windows {
+            open |= window.present();
+        }
+        open
+    }
+
+    /// Waits up to `timeout` for the first window to get a frame or be closed, then presents
+    /// like `present`.
+    pub fn wait_and_present(&mut self, timeout: Duration) -> bool {
+        if let Some(window) = self.windows.first() {
+            let slot = window.shared.slot.lock().unwrap();
+            if !slot.fresh && slot.title.is_none() && !slot.closed {
+                let _ = window.shared.changed.wait_timeout(slot, timeout);
+            }
+        }
+        self.present()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::ppu::display::Color;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Keeps every frame it's asked to show.
+    struct Recording {
+        frames: Rc<RefCell<Vec<Vec<u8>>>>,
+        frame: Vec<u8>,
+    }
+
+    impl Display for Recording {
+        fn clear(&mut self, _color: Color) {}
+
+        fn draw_pixel(&mut self, _x: usize, _y: usize, _color: Color) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn draw_frame(&mut self, _width: usize, rgb: &[u8]) -> Result<(), String> {
+            self.frame = rgb.to_vec();
+            Ok(())
+        }
+
+        fn show(&mut self) {
+            self.frames.borrow_mut().push(self.frame.clone());
+        }
+
+        fn set_title(&mut self, _title: &str) {}
+    }
+
+    #[test]
+    fn presents_latest_frame() {
+        let frames = Rc::new(RefCell::new(vec![]));
+        let mut presenter = Presenter::default();
+        let mut sender = presenter.add_window(
+            Box::new(Recording {
+                frames: frames.clone(),
+                frame: vec![],
+            }),
+            1,
+        );
+        // The first frame is replaced before the presenter gets to it.
+        sender.send(|rgb| rgb.extend_from_slice(&[1, 1, 1]));
+        sender.send(|rgb| rgb.extend_from_slice(&[2, 2, 2]));
+        assert!(presenter.present());
+        // Nothing new, so nothing's shown.
+        assert!(presenter.present());
+        assert_eq!(*frames.borrow(), vec![vec![2, 2, 2]]);
+
+        // Frames can come from another thread, and the presenter notices when they stop.
+        let emulation = thread::spawn(move || {
+            sender.send(|rgb| rgb.extend_from_slice(&[3, 3, 3]));
+        });
+        emulation.join().unwrap();
+        assert!(!presenter.wait_and_present(Duration::from_secs(1)));
+        assert_eq!(frames.borrow().last(), Some(&vec![3, 3, 3]));
+        // Only the frames that were shown were timed.
+        let sender = FrameSender {
+            back: vec![],
+            shared: presenter.windows[0].shared.clone(),
+        };
+        assert_eq!(sender.presentation_times().unwrap().frames, 2);
+    }
+
+    // Stays in `show` until the emulation thread says it's sent more frames.
+    struct Slow {
+        showing: mpsc::Sender<()>,
+        sent: mpsc::Receiver<()>,
+        sent_while_showing: Rc<Cell<bool>>,
+    }
+
+    impl Display for Slow {
+        fn clear(&mut self, _color: Color) {}
+
+        fn draw_pixel(&mut self, _x: usize, _y: usize, _color: Color) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn draw_frame(&mut self, _width: usize, _rgb: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn show(&mut self) {
+            self.showing.send(()).unwrap();
+            let sent = self.sent.recv_timeout(Duration::from_secs(5)).is_ok();
+            self.sent_while_showing.set(sent);
+        }
+
+        fn set_title(&mut self, _title: &str) {}
+    }
+
+    #[test]
+    fn sending_never_waits_for_presentation() {
+        let (showing_tx, showing_rx) = mpsc::channel();
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let sent_while_showing = Rc::new(Cell::new(false));
+        let mut presenter = Presenter::default();
+        let mut sender = presenter.add_window(
+            Box::new(Slow {
+                showing: showing_tx,
+                sent: sent_rx,
+                sent_while_showing: sent_while_showing.clone(),
+            }),
+            1,
+        );
+        sender.send(|rgb| rgb.push(0));
+        let emulation = thread::spawn(move || {
+            showing_rx.recv().unwrap();
+            for i in 1..10 {
+                sender.send(|rgb| rgb.push(i));
+            }
+            sent_tx.send(()).unwrap();
+            sender
+        });
+        assert!(presenter.present());
+        assert!(sent_while_showing.get());
+        drop(emulation.join().unwrap());
+    }
+}
@@ -0,0 +1,123 @@
+//! Records frames coming out of the PPU, either as an animated GIF, or as a raw stream of RGB24
+//! frames (which can be turned into a video with, e.g.,
+//! `ffmpeg -f rawvideo -pix_fmt rgb24 -s 160x144 -r 59.73 -i frames.rgb out.mp4`).
+// TODO(slongfield): Capture audio alongside the frames.
+use gif;
+use gif::SetParameter;
+use peripherals::ppu::Colors;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// A frame is 70224 cycles at 4.194304MHz, or about 1.674 hundredths of a second.
+const FRAME_CYCLES: u64 = 70_224;
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+enum Sink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Raw(BufWriter<File>),
+}
+
+pub struct Recorder {
+    sink: Sink,
+    colors: Colors,
+    width: u16,
+    height: u16,
+    frames: u32,
+    // GIF delays are in whole centiseconds, so track how much has been written out, to keep the
+    // GIF from drifting from the time that's actually passed.
+    written: u64,
+}
+
+impl Recorder {
+    /// Starts a recording. Paths ending in `.gif` are written as GIFs, anything else is written
+    /// as raw RGB24 frames.
+    pub fn new(
+        path: &Path,
+        width: usize,
+        height: usize,
+        colors: Colors,
+    ) -> Result<Self, io::Error> {
+        let file = BufWriter::new(File::create(path)?);
+        let sink = if path.extension() == Some(OsStr::new("gif")) {
+            let mut palette = vec![];
+            for shade in 0..4 {
+                let (r, g, b) = colors.rgb(shade);
+                palette.extend_from_slice(&[r, g, b]);
+            }
+            let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &palette)?;
+            encoder.set(gif::Repeat::Infinite)?;
+            Sink::Gif(encoder)
+        } else {
+            Sink::Raw(file)
+        };
+        info!("Started recording to {:?}", path);
+        Ok(Self {
+            sink,
+            colors,
+            width: width as u16,
+            height: height as u16,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    /// Adds a frame of shades (0-3) to the recording.
+    pub fn add_frame(&mut self, shades: &[u8]) -> Result<(), io::Error> {
+        self.frames += 1;
+        match self.sink {
+            Sink::Gif(ref mut encoder) => {
+                // Most GIF viewers treat delays under 2cs as "as slow as possible", so only every
+                // other frame is kept.
+                if self.frames % 2 == 1 {
+                    return Ok(());
+                }
+                let delay = gif_delay(self.frames, &mut self.written);
+                let mut frame =
+                    gif::Frame::from_indexed_pixels(self.width, self.height, shades, None);
+                frame.delay = delay;
+                encoder.write_frame(&frame)
+            }
+            Sink::Raw(ref mut file) => {
+                for shade in shades {
+                    let (r, g, b) = self.colors.rgb(*shade);
+                    file.write_all(&[r, g, b])?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+// The delay for the frame that takes the recording to `frames` long, in centiseconds, given that
+// `written` have been written out already.
+fn gif_delay(frames: u32, written: &mut u64) -> u16 {
+    let elapsed = u64::from(frames) * FRAME_CYCLES * 100 / CYCLES_PER_SECOND;
+    // Only each frame's delay has to fit in the GIF's 16 bits, not the total.
+    let delay = elapsed.saturating_sub(*written).min(u64::from(u16::MAX)) as u16;
+    *written += u64::from(delay);
+    delay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gif_delays_keep_up_with_long_recordings() {
+        let mut written = 0;
+        let mut total = 0;
+        // About an hour, well past where 16 bits of centiseconds runs out.
+        for frames in (2..=215_000).step_by(2) {
+            let delay = gif_delay(frames, &mut written);
+            assert!(delay == 3 || delay == 4);
+            total += u64::from(delay);
+        }
+        assert_eq!(total, 215_000 * FRAME_CYCLES * 100 / CYCLES_PER_SECOND);
+    }
+}
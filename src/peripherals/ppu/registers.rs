@@ -0,0 +1,67 @@
+//! The PPU's I/O registers, from LCDC at 0xFF40 to WX at 0xFF4B.
+use super::{Palette, Ppu};
+use peripherals::io_map::IoRegister;
+
+fn read_palette(palette: &Palette) -> u8 {
+    palette.color3() << 6 | palette.color2() << 4 | palette.color1() << 2 | palette.color0()
+}
+
+fn write_palette(palette: &mut Palette, val: u8) {
+    palette.set_color3(val >> 6);
+    palette.set_color2((val >> 4) & 0x3);
+    palette.set_color1((val >> 2) & 0x3);
+    palette.set_color0(val & 0x3);
+}
+
+impl IoRegister for Ppu {
+    fn read8(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.control.bits(),
+            // Bit 7 of STAT doesn't exist, and the bottom three bits are read-only.
+            0xFF41 => {
+                let status = &self.status;
+                0x80 | status.lyc_interrupt() << 6
+                    | status.mode2_interrupt() << 5
+                    | status.mode1_interrupt() << 4
+                    | status.mode0_interrupt() << 3
+                    | (self.lcd_y_compare() & 0x1) << 2
+                    | status.mode()
+            }
+            0xFF42 => self.scroll_y(),
+            0xFF43 => self.scroll_x(),
+            0xFF44 => self.lcd_y(),
+            0xFF45 => self.lcd_y_compare(),
+            0xFF46 => self.dma(),
+            0xFF47 => read_palette(&self.bg_palette),
+            0xFF48 => read_palette(&self.obj0_palette),
+            0xFF49 => read_palette(&self.obj1_palette),
+            0xFF4A => self.window_y(),
+            0xFF4B => self.window_x(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        match address {
+            0xFF40 => self.set_control(val),
+            0xFF41 => {
+                let status = &mut self.status;
+                status.set_lyc_interrupt((val >> 6) & 0x1);
+                status.set_mode2_interrupt((val >> 5) & 0x1);
+                status.set_mode1_interrupt((val >> 4) & 0x1);
+                status.set_mode0_interrupt((val >> 3) & 0x1);
+            }
+            0xFF42 => self.set_scroll_y(val),
+            0xFF43 => self.set_scroll_x(val),
+            0xFF44 => self.set_lcd_y(val),
+            0xFF45 => self.set_lcd_y_compare(val),
+            0xFF46 => self.set_dma(val),
+            0xFF47 => write_palette(&mut self.bg_palette, val),
+            0xFF48 => write_palette(&mut self.obj0_palette, val),
+            0xFF49 => write_palette(&mut self.obj1_palette, val),
+            0xFF4A => self.set_window_y(val),
+            0xFF4B => self.set_window_x(val),
+            _ => {}
+        }
+    }
+}
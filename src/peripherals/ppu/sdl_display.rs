@@ -9,18 +9,78 @@ const MAX_Y: u32 = 576;
 // Should 'Display' trait actaully be 'Window'?
 pub struct SdlDisplay {
     canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    // Frames are streamed through this, rather than filling a rectangle per pixel. It's created
+    // along with the window, and destroyed before it.
+    texture: Option<sdl2::render::Texture>,
+    // Size of each emulated pixel, in screen pixels.
+    scale: u32,
 }
 
 impl SdlDisplay {
-    pub fn new(video_subsystem: sdl2::VideoSubsystem) -> Self {
+    pub fn new(video_subsystem: sdl2::VideoSubsystem, vsync: bool) -> Result<Self, String> {
         let window = video_subsystem
             .window("Wolfwig Gameboy Emulator", MAX_X, MAX_Y)
             .position_centered()
             .build()
-            .unwrap();
+            .map_err(|err| err.to_string())?;
 
-        Self {
-            canvas: window.into_canvas().build().unwrap(),
+        let canvas = if vsync {
+            window.into_canvas().present_vsync().build()
+        } else {
+            window.into_canvas().build()
+        };
+        let canvas = canvas.map_err(|err| err.to_string())?;
+
+        Self::with_texture(canvas, 4)
+    }
+
+    /// Creates an extra window for debug views. These never vsync, so that they can't slow down
+    /// the main window.
+    pub fn new_debug(
+        video_subsystem: &sdl2::VideoSubsystem,
+        title: &str,
+        width: u32,
+        height: u32,
+        scale: u32,
+    ) -> Result<Self, String> {
+        let window = video_subsystem
+            .window(title, width * scale, height * scale)
+            .build()
+            .map_err(|err| err.to_string())?;
+        let canvas = window
+            .into_canvas()
+            .build()
+            .map_err(|err| err.to_string())?;
+        Self::with_texture(canvas, scale)
+    }
+
+    // Sizes the frame texture to fill the window at `scale`.
+    fn with_texture(
+        canvas: sdl2::render::Canvas<sdl2::video::Window>,
+        scale: u32,
+    ) -> Result<Self, String> {
+        let (width, height) = canvas.window().size();
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(
+                pixels::PixelFormatEnum::RGB24,
+                width / scale,
+                height / scale,
+            )
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            canvas,
+            texture: Some(texture),
+            scale,
+        })
+    }
+}
+
+impl Drop for SdlDisplay {
+    fn drop(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            // The canvas's renderer is still alive, since fields are dropped after this.
+            unsafe { texture.destroy() };
         }
     }
 }
@@ -41,11 +101,37 @@ impl display::Display for SdlDisplay {
         } else {
             self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         }
-        self.canvas
-            .fill_rect(rect::Rect::new((x * 4) as i32, (y * 4) as i32, 4, 4))
+        let scale = self.scale as usize;
+        self.canvas.fill_rect(rect::Rect::new(
+            (x * scale) as i32,
+            (y * scale) as i32,
+            self.scale,
+            self.scale,
+        ))
+    }
+
+    fn draw_frame(&mut self, width: usize, rgb: &[u8]) -> Result<(), String> {
+        let height = rgb.len() / 3 / width;
+        let texture = self
+            .texture
+            .as_mut()
+            .expect("Texture is only taken on drop");
+        let target = rect::Rect::new(0, 0, width as u32, height as u32);
+        texture
+            .update(Some(target), rgb, width * 3)
+            .map_err(|err| err.to_string())?;
+        let scaled = rect::Rect::new(0, 0, width as u32 * self.scale, height as u32 * self.scale);
+        self.canvas.copy(texture, Some(target), Some(scaled))
     }
 
     fn show(&mut self) {
         self.canvas.present();
     }
+
+    fn set_title(&mut self, title: &str) {
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .expect("Window title contained a NUL");
+    }
 }
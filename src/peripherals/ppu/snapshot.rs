@@ -0,0 +1,88 @@
+//! Save state support for the PPU.
+use super::{Palette, Ppu, OAM_DOTS, OAM_MODE, RENDER_MODE};
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
+
+fn save_palette(palette: &Palette, w: &mut Writer) {
+    w.u8(palette.color0);
+    w.u8(palette.color1);
+    w.u8(palette.color2);
+    w.u8(palette.color3);
+}
+
+fn load_palette(palette: &mut Palette, r: &mut Reader) -> io::Result<()> {
+    palette.color0 = r.u8()?;
+    palette.color1 = r.u8()?;
+    palette.color2 = r.u8()?;
+    palette.color3 = r.u8()?;
+    Ok(())
+}
+
+impl Snapshot for Ppu {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        w.u8(self.control.bits());
+        w.bool(self.status.lyc_interrupt);
+        w.bool(self.status.mode2_interrupt);
+        w.bool(self.status.mode1_interrupt);
+        w.bool(self.status.mode0_interrupt);
+        w.u8(self.status.mode);
+        w.u8(self.scroll_x);
+        w.u8(self.scroll_y);
+        w.u8(self.window_x);
+        w.u8(self.window_y);
+        w.bool(self.window_y_reached);
+        w.u8(self.window_line);
+        w.u8(self.lcd_y);
+        w.u8(self.lcd_y_compare);
+        save_palette(&self.bg_palette, w);
+        save_palette(&self.obj0_palette, w);
+        save_palette(&self.obj1_palette, w);
+        w.u16(self.line_dot);
+        w.bool(self.dma.enabled);
+        w.u16(self.dma.source);
+        w.u16(self.dma.dest);
+        w.u8(self.dma_page);
+        w.u32(self.frame);
+        w.bytes(&self.framebuffer);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.bytes_into(&mut self.vram)?;
+        self.tiles.reload(&self.vram);
+        r.bytes_into(&mut self.oam)?;
+        self.control.set_control(r.u8()?);
+        self.status.lyc_interrupt = r.bool()?;
+        self.status.mode2_interrupt = r.bool()?;
+        self.status.mode1_interrupt = r.bool()?;
+        self.status.mode0_interrupt = r.bool()?;
+        self.status.mode = r.u8()? & 0x3;
+        self.scroll_x = r.u8()?;
+        self.scroll_y = r.u8()?;
+        self.window_x = r.u8()?;
+        self.window_y = r.u8()?;
+        self.window_y_reached = r.bool()?;
+        self.window_line = r.u8()?;
+        self.lcd_y = r.u8()?;
+        self.lcd_y_compare = r.u8()?;
+        load_palette(&mut self.bg_palette, r)?;
+        load_palette(&mut self.obj0_palette, r)?;
+        load_palette(&mut self.obj1_palette, r)?;
+        self.line_dot = r.u16()? % super::LINE_DOTS;
+        self.dma.enabled = r.bool()?;
+        self.dma.source = r.u16()?;
+        self.dma.dest = r.u16()?;
+        self.dma_page = r.u8()?;
+        self.frame = r.u32()?;
+        r.bytes_into(&mut self.framebuffer)?;
+        // The sprites for the current line are found at the start of OAM mode, and used at the
+        // start of rendering. They aren't saved, so find them again if that's still to come.
+        if self.status.mode == OAM_MODE
+            || (self.status.mode == RENDER_MODE && self.line_dot == OAM_DOTS)
+        {
+            self.scan_sprites();
+        }
+        Ok(())
+    }
+}
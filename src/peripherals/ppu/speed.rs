@@ -0,0 +1,48 @@
+//! Tracks how fast frames are being emulated compared to wall time.
+use std::time::Duration;
+
+// The DMG runs 70224 cycles per frame at 4.194304MHz.
+pub const NATIVE_FPS: f32 = 4_194_304.0 / 70_224.0;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+pub struct SpeedMeter {
+    window_start: Duration,
+    frames: u32,
+    fps: f32,
+}
+
+impl SpeedMeter {
+    pub fn new() -> Self {
+        Self {
+            window_start: Duration::from_secs(0),
+            frames: 0,
+            fps: 0.0,
+        }
+    }
+
+    /// Counts a frame finished at `now`, by the PPU's clock. Returns true once a second, whenever
+    /// a new FPS measurement is available.
+    pub fn tick(&mut self, now: Duration) -> bool {
+        self.frames += 1;
+        let elapsed = now.checked_sub(self.window_start).unwrap_or_default();
+        if elapsed < WINDOW {
+            return false;
+        }
+        let seconds = elapsed.as_secs() as f32 + elapsed.subsec_micros() as f32 / 1_000_000.0;
+        self.fps = self.frames as f32 / seconds;
+        self.frames = 0;
+        self.window_start = now;
+        true
+    }
+
+    /// Frames per second over the last measurement window.
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Emulation speed, as a percentage of the real hardware.
+    pub fn speed(&self) -> f32 {
+        self.fps / NATIVE_FPS * 100.0
+    }
+}
@@ -0,0 +1,75 @@
+//! Tile data decoded to one shade per pixel. VRAM keeps each row of a tile as two bitplanes, and
+//! pulling pixels back out of those for every tile on every line was most of the cost of
+//! rendering. Instead, each write to tile data decodes the row it changed, and rendering just
+//! looks pixels up.
+
+// 0x8000-0x97FF holds 384 tiles, 16 bytes each.
+const TILE_COUNT: usize = 384;
+const TILE_DATA_SIZE: usize = TILE_COUNT * 16;
+
+pub struct TileCache {
+    // Rows of 8 shades, top to bottom, for each tile.
+    pixels: Vec<[u8; 64]>,
+}
+
+impl TileCache {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![[0; 64]; TILE_COUNT],
+        }
+    }
+
+    /// Decodes the row of the tile containing `offset` into VRAM again, after it's been written.
+    /// Writes past the tile data, to the maps, don't change anything.
+    pub fn update(&mut self, vram: &[u8], offset: usize) {
+        if offset >= TILE_DATA_SIZE {
+            return;
+        }
+        let row_start = offset & !1;
+        let high = vram[row_start];
+        let low = vram[row_start + 1];
+        let row = &mut self.pixels[offset / 16][(offset % 16) / 2 * 8..][..8];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let bit = 7 - x;
+            *pixel = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+        }
+    }
+
+    /// Decodes every tile, after all of VRAM has been replaced.
+    pub fn reload(&mut self, vram: &[u8]) {
+        for offset in (0..TILE_DATA_SIZE).step_by(2) {
+            self.update(vram, offset);
+        }
+    }
+
+    /// Shade of pixel (x, y) of the tile whose data starts at `addr` into VRAM.
+    pub fn pixel(&self, addr: usize, x: usize, y: usize) -> u8 {
+        self.pixels[addr / 16][y * 8 + x]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_written_rows() {
+        let mut vram = [0; 0x2000];
+        let mut tiles = TileCache::new();
+        // Tile 2, row 3.
+        vram[0x26] = 0b1100_0000;
+        vram[0x27] = 0b1010_0001;
+        tiles.update(&vram, 0x27);
+        let row: Vec<u8> = (0..8).map(|x| tiles.pixel(0x20, x, 3)).collect();
+        assert_eq!(row, vec![3, 2, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(tiles.pixel(0x20, 0, 2), 0);
+
+        // The maps aren't tile data.
+        vram[0x1800] = 0xFF;
+        tiles.update(&vram, 0x1800);
+        vram[0x26] = 0;
+        vram[0x27] = 0;
+        tiles.reload(&vram);
+        assert!((0..8).all(|x| tiles.pixel(0x20, x, 3) == 0));
+    }
+}
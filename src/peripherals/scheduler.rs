@@ -0,0 +1,106 @@
+//! When peripherals that are usually idle next need stepping. Rather than every peripheral being
+//! stepped every machine cycle, those that often have nothing to do say which cycle they next
+//! do, and `Peripherals::step` leaves them alone until then. Most cycles, that's one comparison.
+//! The PPU and timer aren't scheduled: their registers can be read on any cycle, and reads can't
+//! catch them up.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The serial port has a transfer running, or a link cable to listen to.
+    Serial,
+    /// Time to check whether the audio device wants more samples.
+    Audio,
+    /// Time to pick up input sent from the frontend.
+    Input,
+    /// The cartridge's clock ticks over to the next second.
+    Cartridge,
+    /// A joypad line fell on the last cycle, so its interrupt is requested.
+    Joypad,
+}
+
+const EVENTS: [Event; 5] = [
+    Event::Serial,
+    Event::Audio,
+    Event::Input,
+    Event::Cartridge,
+    Event::Joypad,
+];
+const NEVER: u64 = u64::MAX;
+
+pub struct Scheduler {
+    // Cycle each event is next due on, indexed by `Event`.
+    due: [u64; 5],
+    // The earliest of `due`.
+    next: u64,
+}
+
+impl Scheduler {
+    /// Everything starts out due on `cycle`, so each peripheral gets to say when it's next needed.
+    pub fn new(cycle: u64) -> Self {
+        Self {
+            due: [cycle; 5],
+            next: cycle,
+        }
+    }
+
+    /// Runs `event` on `cycle`, rather than whenever it was scheduled for before.
+    pub fn schedule(&mut self, event: Event, cycle: u64) {
+        self.due[event as usize] = cycle;
+        self.update_next();
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.schedule(event, NEVER);
+    }
+
+    /// Whether anything is due on or before `cycle`.
+    pub fn pending(&self, cycle: u64) -> bool {
+        cycle >= self.next
+    }
+
+    /// Takes an event that's due on or before `cycle`. It won't run again until it's rescheduled.
+    pub fn pop(&mut self, cycle: u64) -> Option<Event> {
+        if !self.pending(cycle) {
+            return None;
+        }
+        let event = EVENTS
+            .iter()
+            .cloned()
+            .find(|&event| self.due[event as usize] <= cycle)?;
+        self.cancel(event);
+        Some(event)
+    }
+
+    fn update_next(&mut self) {
+        self.next = self.due.iter().cloned().min().unwrap_or(NEVER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_due_events() {
+        let mut scheduler = Scheduler::new(0);
+        assert_eq!(scheduler.pop(0), Some(Event::Serial));
+        assert_eq!(scheduler.pop(0), Some(Event::Audio));
+        assert_eq!(scheduler.pop(0), Some(Event::Input));
+        assert_eq!(scheduler.pop(0), Some(Event::Cartridge));
+        assert_eq!(scheduler.pop(0), Some(Event::Joypad));
+        assert_eq!(scheduler.pop(0), None);
+
+        scheduler.schedule(Event::Audio, 10);
+        scheduler.schedule(Event::Serial, 5);
+        assert!(!scheduler.pending(4));
+        assert_eq!(scheduler.pop(4), None);
+        assert_eq!(scheduler.pop(7), Some(Event::Serial));
+        assert!(!scheduler.pending(9));
+
+        // Rescheduling replaces the old time, even if it's later.
+        scheduler.schedule(Event::Audio, 20);
+        assert!(!scheduler.pending(10));
+        scheduler.cancel(Event::Audio);
+        assert_eq!(scheduler.pop(NEVER - 1), None);
+    }
+}
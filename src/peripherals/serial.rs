@@ -1,50 +1,206 @@
 ///! Model of the serial data peripheral.
+use peripherals::interrupt::Interrupt;
+use peripherals::io_map::IoRegister;
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
 use std::sync::mpsc;
 
+// Cycles per bit with the internal clock, which runs at 8192Hz.
+const CYCLES_PER_BIT: u16 = 128;
+
+/// One end of a link cable between two emulators in the same process. The side with the internal
+/// clock sends its byte when it starts a transfer, and the side with the external clock answers
+/// with its own if it's waiting for one. Both then shift the other's byte in bit by bit.
+pub struct Link {
+    tx: mpsc::Sender<u8>,
+    rx: mpsc::Receiver<u8>,
+}
+
+impl Link {
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (Self { tx: a_tx, rx: a_rx }, Self { tx: b_tx, rx: b_rx })
+    }
+
+    fn send(&self, val: u8) {
+        // This only fails if the other side has gone away, which looks the same as an unplugged
+        // cable.
+        let _ = self.tx.send(val);
+    }
+
+    fn receive(&self) -> Option<u8> {
+        self.rx.try_recv().ok()
+    }
+}
+
 pub struct Serial {
-    // The serial port has a channel connected to it that it sends data along whenever it sees a
+    // The serial port has channels connected to it that it sends data along whenever it sees a
     // serial transfer start. This is an internal detail used for testing--test roms send their
     // status information to both the serial port and to the screen, but testing serial port data
     // is simpler in automated testing.
-    channel: Option<mpsc::Sender<u8>>,
+    channels: Vec<mpsc::Sender<u8>>,
+    // Set while the debugger replays history, so bytes already sent to the channels aren't sent
+    // again.
+    muted: bool,
+    link: Option<Link>,
     start: bool,
+    internal_clock: bool,
     data: u8,
+    // Set once the clock is running for the current transfer. With an external clock, that's not
+    // until the other side starts sending.
+    shifting: bool,
+    bits: u8,
+    countdown: u16,
+    // Byte being shifted in from the other side. Until it's known, or if nothing is connected,
+    // the line reads high so 1s shift in.
+    incoming: Option<u8>,
 }
 
 impl Serial {
     pub fn new(channel: Option<mpsc::Sender<u8>>) -> Self {
         Self {
-            channel,
+            channels: channel.into_iter().collect(),
+            muted: false,
+            link: None,
             start: false,
+            internal_clock: false,
             data: 0,
+            shifting: false,
+            bits: 0,
+            countdown: 0,
+            incoming: None,
         }
     }
 
-    pub fn step(&mut self) {
-        if self.start {
-            if let Some(ref mut sender) = self.channel {
-                // TODO(slongfield): Handle error.
-                sender.send(self.data).unwrap();
+    /// Runs the port for `cycles` machine cycles, which mustn't go past the cycle `next_step`
+    /// asked for. Nothing changes between bits, so that's the same as stepping it cycle by cycle.
+    pub fn step(&mut self, cycles: u64, interrupt: &mut Interrupt) {
+        if !self.start {
+            return;
+        }
+        let mut cycles = cycles;
+        if !self.shifting {
+            // The clock starts now, however long the transfer was waiting for it.
+            cycles = 1;
+            if self.internal_clock {
+                self.begin();
+                if let Some(ref link) = self.link {
+                    link.send(self.data);
+                }
+            } else {
+                // With an external clock, wait for the other side to start clocking.
+                let received = match self.link {
+                    Some(ref link) => link.receive(),
+                    None => None,
+                };
+                match received {
+                    Some(val) => {
+                        if let Some(ref link) = self.link {
+                            link.send(self.data);
+                        }
+                        self.begin();
+                        self.incoming = Some(val);
+                    }
+                    None => return,
+                }
+            }
+        }
+        if self.incoming.is_none() {
+            if let Some(ref link) = self.link {
+                self.incoming = link.receive();
             }
+        }
+        self.countdown -= cycles.min(u64::from(self.countdown)) as u16;
+        if self.countdown == 0 {
+            self.shift(interrupt);
+        }
+    }
+
+    /// Cycles until `step` next has something to do, which is the next bit of a transfer. None
+    /// if nothing happens until a register is written: there's no transfer, or it's waiting for
+    /// an external clock with no cable to bring one. An external clock coming over a cable can
+    /// start at any time, so that's watched for every cycle.
+    pub fn next_step(&self) -> Option<u64> {
+        if !self.start {
+            None
+        } else if self.shifting {
+            Some(u64::from(self.countdown))
+        } else if self.internal_clock || self.link.is_some() {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    fn begin(&mut self) {
+        self.print();
+        self.shifting = true;
+        self.bits = 0;
+        self.countdown = CYCLES_PER_BIT;
+        self.incoming = None;
+    }
+
+    // Shifts out the top bit of the data, and shifts in the next bit from the other side.
+    fn shift(&mut self, interrupt: &mut Interrupt) {
+        let bit = self.incoming.map_or(1, |val| (val >> (7 - self.bits)) & 1);
+        self.data = (self.data << 1) | bit;
+        self.bits += 1;
+        self.countdown = CYCLES_PER_BIT;
+        if self.bits == 8 {
             self.start = false;
-            // TODO(slongfield): Two-way communication. Normally data is shifted in here from the
-            // external source as its shifted out over the course of 8 cycles.
-            self.data = 0;
+            self.shifting = false;
+            interrupt.set_serial_trigger(1);
         }
     }
 
+    fn print(&mut self) {
+        if self.muted {
+            return;
+        }
+        let data = self.data;
+        // Channels whose other end has gone away are dropped.
+        self.channels.retain(|sender| sender.send(data).is_ok());
+    }
+
+    /// Adds a channel that gets every byte sent, alongside any already connected.
     pub fn connect_channel(&mut self, tx: mpsc::Sender<u8>) {
-        self.channel = Some(tx)
+        self.channels.push(tx)
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn connect_link(&mut self, link: Link) {
+        self.link = Some(link)
     }
 
-    pub fn set_start(&mut self, val: bool) {
-        self.start = val;
+    pub fn set_start(&mut self, val: u8) {
+        let was_started = self.start;
+        self.start = val != 0;
+        if !self.start {
+            self.shifting = false;
+        } else if !was_started {
+            // Anything sent while this side wasn't ready is lost, and the other side reads 1s.
+            if let Some(ref link) = self.link {
+                while link.receive().is_some() {}
+            }
+        }
     }
 
     pub fn start(&self) -> bool {
         self.start
     }
 
+    pub fn set_internal_clock(&mut self, val: u8) {
+        self.internal_clock = val != 0;
+    }
+
+    pub fn internal_clock(&self) -> bool {
+        self.internal_clock
+    }
+
     pub fn set_data(&mut self, val: u8) {
         self.data = val;
     }
@@ -54,6 +210,55 @@ impl Serial {
     }
 }
 
+impl IoRegister for Serial {
+    fn read8(&self, address: u16) -> u8 {
+        match address {
+            0xFF01 => self.data(),
+            0xFF02 => 0x7E | u8::from(self.start()) << 7 | u8::from(self.internal_clock()),
+            _ => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        match address {
+            0xFF01 => self.set_data(val),
+            0xFF02 => {
+                self.set_start((val >> 7) & 0x1);
+                self.set_internal_clock(val & 0x1);
+            }
+            _ => {}
+        }
+    }
+}
+
+// The link cable and output channel are connections to the outside, rather than state, so they're
+// left as they are.
+impl Snapshot for Serial {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.start);
+        w.bool(self.internal_clock);
+        w.u8(self.data);
+        w.bool(self.shifting);
+        w.u8(self.bits);
+        w.u16(self.countdown);
+        w.bool(self.incoming.is_some());
+        w.u8(self.incoming.unwrap_or(0));
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.start = r.bool()?;
+        self.internal_clock = r.bool()?;
+        self.data = r.u8()?;
+        self.shifting = r.bool()?;
+        self.bits = r.u8()?;
+        self.countdown = r.u16()?;
+        let has_incoming = r.bool()?;
+        let incoming = r.u8()?;
+        self.incoming = if has_incoming { Some(incoming) } else { None };
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,14 +267,104 @@ mod tests {
     fn basic_serial_write() {
         let (tx, rx) = mpsc::channel();
         let mut serial = Serial::new(Some(tx));
+        let mut interrupt = Interrupt::new();
 
         serial.set_data(0x51);
-        serial.set_start(true);
+        serial.set_internal_clock(1);
+        serial.set_start(1);
 
-        serial.step();
+        for _ in 0..4 * CYCLES_PER_BIT {
+            serial.step(1, &mut interrupt);
+        }
+        // Halfway through, with 1s shifting in since nothing's connected.
+        assert_eq!(serial.data(), 0x1F);
+        assert_eq!(serial.start(), true);
 
-        assert_eq!(serial.data(), 0);
+        for _ in 0..4 * CYCLES_PER_BIT {
+            serial.step(1, &mut interrupt);
+        }
+        assert_eq!(serial.data(), 0xFF);
         assert_eq!(serial.start(), false);
         assert_eq!(rx.recv().unwrap(), 0x51);
     }
+
+    #[test]
+    fn transfer_complete_interrupt() {
+        let mut serial = Serial::new(None);
+        let mut interrupt = Interrupt::new();
+
+        serial.set_internal_clock(1);
+        serial.set_start(1);
+        for _ in 0..8 * CYCLES_PER_BIT - 1 {
+            serial.step(1, &mut interrupt);
+        }
+        assert!(!interrupt.serial_trigger());
+
+        serial.step(1, &mut interrupt);
+        assert!(interrupt.serial_trigger());
+    }
+
+    #[test]
+    fn steps_a_bit_at_a_time() {
+        let mut serial = Serial::new(None);
+        let mut interrupt = Interrupt::new();
+        assert_eq!(serial.next_step(), None);
+
+        serial.set_data(0x51);
+        serial.set_internal_clock(1);
+        serial.set_start(1);
+        assert_eq!(serial.next_step(), Some(1));
+        let mut steps = 0;
+        let mut cycles = 0;
+        while let Some(next) = serial.next_step() {
+            serial.step(next, &mut interrupt);
+            steps += 1;
+            cycles += next;
+        }
+        // One step to start the clock, and one for each bit.
+        assert_eq!(steps, 9);
+        assert_eq!(cycles, 8 * u64::from(CYCLES_PER_BIT));
+        assert_eq!(serial.data(), 0xFF);
+        assert!(interrupt.serial_trigger());
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let mut serial = Serial::new(None);
+        let mut interrupt = Interrupt::new();
+
+        serial.set_data(0x51);
+        serial.set_start(1);
+        for _ in 0..16 * CYCLES_PER_BIT {
+            serial.step(1, &mut interrupt);
+        }
+        assert_eq!(serial.data(), 0x51);
+        assert_eq!(serial.start(), true);
+    }
+
+    #[test]
+    fn linked_transfer() {
+        let (a_link, b_link) = Link::pair();
+        let mut a = Serial::new(None);
+        let mut b = Serial::new(None);
+        a.connect_link(a_link);
+        b.connect_link(b_link);
+        let mut a_interrupt = Interrupt::new();
+        let mut b_interrupt = Interrupt::new();
+
+        a.set_data(0x12);
+        a.set_internal_clock(1);
+        b.set_data(0x34);
+        b.set_start(1);
+        a.set_start(1);
+        for _ in 0..8 * CYCLES_PER_BIT {
+            a.step(1, &mut a_interrupt);
+            b.step(1, &mut b_interrupt);
+        }
+
+        assert_eq!(a.data(), 0x34);
+        assert_eq!(b.data(), 0x12);
+        assert!(!a.start() && !b.start());
+        assert!(a_interrupt.serial_trigger() && b_interrupt.serial_trigger());
+    }
 }
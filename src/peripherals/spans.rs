@@ -0,0 +1,27 @@
+//! Tracing spans for the parts of the machine that run as events, rather than every machine cycle,
+//! so log output can be filtered by where it came from (like `RUST_LOG=[apu]=debug` for only the
+//! APU) and timed. They're made again each frame, under a span for the frame, so the frame's
+//! number shows up alongside everything in it.
+//!
+//! Entering a span costs far more than a machine cycle once logging's on, so the CPU, PPU, and
+//! timer, which step every cycle, don't have spans. Their output can be filtered by module
+//! instead, like `RUST_LOG=wolfwig::peripherals::ppu=trace`, and records the frame as a field
+//! where it's useful.
+use tracing::Span;
+
+pub struct Spans {
+    pub frame: Span,
+    pub apu: Span,
+    pub serial: Span,
+}
+
+impl Spans {
+    pub fn new(frame: u32) -> Self {
+        let frame = info_span!("frame", number = frame);
+        Self {
+            apu: debug_span!(parent: &frame, "apu"),
+            serial: debug_span!(parent: &frame, "serial"),
+            frame,
+        }
+    }
+}
@@ -1,9 +1,24 @@
 use peripherals::interrupt::Interrupt;
+use peripherals::io_map::IoRegister;
+use savestate::{Reader, Snapshot, Writer};
+use std::io;
 
 // Note: This timer is based off of the DMG timer in the Cycle-Accurate GameBoy Docs v 0.0.X by
 // AntonioND. It should accurate represent the bugs in the DMG timer, but not accurately represent
 // the separate set of bugs in the CGB timer.
 // TODO(slongfield): Make a CGB timer, and write a bunch of testroms.
+
+// Where the timer is in reloading TIMA from TMA after it overflows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reload {
+    Idle,
+    // TIMA overflowed this cycle, and reads 0. Next cycle it's reloaded and the interrupt is
+    // requested, unless TIMA is written before then.
+    Pending,
+    // TIMA was reloaded this cycle. Writes to TIMA are lost, and writes to TMA go to TIMA too.
+    Reloading,
+}
+
 #[derive(Debug)]
 pub struct Timer {
     divider: u16,
@@ -11,7 +26,9 @@ pub struct Timer {
     modulo: u8,
     start: bool,
     input_clock: u8,
-    set_counter: bool,
+    reload: Reload,
+    // The timer's input, the selected divider bit ANDed with the enable, as of the last check.
+    // TIMA counts its falling edges.
     prev_increment_bit: bool,
 }
 
@@ -24,48 +41,84 @@ impl Timer {
             start: false,
             input_clock: 0,
             prev_increment_bit: false,
-            set_counter: false,
+            reload: Reload::Idle,
         }
     }
 
     pub fn step(&mut self, interrupt: &mut Interrupt) {
         self.divider = self.divider.wrapping_add(4);
-        if self.set_counter {
-            debug!("Setting off timer interrupt");
-            self.set_counter = false;
-            interrupt.set_timer_trigger(1);
-            self.counter = self.modulo;
-        }
-        if self.start && self.increment_bit_set() && !self.prev_increment_bit {
-            self.counter = self.counter.wrapping_add(1);
-            if self.counter == 0 {
-                self.set_counter = true;
+        match self.reload {
+            Reload::Pending => {
+                debug!("Setting off timer interrupt");
+                interrupt.set_timer_trigger(1);
+                self.counter = self.modulo;
+                self.reload = Reload::Reloading;
             }
+            Reload::Reloading => self.reload = Reload::Idle,
+            Reload::Idle => {}
         }
-        self.prev_increment_bit = self.increment_bit_set();
+        self.check_edge();
         if self.start {
             debug!("{:?}", self);
         }
     }
 
+    /// Resets the divider, as any write to DIV does. If the selected bit was high, that's a
+    /// falling edge, and TIMA increments.
     pub fn set_divider(&mut self) {
         self.divider = 4;
+        self.check_edge();
+    }
+
+    /// Sets the whole internal counter, rather than resetting it like a write to DIV does.
+    pub fn set_internal_divider(&mut self, val: u16) {
+        self.divider = val;
     }
 
     pub fn set_counter(&mut self, val: u8) {
-        self.counter = val;
+        match self.reload {
+            // Writing TIMA before it's reloaded cancels the reload, and the interrupt.
+            Reload::Pending => {
+                self.reload = Reload::Idle;
+                self.counter = val;
+            }
+            Reload::Reloading => {}
+            Reload::Idle => self.counter = val,
+        }
     }
 
     pub fn set_modulo(&mut self, val: u8) {
         self.modulo = val;
+        if self.reload == Reload::Reloading {
+            self.counter = val;
+        }
     }
 
+    /// Stopping the timer while the selected bit is high is a falling edge, and TIMA increments.
+    #[cfg(test)]
     pub fn set_start(&mut self, val: u8) {
         self.start = val != 0;
+        self.check_edge();
     }
 
+    /// Selecting a bit that's low, when the old one was high, is a falling edge too.
+    #[cfg(test)]
     pub fn set_input_clock(&mut self, val: u8) {
         self.input_clock = val & 0x3;
+        self.check_edge();
+    }
+
+    /// Handles a write to TAC. Both fields change at once, so the input is only checked for a
+    /// falling edge against the whole new value, not against one field changed and the other not.
+    pub fn set_control(&mut self, val: u8) {
+        self.start = val & 0x4 != 0;
+        self.input_clock = val & 0x3;
+        self.check_edge();
+    }
+
+    /// The whole internal counter, which DIV is the top byte of.
+    pub fn internal_divider(&self) -> u16 {
+        self.divider
     }
 
     pub fn divider(&self) -> u8 {
@@ -98,6 +151,69 @@ impl Timer {
         };
         self.divider & (1 << bit) != 0
     }
+
+    // Increments TIMA if the timer's input has fallen since the last check.
+    fn check_edge(&mut self) {
+        let input = self.start && self.increment_bit_set();
+        if self.prev_increment_bit && !input {
+            self.counter = self.counter.wrapping_add(1);
+            if self.counter == 0 {
+                self.reload = Reload::Pending;
+            }
+        }
+        self.prev_increment_bit = input;
+    }
+}
+
+impl IoRegister for Timer {
+    fn read8(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => self.divider(),
+            0xFF05 => self.counter(),
+            0xFF06 => self.modulo(),
+            // Only the bottom three bits of TAC exist.
+            0xFF07 => 0xF8 | self.start() << 2 | self.input_clock(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write8(&mut self, address: u16, val: u8) {
+        match address {
+            // Any write resets the divider.
+            0xFF04 => self.set_divider(),
+            0xFF05 => self.set_counter(val),
+            0xFF06 => self.set_modulo(val),
+            0xFF07 => self.set_control(val),
+            _ => {}
+        }
+    }
+}
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.divider);
+        w.u8(self.counter);
+        w.u8(self.modulo);
+        w.bool(self.start);
+        w.u8(self.input_clock);
+        w.u8(self.reload as u8);
+        w.bool(self.prev_increment_bit);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.divider = r.u16()?;
+        self.counter = r.u8()?;
+        self.modulo = r.u8()?;
+        self.start = r.bool()?;
+        self.input_clock = r.u8()?;
+        self.reload = match r.u8()? {
+            1 => Reload::Pending,
+            2 => Reload::Reloading,
+            _ => Reload::Idle,
+        };
+        self.prev_increment_bit = r.bool()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -122,4 +238,124 @@ mod tests {
 
         assert_eq!(timer.counter(), 1);
     }
+
+    #[test]
+    fn div_write_glitch() {
+        let mut timer = Timer::new();
+        let mut irq = Interrupt::new();
+        timer.set_input_clock(0);
+        timer.set_start(1);
+        timer.set_divider();
+
+        // Bit 10 of the divider is low, so resetting it doesn't count.
+        for _ in 0..200 {
+            timer.step(&mut irq);
+        }
+        timer.set_divider();
+        assert_eq!(timer.counter(), 0);
+
+        // Now it's high, and resetting it is a falling edge.
+        for _ in 0..300 {
+            timer.step(&mut irq);
+        }
+        timer.set_divider();
+        assert_eq!(timer.counter(), 1);
+
+        // Resetting it again doesn't count twice.
+        timer.set_divider();
+        assert_eq!(timer.counter(), 1);
+    }
+
+    #[test]
+    fn tac_write_glitch() {
+        let mut timer = Timer::new();
+        let mut irq = Interrupt::new();
+        timer.set_input_clock(0);
+        timer.set_start(1);
+        // Bit 10 is high, bit 4 is low, and bit 6 is high.
+        timer.set_internal_divider(0x0440);
+        timer.step(&mut irq);
+        assert_eq!(timer.counter(), 0);
+
+        // Switching from bit 10 to bit 6 keeps the input high.
+        timer.set_input_clock(2);
+        assert_eq!(timer.counter(), 0);
+        // Switching to bit 4 drops it.
+        timer.set_input_clock(1);
+        assert_eq!(timer.counter(), 1);
+
+        // So does stopping the timer, but starting it doesn't count.
+        timer.set_input_clock(0);
+        timer.set_start(0);
+        assert_eq!(timer.counter(), 2);
+        timer.set_start(1);
+        assert_eq!(timer.counter(), 2);
+    }
+
+    #[test]
+    fn tac_write_changing_both_fields() {
+        let mut timer = Timer::new();
+        // Bit 10 is high and bit 4 is low, so the timer's input is low before and after starting
+        // it on bit 4. Starting it on bit 10 and then switching to bit 4 would be a falling edge.
+        timer.set_internal_divider(0x0400);
+        timer.write8(0xFF07, 0x00);
+        timer.write8(0xFF07, 0x05);
+        assert_eq!(timer.counter(), 0);
+        assert_eq!(timer.read8(0xFF07), 0xFD);
+
+        // Stopping it while switching back to bit 10 still counts, as its input was high.
+        timer.set_internal_divider(0x0410);
+        timer.write8(0xFF07, 0x05);
+        timer.write8(0xFF07, 0x00);
+        assert_eq!(timer.counter(), 1);
+    }
+
+    // Runs the timer at its fastest, every 4 machine cycles, to just before TIMA overflows.
+    fn about_to_overflow() -> (Timer, Interrupt) {
+        let mut timer = Timer::new();
+        let mut irq = Interrupt::new();
+        irq.set_timer_enable(1);
+        timer.set_modulo(0x80);
+        timer.set_input_clock(1);
+        timer.set_start(1);
+        timer.set_counter(0xFF);
+        while timer.counter() == 0xFF {
+            timer.step(&mut irq);
+        }
+        (timer, irq)
+    }
+
+    #[test]
+    fn reload_after_overflow() {
+        let (mut timer, mut irq) = about_to_overflow();
+        // TIMA reads 0 for a cycle before it's reloaded.
+        assert_eq!(timer.counter(), 0);
+        assert!(irq.get_interrupt_pc().is_none());
+        timer.step(&mut irq);
+        assert_eq!(timer.counter(), 0x80);
+        assert_eq!(irq.get_interrupt_pc(), Some(0x50));
+    }
+
+    #[test]
+    fn write_cancels_reload() {
+        let (mut timer, mut irq) = about_to_overflow();
+        timer.set_counter(0x12);
+        timer.step(&mut irq);
+        assert_eq!(timer.counter(), 0x12);
+        assert!(irq.get_interrupt_pc().is_none());
+    }
+
+    #[test]
+    fn writes_while_reloading() {
+        let (mut timer, mut irq) = about_to_overflow();
+        timer.step(&mut irq);
+        // TMA wins over TIMA on the cycle it's reloaded, and writes to it go straight through.
+        timer.set_counter(0x12);
+        assert_eq!(timer.counter(), 0x80);
+        timer.set_modulo(0x34);
+        assert_eq!(timer.counter(), 0x34);
+        timer.step(&mut irq);
+        timer.set_counter(0x56);
+        assert_eq!(timer.counter(), 0x56);
+    }
 }
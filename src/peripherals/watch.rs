@@ -0,0 +1,198 @@
+//! Hooks for observing memory accesses, used by the debugger for watchpoints, and by anything
+//! else that wants to see accesses to a range of addresses.
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// An access to a watched address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchHit {
+    pub access: Access,
+    pub address: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "Read 0x{:02X} from 0x{:04X}", self.value, self.address),
+            Access::Write => write!(f, "Wrote 0x{:02X} to 0x{:04X}", self.value, self.address),
+        }
+    }
+}
+
+/// Called with the address and value of each access in the range it was added for.
+pub type Hook = Box<dyn FnMut(u16, u8) + Send>;
+
+/// Identifies a hook, so that it can be removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HookId(u32);
+
+struct RangeHook {
+    id: HookId,
+    range: RangeInclusive<u16>,
+    hook: Hook,
+}
+
+#[derive(Default)]
+pub struct Watch {
+    reads: HashSet<u16>,
+    writes: HashSet<u16>,
+    // Reads go through a shared reference, so the hit and read hooks have to be interior-mutable.
+    hit: Cell<Option<WatchHit>>,
+    read_hooks: RefCell<Vec<RangeHook>>,
+    write_hooks: Vec<RangeHook>,
+    next_hook: u32,
+}
+
+impl Watch {
+    pub fn watch(&mut self, access: Access, address: u16) {
+        match access {
+            Access::Read => self.reads.insert(address),
+            Access::Write => self.writes.insert(address),
+        };
+    }
+
+    pub fn unwatch(&mut self, address: u16) {
+        self.reads.remove(&address);
+        self.writes.remove(&address);
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "Reads: {:?}\nWrites: {:?}",
+            self.reads
+                .iter()
+                .map(|a| format!("0x{:04X}", a))
+                .collect::<Vec<_>>(),
+            self.writes
+                .iter()
+                .map(|a| format!("0x{:04X}", a))
+                .collect::<Vec<_>>()
+        )
+    }
+
+    pub fn add_hook(&mut self, access: Access, range: RangeInclusive<u16>, hook: Hook) -> HookId {
+        let id = HookId(self.next_hook);
+        self.next_hook += 1;
+        let hook = RangeHook { id, range, hook };
+        match access {
+            Access::Read => self.read_hooks.get_mut().push(hook),
+            Access::Write => self.write_hooks.push(hook),
+        }
+        id
+    }
+
+    /// Returns whether there was a hook to remove.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let before = self.read_hooks.get_mut().len() + self.write_hooks.len();
+        self.read_hooks.get_mut().retain(|hook| hook.id != id);
+        self.write_hooks.retain(|hook| hook.id != id);
+        self.read_hooks.get_mut().len() + self.write_hooks.len() != before
+    }
+
+    pub fn read(&self, address: u16, value: u8) {
+        for hook in self.read_hooks.borrow_mut().iter_mut() {
+            if hook.range.contains(&address) {
+                (hook.hook)(address, value);
+            }
+        }
+        if self.reads.contains(&address) {
+            self.hit.set(Some(WatchHit {
+                access: Access::Read,
+                address,
+                value,
+            }));
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        for hook in &mut self.write_hooks {
+            if hook.range.contains(&address) {
+                (hook.hook)(address, value);
+            }
+        }
+        if self.writes.contains(&address) {
+            self.hit.set(Some(WatchHit {
+                access: Access::Write,
+                address,
+                value,
+            }));
+        }
+    }
+
+    /// The most recent access to a watched address since this was last called.
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn watch_hits() {
+        let mut watch = Watch::default();
+        watch.watch(Access::Write, 0xC000);
+        watch.watch(Access::Read, 0xFF44);
+
+        watch.read(0xC000, 1);
+        watch.write(0xFF44, 2);
+        assert_eq!(watch.take_hit(), None);
+
+        watch.write(0xC000, 3);
+        assert_eq!(
+            watch.take_hit(),
+            Some(WatchHit {
+                access: Access::Write,
+                address: 0xC000,
+                value: 3
+            })
+        );
+        assert_eq!(watch.take_hit(), None);
+
+        watch.unwatch(0xFF44);
+        watch.read(0xFF44, 4);
+        assert_eq!(watch.take_hit(), None);
+    }
+
+    #[test]
+    fn hooks() {
+        let mut watch = Watch::default();
+        let (tx, rx) = mpsc::channel();
+        let reads = tx.clone();
+        let read = watch.add_hook(
+            Access::Read,
+            0xC000..=0xC0FF,
+            Box::new(move |address, value| reads.send((Access::Read, address, value)).unwrap()),
+        );
+        watch.add_hook(
+            Access::Write,
+            0xFF00..=0xFF00,
+            Box::new(move |address, value| tx.send((Access::Write, address, value)).unwrap()),
+        );
+
+        watch.read(0xC010, 1);
+        watch.read(0xC100, 2);
+        watch.write(0xC010, 3);
+        watch.write(0xFF00, 4);
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![(Access::Read, 0xC010, 1), (Access::Write, 0xFF00, 4)]
+        );
+
+        assert!(watch.remove_hook(read));
+        assert!(!watch.remove_hook(read));
+        watch.read(0xC010, 5);
+        assert_eq!(rx.try_iter().count(), 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Replay verification. While replaying an input movie, a hash of the emulator state is taken
+//! every `INTERVAL` frames and either written out, or checked against hashes written out by an
+//! earlier run. Since replays are deterministic, any difference means that emulation changed.
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Number of frames between state hashes.
+pub const INTERVAL: u32 = 60;
+
+/// A frame where the state didn't match what was recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    pub frame: u32,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+enum Mode {
+    Record(BufWriter<File>),
+    // Recorded (frame, hash) pairs, in frame order, and the index of the next one to check.
+    Verify(Vec<(u32, u64)>, usize),
+}
+
+pub struct StateHashes {
+    mode: Mode,
+}
+
+impl StateHashes {
+    pub fn record(path: &Path) -> Result<Self, io::Error> {
+        Ok(Self {
+            mode: Mode::Record(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Loads hashes written by `record`. The file has one "frame hash" pair per line, with the
+    /// hash in hex.
+    pub fn verify(path: &Path) -> Result<Self, io::Error> {
+        let mut hashes = vec![];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let parsed = match (fields.next(), fields.next()) {
+                (Some(frame), Some(hash)) => frame
+                    .parse::<u32>()
+                    .ok()
+                    .and_then(|frame| u64::from_str_radix(hash, 16).ok().map(|hash| (frame, hash))),
+                _ => None,
+            };
+            match parsed {
+                Some(entry) => hashes.push(entry),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Could not parse state hash line {:?}", line),
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            mode: Mode::Verify(hashes, 0),
+        })
+    }
+
+    /// Whether a hash should be taken at this frame.
+    pub fn due(frame: u32) -> bool {
+        frame != 0 && frame.is_multiple_of(INTERVAL)
+    }
+
+    /// Records or checks the hash for `frame`.
+    pub fn check(&mut self, frame: u32, hash: u64) -> Result<Option<Divergence>, io::Error> {
+        match self.mode {
+            Mode::Record(ref mut file) => {
+                writeln!(file, "{} {:016x}", frame, hash)?;
+                Ok(None)
+            }
+            Mode::Verify(ref hashes, ref mut next) => {
+                while *next < hashes.len() && hashes[*next].0 < frame {
+                    *next += 1;
+                }
+                match hashes.get(*next) {
+                    Some(&(recorded, expected)) if recorded == frame && expected != hash => {
+                        Ok(Some(Divergence {
+                            frame,
+                            expected,
+                            actual: hash,
+                        }))
+                    }
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Whether every recorded hash has been checked.
+    pub fn finished(&self) -> bool {
+        match self.mode {
+            Mode::Record(_) => false,
+            Mode::Verify(ref hashes, next) => next >= hashes.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn detects_divergence() {
+        let path = env::temp_dir().join(format!("wolfwig_state_hashes_{}.txt", process::id()));
+        {
+            let mut hashes = StateHashes::record(&path).unwrap();
+            hashes.check(60, 0xAAAA).unwrap();
+            hashes.check(120, 0xBBBB).unwrap();
+        }
+        let mut hashes = StateHashes::verify(&path).unwrap();
+        assert_eq!(hashes.check(60, 0xAAAA).unwrap(), None);
+        assert_eq!(
+            hashes.check(120, 0xCCCC).unwrap(),
+            Some(Divergence {
+                frame: 120,
+                expected: 0xBBBB,
+                actual: 0xCCCC,
+            })
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Save states. Each part of the machine writes its state into a flat little-endian byte buffer
+//! in a fixed order, and reads it back in the same order. The file starts with a header that ties
+//! it to a format version and to the ROM it was saved from, and says when it was saved.
+use std::io;
+
+const MAGIC: &[u8; 4] = b"WWSS";
+const VERSION: u32 = 19;
+// Magic, version, ROM hash, frame, and time.
+const HEADER_BYTES: usize = 28;
+
+/// When a save state was made, from its header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metadata {
+    /// The frame it was saved on.
+    pub frame: u32,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+/// Implemented by everything that holds emulated state.
+pub trait Snapshot {
+    fn save(&self, w: &mut Writer);
+    fn load(&mut self, r: &mut Reader) -> io::Result<()>;
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    /// Starts a save state for the ROM with the given hash.
+    pub fn new(rom_hash: u64, metadata: Metadata) -> Self {
+        let mut w = Self::default();
+        w.buf.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.u64(rom_hash);
+        w.u32(metadata.frame);
+        w.u64(metadata.saved_at);
+        w
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val as u8);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Writes a length-prefixed run of bytes.
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.u32(val.len() as u32);
+        self.buf.extend_from_slice(val);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the header of a save state, and returns a reader for the rest of it.
+    pub fn new(buf: &'a [u8], rom_hash: u64) -> io::Result<Self> {
+        let (r, hash, _) = Self::header(buf)?;
+        if hash != rom_hash {
+            return Err(invalid(format!(
+                "Save state is for ROM {:016x}, but {:016x} is loaded",
+                hash, rom_hash
+            )));
+        }
+        Ok(r)
+    }
+
+    /// Reads when a save state was made, without checking which ROM it's for.
+    pub fn metadata(buf: &[u8]) -> io::Result<Metadata> {
+        Reader::header(buf).map(|(_, _, metadata)| metadata)
+    }
+
+    // Returns the ROM hash and metadata, and a reader positioned after them.
+    fn header(buf: &'a [u8]) -> io::Result<(Self, u64, Metadata)> {
+        if buf.len() < HEADER_BYTES || &buf[0..4] != MAGIC {
+            return Err(invalid("Not a save state".to_string()));
+        }
+        let mut r = Self { buf: &buf[4..] };
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(invalid(format!(
+                "Save state is version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        let hash = r.u64()?;
+        let metadata = Metadata {
+            frame: r.u32()?,
+            saved_at: r.u64()?,
+        };
+        Ok((r, hash, metadata))
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Save state is truncated",
+            ));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads a length-prefixed run of bytes.
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a run of bytes into a buffer, which has to be the same size as what was saved.
+    pub fn bytes_into(&mut self, dest: &mut [u8]) -> io::Result<()> {
+        let bytes = self.bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(invalid(format!(
+                "Expected {} bytes in save state, found {}",
+                dest.len(),
+                bytes.len()
+            )));
+        }
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Errors if anything is left over, which means the state was saved by something else.
+    pub fn finish(&self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "{} unexpected bytes at end of save state",
+                self.buf.len()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripherals::Peripherals;
+
+    #[test]
+    fn round_trip() {
+        let metadata = Metadata {
+            frame: 42,
+            saved_at: 1_500_000_000,
+        };
+        let mut w = Writer::new(0x1234, metadata);
+        w.u8(1);
+        w.bool(true);
+        w.u16(0x0203);
+        w.u32(0x0405_0607);
+        w.u64(0x0809_0A0B_0C0D_0E0F);
+        w.bytes(&[1, 2, 3]);
+        let bytes = w.into_bytes();
+
+        let mut r = Reader::new(&bytes, 0x1234).unwrap();
+        assert_eq!(r.u8().unwrap(), 1);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0x0203);
+        assert_eq!(r.u32().unwrap(), 0x0405_0607);
+        assert_eq!(r.u64().unwrap(), 0x0809_0A0B_0C0D_0E0F);
+        let mut dest = [0; 3];
+        r.bytes_into(&mut dest).unwrap();
+        assert_eq!(dest, [1, 2, 3]);
+        r.finish().unwrap();
+        assert!(r.u8().is_err());
+
+        assert!(Reader::new(&bytes, 0x4321).is_err());
+        assert!(Reader::new(&bytes[..8], 0x1234).is_err());
+        // The metadata can be read whichever ROM is loaded.
+        assert_eq!(Reader::metadata(&bytes).unwrap(), metadata);
+        assert!(Reader::metadata(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn peripherals_round_trip() {
+        let mut mem = Peripherals::new_fake();
+        mem.write(0xC000, 0x12);
+        mem.write(0xFF80, 0x34);
+        mem.write(0xFF43, 0x56);
+        mem.write(0xFF06, 0x78);
+        let mut w = Writer::new(
+            mem.rom_hash(),
+            Metadata {
+                frame: 0,
+                saved_at: 0,
+            },
+        );
+        mem.save(&mut w);
+        let bytes = w.into_bytes();
+
+        for &addr in &[0xC000, 0xFF80, 0xFF43, 0xFF06] {
+            mem.write(addr, 0);
+        }
+        let mut r = Reader::new(&bytes, mem.rom_hash()).unwrap();
+        mem.load(&mut r).unwrap();
+        r.finish().unwrap();
+        assert_eq!(mem.read(0xC000), 0x12);
+        assert_eq!(mem.read(0xFF80), 0x34);
+        assert_eq!(mem.read(0xFF43), 0x56);
+        assert_eq!(mem.read(0xFF06), 0x78);
+    }
+}
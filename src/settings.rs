@@ -0,0 +1,119 @@
+//! Settings kept for one game, which are applied whenever it's opened. They're stored in the save
+//! directory, in a file named after the game's title and global checksum, with a `key = value`
+//! setting on each line:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! colors = gray
+//! speed = 2
+//! cheat = 01FF00C0
+//! cheat = 00A-17B-C49
+//! ```
+//!
+//! `speed` is a multiplier, where 0 runs as fast as possible. Each `cheat` line turns on a
+//! GameShark or Game Genie code. Settings that aren't in the file are left as they are.
+use peripherals::Colors;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use Speed;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GameSettings {
+    pub colors: Option<Colors>,
+    pub speed: Option<Speed>,
+    pub cheats: Vec<String>,
+}
+
+impl GameSettings {
+    /// Reads settings from `path`, or returns None if there's no file there.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map(Some),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut settings = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |why: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {}: {}", number + 1, why),
+                )
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(invalid(format!("expected key = value, got {:?}", line))),
+            };
+            match key {
+                "colors" => settings.colors = Some(value.parse().map_err(invalid)?),
+                "speed" => {
+                    let multiplier = value
+                        .parse()
+                        .map_err(|_| invalid(format!("bad speed {:?}", value)))?;
+                    settings.speed = Some(Speed::from_multiplier(multiplier));
+                }
+                "cheat" => settings.cheats.push(value.to_string()),
+                other => return Err(invalid(format!("unknown setting {:?}", other))),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+impl fmt::Display for GameSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(colors) = self.colors {
+            writeln!(f, "colors = {}", colors)?;
+        }
+        match self.speed {
+            Some(Speed::Times(n)) => writeln!(f, "speed = {}", n)?,
+            Some(Speed::Uncapped) => writeln!(f, "speed = 0")?,
+            None => {}
+        }
+        for cheat in &self.cheats {
+            writeln!(f, "cheat = {}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let text = "# Settings\ncolors = gray\n\nspeed=0\ncheat = 01FF00C0 # infinite lives\n";
+        let settings = GameSettings::parse(text).unwrap();
+        assert_eq!(
+            settings,
+            GameSettings {
+                colors: Some(Colors::Gray),
+                speed: Some(Speed::Uncapped),
+                cheats: vec!["01FF00C0".to_string()],
+            }
+        );
+        assert_eq!(
+            GameSettings::parse(&settings.to_string()).unwrap(),
+            settings
+        );
+
+        assert!(GameSettings::parse("colors = purple").is_err());
+        assert!(GameSettings::parse("speed = fast").is_err());
+        assert!(GameSettings::parse("volume = 11").is_err());
+        assert!(GameSettings::parse("cheat").is_err());
+    }
+}
@@ -20,3 +20,15 @@ pub fn bytes_to_u16(bytes: &[u8]) -> u16 {
     }
     outp
 }
+
+/// 64-bit FNV-1a hash. Used instead of `DefaultHasher` where the hash gets written out or compared
+/// against a checked-in value, since `DefaultHasher` isn't guaranteed to be stable between Rust
+/// releases.
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
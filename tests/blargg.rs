@@ -0,0 +1,78 @@
+//! Runs Blargg's cpu_instrs test ROMs, which print their results out of the serial port. The ROMs
+//! aren't checked in: put the `individual` directory from cpu_instrs under
+//! `testroms/blargg/cpu_instrs/`, and run with `cargo test -- --ignored`.
+extern crate wolfwig;
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use wolfwig::{Model, SerialSink, Wolfwig};
+
+const ROM_DIR: &str = "testroms/blargg/cpu_instrs/individual";
+
+// Machine cycles each ROM gets to finish in, about a minute of emulated time. The slowest take
+// around ten seconds.
+const CYCLE_BUDGET: u64 = 60 * 1_048_576;
+
+// How often to check the serial output.
+const CHECK_INTERVAL: u64 = 17_556;
+
+fn run(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(ROM_DIR)
+        .join(name);
+    let rom =
+        fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+    let mut wolfwig = Wolfwig::new_headless(rom, Model::Dmg);
+    let (tx, rx) = mpsc::channel();
+    wolfwig
+        .set_serial_sink(SerialSink::Callback(Box::new(move |byte| {
+            // The test is over once nobody is listening.
+            let _ = tx.send(byte);
+        })))
+        .unwrap();
+
+    let mut output = String::new();
+    for cycle in 0..CYCLE_BUDGET {
+        wolfwig.step();
+        if cycle % CHECK_INTERVAL != 0 {
+            continue;
+        }
+        output.extend(rx.try_iter().map(char::from));
+        if output.contains("Passed") {
+            return;
+        }
+        if output.contains("Failed") || wolfwig.locked() {
+            break;
+        }
+    }
+    // Bytes can still be on their way from the serial thread.
+    output.extend(rx.try_iter().map(char::from));
+    panic!("{} did not pass. Serial output:\n{}", name, output);
+}
+
+macro_rules! cpu_instrs {
+    ($($test:ident => $rom:expr,)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the cpu_instrs ROMs in testroms/blargg"]
+            fn $test() {
+                run($rom);
+            }
+        )*
+    };
+}
+
+cpu_instrs! {
+    special => "01-special.gb",
+    interrupts => "02-interrupts.gb",
+    op_sp_hl => "03-op sp,hl.gb",
+    op_r_imm => "04-op r,imm.gb",
+    op_rp => "05-op rp.gb",
+    ld_r_r => "06-ld r,r.gb",
+    jr_jp_call_ret_rst => "07-jr,jp,call,ret,rst.gb",
+    misc_instrs => "08-misc instrs.gb",
+    op_r_r => "09-op r,r.gb",
+    bit_ops => "10-bit ops.gb",
+    op_a_hl => "11-op a,(hl).gb",
+}
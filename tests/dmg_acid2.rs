@@ -0,0 +1,73 @@
+//! Renders Matt Currie's dmg-acid2, and checks the hash of the frame against the hash of its
+//! reference image. The ROM and image aren't checked in: put `dmg-acid2.gb` and
+//! `reference-dmg.png` in `testroms/dmg-acid2/`, and run with `cargo test -- --ignored`.
+extern crate png;
+extern crate wolfwig;
+
+use std::fs::{self, File};
+use std::path::Path;
+use wolfwig::{Model, Wolfwig};
+
+const ROM_DIR: &str = "testroms/dmg-acid2";
+
+// The test draws its face within the first few frames, and then leaves it be.
+const FRAMES: u32 = 30;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+// Same hash as `Wolfwig::frame_hash` and the names written by --dump_frames.
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// Reads the reference image as shades, from 0 for white to 3 for black.
+fn reference_shades(path: &Path) -> Vec<u8> {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert_eq!((info.width as usize, info.height as usize), (WIDTH, HEIGHT));
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    let channels = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        other => panic!("Unexpected color type in reference image: {:?}", other),
+    };
+    // The reference uses white, 0xAA, 0x55, and black. Only the first channel is needed.
+    pixels
+        .chunks(channels)
+        .map(|pixel| ((255 - u16::from(pixel[0]) + 42) / 85) as u8)
+        .collect()
+}
+
+#[test]
+#[ignore = "needs dmg-acid2 and its reference in testroms/dmg-acid2"]
+fn dmg_acid2() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIR);
+    let rom_path = dir.join("dmg-acid2.gb");
+    let rom = fs::read(&rom_path)
+        .unwrap_or_else(|err| panic!("Could not read {}: {}", rom_path.display(), err));
+    let reference = dir.join("reference-dmg.png");
+    let expected = fnv1a_64(&reference_shades(&reference));
+
+    let mut wolfwig = Wolfwig::new_headless(rom, Model::Dmg);
+    while wolfwig.frame() < FRAMES {
+        wolfwig.step();
+    }
+    let actual = wolfwig.frame_hash();
+    if actual != expected {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dmg-acid2.png");
+        wolfwig.screenshot(&path).unwrap();
+        panic!(
+            "Frame hash {:016x} doesn't match the reference's {:016x}. Wrote the frame to {}",
+            actual,
+            expected,
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Runs the mooneye-gb acceptance test ROMs, and prints a table of which pass. The ROMs aren't
+//! checked in: put the `acceptance` directory from mooneye-gb's test suite under
+//! `testroms/mooneye/`, and run with `cargo test -- --ignored`.
+//!
+//! Most of them check timing that isn't emulated accurately yet, so failing ones don't fail the
+//! test. The ones in `PASSING` are expected to keep passing, and ones that start passing are
+//! listed so they can be added to it.
+extern crate wolfwig;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use wolfwig::{Model, Reg16, Wolfwig};
+
+const ROM_DIR: &str = "testroms/mooneye/acceptance";
+
+// ROMs known to pass, relative to `ROM_DIR`. Any that stop passing fail the test.
+// TODO(slongfield): Fill in from a run with the ROMs, using the list the test prints.
+const PASSING: &[&str] = &[];
+
+// Machine cycles each ROM gets to finish in, about two seconds of emulated time. They all finish
+// well within a second.
+const CYCLE_BUDGET: u64 = 2 * 1_048_576;
+
+// LD B,B, which the ROMs run once they're done.
+const BREAKPOINT: u8 = 0x40;
+
+// What BC, DE, and HL hold when a test passes. Failing tests fill them with 0x42.
+const PASS_FINGERPRINT: [u16; 3] = [0x0305, 0x080D, 0x1522];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    TimedOut,
+    LockedUp,
+}
+
+fn run(rom: Vec<u8>) -> Outcome {
+    let mut wolfwig = Wolfwig::new_headless(rom, Model::Dmg);
+    for _ in 0..CYCLE_BUDGET {
+        wolfwig.step();
+        if wolfwig.locked() {
+            return Outcome::LockedUp;
+        }
+        if wolfwig.at_instruction() && wolfwig.peripherals.read(wolfwig.pc()) == BREAKPOINT {
+            let fingerprint = [
+                wolfwig.reg16(Reg16::BC),
+                wolfwig.reg16(Reg16::DE),
+                wolfwig.reg16(Reg16::HL),
+            ];
+            return if fingerprint == PASS_FINGERPRINT {
+                Outcome::Passed
+            } else {
+                Outcome::Failed
+            };
+        }
+    }
+    Outcome::TimedOut
+}
+
+// ROM names end in the models they're for, like "boot_regs-dmgABC". Groups are upper case, where
+// G is the DMG and MGB. No suffix means every model.
+fn runs_on_dmg(name: &str) -> bool {
+    match name.rsplit_once('-') {
+        None => true,
+        Some((_, models)) => {
+            models.starts_with("dmgABC")
+                || (models.contains('G') && models.chars().all(|c| c.is_ascii_uppercase()))
+        }
+    }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "gb")
+            && path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(runs_on_dmg)
+        {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs the mooneye-gb ROMs in testroms/mooneye"]
+fn acceptance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIR);
+    let mut roms = vec![];
+    find_roms(&root, &mut roms);
+    assert!(!roms.is_empty(), "No ROMs in {}", root.display());
+    roms.sort();
+
+    let mut passed = 0;
+    let mut regressions = vec![];
+    let mut newly_passing = vec![];
+    for path in &roms {
+        let name = path.strip_prefix(&root).unwrap().to_string_lossy();
+        let outcome = run(fs::read(path).unwrap());
+        println!("{:<50} {:?}", name, outcome);
+        let known = PASSING.contains(&name.as_ref());
+        if outcome == Outcome::Passed {
+            passed += 1;
+            if !known {
+                newly_passing.push(name.into_owned());
+            }
+        } else if known {
+            regressions.push(name.into_owned());
+        }
+    }
+    println!("{}/{} passed", passed, roms.len());
+    if !newly_passing.is_empty() {
+        println!("Newly passing, to add to PASSING:");
+        for name in &newly_passing {
+            println!("    {:?},", name);
+        }
+    }
+    assert!(
+        regressions.is_empty(),
+        "No longer passing: {:?}",
+        regressions
+    );
+}
+
+#[test]
+fn model_suffixes() {
+    assert!(runs_on_dmg("add_sp_e_timing"));
+    assert!(runs_on_dmg("boot_regs-dmgABC"));
+    assert!(runs_on_dmg("di_timing-GS"));
+    assert!(!runs_on_dmg("boot_regs-dmg0"));
+    assert!(!runs_on_dmg("boot_regs-sgb"));
+    assert!(!runs_on_dmg("boot_hwio-S"));
+}